@@ -0,0 +1,173 @@
+//! 原子写入：先写入临时文件，再重命名到目标路径，避免中途失败留下不完整的文件
+
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+use tempfile::NamedTempFile;
+
+/// 为 `target` 创建临时文件
+///
+/// 默认位于目标文件所在目录（保证重命名在同一文件系统内），
+/// 指定 `temp_dir` 时位于该目录。
+pub(crate) fn temp_file_for(
+    target: &Path,
+    temp_dir: Option<&Path>,
+) -> Result<NamedTempFile, String> {
+    let dir = match temp_dir {
+        Some(dir) => dir,
+        None => parent_dir(target),
+    };
+    NamedTempFile::new_in(dir).map_err(|e| format!("创建临时文件失败 {}: {}", dir.display(), e))
+}
+
+/// 将写好的临时文件移动到 `target`
+///
+/// 临时目录与目标不在同一文件系统（重命名返回 EXDEV）时，先复制到目标目录中的
+/// 临时文件再重命名，仍然保证目标文件不会处于写了一半的状态。
+pub(crate) fn persist(temp: NamedTempFile, target: &Path) -> Result<(), String> {
+    persist_with(temp, target, |from, to| fs::rename(from, to))
+}
+
+/// 原子地写入整个文件
+pub fn write_atomic(target: &Path, data: &[u8], temp_dir: Option<&Path>) -> Result<(), String> {
+    let mut temp = temp_file_for(target, temp_dir)?;
+    temp.write_all(data)
+        .map_err(|e| format!("写入文件失败 {}: {}", target.display(), e))?;
+    persist(temp, target)
+}
+
+/// `persist` 的实现，重命名操作可替换以便测试跨文件系统的情况
+fn persist_with<F>(temp: NamedTempFile, target: &Path, rename: F) -> Result<(), String>
+where
+    F: FnOnce(&Path, &Path) -> io::Result<()>,
+{
+    temp.as_file()
+        .sync_all()
+        .map_err(|e| format!("写入文件失败 {}: {}", target.display(), e))?;
+    let temp_path = temp.into_temp_path();
+
+    match rename(&temp_path, target) {
+        Ok(()) => {
+            // 临时文件已被移走，不再需要清理
+            let _ = temp_path.keep();
+            Ok(())
+        }
+        Err(e) if is_cross_device(&e) => {
+            let staging = NamedTempFile::new_in(parent_dir(target))
+                .map_err(|e| format!("创建临时文件失败 {}: {}", target.display(), e))?;
+            fs::copy(&temp_path, staging.path())
+                .map_err(|e| format!("复制临时文件失败 {}: {}", target.display(), e))?;
+            staging
+                .persist(target)
+                .map_err(|e| format!("替换文件失败 {}: {}", target.display(), e.error))?;
+            // temp_path 在此处被丢弃时删除原临时文件
+            Ok(())
+        }
+        Err(e) => Err(format!("替换文件失败 {}: {}", target.display(), e)),
+    }
+}
+
+/// 检查 `target` 所在文件系统是否还能写入 `needed` 字节
+///
+/// 无法查询可用空间时（非 Unix 平台或查询失败）不做限制。
+pub(crate) fn ensure_free_space(target: &Path, needed: u64) -> Result<(), String> {
+    match available_space(parent_dir(target)) {
+        Some(available) if available < needed => Err("目标磁盘空间不足".to_string()),
+        _ => Ok(()),
+    }
+}
+
+/// 查询目录所在文件系统对当前用户可用的字节数
+#[cfg(unix)]
+fn available_space(dir: &Path) -> Option<u64> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let c_path = std::ffi::CString::new(dir.as_os_str().as_bytes()).ok()?;
+    let mut stat = std::mem::MaybeUninit::<libc::statvfs>::uninit();
+    // SAFETY: c_path 为以 NUL 结尾的路径，stat 仅在调用成功后读取
+    let stat = unsafe {
+        if libc::statvfs(c_path.as_ptr(), stat.as_mut_ptr()) != 0 {
+            return None;
+        }
+        stat.assume_init()
+    };
+    #[allow(clippy::unnecessary_cast)]
+    Some(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+#[cfg(not(unix))]
+fn available_space(_dir: &Path) -> Option<u64> {
+    None
+}
+
+/// 目标文件所在目录（相对路径的文件名视为当前目录）
+fn parent_dir(target: &Path) -> &Path {
+    match target.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    }
+}
+
+/// 判断是否为跨文件系统重命名导致的错误
+fn is_cross_device(error: &io::Error) -> bool {
+    error.kind() == io::ErrorKind::CrossesDevices || error.raw_os_error() == Some(libc::EXDEV)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_atomic_with_temp_dir() {
+        let target_dir = tempfile::tempdir().unwrap();
+        let temp_dir = tempfile::tempdir().unwrap();
+        let target = target_dir.path().join("out.bin");
+        fs::write(&target, b"old").unwrap();
+
+        write_atomic(&target, b"new content", Some(temp_dir.path())).unwrap();
+
+        assert_eq!(fs::read(&target).unwrap(), b"new content");
+        assert_eq!(fs::read_dir(temp_dir.path()).unwrap().count(), 0);
+        assert_eq!(fs::read_dir(target_dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_ensure_free_space() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("out.bin");
+        assert!(ensure_free_space(&target, 1).is_ok());
+        if cfg!(unix) {
+            assert_eq!(
+                ensure_free_space(&target, u64::MAX).unwrap_err(),
+                "目标磁盘空间不足"
+            );
+        }
+    }
+
+    #[test]
+    fn test_persist_falls_back_on_cross_device_rename() {
+        let target_dir = tempfile::tempdir().unwrap();
+        let temp_dir = tempfile::tempdir().unwrap();
+        let target = target_dir.path().join("out.bin");
+
+        let mut temp = temp_file_for(&target, Some(temp_dir.path())).unwrap();
+        temp.write_all(b"copied across devices").unwrap();
+        persist_with(temp, &target, |_, _| {
+            Err(io::Error::from_raw_os_error(libc::EXDEV))
+        })
+        .unwrap();
+
+        assert_eq!(fs::read(&target).unwrap(), b"copied across devices");
+        // 原临时文件和中转文件都已清理
+        assert_eq!(fs::read_dir(temp_dir.path()).unwrap().count(), 0);
+        assert_eq!(fs::read_dir(target_dir.path()).unwrap().count(), 1);
+
+        // 其他重命名错误直接返回
+        let temp = temp_file_for(&target, Some(temp_dir.path())).unwrap();
+        let result = persist_with(temp, &target, |_, _| {
+            Err(io::Error::from(io::ErrorKind::PermissionDenied))
+        });
+        assert!(result.is_err());
+        assert_eq!(fs::read(&target).unwrap(), b"copied across devices");
+    }
+}
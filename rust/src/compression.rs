@@ -1,12 +1,118 @@
-use flate2::Compression;
-use flate2::read::{ZlibDecoder, ZlibEncoder};
-use flate2::write::{ZlibDecoder as ZlibDecoderWrite, ZlibEncoder as ZlibEncoderWrite};
+use crate::progress::{Progress, ProgressSink};
+use flate2::bufread;
+use flate2::read::{DeflateDecoder, GzDecoder, ZlibDecoder, ZlibEncoder};
+use flate2::write::{
+    DeflateDecoder as DeflateDecoderWrite, GzDecoder as GzDecoderWrite,
+    ZlibDecoder as ZlibDecoderWrite, ZlibEncoder as ZlibEncoderWrite,
+};
+use flate2::{Compress, Compression, Crc, Decompress, FlushCompress, FlushDecompress, Status};
+use rayon::prelude::*;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::fs;
-use std::io::{Read, Write};
+use std::io::{BufWriter, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::SystemTime;
+
+mod archive;
+mod chunking;
+
+pub use archive::{ExtractStatus, ExtractedFile, OverwritePolicy};
+
+/// zlib 预置字典的最大有效长度（超出部分不在压缩窗口内）
+const MAX_DICTIONARY_SIZE: usize = 32 * 1024;
+
+/// 字典文件的大小上限，超过时视为误用（如选错了文件）直接拒绝
+const MAX_DICTIONARY_FILE_SIZE: u64 = 1024 * 1024;
+
+/// 导出的字典集合魔数与格式版本
+const DICTIONARY_SET_MAGIC: &[u8; 4] = b"SCDS";
+const DICTIONARY_SET_VERSION: u8 = 1;
+
+/// zlib 头部 FLG 字节中表示使用了预置字典的标志位
+const ZLIB_FDICT: u8 = 0x20;
+
+/// 默认的 deflate 窗口大小（2^15 = 32KB）
+const DEFAULT_WINDOW_BITS: u8 = 15;
+
+/// 文件到文件流式压缩的读写缓冲区大小
+const STREAM_BUFFER_SIZE: usize = 64 * 1024;
+
+/// 可复现模式下的 gzip 头部：无文件名、修改时间为 0、操作系统为 255（未知）
+const REPRODUCIBLE_GZIP_HEADER: [u8; 10] = [0x1f, 0x8b, 0x08, 0x00, 0, 0, 0, 0, 0x00, 0xff];
+
+/// `compress_best_of` 直接比较完整数据的大小上限，超过时只比较采样
+const BEST_OF_FULL_LIMIT: usize = 1024 * 1024;
+
+/// `compress_best_of` 采样的片段数和每个片段的大小
+const BEST_OF_SAMPLES: usize = 8;
+const BEST_OF_SAMPLE_SIZE: usize = 32 * 1024;
+
+/// 压缩算法（数据格式）
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Algorithm {
+    /// zlib 格式（默认），带 Adler-32 校验
+    Zlib,
+    /// gzip 格式，带 CRC-32 校验，适合长期归档
+    Gzip,
+    /// 原始 deflate 流，无头部和校验，体积最小
+    Deflate,
+    /// 不压缩，原样存储，适合已压缩或加密的数据
+    Stored,
+}
+
+/// 压缩预设
+///
+/// | 预设       | 算法 | 级别 | 窗口       |
+/// |------------|------|------|------------|
+/// | `Fast`     | zlib | 1    | 4KB (12)   |
+/// | `Balanced` | zlib | 6    | 32KB (15)  |
+/// | `Max`      | zlib | 9    | 32KB (15)  |
+/// | `Archive`  | gzip | 9    | 32KB (15)  |
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Profile {
+    /// 速度优先，小窗口、低内存
+    Fast,
+    /// 速度与压缩率平衡（与默认配置相同）
+    Balanced,
+    /// 压缩率优先
+    Max,
+    /// 压缩率优先并带 CRC-32 校验的 gzip 格式，用于长期归档
+    Archive,
+}
+
+impl Profile {
+    /// 预设对应的 (算法, 级别, 窗口位数)
+    pub fn parameters(self) -> (Algorithm, u32, u8) {
+        match self {
+            Profile::Fast => (Algorithm::Zlib, 1, 12),
+            Profile::Balanced => (Algorithm::Zlib, 6, DEFAULT_WINDOW_BITS),
+            Profile::Max => (Algorithm::Zlib, 9, DEFAULT_WINDOW_BITS),
+            Profile::Archive => (Algorithm::Gzip, 9, DEFAULT_WINDOW_BITS),
+        }
+    }
+}
 
 /// 压缩器
 pub struct Compressor {
     compression_level: Compression,
+    algorithm: Algorithm,
+    window_bits: u8,
+    /// 批量操作中同时打开文件数的限制
+    io_limiter: Option<Arc<IoLimiter>>,
+    /// 写文件时临时文件所在目录（见 `with_temp_dir`）
+    temp_dir: Option<PathBuf>,
+    /// 解压到文件前是否检查目标磁盘的可用空间
+    free_space_check: bool,
+    /// 压缩/解压上下文复用池（见 `with_pool`）
+    pool: Option<Arc<CompressorPool>>,
+    /// 是否输出与平台和时间无关的字节（见 `with_reproducible`）
+    reproducible: bool,
+    /// zlib 预置字典（见 `with_dictionary`）
+    dictionary: Option<Vec<u8>>,
+    /// 不压缩、原样保存的文件扩展名（小写，不含点，见 `with_skip_extensions`）
+    skip_extensions: HashSet<String>,
 }
 
 impl Compressor {
@@ -14,6 +120,15 @@ impl Compressor {
     pub fn new() -> Self {
         Self {
             compression_level: Compression::default(),
+            algorithm: Algorithm::Zlib,
+            window_bits: DEFAULT_WINDOW_BITS,
+            io_limiter: None,
+            temp_dir: None,
+            free_space_check: false,
+            pool: None,
+            reproducible: false,
+            dictionary: None,
+            skip_extensions: HashSet::new(),
         }
     }
 
@@ -21,11 +136,166 @@ impl Compressor {
     pub fn with_level(level: u32) -> Self {
         Self {
             compression_level: Compression::new(level),
+            ..Self::new()
+        }
+    }
+
+    /// 使用压缩预设创建压缩器（见 `Profile`）
+    pub fn with_profile(profile: Profile) -> Self {
+        let (algorithm, level, window_bits) = profile.parameters();
+        Self {
+            compression_level: Compression::new(level),
+            algorithm,
+            window_bits,
+            ..Self::new()
+        }
+    }
+
+    /// 设置压缩算法
+    pub fn with_algorithm(mut self, algorithm: Algorithm) -> Self {
+        self.algorithm = algorithm;
+        self
+    }
+
+    /// 当前使用的压缩算法
+    pub fn algorithm(&self) -> Algorithm {
+        self.algorithm
+    }
+
+    /// 限制批量操作中同时打开的文件数
+    ///
+    /// 与 rayon 线程数无关，用于避免大批量文件触发 EMFILE（打开文件过多）。
+    /// 目前只作用于 `compress_multiple_files`；`analyze_tree` 等处理内存数据的
+    /// 批量操作不打开文件，不受此限制。
+    pub fn with_max_concurrent_io(mut self, max_open_files: usize) -> Self {
+        self.io_limiter = Some(Arc::new(IoLimiter::new(max_open_files)));
+        self
+    }
+
+    /// 设置写文件时临时文件所在的目录
+    ///
+    /// 输出文件先写入临时文件再重命名到目标路径，默认临时文件位于目标所在目录。
+    /// 临时目录与目标不在同一文件系统时自动退回为复制。
+    pub fn with_temp_dir(mut self, temp_dir: impl Into<PathBuf>) -> Self {
+        self.temp_dir = Some(temp_dir.into());
+        self
+    }
+
+    /// 设置解压到文件前是否检查目标磁盘的可用空间
+    ///
+    /// 空间不足时 `decompress_to_file` 在写入前返回 `Err("目标磁盘空间不足")`。
+    pub fn with_free_space_check(mut self, enabled: bool) -> Self {
+        self.free_space_check = enabled;
+        self
+    }
+
+    /// 通过上下文池复用底层压缩/解压状态，减少高吞吐场景下的内存分配
+    ///
+    /// 同一个池可以在多个压缩器和线程之间共享，复用的上下文与新建的上下文输出完全相同。
+    pub fn with_pool(mut self, pool: Arc<CompressorPool>) -> Self {
+        self.pool = Some(pool);
+        self
+    }
+
+    /// 可复现模式：相同输入总是产生逐字节相同的输出，便于按内容做缓存键
+    ///
+    /// gzip 使用固定头部（修改时间为 0、不含文件名、操作系统字段为 255），
+    /// 默认模式下头部的操作系统字段随构建平台而不同；归档成员按名称排序后写入。
+    pub fn with_reproducible(mut self, enabled: bool) -> Self {
+        self.reproducible = enabled;
+        self
+    }
+
+    /// 设置不压缩的文件扩展名（如 `jpg`、`mp4`、`zip`，忽略大小写和开头的点）
+    ///
+    /// 作用于按文件处理的批量操作：`compress_multiple_files` 原样返回文件内容并标记为
+    /// `stored`，`analyze_tree` 按未压缩统计，`create_archive` 以存储方法 0x00 写入成员，
+    /// 匹配的文件都不会尝试压缩。
+    pub fn with_skip_extensions(mut self, extensions: HashSet<String>) -> Self {
+        self.skip_extensions = extensions
+            .iter()
+            .map(|ext| ext.trim_start_matches('.').to_lowercase())
+            .collect();
+        self
+    }
+
+    /// 该路径的扩展名是否在跳过压缩的列表中
+    fn skips_compression(&self, path: &str) -> bool {
+        !self.skip_extensions.is_empty()
+            && Path::new(path).extension().is_some_and(|ext| {
+                self.skip_extensions
+                    .contains(&ext.to_string_lossy().to_lowercase())
+            })
+    }
+
+    /// 使用预置字典压缩和解压（仅支持 zlib 算法）
+    ///
+    /// 只作用于 `compress` 和 `decompress`，解压方必须配置相同的字典。
+    /// 字典只有末尾 32KB 在压缩窗口内，超出部分会被丢弃。
+    pub fn with_dictionary(mut self, dictionary: Vec<u8>) -> Self {
+        self.dictionary = Some(truncate_dictionary(dictionary));
+        self
+    }
+
+    /// 从文件加载预置字典并应用（见 `load_dictionary` 和 `with_dictionary`）
+    pub fn with_dictionary_file(self, path: &str) -> Result<Self, String> {
+        let dictionary = Self::load_dictionary(path)?;
+        Ok(self.with_dictionary(dictionary))
+    }
+
+    /// 读取字典文件
+    ///
+    /// 空文件和超过 1MB 的文件会被拒绝。
+    pub fn load_dictionary(path: &str) -> Result<Vec<u8>, String> {
+        let size = fs::metadata(path)
+            .map_err(|e| format!("读取字典文件失败 {}: {}", path, e))?
+            .len();
+        if size > MAX_DICTIONARY_FILE_SIZE {
+            return Err(format!(
+                "字典文件过大 {}: {} 字节，上限为 {} 字节",
+                path, size, MAX_DICTIONARY_FILE_SIZE
+            ));
         }
+
+        let dictionary = crate::error::read_file(path)?;
+        if dictionary.is_empty() {
+            return Err(format!("字典文件为空: {}", path));
+        }
+        Ok(dictionary)
+    }
+
+    /// 当前配置的 IO 并发限制器
+    pub fn io_limiter(&self) -> Option<&IoLimiter> {
+        self.io_limiter.as_deref()
     }
 
     /// 压缩数据
     pub fn compress(&self, data: &[u8]) -> Result<Vec<u8>, String> {
+        if self.dictionary.is_some() {
+            return self.compress_with_level(data, self.compression_level);
+        }
+        if self.algorithm == Algorithm::Stored {
+            return Ok(data.to_vec());
+        }
+        if self.reproducible_gzip() {
+            return self.run_configured(&mut self.new_compress()?, data);
+        }
+        if let Some(pool) = &self.pool {
+            let key = (
+                self.algorithm,
+                self.compression_level.level(),
+                self.window_bits,
+            );
+            return pool.with_encoder(
+                key,
+                || self.new_compress(),
+                |compress| run_compress(compress, data),
+            );
+        }
+        if self.algorithm != Algorithm::Zlib || self.window_bits != DEFAULT_WINDOW_BITS {
+            return run_compress(&mut self.new_compress()?, data);
+        }
+
         let mut encoder = ZlibEncoder::new(data, self.compression_level);
         let mut compressed_data = Vec::new();
 
@@ -36,45 +306,328 @@ impl Compressor {
         Ok(compressed_data)
     }
 
+    /// 按当前算法、级别和窗口大小创建底层压缩流
+    ///
+    /// `Stored` 不经过压缩流，调用方需单独处理，误用时返回错误而不是输出 deflate 数据。
+    /// 可复现模式下的 gzip 只输出裸 deflate 数据，头部和尾部由调用方写入。
+    fn new_compress(&self) -> Result<Compress, String> {
+        self.new_compress_with_level(self.compression_level)
+    }
+
+    /// 与 `new_compress` 相同，但使用指定的压缩级别
+    fn new_compress_with_level(&self, level: Compression) -> Result<Compress, String> {
+        Ok(match self.algorithm {
+            Algorithm::Zlib => Compress::new_with_window_bits(level, true, self.window_bits),
+            Algorithm::Gzip if self.reproducible => {
+                Compress::new_with_window_bits(level, false, self.window_bits)
+            }
+            Algorithm::Deflate => Compress::new_with_window_bits(level, false, self.window_bits),
+            Algorithm::Gzip => Compress::new_gzip(level, self.window_bits),
+            Algorithm::Stored => return Err("原样存储不使用压缩流".to_string()),
+        })
+    }
+
+    /// 按当前算法、窗口大小、字典和可复现设置压缩，但使用指定的压缩级别（不经过上下文池）
+    fn compress_with_level(&self, data: &[u8], level: Compression) -> Result<Vec<u8>, String> {
+        if self.dictionary.is_some() && self.algorithm != Algorithm::Zlib {
+            return Err("预置字典仅支持 zlib 算法".to_string());
+        }
+        if self.algorithm == Algorithm::Stored {
+            return Ok(data.to_vec());
+        }
+        let mut compress = self.new_compress_with_level(level)?;
+        if let Some(dictionary) = &self.dictionary {
+            compress
+                .set_dictionary(dictionary)
+                .map_err(|e| format!("设置压缩字典失败: {}", e))?;
+        }
+        self.run_configured(&mut compress, data)
+    }
+
+    /// 是否需要手动写入固定的 gzip 头部和尾部
+    fn reproducible_gzip(&self) -> bool {
+        self.reproducible && self.algorithm == Algorithm::Gzip
+    }
+
+    /// 用 `new_compress` 系列创建的压缩流一次性压缩全部数据，
+    /// 可复现模式下的 gzip 在裸 deflate 数据前后补上固定头部和尾部
+    fn run_configured(&self, compress: &mut Compress, data: &[u8]) -> Result<Vec<u8>, String> {
+        let compressed = run_compress(compress, data)?;
+        if !self.reproducible_gzip() {
+            return Ok(compressed);
+        }
+
+        let mut crc = Crc::new();
+        crc.update(data);
+        let mut output = Vec::with_capacity(compressed.len() + 18);
+        output.extend_from_slice(&REPRODUCIBLE_GZIP_HEADER);
+        output.extend_from_slice(&compressed);
+        output.extend_from_slice(&gzip_trailer(&crc));
+        Ok(output)
+    }
+
     /// 解压缩数据
     pub fn decompress(&self, compressed_data: &[u8]) -> Result<Vec<u8>, String> {
-        let mut decoder = ZlibDecoder::new(compressed_data);
+        // 配置了字典或数据头部声明需要字典时，由 `run_decompress` 处理字典
+        let needs_dictionary = compressed_data
+            .get(1)
+            .is_some_and(|flags| flags & ZLIB_FDICT != 0);
+        if self.algorithm == Algorithm::Zlib && (self.dictionary.is_some() || needs_dictionary) {
+            return run_decompress(
+                &mut Decompress::new(true),
+                compressed_data,
+                self.dictionary.as_deref(),
+            );
+        }
+        if let Some(pool) = &self.pool
+            && matches!(self.algorithm, Algorithm::Zlib | Algorithm::Deflate)
+        {
+            return pool.with_decoder(self.algorithm, |decompress| {
+                run_decompress(decompress, compressed_data, None)
+            });
+        }
+
         let mut decompressed_data = Vec::new();
 
-        decoder
-            .read_to_end(&mut decompressed_data)
-            .map_err(|e| format!("解压缩失败: {}", e))?;
+        let read_result = match self.algorithm {
+            Algorithm::Zlib => {
+                ZlibDecoder::new(compressed_data).read_to_end(&mut decompressed_data)
+            }
+            Algorithm::Gzip => GzDecoder::new(compressed_data).read_to_end(&mut decompressed_data),
+            Algorithm::Deflate => {
+                DeflateDecoder::new(compressed_data).read_to_end(&mut decompressed_data)
+            }
+            Algorithm::Stored => {
+                decompressed_data.extend_from_slice(compressed_data);
+                Ok(compressed_data.len())
+            }
+        };
+        read_result.map_err(|e| format!("解压缩失败: {}", e))?;
 
         Ok(decompressed_data)
     }
 
+    /// 检查压缩数据解压后的大小是否等于 `expected`
+    ///
+    /// 流式解压并只计数不保存输出；输出超过 `expected` 时立即返回错误，
+    /// 防止解压炸弹耗尽资源。
+    pub fn check_decompressed_size(
+        &self,
+        compressed: &[u8],
+        expected: usize,
+    ) -> Result<bool, String> {
+        let mut decoder: Box<dyn Read + '_> = match self.algorithm {
+            Algorithm::Zlib => Box::new(ZlibDecoder::new(compressed)),
+            Algorithm::Gzip => Box::new(GzDecoder::new(compressed)),
+            Algorithm::Deflate => Box::new(DeflateDecoder::new(compressed)),
+            Algorithm::Stored => Box::new(compressed),
+        };
+
+        let mut buffer = vec![0u8; STREAM_BUFFER_SIZE];
+        let mut total = 0usize;
+        loop {
+            let n = match decoder.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(format!("解压缩失败: {}", e)),
+            };
+            total += n;
+            if total > expected {
+                return Err(format!("解压后大小超出预期: 超过 {} 字节", expected));
+            }
+        }
+
+        Ok(total == expected)
+    }
+
+    /// 只解压缩数据开头的第一个压缩流
+    ///
+    /// 返回解压后的数据以及消耗的输入字节数，多个独立压缩流首尾相接时，
+    /// 可用 `&data[consumed..]` 继续解压下一个。`Stored` 数据没有流边界，总是消耗全部输入。
+    pub fn decompress_one(&self, data: &[u8]) -> Result<(Vec<u8>, usize), String> {
+        let mut decompressed_data = Vec::new();
+        // bufread 解码器只消耗属于当前压缩流的字节，剩余部分留在 `remaining` 中
+        let mut remaining = data;
+
+        let read_result = match self.algorithm {
+            Algorithm::Zlib => {
+                bufread::ZlibDecoder::new(&mut remaining).read_to_end(&mut decompressed_data)
+            }
+            Algorithm::Gzip => {
+                bufread::GzDecoder::new(&mut remaining).read_to_end(&mut decompressed_data)
+            }
+            Algorithm::Deflate => {
+                bufread::DeflateDecoder::new(&mut remaining).read_to_end(&mut decompressed_data)
+            }
+            Algorithm::Stored => remaining.read_to_end(&mut decompressed_data),
+        };
+        read_result.map_err(|e| format!("解压缩失败: {}", e))?;
+
+        Ok((decompressed_data, data.len() - remaining.len()))
+    }
+
     /// 压缩文件
     pub fn compress_file(&self, file_path: &str) -> Result<Vec<u8>, String> {
-        let file_data =
-            fs::read(file_path).map_err(|e| format!("读取文件失败 {}: {}", file_path, e))?;
+        let file_data = crate::error::read_file(file_path)?;
 
         self.compress(&file_data)
     }
 
+    /// 将文件流式压缩到另一个文件
+    ///
+    /// 按固定大小的缓冲区边读边写，内存占用与文件大小无关。返回的
+    /// `CompressionResult` 只包含统计信息，`compressed_data` 为空。
+    pub fn compress_file_to_file(
+        &self,
+        input_path: &str,
+        output_path: &str,
+    ) -> Result<CompressionResult, String> {
+        let mut input = fs::File::open(input_path)
+            .map_err(|e| format!("打开文件失败 {}: {}", input_path, e))?;
+        let output =
+            crate::atomic::temp_file_for(Path::new(output_path), self.temp_dir.as_deref())?;
+        let mut writer = BufWriter::new(output.as_file());
+        let write_error = |e: std::io::Error| format!("写入文件失败 {}: {}", output_path, e);
+
+        if self.algorithm == Algorithm::Stored {
+            let copied = std::io::copy(&mut input, &mut writer)
+                .map_err(|e| format!("复制文件失败 {}: {}", input_path, e))?;
+            writer.flush().map_err(write_error)?;
+            drop(writer);
+            crate::atomic::persist(output, Path::new(output_path))?;
+            return Ok(self.stats(Vec::new(), copied as usize, copied as usize));
+        }
+
+        let mut compress = self.new_compress()?;
+        let mut crc = Crc::new();
+        if self.reproducible_gzip() {
+            writer
+                .write_all(&REPRODUCIBLE_GZIP_HEADER)
+                .map_err(write_error)?;
+        }
+        let mut in_buf = vec![0u8; STREAM_BUFFER_SIZE];
+        let mut out_buf = Vec::with_capacity(STREAM_BUFFER_SIZE);
+        loop {
+            let n = match input.read(&mut in_buf) {
+                Ok(n) => n,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(format!("读取文件失败 {}: {}", input_path, e)),
+            };
+            crc.update(&in_buf[..n]);
+            let finish = n == 0;
+            let flush = if finish {
+                FlushCompress::Finish
+            } else {
+                FlushCompress::None
+            };
+
+            let mut pending = &in_buf[..n];
+            loop {
+                out_buf.clear();
+                let before = compress.total_in();
+                let status = compress
+                    .compress_vec(pending, &mut out_buf, flush)
+                    .map_err(|e| format!("压缩失败: {}", e))?;
+                pending = &pending[(compress.total_in() - before) as usize..];
+                writer.write_all(&out_buf).map_err(write_error)?;
+
+                let done = if finish {
+                    status == Status::StreamEnd
+                } else {
+                    pending.is_empty()
+                };
+                if done {
+                    break;
+                }
+            }
+
+            if finish {
+                break;
+            }
+        }
+        let mut total_out = compress.total_out() as usize;
+        if self.reproducible_gzip() {
+            writer.write_all(&gzip_trailer(&crc)).map_err(write_error)?;
+            total_out += REPRODUCIBLE_GZIP_HEADER.len() + 8;
+        }
+        writer.flush().map_err(write_error)?;
+        drop(writer);
+        crate::atomic::persist(output, Path::new(output_path))?;
+
+        Ok(self.stats(Vec::new(), compress.total_in() as usize, total_out))
+    }
+
     /// 解压缩到文件
     pub fn decompress_to_file(
         &self,
         compressed_data: &[u8],
         output_path: &str,
     ) -> Result<(), String> {
-        let decompressed_data = self.decompress(compressed_data)?;
-
-        fs::write(output_path, decompressed_data)
-            .map_err(|e| format!("写入文件失败 {}: {}", output_path, e))?;
+        self.decompress_to_file_with_size_hint(compressed_data, output_path, None)
+    }
 
+    /// 解压缩到文件，并将输出文件的修改时间设为 `mtime`
+    ///
+    /// 用于缓存等先压缩再还原的场景，使还原后的文件保留原始修改时间，
+    /// 不影响基于修改时间的差异比较。`mtime` 为 None 时与 `decompress_to_file` 相同。
+    pub fn decompress_to_file_with_mtime(
+        &self,
+        compressed_data: &[u8],
+        output_path: &str,
+        mtime: Option<SystemTime>,
+    ) -> Result<(), String> {
+        self.decompress_to_file(compressed_data, output_path)?;
+
+        if let Some(mtime) = mtime {
+            fs::File::options()
+                .write(true)
+                .open(output_path)
+                .and_then(|file| file.set_modified(mtime))
+                .map_err(|e| format!("设置修改时间失败 {}: {}", output_path, e))?;
+        }
         Ok(())
     }
 
-    /// 流式压缩（用于大文件）
+    /// 解压缩到文件，`expected_size` 为预期的解压后大小
+    ///
+    /// 启用空间检查时，给出预期大小则在解压前检查，否则按实际解压结果的大小在写入前检查。
+    pub fn decompress_to_file_with_size_hint(
+        &self,
+        compressed_data: &[u8],
+        output_path: &str,
+        expected_size: Option<u64>,
+    ) -> Result<(), String> {
+        let target = Path::new(output_path);
+        if self.free_space_check
+            && let Some(expected_size) = expected_size
+        {
+            crate::atomic::ensure_free_space(target, expected_size)?;
+        }
+
+        let decompressed_data = self.decompress(compressed_data)?;
+        if self.free_space_check && expected_size.is_none() {
+            crate::atomic::ensure_free_space(target, decompressed_data.len() as u64)?;
+        }
+
+        crate::atomic::write_atomic(target, &decompressed_data, self.temp_dir.as_deref())
+    }
+
+    /// 流式压缩（用于大文件），输出格式与 `compress` 相同
     pub fn compress_stream(&self, input_data: &[u8]) -> Result<Vec<u8>, String> {
+        // 原样存储和预置字典没有单独的流式实现
+        if self.algorithm == Algorithm::Stored || self.dictionary.is_some() {
+            return self.compress(input_data);
+        }
+
         let mut output = Vec::new();
+        if self.reproducible_gzip() {
+            output.extend_from_slice(&REPRODUCIBLE_GZIP_HEADER);
+        }
         {
-            let mut encoder = ZlibEncoderWrite::new(&mut output, self.compression_level);
+            // 数据格式（zlib/gzip/deflate）由底层压缩流决定，写入端包装本身不添加头部
+            let mut encoder =
+                ZlibEncoderWrite::new_with_compress(&mut output, self.new_compress()?);
             encoder
                 .write_all(input_data)
                 .map_err(|e| format!("流式压缩写入失败: {}", e))?;
@@ -82,104 +635,399 @@ impl Compressor {
                 .finish()
                 .map_err(|e| format!("流式压缩完成失败: {}", e))?;
         }
+        if self.reproducible_gzip() {
+            let mut crc = Crc::new();
+            crc.update(input_data);
+            output.extend_from_slice(&gzip_trailer(&crc));
+        }
         Ok(output)
     }
 
-    /// 流式解压缩
+    /// 流式解压缩，按当前算法选择解码器
     pub fn decompress_stream(&self, compressed_data: &[u8]) -> Result<Vec<u8>, String> {
-        let mut output = Vec::new();
-        {
-            let mut decoder = ZlibDecoderWrite::new(&mut output);
-            decoder
-                .write_all(compressed_data)
-                .map_err(|e| format!("流式解压缩写入失败: {}", e))?;
-            decoder
-                .finish()
-                .map_err(|e| format!("流式解压缩完成失败: {}", e))?;
+        match self.algorithm {
+            Algorithm::Zlib if self.dictionary.is_some() => self.decompress(compressed_data),
+            Algorithm::Zlib => write_decoded(
+                ZlibDecoderWrite::new(Vec::new()),
+                compressed_data,
+                ZlibDecoderWrite::finish,
+            ),
+            Algorithm::Gzip => write_decoded(
+                GzDecoderWrite::new(Vec::new()),
+                compressed_data,
+                GzDecoderWrite::finish,
+            ),
+            Algorithm::Deflate => write_decoded(
+                DeflateDecoderWrite::new(Vec::new()),
+                compressed_data,
+                DeflateDecoderWrite::finish,
+            ),
+            Algorithm::Stored => Ok(compressed_data.to_vec()),
         }
-        Ok(output)
     }
 
-    /// 计算压缩比
+    /// 压缩长度未知的数据流（如持续追加的日志），一直读到 EOF
+    ///
+    /// 每读入 `flush_every` 字节执行一次同步刷新并刷新 `output`，下游无需等到
+    /// EOF 即可解出已写入的内容，延迟有上界；EOF 时结束压缩流。
+    /// 返回的 `CompressionResult` 只包含统计信息，`compressed_data` 为空。
+    pub fn compress_unbounded<R: Read, W: Write>(
+        &self,
+        mut input: R,
+        mut output: W,
+        flush_every: usize,
+    ) -> Result<CompressionResult, String> {
+        if flush_every == 0 {
+            return Err("flush_every 必须大于 0".to_string());
+        }
+        let write_error = |e: std::io::Error| format!("流式压缩写入失败: {}", e);
+
+        // 原样存储时没有压缩流，数据直接写出
+        let mut compress = match self.algorithm {
+            Algorithm::Stored => None,
+            _ => Some(self.new_compress()?),
+        };
+        let mut in_buf = vec![0u8; STREAM_BUFFER_SIZE.min(flush_every)];
+        let mut out_buf = Vec::with_capacity(STREAM_BUFFER_SIZE);
+        let mut since_flush = 0usize;
+        let (mut total_in, mut total_out) = (0usize, 0usize);
+        loop {
+            let want = in_buf.len().min(flush_every - since_flush);
+            let n = match input.read(&mut in_buf[..want]) {
+                Ok(n) => n,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(format!("流式压缩读取失败: {}", e)),
+            };
+            let finish = n == 0;
+            since_flush += n;
+            total_in += n;
+            let flush = if finish {
+                FlushCompress::Finish
+            } else if since_flush == flush_every {
+                FlushCompress::Sync
+            } else {
+                FlushCompress::None
+            };
+
+            if let Some(compress) = &mut compress {
+                let mut pending = &in_buf[..n];
+                loop {
+                    out_buf.clear();
+                    let before = compress.total_in();
+                    let status = compress
+                        .compress_vec(pending, &mut out_buf, flush)
+                        .map_err(|e| format!("压缩失败: {}", e))?;
+                    pending = &pending[(compress.total_in() - before) as usize..];
+                    output.write_all(&out_buf).map_err(write_error)?;
+                    total_out += out_buf.len();
+
+                    // 输出缓冲区未写满说明本次刷新的数据已全部取出
+                    let done = match flush {
+                        FlushCompress::Finish => status == Status::StreamEnd,
+                        FlushCompress::Sync => {
+                            pending.is_empty() && out_buf.len() < out_buf.capacity()
+                        }
+                        _ => pending.is_empty(),
+                    };
+                    if done {
+                        break;
+                    }
+                }
+            } else {
+                output.write_all(&in_buf[..n]).map_err(write_error)?;
+                total_out += n;
+            }
+
+            if finish || since_flush == flush_every {
+                output.flush().map_err(write_error)?;
+                since_flush = 0;
+            }
+            if finish {
+                break;
+            }
+        }
+
+        Ok(self.stats(Vec::new(), total_in, total_out))
+    }
+
+    /// 计算压缩比（节省的百分比，等同于 `RatioKind::PercentSaved`）
     pub fn calculate_compression_ratio(&self, original_size: usize, compressed_size: usize) -> f64 {
-        if original_size == 0 {
-            return 0.0;
+        self.compression_ratio_as(original_size, compressed_size, RatioKind::PercentSaved)
+    }
+
+    /// 按指定的表示方式计算压缩比
+    ///
+    /// 原始大小为 0 时视为没有压缩：节省比例为 0，压缩后/原始为 1。
+    pub fn compression_ratio_as(
+        &self,
+        original_size: usize,
+        compressed_size: usize,
+        kind: RatioKind,
+    ) -> f64 {
+        let compressed_over_original = if original_size == 0 {
+            1.0
+        } else {
+            compressed_size as f64 / original_size as f64
+        };
+        match kind {
+            RatioKind::PercentSaved => (1.0 - compressed_over_original) * 100.0,
+            RatioKind::FractionSaved => 1.0 - compressed_over_original,
+            RatioKind::CompressedOverOriginal => compressed_over_original,
         }
-        (original_size as f64 - compressed_size as f64) / original_size as f64 * 100.0
     }
 
     /// 压缩并返回统计信息
     pub fn compress_with_stats(&self, data: &[u8]) -> Result<CompressionResult, String> {
-        let original_size = data.len();
         let compressed_data = self.compress(data)?;
         let compressed_size = compressed_data.len();
-        let compression_ratio = self.calculate_compression_ratio(original_size, compressed_size);
+        Ok(self.stats(compressed_data, data.len(), compressed_size))
+    }
 
-        Ok(CompressionResult {
+    /// 按当前配置组装压缩统计信息
+    fn stats(
+        &self,
+        compressed_data: Vec<u8>,
+        original_size: usize,
+        compressed_size: usize,
+    ) -> CompressionResult {
+        CompressionResult {
             compressed_data,
             original_size,
             compressed_size,
-            compression_ratio,
+            compression_ratio: self.calculate_compression_ratio(original_size, compressed_size),
             compression_level: self.compression_level.level(),
-        })
+        }
     }
 
-    /// 批量压缩文件
+    /// 用每个候选算法压缩，返回胜出的算法及其输出最小的结果
+    ///
+    /// 候选可包含 `Stored`，已压缩或随机数据上原样存储通常胜出。
+    /// 数据不超过 1MB 时直接比较完整压缩结果；更大的数据只比较均匀分布的 8 个
+    /// 32KB 采样，再用胜出的算法压缩全部数据。大小相同时取靠前的候选。
+    pub fn compress_best_of(
+        &self,
+        data: &[u8],
+        algos: &[Algorithm],
+    ) -> Result<(Algorithm, CompressionResult), String> {
+        if algos.is_empty() {
+            return Err("候选压缩算法为空".to_string());
+        }
+        let candidate = |algorithm: Algorithm| Compressor {
+            compression_level: self.compression_level,
+            algorithm,
+            window_bits: self.window_bits,
+            ..Self::new()
+        };
+
+        if data.len() <= BEST_OF_FULL_LIMIT {
+            let mut best: Option<(Algorithm, CompressionResult)> = None;
+            for &algorithm in algos {
+                let result = candidate(algorithm).compress_with_stats(data)?;
+                if best
+                    .as_ref()
+                    .is_none_or(|(_, best)| result.compressed_size < best.compressed_size)
+                {
+                    best = Some((algorithm, result));
+                }
+            }
+            return best.ok_or_else(|| "候选压缩算法为空".to_string());
+        }
+
+        let stride = data.len() / BEST_OF_SAMPLES;
+        let sample: Vec<u8> = (0..BEST_OF_SAMPLES)
+            .flat_map(|i| &data[i * stride..i * stride + BEST_OF_SAMPLE_SIZE.min(stride)])
+            .copied()
+            .collect();
+        let mut winner = (algos[0], usize::MAX);
+        for &algorithm in algos {
+            let size = candidate(algorithm).compress(&sample)?.len();
+            if size < winner.1 {
+                winner = (algorithm, size);
+            }
+        }
+        Ok((winner.0, candidate(winner.0).compress_with_stats(data)?))
+    }
+
+    /// 压缩到目标大小以内
+    ///
+    /// 从级别 1 起逐级尝试，返回第一个压缩结果不超过 `max_output` 字节的级别
+    /// （即满足目标的最快级别）；级别 9 仍然超出时返回错误。
+    /// 除级别外沿用当前的算法、窗口大小、字典和可复现设置。
+    pub fn compress_to_target(
+        &self,
+        data: &[u8],
+        max_output: usize,
+    ) -> Result<CompressionResult, String> {
+        for level in 1..=9 {
+            let compressed_data = self.compress_with_level(data, Compression::new(level))?;
+            let compressed_size = compressed_data.len();
+            if compressed_size <= max_output {
+                return Ok(CompressionResult {
+                    compression_level: level,
+                    ..self.stats(compressed_data, data.len(), compressed_size)
+                });
+            }
+        }
+
+        Err("无法压缩到目标大小".to_string())
+    }
+
+    /// 将多个小文件拼接后作为一个压缩流压缩
+    ///
+    /// 避免每个文件单独压缩时重复的格式头部和压缩上下文重置，适合大量小文件。
+    /// 返回压缩数据和每个文件在解压结果中的位置，解压后用 `FileOffset::slice` 取回单个文件。
+    pub fn compress_batch_concat(
+        &self,
+        files: &[(String, Vec<u8>)],
+    ) -> Result<(Vec<u8>, Vec<FileOffset>), String> {
+        let total_len = files.iter().map(|(_, data)| data.len()).sum();
+        let mut concatenated = Vec::with_capacity(total_len);
+        let mut offsets = Vec::with_capacity(files.len());
+        for (name, data) in files {
+            offsets.push(FileOffset {
+                name: name.clone(),
+                offset: concatenated.len(),
+                len: data.len(),
+            });
+            concatenated.extend_from_slice(data);
+        }
+
+        Ok((self.compress(&concatenated)?, offsets))
+    }
+
+    /// 批量压缩文件（并行执行）
     pub fn compress_multiple_files(
         &self,
         file_paths: &[String],
     ) -> Result<Vec<FileCompressionResult>, String> {
-        let mut results = Vec::new();
-
-        for file_path in file_paths {
-            match self.compress_file(file_path) {
-                Ok(compressed_data) => {
-                    let original_size = fs::metadata(file_path)
-                        .map_err(|e| format!("获取文件元数据失败 {}: {}", file_path, e))?
-                        .len() as usize;
-
-                    let compressed_size = compressed_data.len();
-                    let compression_ratio =
-                        self.calculate_compression_ratio(original_size, compressed_size);
-
-                    results.push(FileCompressionResult {
-                        file_path: file_path.clone(),
-                        success: true,
-                        compressed_data: Some(compressed_data),
-                        original_size,
-                        compressed_size,
-                        compression_ratio,
-                        error_message: None,
-                    });
-                }
-                Err(e) => {
-                    results.push(FileCompressionResult {
-                        file_path: file_path.clone(),
-                        success: false,
-                        compressed_data: None,
-                        original_size: 0,
-                        compressed_size: 0,
-                        compression_ratio: 0.0,
-                        error_message: Some(e),
-                    });
-                }
+        self.compress_multiple_files_with_progress(file_paths, None)
+    }
+
+    /// 批量压缩文件并报告进度，进度单位为文件数（压缩失败的文件同样计入）
+    pub fn compress_multiple_files_with_progress(
+        &self,
+        file_paths: &[String],
+        progress: Option<&mut dyn ProgressSink>,
+    ) -> Result<Vec<FileCompressionResult>, String> {
+        let progress = Progress::new(progress);
+        progress.start(file_paths.len() as u64);
+        let results = file_paths
+            .par_iter()
+            .map(|file_path| {
+                let result = self.compress_one_file(file_path);
+                progress.advance(1);
+                result
+            })
+            .collect();
+        progress.done();
+        results
+    }
+
+    /// 压缩单个文件，压缩失败记录在结果中而不是返回错误
+    fn compress_one_file(&self, file_path: &str) -> Result<FileCompressionResult, String> {
+        let _permit = self.io_limiter.as_ref().map(|limiter| limiter.acquire());
+
+        let stored = self.skips_compression(file_path);
+        let compressed = if stored {
+            crate::error::read_file(file_path).map_err(String::from)
+        } else {
+            self.compress_file(file_path)
+        };
+        match compressed {
+            Ok(compressed_data) => {
+                let original_size = fs::metadata(file_path)
+                    .map_err(|e| format!("获取文件元数据失败 {}: {}", file_path, e))?
+                    .len() as usize;
+
+                let compressed_size = compressed_data.len();
+                let compression_ratio =
+                    self.calculate_compression_ratio(original_size, compressed_size);
+
+                Ok(FileCompressionResult {
+                    file_path: file_path.to_string(),
+                    success: true,
+                    compressed_data: Some(compressed_data),
+                    original_size,
+                    compressed_size,
+                    compression_ratio,
+                    error_message: None,
+                    stored,
+                })
             }
+            Err(e) => Ok(FileCompressionResult {
+                file_path: file_path.to_string(),
+                success: false,
+                compressed_data: None,
+                original_size: 0,
+                compressed_size: 0,
+                compression_ratio: 0.0,
+                error_message: Some(e),
+                stored,
+            }),
         }
+    }
 
-        Ok(results)
+    /// 按扩展名（小写）统计一组文件的压缩效果（并行压缩）
+    ///
+    /// 无扩展名的文件归入空字符串键，压缩失败的文件不计入统计。
+    /// 跳过压缩的扩展名（见 `with_skip_extensions`）按压缩后大小等于原始大小统计。
+    pub fn analyze_tree(&self, files: &[(String, Vec<u8>)]) -> HashMap<String, CompressionStats> {
+        let results: Vec<(String, usize, usize)> = files
+            .par_iter()
+            .filter_map(|(path, data)| {
+                let compressed_len = if self.skips_compression(path) {
+                    data.len()
+                } else {
+                    self.compress(data).ok()?.len()
+                };
+                let extension = Path::new(path)
+                    .extension()
+                    .map(|ext| ext.to_string_lossy().to_lowercase())
+                    .unwrap_or_default();
+                Some((extension, data.len(), compressed_len))
+            })
+            .collect();
+
+        let mut groups: HashMap<String, CompressionStats> = HashMap::new();
+        for (extension, original_size, compressed_size) in results {
+            let stats = groups.entry(extension).or_default();
+            stats.count += 1;
+            stats.average_ratio += self.calculate_compression_ratio(original_size, compressed_size);
+            stats.total_original_size += original_size;
+            stats.total_compressed_size += compressed_size;
+            stats.saved_bytes += original_size as i64 - compressed_size as i64;
+        }
+        for stats in groups.values_mut() {
+            stats.average_ratio /= stats.count as f64;
+        }
+        groups
     }
 
-    /// 检查数据是否已压缩
+    /// 检查数据是否像当前算法压缩过的数据
+    ///
+    /// 启发式检查：zlib 和 gzip 校验头部，裸 deflate 没有头部，尝试解压开头部分；
+    /// `Stored` 数据与原始数据无法区分，总是返回 false。
     pub fn is_compressed(&self, data: &[u8]) -> bool {
-        // 简单的启发式检查：尝试解压缩前几个字节
         if data.len() < 10 {
             return false;
         }
 
-        // zlib 数据通常以特定的字节开始
-        match data[0] {
-            0x78 => true, // zlib 压缩数据的常见开始字节
-            _ => false,
+        match self.algorithm {
+            // CMF 的压缩方法为 8（deflate）、窗口不超过 32KB，且 CMF/FLG 满足 31 的校验
+            Algorithm::Zlib => {
+                data[0] & 0x0f == 8
+                    && data[0] >> 4 <= 7
+                    && u16::from_be_bytes([data[0], data[1]]).is_multiple_of(31)
+            }
+            Algorithm::Gzip => data[..3] == [0x1f, 0x8b, 0x08],
+            Algorithm::Deflate => {
+                let prefix = &data[..data.len().min(1024)];
+                let mut output = Vec::with_capacity(4096);
+                Decompress::new(false)
+                    .decompress_vec(prefix, &mut output, FlushDecompress::None)
+                    .is_ok()
+            }
+            Algorithm::Stored => false,
         }
     }
 
@@ -197,20 +1045,29 @@ impl Compressor {
             Compression::default()
         };
 
-        let mut encoder = ZlibEncoder::new(data, compression_level);
-        let mut compressed_data = Vec::new();
-
-        encoder
-            .read_to_end(&mut compressed_data)
-            .map_err(|e| format!("自适应压缩失败: {}", e))?;
-
-        Ok(compressed_data)
+        if self.algorithm == Algorithm::Stored {
+            return Ok(data.to_vec());
+        }
+        self.run_configured(&mut self.new_compress_with_level(compression_level)?, data)
+            .map_err(|e| format!("自适应压缩失败: {}", e))
     }
 }
 
+/// 压缩比的表示方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RatioKind {
+    /// 节省的百分比（0–100），`calculate_compression_ratio` 的默认表示
+    PercentSaved,
+    /// 节省的比例（0–1）
+    FractionSaved,
+    /// 压缩后大小 / 原始大小（越小越好）
+    CompressedOverOriginal,
+}
+
 /// 压缩结果
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct CompressionResult {
+    #[serde(skip)]
     pub compressed_data: Vec<u8>,
     pub original_size: usize,
     pub compressed_size: usize,
@@ -218,6 +1075,19 @@ pub struct CompressionResult {
     pub compression_level: u32,
 }
 
+/// 一类文件的压缩效果统计（见 `Compressor::analyze_tree`）
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct CompressionStats {
+    /// 文件数
+    pub count: usize,
+    /// 各文件压缩比（节省百分比）的平均值
+    pub average_ratio: f64,
+    pub total_original_size: usize,
+    pub total_compressed_size: usize,
+    /// 节省的字节数，不可压缩的数据可能为负
+    pub saved_bytes: i64,
+}
+
 /// 文件压缩结果
 #[derive(Debug, Clone)]
 pub struct FileCompressionResult {
@@ -228,6 +1098,493 @@ pub struct FileCompressionResult {
     pub compressed_size: usize,
     pub compression_ratio: f64,
     pub error_message: Option<String>,
+    /// 按扩展名跳过了压缩，`compressed_data` 为原始内容（见 `with_skip_extensions`）
+    pub stored: bool,
+}
+
+/// 拼接压缩时单个文件在解压结果中的位置
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FileOffset {
+    pub name: String,
+    pub offset: usize,
+    pub len: usize,
+}
+
+impl FileOffset {
+    /// 从解压后的拼接数据中取出该文件，范围越界时返回 None
+    pub fn slice<'a>(&self, decompressed: &'a [u8]) -> Option<&'a [u8]> {
+        decompressed.get(self.offset..self.offset.checked_add(self.len)?)
+    }
+}
+
+/// 文件 IO 并发限制器（计数信号量）
+///
+/// 同时记录使用峰值，便于确认限制是否生效。
+pub struct IoLimiter {
+    max_permits: usize,
+    state: Mutex<IoLimiterState>,
+    available: Condvar,
+}
+
+#[derive(Default)]
+struct IoLimiterState {
+    in_use: usize,
+    peak: usize,
+}
+
+impl IoLimiter {
+    /// 创建限制器，`max_permits` 为 0 时按 1 处理
+    pub fn new(max_permits: usize) -> Self {
+        Self {
+            max_permits: max_permits.max(1),
+            state: Mutex::new(IoLimiterState::default()),
+            available: Condvar::new(),
+        }
+    }
+
+    /// 获取一个许可，达到上限时阻塞等待
+    pub fn acquire(&self) -> IoPermit<'_> {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        while state.in_use >= self.max_permits {
+            state = self
+                .available
+                .wait(state)
+                .unwrap_or_else(|e| e.into_inner());
+        }
+        state.in_use += 1;
+        state.peak = state.peak.max(state.in_use);
+        IoPermit { limiter: self }
+    }
+
+    /// 同时持有许可数的峰值
+    pub fn peak_in_use(&self) -> usize {
+        self.state.lock().unwrap_or_else(|e| e.into_inner()).peak
+    }
+
+    fn release(&self) {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.in_use -= 1;
+        self.available.notify_one();
+    }
+}
+
+/// IO 许可，drop 时归还
+pub struct IoPermit<'a> {
+    limiter: &'a IoLimiter,
+}
+
+impl Drop for IoPermit<'_> {
+    fn drop(&mut self) {
+        self.limiter.release();
+    }
+}
+
+/// 压缩上下文池的键：(算法, 级别, 窗口位数)
+type EncoderKey = (Algorithm, u32, u8);
+
+/// 压缩/解压上下文复用池
+///
+/// 每次压缩都新建底层压缩流会重新分配内部缓冲区，高吞吐服务中这部分分配很明显。
+/// 池按 (算法, 级别, 窗口位数) 保存用完并 `reset` 过的压缩上下文，按算法保存解压
+/// 上下文，通过 `Compressor::with_pool` 启用后由 `compress`/`decompress` 自动借用和归还。
+/// gzip 解压和 `Stored` 不经过池。
+pub struct CompressorPool {
+    max_idle: usize,
+    encoders: Mutex<HashMap<EncoderKey, Vec<Compress>>>,
+    decoders: Mutex<HashMap<Algorithm, Vec<Decompress>>>,
+}
+
+impl CompressorPool {
+    /// 创建上下文池，每种配置最多保留 `max_idle` 个空闲上下文
+    pub fn new(max_idle: usize) -> Self {
+        Self {
+            max_idle,
+            encoders: Mutex::new(HashMap::new()),
+            decoders: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// 当前空闲的压缩上下文总数
+    pub fn idle_encoders(&self) -> usize {
+        let encoders = self.encoders.lock().unwrap_or_else(|e| e.into_inner());
+        encoders.values().map(Vec::len).sum()
+    }
+
+    /// 当前空闲的解压上下文总数
+    pub fn idle_decoders(&self) -> usize {
+        let decoders = self.decoders.lock().unwrap_or_else(|e| e.into_inner());
+        decoders.values().map(Vec::len).sum()
+    }
+
+    /// 借用一个压缩上下文执行 `f`，没有空闲上下文时用 `create` 新建
+    fn with_encoder<T>(
+        &self,
+        key: EncoderKey,
+        create: impl FnOnce() -> Result<Compress, String>,
+        f: impl FnOnce(&mut Compress) -> Result<T, String>,
+    ) -> Result<T, String> {
+        let pooled = {
+            let mut encoders = self.encoders.lock().unwrap_or_else(|e| e.into_inner());
+            encoders.get_mut(&key).and_then(Vec::pop)
+        };
+        let mut compress = match pooled {
+            Some(compress) => compress,
+            None => create()?,
+        };
+        let result = f(&mut compress);
+
+        compress.reset();
+        let mut encoders = self.encoders.lock().unwrap_or_else(|e| e.into_inner());
+        let idle = encoders.entry(key).or_default();
+        if idle.len() < self.max_idle {
+            idle.push(compress);
+        }
+        result
+    }
+
+    /// 借用一个解压上下文执行 `f`（仅支持 zlib 和原始 deflate）
+    fn with_decoder<T>(&self, algorithm: Algorithm, f: impl FnOnce(&mut Decompress) -> T) -> T {
+        let zlib_header = algorithm == Algorithm::Zlib;
+        let pooled = {
+            let mut decoders = self.decoders.lock().unwrap_or_else(|e| e.into_inner());
+            decoders.get_mut(&algorithm).and_then(Vec::pop)
+        };
+        let mut decompress = pooled.unwrap_or_else(|| Decompress::new(zlib_header));
+        let result = f(&mut decompress);
+
+        decompress.reset(zlib_header);
+        let mut decoders = self.decoders.lock().unwrap_or_else(|e| e.into_inner());
+        let idle = decoders.entry(algorithm).or_default();
+        if idle.len() < self.max_idle {
+            idle.push(decompress);
+        }
+        result
+    }
+}
+
+/// 带版本化预置字典的压缩器
+///
+/// 每个压缩块以 4 字节（小端）字典版本号开头，后接使用该版本字典压缩的
+/// zlib 数据。启用自动刷新后，压缩器会从最近的输入样本中周期性地重新训练
+/// 字典并递增版本号；旧版本字典默认全部保留，因此旧数据块仍可解压。
+/// 可用 `with_max_versions` 限制保留的版本数，被淘汰版本的数据块解压时返回明确的错误。
+///
+/// 字典只保存在内存中，需要跨进程解压时用 `export_dictionaries` 持久化，
+/// 再通过 `import_dictionaries` 载入。
+pub struct DictionaryCompressor {
+    compression_level: Compression,
+    dictionaries: HashMap<u32, Vec<u8>>,
+    current_version: u32,
+    max_versions: Option<usize>,
+    evicted_below: u32,
+    samples: VecDeque<Vec<u8>>,
+    sample_window: usize,
+    retrain_every: Option<usize>,
+    compressed_since_retrain: usize,
+}
+
+impl DictionaryCompressor {
+    /// 使用初始字典（版本 1）创建压缩器
+    pub fn new(initial_dictionary: Vec<u8>) -> Self {
+        let mut dictionaries = HashMap::new();
+        dictionaries.insert(1, truncate_dictionary(initial_dictionary));
+
+        Self {
+            compression_level: Compression::default(),
+            dictionaries,
+            current_version: 1,
+            max_versions: None,
+            evicted_below: 1,
+            samples: VecDeque::new(),
+            sample_window: 0,
+            retrain_every: None,
+            compressed_since_retrain: 0,
+        }
+    }
+
+    /// 启用字典自动刷新：每压缩 `retrain_every` 个数据块后，
+    /// 用最近 `sample_window` 个输入重新训练字典
+    pub fn with_auto_refresh(mut self, retrain_every: usize, sample_window: usize) -> Self {
+        self.retrain_every = (retrain_every > 0).then_some(retrain_every);
+        self.sample_window = sample_window;
+        self
+    }
+
+    /// 最多保留 `max_versions` 个字典版本（至少 1 个），超出时淘汰最旧的版本
+    pub fn with_max_versions(mut self, max_versions: usize) -> Self {
+        self.max_versions = Some(max_versions.max(1));
+        self.evict_old_versions();
+        self
+    }
+
+    /// 当前使用的字典版本
+    pub fn current_version(&self) -> u32 {
+        self.current_version
+    }
+
+    /// 导出当前保留的全部字典版本
+    ///
+    /// 格式：魔数 `SCDS`(4) + 格式版本(1) + 当前版本(4) + 字典数(4)，
+    /// 之后每个字典为 版本号(4) + 长度(4) + 内容，整数均为小端序。
+    pub fn export_dictionaries(&self) -> Vec<u8> {
+        let mut versions: Vec<u32> = self.dictionaries.keys().copied().collect();
+        versions.sort_unstable();
+
+        let mut output = DICTIONARY_SET_MAGIC.to_vec();
+        output.push(DICTIONARY_SET_VERSION);
+        output.extend_from_slice(&self.current_version.to_le_bytes());
+        output.extend_from_slice(&(versions.len() as u32).to_le_bytes());
+        for version in versions {
+            let dictionary = &self.dictionaries[&version];
+            output.extend_from_slice(&version.to_le_bytes());
+            output.extend_from_slice(&(dictionary.len() as u32).to_le_bytes());
+            output.extend_from_slice(dictionary);
+        }
+        output
+    }
+
+    /// 导入 `export_dictionaries` 导出的字典集合
+    ///
+    /// 导入的版本与已有版本合并，当前版本取两者中较新的一个；
+    /// 同一版本号对应不同内容时返回错误，且不修改压缩器。
+    pub fn import_dictionaries(&mut self, data: &[u8]) -> Result<(), String> {
+        let mut rest = data;
+        let mut take = |len: usize| -> Result<&[u8], String> {
+            if rest.len() < len {
+                return Err("字典集合数据不完整".to_string());
+            }
+            let (head, tail) = rest.split_at(len);
+            rest = tail;
+            Ok(head)
+        };
+        let read_u32 = |bytes: &[u8]| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+
+        if take(4)? != DICTIONARY_SET_MAGIC {
+            return Err("不是有效的字典集合数据".to_string());
+        }
+        let format_version = take(1)?[0];
+        if format_version != DICTIONARY_SET_VERSION {
+            return Err(format!("不支持的字典集合版本: {}", format_version));
+        }
+        let current_version = read_u32(take(4)?);
+        let count = read_u32(take(4)?) as usize;
+
+        let mut imported = HashMap::new();
+        for _ in 0..count {
+            let version = read_u32(take(4)?);
+            let len = read_u32(take(4)?) as usize;
+            if version == 0 || len > MAX_DICTIONARY_SIZE {
+                return Err(format!("字典集合中的字典无效: 版本 {}", version));
+            }
+            let dictionary = take(len)?.to_vec();
+            if let Some(existing) = self.dictionaries.get(&version)
+                && *existing != dictionary
+            {
+                return Err(format!("字典版本 {} 与已有字典内容不一致", version));
+            }
+            imported.insert(version, dictionary);
+        }
+        if !imported.contains_key(&current_version) {
+            return Err(format!("字典集合缺少当前版本 {}", current_version));
+        }
+
+        self.dictionaries.extend(imported);
+        self.current_version = self.current_version.max(current_version);
+        self.evict_old_versions();
+        Ok(())
+    }
+
+    /// 压缩数据（使用当前版本字典）
+    pub fn compress(&mut self, data: &[u8]) -> Result<Vec<u8>, String> {
+        let dictionary = &self.dictionaries[&self.current_version];
+        let mut output = self.current_version.to_le_bytes().to_vec();
+        output.extend(deflate_with_dictionary(
+            data,
+            dictionary,
+            self.compression_level,
+        )?);
+
+        self.record_sample(data);
+        Ok(output)
+    }
+
+    /// 解压缩数据（根据头部的版本号选择字典）
+    pub fn decompress(&self, compressed_data: &[u8]) -> Result<Vec<u8>, String> {
+        if compressed_data.len() < 4 {
+            return Err("压缩数据缺少字典版本头".to_string());
+        }
+
+        let (header, body) = compressed_data.split_at(4);
+        let version = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+        let dictionary = self.dictionaries.get(&version).ok_or_else(|| {
+            if version != 0 && version < self.evicted_below {
+                format!("字典版本 {} 已被淘汰", version)
+            } else {
+                format!("未知的字典版本: {}", version)
+            }
+        })?;
+
+        inflate_with_dictionary(body, dictionary)
+    }
+
+    /// 立即用最近的样本重新训练字典，返回新版本号
+    ///
+    /// 没有样本时保持当前版本不变。
+    pub fn retrain(&mut self) -> u32 {
+        self.compressed_since_retrain = 0;
+        if self.samples.is_empty() {
+            return self.current_version;
+        }
+
+        // 越新的样本越靠近字典末尾，zlib 对字典末尾的内容匹配代价最小
+        let dictionary: Vec<u8> = self.samples.iter().flatten().copied().collect();
+        self.current_version += 1;
+        self.dictionaries
+            .insert(self.current_version, truncate_dictionary(dictionary));
+        self.evict_old_versions();
+        self.current_version
+    }
+
+    /// 按保留策略淘汰最旧的字典版本（当前版本始终保留）
+    fn evict_old_versions(&mut self) {
+        let Some(max_versions) = self.max_versions else {
+            return;
+        };
+        while self.dictionaries.len() > max_versions {
+            let Some(oldest) = self
+                .dictionaries
+                .keys()
+                .copied()
+                .filter(|&version| version != self.current_version)
+                .min()
+            else {
+                break;
+            };
+            self.dictionaries.remove(&oldest);
+            self.evicted_below = self.evicted_below.max(oldest + 1);
+        }
+    }
+
+    /// 记录输入样本，并在达到间隔时重新训练
+    fn record_sample(&mut self, data: &[u8]) {
+        let Some(retrain_every) = self.retrain_every else {
+            return;
+        };
+
+        if self.sample_window > 0 {
+            let sample_len = data.len().min(MAX_DICTIONARY_SIZE);
+            self.samples.push_back(data[..sample_len].to_vec());
+            while self.samples.len() > self.sample_window {
+                self.samples.pop_front();
+            }
+        }
+
+        self.compressed_since_retrain += 1;
+        if self.compressed_since_retrain >= retrain_every {
+            self.retrain();
+        }
+    }
+}
+
+/// 截断字典，只保留末尾的有效窗口部分
+fn truncate_dictionary(mut dictionary: Vec<u8>) -> Vec<u8> {
+    if dictionary.len() > MAX_DICTIONARY_SIZE {
+        dictionary.drain(..dictionary.len() - MAX_DICTIONARY_SIZE);
+    }
+    dictionary
+}
+
+/// 使用预置字典压缩为 zlib 数据
+fn deflate_with_dictionary(
+    data: &[u8],
+    dictionary: &[u8],
+    level: Compression,
+) -> Result<Vec<u8>, String> {
+    let mut compress = Compress::new(level, true);
+    compress
+        .set_dictionary(dictionary)
+        .map_err(|e| format!("设置压缩字典失败: {}", e))?;
+
+    run_compress(&mut compress, data)
+}
+
+/// 用给定的压缩状态一次性压缩全部数据
+fn run_compress(compress: &mut Compress, data: &[u8]) -> Result<Vec<u8>, String> {
+    let mut output = Vec::with_capacity(data.len() / 2 + 64);
+    loop {
+        let consumed = compress.total_in() as usize;
+        let status = compress
+            .compress_vec(&data[consumed..], &mut output, FlushCompress::Finish)
+            .map_err(|e| format!("压缩失败: {}", e))?;
+        match status {
+            Status::StreamEnd => break,
+            _ => output.reserve(output.capacity().max(1024)),
+        }
+    }
+
+    Ok(output)
+}
+
+/// 将数据写入写入端解码器并结束解码，返回解码输出
+fn write_decoded<D: Write>(
+    mut decoder: D,
+    data: &[u8],
+    finish: impl FnOnce(D) -> std::io::Result<Vec<u8>>,
+) -> Result<Vec<u8>, String> {
+    decoder
+        .write_all(data)
+        .map_err(|e| format!("流式解压缩写入失败: {}", e))?;
+    finish(decoder).map_err(|e| format!("流式解压缩完成失败: {}", e))
+}
+
+/// gzip 尾部：CRC-32 和原始长度（模 2^32），均为小端
+fn gzip_trailer(crc: &Crc) -> [u8; 8] {
+    let mut trailer = [0u8; 8];
+    trailer[..4].copy_from_slice(&crc.sum().to_le_bytes());
+    trailer[4..].copy_from_slice(&crc.amount().to_le_bytes());
+    trailer
+}
+
+/// 使用预置字典解压缩 zlib 数据
+fn inflate_with_dictionary(data: &[u8], dictionary: &[u8]) -> Result<Vec<u8>, String> {
+    run_decompress(&mut Decompress::new(true), data, Some(dictionary))
+}
+
+/// 用给定的解压状态一次性解压全部数据，数据需要预置字典时使用 `dictionary`
+fn run_decompress(
+    decompress: &mut Decompress,
+    data: &[u8],
+    dictionary: Option<&[u8]>,
+) -> Result<Vec<u8>, String> {
+    let mut output = Vec::with_capacity(data.len() * 2 + 64);
+
+    loop {
+        let consumed = decompress.total_in() as usize;
+        let produced = decompress.total_out();
+        match decompress.decompress_vec(&data[consumed..], &mut output, FlushDecompress::Finish) {
+            Ok(Status::StreamEnd) => break,
+            Ok(_) => {
+                let stalled = decompress.total_in() as usize == consumed
+                    && decompress.total_out() == produced;
+                if output.len() == output.capacity() {
+                    output.reserve(output.capacity().max(1024));
+                } else if stalled {
+                    return Err("解压缩失败: 数据不完整".to_string());
+                }
+            }
+            Err(e) => match (e.needs_dictionary(), dictionary) {
+                (Some(_), Some(dictionary)) => {
+                    decompress
+                        .set_dictionary(dictionary)
+                        .map_err(|e| format!("解压缩失败: 字典不匹配 ({})", e))?;
+                }
+                (Some(_), None) => return Err("解压缩失败: 数据需要预置字典".to_string()),
+                _ => return Err(format!("解压缩失败: {}", e)),
+            },
+        }
+    }
+
+    Ok(output)
 }
 
 impl Default for Compressor {
@@ -267,6 +1624,45 @@ mod tests {
         assert_eq!(empty_data.to_vec(), decompressed);
     }
 
+    #[test]
+    fn test_check_decompressed_size() {
+        let compressor = Compressor::new();
+        let compressed = compressor.compress(&vec![0u8; 100_000]).unwrap();
+
+        assert!(
+            compressor
+                .check_decompressed_size(&compressed, 100_000)
+                .unwrap()
+        );
+        assert!(
+            !compressor
+                .check_decompressed_size(&compressed, 100_001)
+                .unwrap()
+        );
+        let err = compressor
+            .check_decompressed_size(&compressed, 1000)
+            .unwrap_err();
+        assert!(err.contains("超出预期"), "{}", err);
+    }
+
+    #[test]
+    fn test_decompress_one_splits_concatenated_members() {
+        for algorithm in [Algorithm::Zlib, Algorithm::Gzip, Algorithm::Deflate] {
+            let compressor = Compressor::new().with_algorithm(algorithm);
+            let first = compressor.compress(b"first member, first member").unwrap();
+            let second = compressor.compress(b"second member").unwrap();
+            let buffer = [first.as_slice(), second.as_slice()].concat();
+
+            let (data, consumed) = compressor.decompress_one(&buffer).unwrap();
+            assert_eq!(data, b"first member, first member");
+            assert_eq!(consumed, first.len(), "{:?}", algorithm);
+
+            let (data, consumed) = compressor.decompress_one(&buffer[consumed..]).unwrap();
+            assert_eq!(data, b"second member");
+            assert_eq!(consumed, second.len());
+        }
+    }
+
     #[test]
     fn test_compress_file() {
         let compressor = Compressor::new();
@@ -289,6 +1685,165 @@ mod tests {
         assert_eq!(test_content.to_vec(), decompressed_data);
     }
 
+    #[test]
+    fn test_decompress_to_file_free_space_check() {
+        let compressor = Compressor::new().with_free_space_check(true);
+        let compressed = compressor.compress(b"small payload").unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path().join("out.txt");
+        let output_path = output.to_str().unwrap();
+
+        compressor
+            .decompress_to_file_with_size_hint(&compressed, output_path, Some(13))
+            .unwrap();
+        assert_eq!(fs::read(&output).unwrap(), b"small payload");
+        compressor
+            .decompress_to_file(&compressed, output_path)
+            .unwrap();
+
+        if cfg!(unix) {
+            fs::remove_file(&output).unwrap();
+            let result = compressor.decompress_to_file_with_size_hint(
+                &compressed,
+                output_path,
+                Some(u64::MAX),
+            );
+            assert_eq!(result.unwrap_err(), "目标磁盘空间不足");
+            assert!(!output.exists());
+        }
+
+        // 未启用检查时忽略预期大小
+        Compressor::new()
+            .decompress_to_file_with_size_hint(&compressed, output_path, Some(u64::MAX))
+            .unwrap();
+        assert!(output.exists());
+    }
+
+    #[test]
+    fn test_compress_best_of() {
+        let candidates = [
+            Algorithm::Gzip,
+            Algorithm::Zlib,
+            Algorithm::Deflate,
+            Algorithm::Stored,
+        ];
+        let compressor = Compressor::new();
+
+        // 同一数据上 deflate 的输出最小（没有头部和校验和）
+        let text = "The quick brown fox jumps over the lazy dog. ".repeat(200);
+        let (algorithm, result) = compressor
+            .compress_best_of(text.as_bytes(), &candidates)
+            .unwrap();
+        assert_eq!(algorithm, Algorithm::Deflate);
+        assert!(result.compressed_size < text.len());
+
+        // 已压缩的数据再压缩只会变大，原样存储胜出
+        let noise: Vec<u8> = (0..4096).map(|_| rand::random::<u8>()).collect();
+        let precompressed = compressor.compress(&noise).unwrap();
+        let (algorithm, result) = compressor
+            .compress_best_of(&precompressed, &candidates)
+            .unwrap();
+        assert_eq!(algorithm, Algorithm::Stored);
+        assert_eq!(result.compressed_data, precompressed);
+
+        // 大数据按采样选择，结果仍可用胜出的算法解压
+        let large = text.repeat(200);
+        let (algorithm, result) = compressor
+            .compress_best_of(large.as_bytes(), &candidates)
+            .unwrap();
+        assert_eq!(algorithm, Algorithm::Deflate);
+        let decompressed = Compressor::new()
+            .with_algorithm(algorithm)
+            .decompress(&result.compressed_data)
+            .unwrap();
+        assert_eq!(decompressed, large.as_bytes());
+
+        assert!(compressor.compress_best_of(b"data", &[]).is_err());
+
+        // 原样存储不会落入压缩流
+        let stored = Compressor::new().with_algorithm(Algorithm::Stored);
+        assert!(stored.new_compress().is_err());
+    }
+
+    #[test]
+    fn test_compressor_pool_reuses_contexts() {
+        let data = "pooled compression context reuse test data. ".repeat(500);
+        let pool = Arc::new(CompressorPool::new(2));
+
+        for algorithm in [Algorithm::Zlib, Algorithm::Gzip, Algorithm::Deflate] {
+            let plain = Compressor::new().with_algorithm(algorithm);
+            let pooled = Compressor::new()
+                .with_algorithm(algorithm)
+                .with_pool(pool.clone());
+
+            // 首次使用新建的上下文，之后复用归还的上下文，输出完全相同
+            let fresh = pooled.compress(data.as_bytes()).unwrap();
+            assert_eq!(plain.decompress(&fresh).unwrap(), data.as_bytes());
+            for _ in 0..2 {
+                let reused = pooled.compress(data.as_bytes()).unwrap();
+                assert_eq!(reused, fresh, "{:?}", algorithm);
+                assert_eq!(pooled.decompress(&reused).unwrap(), data.as_bytes());
+            }
+            let compressed = plain.compress(data.as_bytes()).unwrap();
+            assert_eq!(pooled.decompress(&compressed).unwrap(), data.as_bytes());
+        }
+        assert_eq!(pool.idle_encoders(), 3);
+        assert_eq!(pool.idle_decoders(), 2);
+
+        // 不同级别使用不同的上下文
+        let level_one = Compressor::with_level(1).with_pool(pool.clone());
+        let compressed = level_one.compress(data.as_bytes()).unwrap();
+        assert_eq!(pool.idle_encoders(), 4);
+        assert_eq!(level_one.compress(data.as_bytes()).unwrap(), compressed);
+        assert_eq!(pool.idle_encoders(), 4);
+
+        // 并发使用时空闲上下文数量不超过上限
+        let compressor = Compressor::new().with_pool(pool.clone());
+        (0..32).into_par_iter().for_each(|_| {
+            let compressed = compressor.compress(data.as_bytes()).unwrap();
+            assert_eq!(compressor.decompress(&compressed).unwrap(), data.as_bytes());
+        });
+        assert!(pool.idle_encoders() <= 4);
+        assert!(pool.idle_decoders() <= 2);
+
+        assert!(compressor.decompress(b"not zlib data").is_err());
+    }
+
+    #[test]
+    fn test_decompress_to_file_preserves_mtime() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("original.txt");
+        fs::write(&input, b"cached file content").unwrap();
+        let mtime = SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_600_000_000);
+        fs::File::options()
+            .write(true)
+            .open(&input)
+            .unwrap()
+            .set_modified(mtime)
+            .unwrap();
+
+        let compressor = Compressor::new();
+        let compressed = compressor.compress_file(input.to_str().unwrap()).unwrap();
+        let original_mtime = fs::metadata(&input).unwrap().modified().unwrap();
+
+        let output = dir.path().join("restored.txt");
+        compressor
+            .decompress_to_file_with_mtime(
+                &compressed,
+                output.to_str().unwrap(),
+                Some(original_mtime),
+            )
+            .unwrap();
+        assert_eq!(fs::read(&output).unwrap(), b"cached file content");
+        assert_eq!(fs::metadata(&output).unwrap().modified().unwrap(), mtime);
+
+        // 不指定时为写入时间
+        compressor
+            .decompress_to_file_with_mtime(&compressed, output.to_str().unwrap(), None)
+            .unwrap();
+        assert!(fs::metadata(&output).unwrap().modified().unwrap() > mtime);
+    }
+
     #[test]
     fn test_compression_levels() {
         let original_data = b"Test data for compression level testing. \
@@ -338,6 +1893,29 @@ mod tests {
         assert_eq!(compressor.calculate_compression_ratio(100, 100), 0.0);
     }
 
+    #[test]
+    fn test_compression_ratio_kinds() {
+        let compressor = Compressor::new();
+        let cases = [
+            (RatioKind::PercentSaved, 75.0, 0.0),
+            (RatioKind::FractionSaved, 0.75, 0.0),
+            (RatioKind::CompressedOverOriginal, 0.25, 1.0),
+        ];
+        for (kind, expected, expected_empty) in cases {
+            assert_eq!(compressor.compression_ratio_as(200, 50, kind), expected);
+            assert_eq!(compressor.compression_ratio_as(0, 8, kind), expected_empty);
+        }
+        assert_eq!(
+            compressor.compression_ratio_as(200, 50, RatioKind::PercentSaved),
+            compressor.calculate_compression_ratio(200, 50)
+        );
+        // 数据变大时节省比例为负
+        assert_eq!(
+            compressor.compression_ratio_as(100, 150, RatioKind::FractionSaved),
+            -0.5
+        );
+    }
+
     #[test]
     fn test_stream_compression() {
         let compressor = Compressor::new();
@@ -349,6 +1927,202 @@ mod tests {
         assert_eq!(test_data.to_vec(), decompressed);
     }
 
+    #[test]
+    fn test_stream_and_adaptive_use_configured_algorithm() {
+        let data = "configured algorithm test data ".repeat(100);
+        let data = data.as_bytes();
+
+        for profile in [Profile::Fast, Profile::Archive] {
+            let compressor = Compressor::with_profile(profile).with_reproducible(true);
+            let streamed = compressor.compress_stream(data).unwrap();
+            assert_eq!(compressor.decompress(&streamed).unwrap(), data);
+            assert_eq!(compressor.decompress_stream(&streamed).unwrap(), data);
+            assert!(compressor.is_compressed(&streamed));
+
+            let adaptive = compressor.adaptive_compress(data).unwrap();
+            assert_eq!(compressor.decompress(&adaptive).unwrap(), data);
+        }
+
+        let deflate = Compressor::new().with_algorithm(Algorithm::Deflate);
+        let streamed = deflate.compress_stream(data).unwrap();
+        assert_eq!(deflate.decompress(&streamed).unwrap(), data);
+        assert_eq!(deflate.decompress_stream(&streamed).unwrap(), data);
+        assert!(!Compressor::new().is_compressed(&streamed));
+    }
+
+    #[test]
+    fn test_compress_unbounded_flushes_incrementally() {
+        /// 记录每次 flush 时已写入字节数的输出
+        struct FlushRecorder {
+            data: Vec<u8>,
+            flush_points: Vec<usize>,
+        }
+        impl Write for FlushRecorder {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.data.extend_from_slice(buf);
+                Ok(buf.len())
+            }
+            fn flush(&mut self) -> std::io::Result<()> {
+                self.flush_points.push(self.data.len());
+                Ok(())
+            }
+        }
+
+        let input: Vec<u8> = (0..500_000u32)
+            .flat_map(|i| format!("log line {}\n", i % 977).into_bytes())
+            .collect();
+        let flush_every = 64 * 1024;
+        let mut recorder = FlushRecorder {
+            data: Vec::new(),
+            flush_points: Vec::new(),
+        };
+        let compressor = Compressor::new();
+        let result = compressor
+            .compress_unbounded(input.as_slice(), &mut recorder, flush_every)
+            .unwrap();
+
+        assert_eq!(result.original_size, input.len());
+        assert_eq!(result.compressed_size, recorder.data.len());
+        assert!(recorder.flush_points.len() >= input.len() / flush_every);
+
+        // 每个中间刷新点之前的输出都能独立解出对应的输入前缀
+        for (index, &point) in recorder.flush_points.iter().enumerate().take(3) {
+            assert!(point < recorder.data.len());
+            let mut decoder = ZlibDecoder::new(&recorder.data[..point]);
+            let mut prefix = Vec::new();
+            let _ = decoder.read_to_end(&mut prefix);
+            assert_eq!(prefix, input[..(index + 1) * flush_every]);
+        }
+
+        assert_eq!(compressor.decompress(&recorder.data).unwrap(), input);
+        assert!(
+            compressor
+                .compress_unbounded(input.as_slice(), Vec::new(), 0)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_analyze_tree_by_extension() {
+        let text = |i: usize| format!("log entry {} ", i % 7).repeat(500).into_bytes();
+        let noise = || (0..5000).map(|_| rand::random::<u8>()).collect::<Vec<u8>>();
+        let files = vec![
+            ("logs/a.txt".to_string(), text(1)),
+            ("logs/b.TXT".to_string(), text(2)),
+            ("media/c.jpg".to_string(), noise()),
+            ("media/d.jpg".to_string(), noise()),
+            ("media/e.jpg".to_string(), noise()),
+        ];
+
+        let stats = Compressor::new().analyze_tree(&files);
+        assert_eq!(stats.len(), 2);
+        let txt = &stats["txt"];
+        let jpg = &stats["jpg"];
+        assert_eq!(txt.count, 2);
+        assert_eq!(jpg.count, 3);
+        assert_eq!(jpg.total_original_size, 15000);
+
+        assert!(txt.average_ratio > 90.0, "{:?}", txt);
+        assert!(jpg.average_ratio < 1.0, "{:?}", jpg);
+        assert!(txt.saved_bytes > 0);
+        assert_eq!(
+            jpg.saved_bytes,
+            jpg.total_original_size as i64 - jpg.total_compressed_size as i64
+        );
+    }
+
+    #[test]
+    fn test_reproducible_gzip() {
+        let data = b"cache me, cache me, cache me".repeat(100);
+        let reproducible = Compressor::new()
+            .with_algorithm(Algorithm::Gzip)
+            .with_reproducible(true);
+
+        let first = reproducible.compress(&data).unwrap();
+        assert_eq!(first, reproducible.compress(&data).unwrap());
+        assert_eq!(first[..10], REPRODUCIBLE_GZIP_HEADER);
+        assert_eq!(reproducible.decompress(&first).unwrap(), data);
+        let mut decoded = Vec::new();
+        GzDecoder::new(first.as_slice())
+            .read_to_end(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, data);
+
+        // 默认模式的头部带有构建平台的操作系统代码
+        let default = Compressor::new().with_algorithm(Algorithm::Gzip);
+        assert_eq!(default.decompress(&first).unwrap(), data);
+        let platform = default.compress(&data).unwrap();
+        assert_eq!(platform[10..], first[10..]);
+        assert_ne!(platform[9], 0xff);
+
+        // 文件到文件的流式压缩输出相同的字节
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("input.txt");
+        let output = dir.path().join("output.gz");
+        fs::write(&input, &data).unwrap();
+        let result = reproducible
+            .compress_file_to_file(input.to_str().unwrap(), output.to_str().unwrap())
+            .unwrap();
+        let written = fs::read(&output).unwrap();
+        assert_eq!(written, first);
+        assert_eq!(result.compressed_size, written.len());
+    }
+
+    #[test]
+    fn test_compress_multiple_files_progress() {
+        use crate::progress::tests::RecordingSink;
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut paths: Vec<String> = (0..12)
+            .map(|i| {
+                let path = dir.path().join(format!("file_{}.txt", i));
+                fs::write(&path, format!("content {}", i).repeat(i + 1)).unwrap();
+                path.to_str().unwrap().to_string()
+            })
+            .collect();
+        paths.push(dir.path().join("missing.txt").to_str().unwrap().to_string());
+
+        let mut sink = RecordingSink::default();
+        let results = Compressor::new()
+            .compress_multiple_files_with_progress(&paths, Some(&mut sink))
+            .unwrap();
+        assert_eq!(results.len(), 13);
+        assert!(!results[12].success);
+        sink.assert_complete();
+        assert_eq!(sink.total, Some(13));
+    }
+
+    #[test]
+    fn test_compress_batch_concat() {
+        let compressor = Compressor::new();
+        let files: Vec<(String, Vec<u8>)> = (0..20)
+            .map(|i| {
+                let content = format!(
+                    "{{\"id\": {}, \"name\": \"config-{}\", \"enabled\": true}}",
+                    i, i
+                );
+                (format!("conf/{}.json", i), content.into_bytes())
+            })
+            .chain(std::iter::once(("empty.txt".to_string(), Vec::new())))
+            .collect();
+
+        let (compressed, offsets) = compressor.compress_batch_concat(&files).unwrap();
+        assert_eq!(offsets.len(), files.len());
+
+        let decompressed = compressor.decompress(&compressed).unwrap();
+        for ((name, data), offset) in files.iter().zip(&offsets) {
+            assert_eq!(&offset.name, name);
+            assert_eq!(offset.slice(&decompressed).unwrap(), data.as_slice());
+        }
+
+        // 拼接压缩比逐个压缩更小
+        let individual: usize = files
+            .iter()
+            .map(|(_, data)| compressor.compress(data).unwrap().len())
+            .sum();
+        assert!(compressed.len() < individual);
+    }
+
     #[test]
     fn test_is_compressed() {
         let compressor = Compressor::new();
@@ -376,4 +2150,211 @@ mod tests {
         let medium_decompressed = compressor.decompress(&medium_compressed).unwrap();
         assert_eq!(medium_data, medium_decompressed);
     }
+
+    #[test]
+    fn test_compress_to_target() {
+        let compressor = Compressor::new();
+
+        let compressible = "thumbnail payload ".repeat(500).into_bytes();
+        let result = compressor
+            .compress_to_target(&compressible, compressible.len() / 4)
+            .unwrap();
+        assert!(result.compressed_size <= compressible.len() / 4);
+        assert_eq!(
+            compressor.decompress(&result.compressed_data).unwrap(),
+            compressible
+        );
+
+        let incompressible: Vec<u8> = (0..4096).map(|_| rand::random::<u8>()).collect();
+        let result = compressor.compress_to_target(&incompressible, 16);
+        assert_eq!(result.unwrap_err(), "无法压缩到目标大小");
+
+        // 沿用压缩器自身的算法和可复现设置，只改变级别
+        let gzip = Compressor::with_level(9)
+            .with_algorithm(Algorithm::Gzip)
+            .with_reproducible(true);
+        let result = gzip
+            .compress_to_target(&compressible, compressible.len() / 4)
+            .unwrap();
+        assert_eq!(result.compression_level, 1);
+        assert_eq!(&result.compressed_data[..10], &REPRODUCIBLE_GZIP_HEADER);
+        assert_eq!(
+            gzip.decompress(&result.compressed_data).unwrap(),
+            compressible
+        );
+
+        let with_dictionary = Compressor::new().with_dictionary(b"thumbnail payload ".to_vec());
+        let result = with_dictionary
+            .compress_to_target(&compressible, compressible.len() / 4)
+            .unwrap();
+        assert!(
+            Compressor::new()
+                .decompress(&result.compressed_data)
+                .is_err()
+        );
+        assert_eq!(
+            with_dictionary.decompress(&result.compressed_data).unwrap(),
+            compressible
+        );
+    }
+
+    #[test]
+    fn test_dictionary_auto_refresh_keeps_old_versions() {
+        let mut compressor = DictionaryCompressor::new(b"{\"level\":\"info\",\"msg\":\"".to_vec())
+            .with_auto_refresh(3, 8);
+        assert_eq!(compressor.current_version(), 1);
+
+        let old_record = b"{\"level\":\"info\",\"msg\":\"server started\"}".to_vec();
+        let old_blob = compressor.compress(&old_record).unwrap();
+
+        // 数据分布漂移后自动重新训练
+        for i in 0..2 {
+            let record = format!("<event id=\"{}\" kind=\"upload\" status=\"ok\"/>", i);
+            compressor.compress(record.as_bytes()).unwrap();
+        }
+        assert_eq!(compressor.current_version(), 2);
+
+        let new_record = b"<event id=\"42\" kind=\"upload\" status=\"ok\"/>".to_vec();
+        let new_blob = compressor.compress(&new_record).unwrap();
+        assert_eq!(&new_blob[..4], &2u32.to_le_bytes());
+
+        assert_eq!(compressor.decompress(&old_blob).unwrap(), old_record);
+        assert_eq!(compressor.decompress(&new_blob).unwrap(), new_record);
+    }
+
+    #[test]
+    fn test_dictionary_export_import_and_retention() {
+        let mut compressor = DictionaryCompressor::new(b"{\"level\":\"info\"".to_vec())
+            .with_auto_refresh(1, 1)
+            .with_max_versions(2);
+
+        let first = compressor
+            .compress(b"{\"level\":\"info\",\"msg\":\"a\"}")
+            .unwrap();
+        let second = compressor.compress(b"<event kind=\"upload\"/>").unwrap();
+        let third = compressor.compress(b"<event kind=\"delete\"/>").unwrap();
+        assert_eq!(compressor.current_version(), 4);
+
+        // 版本 1、2 已按保留策略淘汰
+        let error = compressor.decompress(&first).unwrap_err();
+        assert!(error.contains("已被淘汰"), "{}", error);
+        assert!(
+            compressor
+                .decompress(&second)
+                .unwrap_err()
+                .contains("已被淘汰")
+        );
+        assert_eq!(
+            compressor.decompress(&third).unwrap(),
+            b"<event kind=\"delete\"/>"
+        );
+
+        // 新进程中导入字典集合后仍可解压
+        let exported = compressor.export_dictionaries();
+        let mut restored = DictionaryCompressor::new(Vec::new());
+        restored.import_dictionaries(&exported).unwrap();
+        assert_eq!(restored.current_version(), 4);
+        assert_eq!(
+            restored.decompress(&third).unwrap(),
+            b"<event kind=\"delete\"/>"
+        );
+        let latest = restored.compress(b"<event kind=\"delete\"/>").unwrap();
+        assert_eq!(&latest[..4], &4u32.to_le_bytes());
+        assert_eq!(
+            compressor.decompress(&latest).unwrap(),
+            b"<event kind=\"delete\"/>"
+        );
+
+        assert!(restored.import_dictionaries(&exported[..10]).is_err());
+    }
+
+    #[test]
+    fn test_dictionary_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let dictionary_path = dir.path().join("records.dict");
+        fs::write(&dictionary_path, b"{\"level\":\"info\",\"msg\":\"").unwrap();
+        let dictionary_path = dictionary_path.to_str().unwrap();
+
+        let compressor = Compressor::new()
+            .with_dictionary_file(dictionary_path)
+            .unwrap();
+        let record = b"{\"level\":\"info\",\"msg\":\"server started\"}";
+        let compressed = compressor.compress(record).unwrap();
+        assert!(compressed.len() < Compressor::new().compress(record).unwrap().len());
+
+        let same_dictionary = Compressor::new()
+            .with_dictionary_file(dictionary_path)
+            .unwrap();
+        assert_eq!(same_dictionary.decompress(&compressed).unwrap(), record);
+        assert_eq!(
+            Compressor::new().decompress(&compressed).unwrap_err(),
+            "解压缩失败: 数据需要预置字典"
+        );
+
+        let empty_path = dir.path().join("empty.dict");
+        fs::write(&empty_path, b"").unwrap();
+        assert!(
+            Compressor::load_dictionary(empty_path.to_str().unwrap())
+                .unwrap_err()
+                .starts_with("字典文件为空")
+        );
+        let huge_path = dir.path().join("huge.dict");
+        fs::File::create(&huge_path)
+            .unwrap()
+            .set_len(MAX_DICTIONARY_FILE_SIZE + 1)
+            .unwrap();
+        assert!(
+            Compressor::load_dictionary(huge_path.to_str().unwrap())
+                .unwrap_err()
+                .starts_with("字典文件过大")
+        );
+    }
+
+    #[test]
+    fn test_max_concurrent_io_bounds_open_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let file_paths: Vec<String> = (0..64)
+            .map(|i| {
+                let path = dir.path().join(format!("file_{}.txt", i));
+                fs::write(&path, format!("content of file {}", i).repeat(200)).unwrap();
+                path.to_str().unwrap().to_string()
+            })
+            .collect();
+
+        let compressor = Compressor::new().with_max_concurrent_io(2);
+        let results = compressor.compress_multiple_files(&file_paths).unwrap();
+
+        assert_eq!(results.len(), 64);
+        assert!(results.iter().all(|result| result.success));
+        assert_eq!(results[5].file_path, file_paths[5]);
+
+        let peak = compressor.io_limiter().unwrap().peak_in_use();
+        assert!((1..=2).contains(&peak), "peak = {}", peak);
+    }
+
+    #[test]
+    fn test_compression_profiles() {
+        let data = "profile test data with plenty of repetition. ".repeat(400);
+        let data = data.as_bytes();
+
+        let mut sizes = HashMap::new();
+        for profile in [
+            Profile::Fast,
+            Profile::Balanced,
+            Profile::Max,
+            Profile::Archive,
+        ] {
+            let compressor = Compressor::with_profile(profile);
+            let compressed = compressor.compress(data).unwrap();
+            assert_eq!(compressor.decompress(&compressed).unwrap(), data);
+            sizes.insert(format!("{:?}", profile), compressed.len());
+        }
+
+        assert!(sizes["Max"] <= sizes["Fast"]);
+        assert!(sizes["Archive"] <= sizes["Fast"]);
+        assert_eq!(
+            Compressor::with_profile(Profile::Archive).algorithm(),
+            Algorithm::Gzip
+        );
+    }
 }
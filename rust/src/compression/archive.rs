@@ -0,0 +1,693 @@
+//! 多成员归档格式
+//!
+//! 布局（整数均为小端）：
+//!
+//! ```text
+//! 魔数 "SCAR" | 版本 u8 | 成员数 u32
+//! 索引：每个成员 名称长度 u16 | 名称 | 方法 u8 | 数据偏移 u64 | 压缩长度 u64 | 原始长度 u64
+//! 数据区：各成员的压缩数据依次排列
+//! ```
+//!
+//! 索引位于数据区之前，读取单个成员时只需解析索引并解压对应区间。
+//! 空归档只有魔数、版本和值为 0 的成员数，没有索引和数据区。
+
+use super::{Algorithm, Compressor};
+use flate2::read::{DeflateDecoder, GzDecoder, ZlibDecoder};
+use std::collections::HashSet;
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+/// 归档魔数
+const ARCHIVE_MAGIC: &[u8; 4] = b"SCAR";
+/// 归档格式版本
+const ARCHIVE_VERSION: u8 = 1;
+/// 归档头部长度：魔数(4) + 版本(1) + 成员数(4)
+const ARCHIVE_HEADER_LEN: usize = 9;
+
+/// 成员存储方法
+const METHOD_STORED: u8 = 0x00;
+const METHOD_ZLIB: u8 = 0x01;
+const METHOD_GZIP: u8 = 0x02;
+const METHOD_DEFLATE: u8 = 0x03;
+
+/// 解压到目录时目标文件已存在的处理策略
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverwritePolicy {
+    /// 保留已有文件，跳过该成员
+    Skip,
+    /// 覆盖已有文件
+    Overwrite,
+    /// 任一目标文件已存在时报错，不写入任何文件
+    Error,
+}
+
+/// 单个成员的解压结果
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExtractStatus {
+    /// 新建文件
+    Created,
+    /// 覆盖了已有文件
+    Overwritten,
+    /// 目标文件已存在，按策略跳过
+    Skipped,
+}
+
+/// 解压到目录时每个成员的结果
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExtractedFile {
+    /// 成员名称
+    pub name: String,
+    /// 写入的路径
+    pub path: PathBuf,
+    pub status: ExtractStatus,
+}
+
+/// 归档索引中的成员条目
+#[derive(Debug, Clone)]
+struct MemberEntry {
+    name: String,
+    method: u8,
+    offset: u64,
+    compressed_len: u64,
+    original_len: u64,
+}
+
+impl Compressor {
+    /// 创建多成员归档，每个成员独立压缩
+    ///
+    /// 可复现模式下成员按名称排序，输入顺序不同也得到相同的归档。
+    /// `files` 为空时生成只有头部的空归档；空文件作为原始长度为 0 的成员正常压缩。
+    /// 扩展名在跳过列表中的成员（见 `with_skip_extensions`）不压缩，以存储方法原样写入。
+    pub fn create_archive(&self, files: &[(String, Vec<u8>)]) -> Result<Vec<u8>, String> {
+        if files.is_empty() {
+            return Ok(archive_header(0).to_vec());
+        }
+
+        let method = algorithm_method(self.algorithm);
+        let mut entries = Vec::with_capacity(files.len());
+        let mut data_section = Vec::new();
+
+        let mut ordered: Vec<&(String, Vec<u8>)> = files.iter().collect();
+        if self.reproducible {
+            ordered.sort_by(|a, b| a.0.cmp(&b.0));
+        }
+        for (name, content) in ordered {
+            if name.len() > u16::MAX as usize {
+                return Err(format!("成员名称过长: {}", name));
+            }
+            let (method, compressed) = if self.skips_compression(name) {
+                (METHOD_STORED, content.clone())
+            } else {
+                (method, self.compress(content)?)
+            };
+            entries.push(MemberEntry {
+                name: name.clone(),
+                method,
+                offset: data_section.len() as u64,
+                compressed_len: compressed.len() as u64,
+                original_len: content.len() as u64,
+            });
+            data_section.extend_from_slice(&compressed);
+        }
+
+        let mut archive =
+            Vec::with_capacity(ARCHIVE_HEADER_LEN + 64 * entries.len() + data_section.len());
+        archive.extend_from_slice(&archive_header(entries.len() as u32));
+        for entry in &entries {
+            archive.extend_from_slice(&(entry.name.len() as u16).to_le_bytes());
+            archive.extend_from_slice(entry.name.as_bytes());
+            archive.push(entry.method);
+            archive.extend_from_slice(&entry.offset.to_le_bytes());
+            archive.extend_from_slice(&entry.compressed_len.to_le_bytes());
+            archive.extend_from_slice(&entry.original_len.to_le_bytes());
+        }
+        archive.extend_from_slice(&data_section);
+
+        Ok(archive)
+    }
+
+    /// 解压归档中的全部成员
+    ///
+    /// 数据区长度必须与索引一致，空归档返回空列表，其后有多余数据时报错。
+    pub fn extract_archive(&self, archive: &[u8]) -> Result<Vec<(String, Vec<u8>)>, String> {
+        let (entries, data_start) = parse_index(archive)?;
+        let data_end = entries
+            .iter()
+            .map(|entry| entry.offset.saturating_add(entry.compressed_len))
+            .max()
+            .unwrap_or(0);
+        if (archive.len() - data_start) as u64 != data_end {
+            return Err("归档数据区长度与索引不一致".to_string());
+        }
+        if entries.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        entries
+            .iter()
+            .map(|entry| {
+                let content = self.decompress_member(archive, data_start, entry)?;
+                Ok((entry.name.clone(), content))
+            })
+            .collect()
+    }
+
+    /// 将归档中的全部成员解压到 `dir` 下，按需创建父目录
+    ///
+    /// 成员名称按 `/` 分隔为相对路径；绝对路径或含 `..` 的名称、以及 `dir` 下经过符号链接的
+    /// 路径在写入任何文件之前被拒绝，防止写到 `dir` 之外。`OverwritePolicy::Error` 下多个成员
+    /// 指向同一文件（如 `a/b` 与 `a\b`）同样在写入前报错。每个文件原子写入，返回各成员的结果
+    /// （与归档中的顺序一致）。
+    pub fn extract_archive_to_dir(
+        &self,
+        archive: &[u8],
+        dir: &str,
+        policy: OverwritePolicy,
+    ) -> Result<Vec<ExtractedFile>, String> {
+        let members = self.extract_archive(archive)?;
+        let root = Path::new(dir);
+        let targets = members
+            .iter()
+            .map(|(name, _)| member_path(root, name))
+            .collect::<Result<Vec<_>, String>>()?;
+        for path in &targets {
+            check_no_symlink_ancestor(root, path)?;
+        }
+
+        if policy == OverwritePolicy::Error {
+            let mut seen = HashSet::with_capacity(targets.len());
+            if let Some(duplicate) = targets.iter().find(|path| !seen.insert(*path)) {
+                return Err(format!("多个成员指向同一文件: {}", duplicate.display()));
+            }
+            // 不跟随符号链接，悬空的链接也视为已存在
+            if let Some(existing) = targets
+                .iter()
+                .find(|path| fs::symlink_metadata(path).is_ok())
+            {
+                return Err(format!("目标文件已存在: {}", existing.display()));
+            }
+        }
+
+        let mut results = Vec::with_capacity(members.len());
+        for ((name, content), path) in members.into_iter().zip(targets) {
+            let status = if !path.exists() {
+                ExtractStatus::Created
+            } else if policy == OverwritePolicy::Skip {
+                ExtractStatus::Skipped
+            } else {
+                ExtractStatus::Overwritten
+            };
+
+            if status != ExtractStatus::Skipped {
+                if let Some(parent) = path.parent() {
+                    fs::create_dir_all(parent)
+                        .map_err(|e| format!("创建目录失败 {}: {}", parent.display(), e))?;
+                }
+                crate::atomic::write_atomic(&path, &content, self.temp_dir.as_deref())?;
+            }
+            results.push(ExtractedFile { name, path, status });
+        }
+        Ok(results)
+    }
+
+    /// 按索引只解压归档中的单个成员
+    pub fn extract_member(
+        &self,
+        archive: &[u8],
+        index: usize,
+    ) -> Result<(String, Vec<u8>), String> {
+        let (entries, data_start) = parse_index(archive)?;
+        let entry = entries
+            .get(index)
+            .ok_or_else(|| format!("成员索引越界: {} (共 {} 个成员)", index, entries.len()))?;
+
+        let content = self.decompress_member(archive, data_start, entry)?;
+        Ok((entry.name.clone(), content))
+    }
+
+    /// 解压单个成员的数据区间
+    fn decompress_member(
+        &self,
+        archive: &[u8],
+        data_start: usize,
+        entry: &MemberEntry,
+    ) -> Result<Vec<u8>, String> {
+        let start = data_start
+            .checked_add(entry.offset as usize)
+            .ok_or("归档索引损坏")?;
+        let end = start
+            .checked_add(entry.compressed_len as usize)
+            .ok_or("归档索引损坏")?;
+        let data = archive
+            .get(start..end)
+            .ok_or_else(|| format!("归档成员数据不完整: {}", entry.name))?;
+
+        let content = match entry.method {
+            METHOD_STORED => data.to_vec(),
+            method => {
+                let algorithm = method_algorithm(method)
+                    .ok_or_else(|| format!("未知的成员存储方法: {}", method))?;
+                let decoder: Box<dyn Read + '_> = match algorithm {
+                    Algorithm::Zlib => Box::new(ZlibDecoder::new(data)),
+                    Algorithm::Gzip => Box::new(GzDecoder::new(data)),
+                    Algorithm::Deflate => Box::new(DeflateDecoder::new(data)),
+                    Algorithm::Stored => Box::new(data),
+                };
+                // 最多比索引记录的原始长度多读 1 字节，超出即判定为损坏，防止解压炸弹
+                let mut content = Vec::new();
+                decoder
+                    .take(entry.original_len.saturating_add(1))
+                    .read_to_end(&mut content)
+                    .map_err(|e| format!("解压缩失败: {}", e))?;
+                content
+            }
+        };
+
+        if content.len() as u64 != entry.original_len {
+            return Err(format!("归档成员大小不一致: {}", entry.name));
+        }
+        Ok(content)
+    }
+}
+
+/// 成员在 `dir` 下的目标路径，拒绝可能写到 `dir` 之外的名称
+fn member_path(dir: &Path, name: &str) -> Result<PathBuf, String> {
+    let parts: Vec<&str> = name.split(['/', '\\']).collect();
+    let unsafe_name = name.is_empty()
+        || Path::new(name).is_absolute()
+        || name.starts_with(['/', '\\'])
+        || parts
+            .iter()
+            .any(|part| part.is_empty() || *part == "." || *part == ".." || part.contains(':'));
+    if unsafe_name {
+        return Err(format!("不安全的成员名称: {}", name));
+    }
+    Ok(parts
+        .iter()
+        .fold(dir.to_path_buf(), |path, part| path.join(part)))
+}
+
+/// 检查 `path` 在 `dir` 之下的各级父目录都不是符号链接，避免经由已有的链接写到 `dir` 之外
+fn check_no_symlink_ancestor(dir: &Path, path: &Path) -> Result<(), String> {
+    for ancestor in path
+        .ancestors()
+        .skip(1)
+        .take_while(|ancestor| *ancestor != dir)
+    {
+        if fs::symlink_metadata(ancestor).is_ok_and(|metadata| metadata.file_type().is_symlink()) {
+            return Err(format!("成员路径经过符号链接: {}", ancestor.display()));
+        }
+    }
+    Ok(())
+}
+
+/// 生成成员数为 `count` 的归档头部
+fn archive_header(count: u32) -> [u8; ARCHIVE_HEADER_LEN] {
+    let mut header = [0u8; ARCHIVE_HEADER_LEN];
+    header[..4].copy_from_slice(ARCHIVE_MAGIC);
+    header[4] = ARCHIVE_VERSION;
+    header[5..].copy_from_slice(&count.to_le_bytes());
+    header
+}
+
+/// 压缩算法对应的存储方法
+fn algorithm_method(algorithm: Algorithm) -> u8 {
+    match algorithm {
+        Algorithm::Zlib => METHOD_ZLIB,
+        Algorithm::Gzip => METHOD_GZIP,
+        Algorithm::Deflate => METHOD_DEFLATE,
+        Algorithm::Stored => METHOD_STORED,
+    }
+}
+
+/// 存储方法对应的压缩算法
+fn method_algorithm(method: u8) -> Option<Algorithm> {
+    match method {
+        METHOD_ZLIB => Some(Algorithm::Zlib),
+        METHOD_GZIP => Some(Algorithm::Gzip),
+        METHOD_DEFLATE => Some(Algorithm::Deflate),
+        _ => None,
+    }
+}
+
+/// 解析归档索引，返回成员条目和数据区起始位置
+fn parse_index(archive: &[u8]) -> Result<(Vec<MemberEntry>, usize), String> {
+    let mut reader = ByteReader::new(archive);
+
+    if reader.take(4)? != ARCHIVE_MAGIC {
+        return Err("不是有效的归档数据".to_string());
+    }
+    let version = reader.u8()?;
+    if version != ARCHIVE_VERSION {
+        return Err(format!("不支持的归档版本: {}", version));
+    }
+
+    let count = reader.u32()? as usize;
+    let mut entries = Vec::with_capacity(count.min(1024));
+    for _ in 0..count {
+        let name_len = reader.u16()? as usize;
+        let name = String::from_utf8(reader.take(name_len)?.to_vec())
+            .map_err(|_| "归档成员名称不是有效的UTF-8".to_string())?;
+        entries.push(MemberEntry {
+            name,
+            method: reader.u8()?,
+            offset: reader.u64()?,
+            compressed_len: reader.u64()?,
+            original_len: reader.u64()?,
+        });
+    }
+
+    Ok((entries, reader.position))
+}
+
+/// 按小端顺序读取归档字段的游标
+struct ByteReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> ByteReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, position: 0 }
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        let bytes = self
+            .position
+            .checked_add(len)
+            .and_then(|end| self.data.get(self.position..end))
+            .ok_or("归档索引不完整")?;
+        self.position += len;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        let mut bytes = [0u8; 4];
+        bytes.copy_from_slice(self.take(4)?);
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    fn u64(&mut self) -> Result<u64, String> {
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(bytes))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    fn sample_files() -> Vec<(String, Vec<u8>)> {
+        vec![
+            ("first.txt".to_string(), b"first member ".repeat(50)),
+            ("second.txt".to_string(), b"second member ".repeat(80)),
+            ("third.bin".to_string(), vec![7u8; 300]),
+        ]
+    }
+
+    #[test]
+    fn test_archive_round_trip() {
+        let compressor = Compressor::new();
+        let files = sample_files();
+
+        let archive = compressor.create_archive(&files).unwrap();
+        let extracted = compressor.extract_archive(&archive).unwrap();
+
+        assert_eq!(extracted, files);
+    }
+
+    #[test]
+    fn test_skip_extensions() {
+        let compressor =
+            Compressor::new().with_skip_extensions(HashSet::from([".JPG".to_string()]));
+        let photo = b"\xff\xd8\xff\xe0 not really a jpeg ".repeat(20);
+        let text = b"plain text member ".repeat(40);
+        let files = vec![
+            ("photo.jpg".to_string(), photo.clone()),
+            ("notes.txt".to_string(), text.clone()),
+        ];
+
+        let archive = compressor.create_archive(&files).unwrap();
+        let (entries, data_start) = parse_index(&archive).unwrap();
+        assert_eq!(entries[0].method, METHOD_STORED);
+        assert_eq!(entries[0].compressed_len, photo.len() as u64);
+        let photo_start = data_start + entries[0].offset as usize;
+        assert_eq!(&archive[photo_start..photo_start + photo.len()], &photo[..]);
+        assert_eq!(entries[1].method, METHOD_ZLIB);
+        assert!(entries[1].compressed_len < text.len() as u64);
+        assert_eq!(compressor.extract_archive(&archive).unwrap(), files);
+
+        let dir = tempfile::tempdir().unwrap();
+        let paths: Vec<String> = files
+            .iter()
+            .map(|(name, content)| {
+                let path = dir.path().join(name);
+                fs::write(&path, content).unwrap();
+                path.to_string_lossy().into_owned()
+            })
+            .collect();
+        let results = compressor.compress_multiple_files(&paths).unwrap();
+        assert!(results[0].stored);
+        assert_eq!(results[0].compressed_data.as_deref(), Some(&photo[..]));
+        assert!(!results[1].stored);
+        let compressed = results[1].compressed_data.as_ref().unwrap();
+        assert_eq!(compressor.decompress(compressed).unwrap(), text);
+    }
+
+    #[test]
+    fn test_extract_member_by_index() {
+        let compressor = Compressor::new();
+        let files = sample_files();
+        let archive = compressor.create_archive(&files).unwrap();
+
+        let (name, content) = compressor.extract_member(&archive, 1).unwrap();
+        assert_eq!(name, "second.txt");
+        assert_eq!(content, files[1].1);
+
+        let error = compressor.extract_member(&archive, 3).unwrap_err();
+        assert!(error.contains("越界"));
+    }
+
+    #[test]
+    fn test_extract_rejects_member_larger_than_index() {
+        let compressor = Compressor::new();
+        let files = sample_files();
+        let mut archive = compressor.create_archive(&files).unwrap();
+
+        // 第一个成员的原始长度字段：头部(9) + 名称长度(2) + "first.txt"(9) + 方法(1) + 偏移(8) + 压缩长度(8)
+        let original_len_at = ARCHIVE_HEADER_LEN + 2 + 9 + 1 + 8 + 8;
+        archive[original_len_at..original_len_at + 8].copy_from_slice(&16u64.to_le_bytes());
+
+        let error = compressor.extract_member(&archive, 0).unwrap_err();
+        assert!(error.contains("大小不一致"), "{}", error);
+    }
+
+    #[test]
+    fn test_empty_archive() {
+        for algorithm in [
+            Algorithm::Zlib,
+            Algorithm::Gzip,
+            Algorithm::Deflate,
+            Algorithm::Stored,
+        ] {
+            let compressor = Compressor::new().with_algorithm(algorithm);
+            let archive = compressor.create_archive(&[]).unwrap();
+            assert_eq!(archive.len(), ARCHIVE_HEADER_LEN);
+            assert!(compressor.extract_archive(&archive).unwrap().is_empty());
+            assert!(compressor.extract_member(&archive, 0).is_err());
+
+            let mut trailing = archive.clone();
+            trailing.push(0);
+            assert!(compressor.extract_archive(&trailing).is_err());
+            assert!(compressor.extract_archive(&archive[..5]).is_err());
+        }
+    }
+
+    #[test]
+    fn test_archive_with_empty_members() {
+        let files = vec![
+            ("empty_a".to_string(), Vec::new()),
+            ("empty_b".to_string(), Vec::new()),
+        ];
+        for algorithm in [
+            Algorithm::Zlib,
+            Algorithm::Gzip,
+            Algorithm::Deflate,
+            Algorithm::Stored,
+        ] {
+            let compressor = Compressor::new().with_algorithm(algorithm);
+            let archive = compressor.create_archive(&files).unwrap();
+            assert_eq!(compressor.extract_archive(&archive).unwrap(), files);
+            assert_eq!(
+                compressor.extract_member(&archive, 1).unwrap(),
+                files[1].clone()
+            );
+        }
+    }
+
+    #[test]
+    fn test_extract_archive_to_dir_policies() {
+        let compressor = Compressor::new();
+        let files = vec![
+            ("top.txt".to_string(), b"new top".to_vec()),
+            ("nested/deeper/leaf.txt".to_string(), b"new leaf".to_vec()),
+        ];
+        let archive = compressor.create_archive(&files).unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().to_str().unwrap();
+        let statuses = |results: Vec<ExtractedFile>| -> Vec<ExtractStatus> {
+            results.into_iter().map(|file| file.status).collect()
+        };
+
+        let results = compressor
+            .extract_archive_to_dir(&archive, root, OverwritePolicy::Error)
+            .unwrap();
+        assert_eq!(results[1].path, dir.path().join("nested/deeper/leaf.txt"));
+        assert_eq!(
+            statuses(results),
+            vec![ExtractStatus::Created, ExtractStatus::Created]
+        );
+        assert_eq!(
+            fs::read(dir.path().join("nested/deeper/leaf.txt")).unwrap(),
+            b"new leaf"
+        );
+
+        // 已有文件：Error 不写入任何文件，Skip 保留原内容，Overwrite 覆盖
+        fs::write(dir.path().join("top.txt"), b"old top").unwrap();
+        fs::remove_file(dir.path().join("nested/deeper/leaf.txt")).unwrap();
+        let err = compressor
+            .extract_archive_to_dir(&archive, root, OverwritePolicy::Error)
+            .unwrap_err();
+        assert!(err.contains("已存在"), "{}", err);
+        assert!(!dir.path().join("nested/deeper/leaf.txt").exists());
+
+        let results = compressor
+            .extract_archive_to_dir(&archive, root, OverwritePolicy::Skip)
+            .unwrap();
+        assert_eq!(
+            statuses(results),
+            vec![ExtractStatus::Skipped, ExtractStatus::Created]
+        );
+        assert_eq!(fs::read(dir.path().join("top.txt")).unwrap(), b"old top");
+
+        let results = compressor
+            .extract_archive_to_dir(&archive, root, OverwritePolicy::Overwrite)
+            .unwrap();
+        assert_eq!(
+            statuses(results),
+            vec![ExtractStatus::Overwritten, ExtractStatus::Overwritten]
+        );
+        assert_eq!(fs::read(dir.path().join("top.txt")).unwrap(), b"new top");
+    }
+
+    #[test]
+    fn test_extract_archive_to_dir_rejects_traversal() {
+        let compressor = Compressor::new();
+        let parent = tempfile::tempdir().unwrap();
+        let dir = parent.path().join("out");
+        fs::create_dir(&dir).unwrap();
+
+        for name in [
+            "../escape",
+            "a/../../escape",
+            "/etc/escape",
+            "a\\..\\..\\escape",
+            "",
+        ] {
+            let archive = compressor
+                .create_archive(&[
+                    ("safe.txt".to_string(), b"safe".to_vec()),
+                    (name.to_string(), b"evil".to_vec()),
+                ])
+                .unwrap();
+            let err = compressor
+                .extract_archive_to_dir(&archive, dir.to_str().unwrap(), OverwritePolicy::Overwrite)
+                .unwrap_err();
+            assert!(err.contains("不安全"), "{}: {}", name, err);
+        }
+        assert!(!parent.path().join("escape").exists());
+        assert!(!dir.join("safe.txt").exists());
+
+        // 经由目录中已有的符号链接写到外部
+        #[cfg(unix)]
+        {
+            let outside = parent.path().join("outside");
+            fs::create_dir(&outside).unwrap();
+            std::os::unix::fs::symlink(&outside, dir.join("link")).unwrap();
+            let archive = compressor
+                .create_archive(&[
+                    ("safe.txt".to_string(), b"safe".to_vec()),
+                    ("link/escape".to_string(), b"evil".to_vec()),
+                ])
+                .unwrap();
+            for policy in [OverwritePolicy::Error, OverwritePolicy::Overwrite] {
+                let err = compressor
+                    .extract_archive_to_dir(&archive, dir.to_str().unwrap(), policy)
+                    .unwrap_err();
+                assert!(err.contains("符号链接"), "{}", err);
+            }
+            assert!(!outside.join("escape").exists());
+            assert!(!dir.join("safe.txt").exists());
+        }
+    }
+
+    #[test]
+    fn test_extract_archive_to_dir_rejects_duplicate_targets() {
+        let compressor = Compressor::new();
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().to_str().unwrap();
+
+        for names in [["a/b.txt", "a\\b.txt"], ["same.txt", "same.txt"]] {
+            let archive = compressor
+                .create_archive(&[
+                    (names[0].to_string(), b"first".to_vec()),
+                    (names[1].to_string(), b"second".to_vec()),
+                ])
+                .unwrap();
+            let err = compressor
+                .extract_archive_to_dir(&archive, root, OverwritePolicy::Error)
+                .unwrap_err();
+            assert!(err.contains("同一文件"), "{}", err);
+            assert!(fs::read_dir(dir.path()).unwrap().next().is_none());
+        }
+    }
+
+    #[test]
+    fn test_reproducible_archive() {
+        let files = sample_files();
+        let mut reversed = files.clone();
+        reversed.reverse();
+
+        // 默认模式按输入顺序写入成员
+        let default = Compressor::new().with_algorithm(Algorithm::Gzip);
+        assert_ne!(
+            default.create_archive(&files).unwrap(),
+            default.create_archive(&reversed).unwrap()
+        );
+
+        let reproducible = Compressor::new()
+            .with_algorithm(Algorithm::Gzip)
+            .with_reproducible(true);
+        let first = reproducible.create_archive(&files).unwrap();
+        let second = reproducible.create_archive(&files).unwrap();
+        assert_eq!(first, second);
+        assert_eq!(first, reproducible.create_archive(&reversed).unwrap());
+
+        let mut sorted = files.clone();
+        sorted.sort();
+        assert_eq!(reproducible.extract_archive(&first).unwrap(), sorted);
+    }
+}
@@ -0,0 +1,168 @@
+//! 内容定义分块（CDC）与按内容哈希寻址的分块存储
+//!
+//! 分块边界由滚动的 gear 哈希决定：哈希低位全为 0 的位置即为边界，因此在数据中插入
+//! 或删除字节只影响附近的分块，其余分块的内容和哈希保持不变，便于跨版本去重。
+
+use super::Compressor;
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+
+/// 分块的最小长度
+const MIN_CHUNK_SIZE: usize = 2 * 1024;
+/// 分块的最大长度
+const MAX_CHUNK_SIZE: usize = 64 * 1024;
+/// 边界判定掩码，平均分块长度约为 8KB
+const BOUNDARY_MASK: u64 = (1 << 13) - 1;
+
+/// gear 哈希使用的每字节随机值（由 splitmix64 生成，保证各版本一致）
+const GEAR: [u64; 256] = gear_table();
+
+const fn gear_table() -> [u64; 256] {
+    let mut table = [0u64; 256];
+    let mut state: u64 = 0x5cc1_c0de_5eed_0001;
+    let mut i = 0;
+    while i < 256 {
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    table
+}
+
+impl Compressor {
+    /// 将数据按内容分块，只压缩并存储 `known` 中没有的分块，返回按顺序排列的分块哈希（配方）
+    ///
+    /// 分块哈希为未压缩内容的 SHA256 十六进制串。`store` 以哈希和压缩后的数据调用，
+    /// 同一次调用中重复出现的分块只存储一次。按配方依次解压各分块并拼接即可还原数据；
+    /// 中断后把已存储的哈希放入 `known` 重新调用，即可从中断处继续而不重复存储。
+    pub fn chunk_and_store<F: FnMut(&str, &[u8])>(
+        &self,
+        data: &[u8],
+        known: &HashSet<String>,
+        mut store: F,
+    ) -> Result<Vec<String>, String> {
+        let mut recipe = Vec::new();
+        let mut stored: HashSet<String> = HashSet::new();
+        for chunk in content_chunks(data) {
+            let hash = hex::encode(Sha256::digest(chunk));
+            if !known.contains(&hash) && !stored.contains(&hash) {
+                store(&hash, &self.compress(chunk)?);
+                stored.insert(hash.clone());
+            }
+            recipe.push(hash);
+        }
+        Ok(recipe)
+    }
+}
+
+/// 按内容定义的边界切分数据，除最后一块外每块长度在 [MIN_CHUNK_SIZE, MAX_CHUNK_SIZE] 内
+fn content_chunks(data: &[u8]) -> Vec<&[u8]> {
+    let mut chunks = Vec::new();
+    let mut rest = data;
+    while !rest.is_empty() {
+        let len = chunk_boundary(rest);
+        let (chunk, tail) = rest.split_at(len);
+        chunks.push(chunk);
+        rest = tail;
+    }
+    chunks
+}
+
+/// 返回 `data` 中第一个分块的长度
+fn chunk_boundary(data: &[u8]) -> usize {
+    if data.len() <= MIN_CHUNK_SIZE {
+        return data.len();
+    }
+    let limit = data.len().min(MAX_CHUNK_SIZE);
+    let mut hash: u64 = 0;
+    for (i, &byte) in data.iter().enumerate().take(limit).skip(MIN_CHUNK_SIZE) {
+        hash = (hash << 1).wrapping_add(GEAR[byte as usize]);
+        if hash & BOUNDARY_MASK == 0 {
+            return i + 1;
+        }
+    }
+    limit
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    /// 生成伪随机数据，压缩率低且边界分布接近实际文件
+    fn pseudo_random(len: usize, seed: u64) -> Vec<u8> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_chunk_and_store_resumes_from_known() {
+        let compressor = Compressor::new();
+        let block = pseudo_random(100_000, 42);
+        // 重复的内容在同一次调用中只存储一次
+        let data = [
+            block.as_slice(),
+            block.as_slice(),
+            &pseudo_random(50_000, 7),
+        ]
+        .concat();
+
+        let mut store: HashMap<String, Vec<u8>> = HashMap::new();
+        let mut store_calls = 0;
+        let recipe = compressor
+            .chunk_and_store(&data, &HashSet::new(), |hash, compressed| {
+                store_calls += 1;
+                store.insert(hash.to_string(), compressed.to_vec());
+            })
+            .unwrap();
+        assert!(recipe.len() > 10);
+        assert_eq!(store_calls, store.len());
+        assert!(store.len() < recipe.len());
+
+        let rebuilt: Vec<u8> = recipe
+            .iter()
+            .flat_map(|hash| compressor.decompress(&store[hash]).unwrap())
+            .collect();
+        assert_eq!(rebuilt, data);
+
+        let known: HashSet<String> = store.keys().cloned().collect();
+        let mut new_chunks = 0;
+        let again = compressor
+            .chunk_and_store(&data, &known, |_, _| new_chunks += 1)
+            .unwrap();
+        assert_eq!(new_chunks, 0);
+        assert_eq!(again, recipe);
+    }
+
+    #[test]
+    fn test_content_chunks_survive_insertion() {
+        let data = pseudo_random(300_000, 99);
+        let chunks = content_chunks(&data);
+        assert_eq!(chunks.concat(), data);
+        assert!(
+            chunks[..chunks.len() - 1]
+                .iter()
+                .all(|chunk| (MIN_CHUNK_SIZE..=MAX_CHUNK_SIZE).contains(&chunk.len()))
+        );
+
+        // 在开头插入字节后，后面的分块仍然相同
+        let shifted = [b"inserted".as_slice(), &data].concat();
+        let original: HashSet<&[u8]> = chunks.iter().copied().collect();
+        let reused = content_chunks(&shifted)
+            .iter()
+            .filter(|chunk| original.contains(*chunk))
+            .count();
+        assert!(reused + 2 >= chunks.len(), "{} of {}", reused, chunks.len());
+        assert!(content_chunks(&[]).is_empty());
+    }
+}
@@ -1,50 +1,468 @@
+use crate::scan::collect_files;
+use crate::{DiffResult, FileMetadata};
 use aes_gcm::{
     Aes256Gcm, Key, Nonce,
-    aead::{Aead, AeadCore, KeyInit, OsRng},
+    aead::{Aead, AeadCore, KeyInit, OsRng, Payload, consts::U12, rand_core::RngCore},
 };
+use hmac::{Hmac, Mac};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
+use std::collections::HashSet;
 use std::fs;
-use std::io::Read;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+mod info;
+mod stream;
+
+pub use info::FileInfo;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// 流式哈希的默认读取缓冲区大小
+const DEFAULT_HASH_BUFFER_SIZE: usize = 64 * 1024;
+
+/// 并行哈希的分块大小
+const PARALLEL_HASH_CHUNK_SIZE: u64 = 4 * 1024 * 1024;
+
+/// 加密数据块的魔数
+pub const MAGIC: &[u8; 4] = b"SCLI";
+/// 加密格式版本
+const FORMAT_VERSION: u8 = 1;
+/// 密码算法标识：AES-256-GCM（单次加密）
+const CIPHER_AES_256_GCM: u8 = 1;
+/// 密码算法标识：AES-256-GCM 分块格式（见 `stream` 模块）
+const CIPHER_AES_256_GCM_STREAM: u8 = 2;
+/// 密码算法标识：AES-256-GCM 分块格式，仅包含文件的一段范围
+const CIPHER_AES_256_GCM_RANGE: u8 = 3;
+/// 密码算法标识：AES-256-GCM 分块格式，每块先独立压缩再加密
+const CIPHER_AES_256_GCM_COMPRESSED_STREAM: u8 = 4;
+/// 密码算法标识：AES-256-GCM 单次加密，附带加密的文件信息块（见 `info` 模块）
+const CIPHER_AES_256_GCM_WITH_INFO: u8 = 5;
+/// 密钥派生算法标识：SHA256(密码 + 固定盐)
+const KDF_SHA256_STATIC_SALT: u8 = 1;
+/// 密钥派生算法标识：PBKDF2-HMAC-SHA256，标识为该值加迭代次数的 log2
+const KDF_PBKDF2_SHA256_BASE: u8 = 0x40;
+/// PBKDF2 迭代次数 log2 的上限
+const MAX_KDF_COST: u8 = 24;
+/// 解密时默认接受的 PBKDF2 迭代次数 log2 上限（见 `with_max_kdf_cost`）
+const DEFAULT_MAX_KDF_COST: u8 = 20;
+/// `Sha256StaticSalt` 使用的固定盐
+const KDF_SALT: &[u8] = b"synccli-salt";
+/// PBKDF2 每个加密数据随机生成的盐长度，盐紧跟在各格式的固定头部之后
+const KDF_SALT_LEN: usize = 16;
+/// 头部长度：魔数(4) + 版本(1) + 密码算法(1) + 密钥派生算法(1)
+const HEADER_LEN: usize = 7;
+/// AES-GCM nonce 长度
+const NONCE_LEN: usize = 12;
+/// AES-GCM 认证标签长度
+const TAG_LEN: usize = 16;
+
+/// 树签名的格式版本
+const TREE_SIGNATURE_VERSION: &str = "v1";
+
+/// 自检使用的密码、明文，以及对应的密钥和 AES-256-GCM 密文（nonce 全零）的已知值
+const SELF_TEST_PASSWORD: &[u8] = b"synccli-self-test";
+const SELF_TEST_PLAINTEXT: &[u8] = b"synccli crypto self-test vector";
+const SELF_TEST_KEY: &str = "27041f684b37289823facf4f3bfb6d4ed33346cc221b834e308ea66da7d50496";
+const SELF_TEST_CIPHERTEXT: &str = "b16adba38b029e106abb7a2aaaf4c21ffb55a175480641b3873f9ebac929fd\
+                                    38572d28614bace126307041cc91f702";
+
+/// 密钥派生算法
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum KdfAlgorithm {
+    /// SHA256(密码 + 固定盐)，无迭代
+    Sha256StaticSalt,
+    /// PBKDF2-HMAC-SHA256，每个加密数据使用随机盐
+    Pbkdf2Sha256,
+}
+
+/// 密钥派生参数，编码在加密数据头部的密钥派生算法字节中
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct KdfParams {
+    pub algorithm: KdfAlgorithm,
+    /// 迭代次数（`Sha256StaticSalt` 为 1，`Pbkdf2Sha256` 为 2 的幂）
+    pub iterations: u32,
+}
+
+impl Default for KdfParams {
+    fn default() -> Self {
+        Self {
+            algorithm: KdfAlgorithm::Sha256StaticSalt,
+            iterations: 1,
+        }
+    }
+}
+
+impl KdfParams {
+    /// 头部中的密钥派生算法字节
+    fn header_byte(self) -> u8 {
+        match self.algorithm {
+            KdfAlgorithm::Sha256StaticSalt => KDF_SHA256_STATIC_SALT,
+            KdfAlgorithm::Pbkdf2Sha256 => KDF_PBKDF2_SHA256_BASE + self.cost(),
+        }
+    }
+
+    /// 解析头部中的密钥派生算法字节
+    fn from_header_byte(byte: u8) -> Result<Self, String> {
+        match byte {
+            KDF_SHA256_STATIC_SALT => Ok(Self::default()),
+            _ if (KDF_PBKDF2_SHA256_BASE..=KDF_PBKDF2_SHA256_BASE + MAX_KDF_COST)
+                .contains(&byte) =>
+            {
+                Ok(Self {
+                    algorithm: KdfAlgorithm::Pbkdf2Sha256,
+                    iterations: 1 << (byte - KDF_PBKDF2_SHA256_BASE),
+                })
+            }
+            _ => Err("不支持的密钥派生算法".to_string()),
+        }
+    }
+
+    /// 迭代次数的 log2
+    fn cost(self) -> u8 {
+        self.iterations.trailing_zeros() as u8
+    }
+
+    /// 加密数据中保存的盐长度，固定盐的算法为 0
+    fn salt_len(self) -> usize {
+        match self.algorithm {
+            KdfAlgorithm::Sha256StaticSalt => 0,
+            KdfAlgorithm::Pbkdf2Sha256 => KDF_SALT_LEN,
+        }
+    }
+
+    /// 从密码和盐派生 256 位密钥，`salt` 的长度须为 `salt_len()`
+    fn derive_key(self, password: &[u8], salt: &[u8]) -> [u8; 32] {
+        let mut key = [0u8; 32];
+        match self.algorithm {
+            KdfAlgorithm::Sha256StaticSalt => {
+                let mut hasher = Sha256::new();
+                hasher.update(password);
+                hasher.update(KDF_SALT);
+                key.copy_from_slice(&hasher.finalize());
+            }
+            KdfAlgorithm::Pbkdf2Sha256 => {
+                // 输出长度等于 HMAC-SHA256 长度，只需计算第一个块
+                let prf =
+                    <HmacSha256 as Mac>::new_from_slice(password).expect("HMAC 接受任意长度密钥");
+                let mut mac = prf.clone();
+                mac.update(salt);
+                mac.update(&1u32.to_be_bytes());
+                let mut block = mac.finalize().into_bytes();
+                key.copy_from_slice(&block);
+                for _ in 1..self.iterations {
+                    let mut mac = prf.clone();
+                    mac.update(&block);
+                    block = mac.finalize().into_bytes();
+                    key.iter_mut().zip(block.iter()).for_each(|(k, b)| *k ^= b);
+                }
+            }
+        }
+        key
+    }
+}
+
+/// 加密数据头部的描述（见 `CryptoCompressor::describe_blob`）
+///
+/// 各字段直接取自未经认证的头部，数据可能已被篡改，只能用于诊断，不能作为安全判断的依据。
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct BlobInfo {
+    /// 头部是否经过认证，恒为 false
+    pub authenticated: bool,
+    pub magic: String,
+    pub version: u8,
+    pub cipher_id: u8,
+    /// 密码算法名称，无法识别时为 None
+    pub cipher: Option<String>,
+    pub kdf_id: u8,
+    /// 密钥派生参数，无法识别时为 None
+    pub kdf: Option<KdfParams>,
+    /// 数据中保存的盐长度，固定盐的算法为 0
+    pub salt_len: usize,
+    /// 数据中保存的 nonce 长度，分块格式为各帧 nonce 的公共前缀
+    pub nonce_len: usize,
+    /// 分块格式的分块大小
+    pub chunk_size: Option<u32>,
+    /// 分块格式中完整数据帧的数量
+    pub chunk_count: Option<u64>,
+    /// 范围加密格式记录的起始偏移
+    pub range_offset: Option<u64>,
+    /// 数据是否短于格式要求（如末尾有不完整的数据帧）
+    pub truncated: bool,
+}
 
 /// 加密压缩器
 pub struct CryptoCompressor {
-    // 可以添加配置选项
+    /// 是否使用确定性 nonce（见 `with_deterministic_nonce`）
+    deterministic_nonce: bool,
+    /// 哈希文件前后是否检查文件是否被修改（见 `with_change_detection`）
+    detect_changes: bool,
+    /// 是否拒绝加密已加密的数据（见 `with_double_encryption_guard`）
+    reject_encrypted_input: bool,
+    /// 流式哈希的读取缓冲区大小（见 `with_hash_buffer_size`）
+    hash_buffer_size: usize,
+    /// 写文件时临时文件所在目录（见 `with_temp_dir`）
+    temp_dir: Option<PathBuf>,
+    /// 分块解密时对数据帧的限制（见 `with_frame_limits`）
+    frame_limits: stream::FrameLimits,
+    /// 加密时使用的密钥派生参数（见 `with_kdf_cost`）
+    kdf: KdfParams,
+    /// 解密时接受的 PBKDF2 迭代次数 log2 上限（见 `with_max_kdf_cost`）
+    max_kdf_cost: u8,
 }
 
 impl CryptoCompressor {
     /// 创建新的加密压缩器
     pub fn new() -> Self {
-        Self {}
+        Self {
+            deterministic_nonce: false,
+            detect_changes: false,
+            reject_encrypted_input: false,
+            hash_buffer_size: DEFAULT_HASH_BUFFER_SIZE,
+            temp_dir: None,
+            frame_limits: stream::FrameLimits::default(),
+            kdf: KdfParams::default(),
+            max_kdf_cost: DEFAULT_MAX_KDF_COST,
+        }
     }
 
-    /// 从密码生成密钥
-    fn derive_key_from_password(&self, password: &[u8]) -> [u8; 32] {
-        let mut hasher = Sha256::new();
-        hasher.update(password);
-        hasher.update(b"synccli-salt"); // 添加盐值
-        let result = hasher.finalize();
-        let mut key = [0u8; 32];
-        key.copy_from_slice(&result);
-        key
+    /// 启用确定性 nonce（SIV 风格，用于去重）
+    ///
+    /// 安全警告：启用后 nonce 由密钥和明文的 HMAC 派生，相同密钥下相同明文
+    /// 会得到完全相同的密文，因此密文会泄露"两段明文是否相等"。仅在去重
+    /// 确实需要时启用。
+    pub fn with_deterministic_nonce(mut self, enabled: bool) -> Self {
+        self.deterministic_nonce = enabled;
+        self
+    }
+
+    /// 启用哈希期间的文件修改检测
+    ///
+    /// 启用后 `hash_file` 会在哈希前后比较文件大小和修改时间，若发生变化
+    /// （例如文件正在被写入）则返回错误，调用方可以重试或跳过该文件。
+    pub fn with_change_detection(mut self, enabled: bool) -> Self {
+        self.detect_changes = enabled;
+        self
+    }
+
+    /// 启用重复加密保护
+    ///
+    /// 启用后 `encrypt_data` 遇到已带有 `SCLI` 头部的输入会直接报错，
+    /// 避免重复加密浪费资源并导致解密混乱。
+    pub fn with_double_encryption_guard(mut self, enabled: bool) -> Self {
+        self.reject_encrypted_input = enabled;
+        self
+    }
+
+    /// 设置 `hash_file`/`hash_reader` 的读取缓冲区大小（默认 64KB）
+    ///
+    /// 最佳值取决于存储介质，网络文件系统上通常需要更大的缓冲区。`size` 为 0 时返回错误。
+    pub fn with_hash_buffer_size(mut self, size: usize) -> Result<Self, String> {
+        if size == 0 {
+            return Err("哈希缓冲区大小必须大于0".to_string());
+        }
+        self.hash_buffer_size = size;
+        Ok(self)
+    }
+
+    /// 设置写文件时临时文件所在的目录（见 `Compressor::with_temp_dir`）
+    pub fn with_temp_dir(mut self, temp_dir: impl Into<PathBuf>) -> Self {
+        self.temp_dir = Some(temp_dir.into());
+        self
+    }
+
+    /// 设置分块解密时的数据帧限制
+    ///
+    /// 除末帧外每帧明文不少于 `min_frame_payload` 字节（默认 512），帧总数不超过
+    /// `max_frames`（默认 2^24），违反时解密报错，防止大量极小的帧放大解密开销。
+    /// 加密时分块大小小于 `min_frame_payload` 会直接返回错误，保证输出能用同样的配置解密。
+    pub fn with_frame_limits(mut self, min_frame_payload: usize, max_frames: u64) -> Self {
+        self.frame_limits = stream::FrameLimits {
+            min_payload: min_frame_payload,
+            max_frames,
+        };
+        self
+    }
+
+    /// 加密时改用 PBKDF2-HMAC-SHA256，迭代 2^`cost` 次（`cost` 不超过 24）
+    ///
+    /// 每次加密随机生成 16 字节的盐，与迭代次数一起写入加密数据头部，解密时按头部中的
+    /// 参数派生密钥。盐随机意味着启用确定性 nonce 时相同明文也不再得到相同密文。
+    pub fn with_kdf_cost(mut self, cost: u8) -> Result<Self, String> {
+        if cost > MAX_KDF_COST {
+            return Err(format!(
+                "密钥派生成本过高: {} (上限 {})",
+                cost, MAX_KDF_COST
+            ));
+        }
+        self.kdf = KdfParams {
+            algorithm: KdfAlgorithm::Pbkdf2Sha256,
+            iterations: 1 << cost,
+        };
+        Ok(self)
+    }
+
+    /// 设置解密时接受的 PBKDF2 迭代次数上限为 2^`cost`（默认 2^20）
+    ///
+    /// 头部中的迭代次数未经认证，超过上限的数据在派生密钥之前就被拒绝，
+    /// 防止恶意数据让解密方长时间计算。
+    pub fn with_max_kdf_cost(mut self, cost: u8) -> Self {
+        self.max_kdf_cost = cost;
+        self
+    }
+
+    /// 当前加密时使用的密钥派生参数（用于审计日志）
+    pub fn kdf_params(&self) -> KdfParams {
+        self.kdf
+    }
+
+    /// 读取加密数据头部记录的密钥派生参数，不需要密码也不解密数据
+    pub fn inspect_header(blob: &[u8]) -> Result<KdfParams, String> {
+        if blob.len() < HEADER_LEN || !blob.starts_with(MAGIC) {
+            return Err("不是有效的加密数据".to_string());
+        }
+        if blob[4] != FORMAT_VERSION {
+            return Err(format!("不支持的加密格式版本: {}", blob[4]));
+        }
+        KdfParams::from_header_byte(blob[6])
+    }
+
+    /// 解析加密数据的头部参数，不需要密码也不尝试解密
+    ///
+    /// 分块格式会遍历帧长度字段统计帧数。头部未经认证，见 `BlobInfo`。
+    pub fn describe_blob(blob: &[u8]) -> Result<BlobInfo, String> {
+        if blob.len() < HEADER_LEN || !blob.starts_with(MAGIC) {
+            return Err("不是有效的加密数据".to_string());
+        }
+        if blob[4] != FORMAT_VERSION {
+            return Err(format!("不支持的加密格式版本: {}", blob[4]));
+        }
+
+        let cipher = match blob[5] {
+            CIPHER_AES_256_GCM => Some("aes-256-gcm"),
+            CIPHER_AES_256_GCM_STREAM => Some("aes-256-gcm-stream"),
+            CIPHER_AES_256_GCM_RANGE => Some("aes-256-gcm-range"),
+            CIPHER_AES_256_GCM_COMPRESSED_STREAM => Some("aes-256-gcm-compressed-stream"),
+            CIPHER_AES_256_GCM_WITH_INFO => Some("aes-256-gcm-with-info"),
+            _ => None,
+        };
+        let mut info = BlobInfo {
+            authenticated: false,
+            magic: String::from_utf8_lossy(MAGIC).into_owned(),
+            version: blob[4],
+            cipher_id: blob[5],
+            cipher: cipher.map(str::to_string),
+            kdf_id: blob[6],
+            kdf: KdfParams::from_header_byte(blob[6]).ok(),
+            salt_len: KdfParams::from_header_byte(blob[6]).map_or(0, KdfParams::salt_len),
+            nonce_len: NONCE_LEN,
+            chunk_size: None,
+            chunk_count: None,
+            range_offset: None,
+            truncated: false,
+        };
+        match blob[5] {
+            CIPHER_AES_256_GCM | CIPHER_AES_256_GCM_WITH_INFO => {
+                info.truncated = blob.len() < HEADER_LEN + info.salt_len + NONCE_LEN + TAG_LEN;
+            }
+            CIPHER_AES_256_GCM_STREAM
+            | CIPHER_AES_256_GCM_RANGE
+            | CIPHER_AES_256_GCM_COMPRESSED_STREAM => stream::describe_frames(blob, &mut info),
+            _ => info.nonce_len = 0,
+        }
+        Ok(info)
+    }
+
+    /// 判断数据是否为本模块生成的加密数据（检查魔数和版本）
+    pub fn is_encrypted(data: &[u8]) -> bool {
+        data.len() >= HEADER_LEN + NONCE_LEN + TAG_LEN
+            && data.starts_with(MAGIC)
+            && data[4] == FORMAT_VERSION
+    }
+
+    /// 生成加密数据头部
+    fn header(&self) -> [u8; HEADER_LEN] {
+        header_for(self.kdf)
+    }
+
+    /// 由密钥和明文派生确定性 nonce（HMAC-SHA256 截断到 12 字节）
+    ///
+    /// HMAC 使用从加密密钥派生的独立子密钥，加密密钥本身不参与其他算法。
+    fn derive_deterministic_nonce(&self, key: &[u8; 32], data: &[u8]) -> Nonce<U12> {
+        let mut subkey_mac =
+            <HmacSha256 as Mac>::new_from_slice(key).expect("HMAC 接受任意长度密钥");
+        subkey_mac.update(b"synccli-siv-nonce-key");
+        let subkey = subkey_mac.finalize().into_bytes();
+
+        let mut mac = <HmacSha256 as Mac>::new_from_slice(&subkey).expect("HMAC 接受任意长度密钥");
+        mac.update(b"synccli-siv");
+        mac.update(data);
+        let tag = mac.finalize().into_bytes();
+        *Nonce::from_slice(&tag[..12])
+    }
+
+    /// 按加密时的密钥派生参数从密码生成密钥，返回密钥和需要写在头部之后的随机盐
+    fn derive_encryption_key(&self, password: &[u8]) -> ([u8; 32], Vec<u8>) {
+        let mut salt = vec![0u8; self.kdf.salt_len()];
+        OsRng.fill_bytes(&mut salt);
+        (self.kdf.derive_key(password, &salt), salt)
+    }
+
+    /// 按头部记录的参数派生解密密钥，迭代次数超过 `with_max_kdf_cost` 的上限时报错
+    fn derive_decryption_key(
+        &self,
+        kdf: KdfParams,
+        password: &[u8],
+        salt: &[u8],
+    ) -> Result<[u8; 32], String> {
+        if kdf.algorithm == KdfAlgorithm::Pbkdf2Sha256 && kdf.cost() > self.max_kdf_cost {
+            return Err(format!(
+                "密钥派生成本超出上限: {} (上限 {})",
+                kdf.cost(),
+                self.max_kdf_cost
+            ));
+        }
+        Ok(kdf.derive_key(password, salt))
     }
 
     /// 加密数据
+    ///
+    /// 输出格式：`SCLI` 头部 + 盐（仅 PBKDF2）+ nonce + 密文（含认证标签），
+    /// 头部和盐作为附加认证数据。
     pub fn encrypt_data(&self, data: &[u8], password: &[u8]) -> Result<Vec<u8>, String> {
+        if self.reject_encrypted_input && Self::is_encrypted(data) {
+            return Err("数据已加密，拒绝重复加密".to_string());
+        }
+
         // 从密码派生密钥
-        let key_bytes = self.derive_key_from_password(password);
+        let (key_bytes, salt) = self.derive_encryption_key(password);
         let key = Key::<Aes256Gcm>::from_slice(&key_bytes);
 
         // 创建加密器
         let cipher = Aes256Gcm::new(key);
 
-        // 生成随机nonce
-        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        // 生成nonce（默认随机，确定性模式下由明文派生）
+        let nonce = if self.deterministic_nonce {
+            self.derive_deterministic_nonce(&key_bytes, data)
+        } else {
+            Aes256Gcm::generate_nonce(&mut OsRng)
+        };
 
-        // 加密数据
-        match cipher.encrypt(&nonce, data) {
+        // 加密数据（头部和盐参与认证）
+        let mut header = self.header().to_vec();
+        header.extend_from_slice(&salt);
+        let payload = Payload {
+            msg: data,
+            aad: &header,
+        };
+        match cipher.encrypt(&nonce, payload) {
             Ok(ciphertext) => {
-                // 将nonce和密文组合
-                let mut result = Vec::new();
+                // 将头部、nonce和密文组合
+                let mut result = Vec::with_capacity(header.len() + NONCE_LEN + ciphertext.len());
+                result.extend_from_slice(&header);
                 result.extend_from_slice(&nonce);
                 result.extend_from_slice(&ciphertext);
                 Ok(result)
@@ -54,80 +472,222 @@ impl CryptoCompressor {
     }
 
     /// 解密数据
+    ///
+    /// 同时支持带 `SCLI` 头部的格式和早期无头部的格式（nonce + 密文）。
     pub fn decrypt_data(&self, encrypted_data: &[u8], password: &[u8]) -> Result<Vec<u8>, String> {
-        if encrypted_data.len() < 12 {
-            return Err("加密数据太短".to_string());
-        }
+        // 早期无头部的格式只使用默认的密钥派生
+        let mut kdf = KdfParams::default();
+        let mut salt: &[u8] = &[];
+        let (aad, encrypted_data) = if encrypted_data.starts_with(MAGIC) {
+            if encrypted_data.len() < HEADER_LEN {
+                return Err("加密数据头部不完整".to_string());
+            }
+            let (header, body) = encrypted_data.split_at(HEADER_LEN);
+            if header[4] != FORMAT_VERSION {
+                return Err(format!("不支持的加密格式版本: {}", header[4]));
+            }
+            if header[5] == CIPHER_AES_256_GCM_STREAM
+                || header[5] == CIPHER_AES_256_GCM_COMPRESSED_STREAM
+            {
+                let mut plaintext = Vec::new();
+                self.decrypt_stream(encrypted_data, &mut plaintext, password)?;
+                return Ok(plaintext);
+            }
+            if header[5] == CIPHER_AES_256_GCM_WITH_INFO {
+                return self.decrypt_with_info(encrypted_data, password);
+            }
+            if header[5] != CIPHER_AES_256_GCM {
+                return Err("不支持的加密算法".to_string());
+            }
+            kdf = KdfParams::from_header_byte(header[6])?;
+            let (kdf_salt, body) = split_salt(kdf, body)?;
+            salt = kdf_salt;
+            (&encrypted_data[..HEADER_LEN + salt.len()], body)
+        } else {
+            (&[][..], encrypted_data)
+        };
 
-        // 从密码派生密钥
-        let key_bytes = self.derive_key_from_password(password);
+        check_sealed_len(encrypted_data)?;
+
+        // 按头部记录的参数从密码派生密钥
+        let key_bytes = self.derive_decryption_key(kdf, password, salt)?;
         let key = Key::<Aes256Gcm>::from_slice(&key_bytes);
 
         // 创建解密器
         let cipher = Aes256Gcm::new(key);
 
         // 提取nonce和密文
-        let (nonce_bytes, ciphertext) = encrypted_data.split_at(12);
+        let (nonce_bytes, ciphertext) = encrypted_data.split_at(NONCE_LEN);
         let nonce = Nonce::from_slice(nonce_bytes);
 
         // 解密数据
-        match cipher.decrypt(nonce, ciphertext) {
+        let payload = Payload {
+            msg: ciphertext,
+            aad,
+        };
+        match cipher.decrypt(nonce, payload) {
             Ok(plaintext) => Ok(plaintext),
             Err(e) => Err(format!("解密失败: {}", e)),
         }
     }
 
+    /// 加密模块自检，用于部署后的冒烟测试
+    ///
+    /// 依次检查密钥派生和 AES-256-GCM 的已知答案、按当前配置加解密的往返结果，
+    /// 以及错误密码和被篡改的密文是否被拒绝，任一步骤异常时返回说明该步骤的错误。
+    pub fn self_test(&self) -> Result<(), String> {
+        // 已知答案按默认的密钥派生参数计算
+        let key_bytes = KdfParams::default().derive_key(SELF_TEST_PASSWORD, &[]);
+        if hex::encode(key_bytes) != SELF_TEST_KEY {
+            return Err("自检失败: 密钥派生结果与已知值不符".to_string());
+        }
+
+        let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key_bytes));
+        let header = header_for(KdfParams::default());
+        let ciphertext = cipher
+            .encrypt(
+                Nonce::from_slice(&[0u8; NONCE_LEN]),
+                Payload {
+                    msg: SELF_TEST_PLAINTEXT,
+                    aad: &header,
+                },
+            )
+            .map_err(|e| format!("自检失败: 加密失败: {}", e))?;
+        if hex::encode(ciphertext) != SELF_TEST_CIPHERTEXT {
+            return Err("自检失败: 加密结果与已知值不符".to_string());
+        }
+
+        let encrypted = self
+            .encrypt_data(SELF_TEST_PLAINTEXT, SELF_TEST_PASSWORD)
+            .map_err(|e| format!("自检失败: {}", e))?;
+        let decrypted = self
+            .decrypt_data(&encrypted, SELF_TEST_PASSWORD)
+            .map_err(|e| format!("自检失败: {}", e))?;
+        if decrypted != SELF_TEST_PLAINTEXT {
+            return Err("自检失败: 解密结果与原文不一致".to_string());
+        }
+
+        if self.decrypt_data(&encrypted, b"wrong-password").is_ok() {
+            return Err("自检失败: 错误的密码未被拒绝".to_string());
+        }
+        let mut tampered = encrypted;
+        let last = tampered.len() - 1;
+        tampered[last] ^= 1;
+        if self.decrypt_data(&tampered, SELF_TEST_PASSWORD).is_ok() {
+            return Err("自检失败: 被篡改的密文未被拒绝".to_string());
+        }
+
+        Ok(())
+    }
+
     /// 加密文件
     pub fn encrypt_file(&self, file_path: &str, password: &[u8]) -> Result<Vec<u8>, String> {
         // 读取文件内容
-        let file_data =
-            fs::read(file_path).map_err(|e| format!("读取文件失败 {}: {}", file_path, e))?;
+        let file_data = crate::error::read_file(file_path)?;
 
         // 加密数据
         self.encrypt_data(&file_data, password)
     }
 
-    /// 解密文件并保存
-    pub fn decrypt_file_to_path(
+    /// 加密文件写入 `output_path`，并读回解密校验
+    ///
+    /// 写入后重新读取输出文件、解密并比较明文哈希，确认落盘的数据可以正确解密才返回成功；
+    /// 校验失败时删除输出文件并返回错误。
+    pub fn encrypt_file_verified(
         &self,
-        encrypted_data: &[u8],
+        input_path: &str,
         password: &[u8],
         output_path: &str,
     ) -> Result<(), String> {
-        // 解密数据
-        let decrypted_data = self.decrypt_data(encrypted_data, password)?;
+        self.encrypt_file_verified_with_hook(input_path, password, output_path, |_| {})
+    }
 
-        // 写入文件
-        fs::write(output_path, decrypted_data)
-            .map_err(|e| format!("写入文件失败 {}: {}", output_path, e))?;
+    /// `encrypt_file_verified` 的实现，`after_write` 在写入输出文件后、读回校验前调用
+    fn encrypt_file_verified_with_hook<F: FnOnce(&Path)>(
+        &self,
+        input_path: &str,
+        password: &[u8],
+        output_path: &str,
+        after_write: F,
+    ) -> Result<(), String> {
+        let file_data = crate::error::read_file(input_path)?;
+        let expected_hash = self.calculate_hash(&file_data);
+        let encrypted = self.encrypt_data(&file_data, password)?;
 
+        let output = Path::new(output_path);
+        crate::atomic::write_atomic(output, &encrypted, self.temp_dir.as_deref())?;
+        after_write(output);
+
+        let verified = crate::error::read_file(output_path)
+            .map_err(|e| e.to_string())
+            .and_then(|written| self.decrypt_data(&written, password))
+            .and_then(|decrypted| {
+                if self.calculate_hash(&decrypted) == expected_hash {
+                    Ok(())
+                } else {
+                    Err("解密结果与原文件不一致".to_string())
+                }
+            });
+        if let Err(e) = verified {
+            let _ = fs::remove_file(output);
+            return Err(format!("加密文件校验失败 {}: {}", output_path, e));
+        }
         Ok(())
     }
 
-    /// 加密文件流（用于大文件）
-    pub fn encrypt_file_stream(
+    /// 原地加密文件：用密文替换原文件内容
+    ///
+    /// 密文先写入同目录下的临时文件并落盘，成功后再原子地重命名覆盖原文件，
+    /// 并保留原文件的权限；任何一步失败时原文件保持不变。
+    pub fn encrypt_file_in_place(&self, path: &str, password: &[u8]) -> Result<(), String> {
+        self.encrypt_file_in_place_with_writer(path, password, |writer, data| {
+            writer.write_all(data)
+        })
+    }
+
+    /// `encrypt_file_in_place` 的实现，`write` 负责把密文写入临时文件
+    fn encrypt_file_in_place_with_writer<F>(
         &self,
-        file_path: &str,
+        path: &str,
         password: &[u8],
-        chunk_size: usize,
-    ) -> Result<Vec<u8>, String> {
-        let mut file =
-            fs::File::open(file_path).map_err(|e| format!("打开文件失败 {}: {}", file_path, e))?;
+        write: F,
+    ) -> Result<(), String>
+    where
+        F: FnOnce(&mut dyn Write, &[u8]) -> std::io::Result<()>,
+    {
+        let target = Path::new(path);
+        let permissions = fs::metadata(target)
+            .map_err(|e| format!("读取文件元数据失败 {}: {}", path, e))?
+            .permissions();
+        let file_data = crate::error::read_file(path).map_err(|e| e.to_string())?;
+        let encrypted = self.encrypt_data(&file_data, password)?;
 
-        let mut buffer = vec![0u8; chunk_size];
-        let mut all_data = Vec::new();
+        // 临时文件必须与原文件在同一目录，保证重命名是原子的
+        let mut temp = crate::atomic::temp_file_for(target, None)?;
+        write(temp.as_file_mut(), &encrypted)
+            .map_err(|e| format!("写入文件失败 {}: {}", path, e))?;
+        temp.as_file()
+            .set_permissions(permissions)
+            .map_err(|e| format!("设置文件权限失败 {}: {}", path, e))?;
+        crate::atomic::persist(temp, target)
+    }
 
-        loop {
-            match file.read(&mut buffer) {
-                Ok(0) => break, // 文件结束
-                Ok(n) => {
-                    all_data.extend_from_slice(&buffer[..n]);
-                }
-                Err(e) => return Err(format!("读取文件失败: {}", e)),
-            }
-        }
+    /// 解密文件并保存
+    pub fn decrypt_file_to_path(
+        &self,
+        encrypted_data: &[u8],
+        password: &[u8],
+        output_path: &str,
+    ) -> Result<(), String> {
+        // 解密数据
+        let decrypted_data = self.decrypt_data(encrypted_data, password)?;
 
-        self.encrypt_data(&all_data, password)
+        // 先写临时文件再重命名，失败时不会留下不完整的明文文件
+        crate::atomic::write_atomic(
+            Path::new(output_path),
+            &decrypted_data,
+            self.temp_dir.as_deref(),
+        )
     }
 
     /// 验证密码是否正确
@@ -137,13 +697,23 @@ impl CryptoCompressor {
 
     /// 生成随机密码
     pub fn generate_random_password(&self, length: usize) -> String {
-        use rand::Rng;
+        self.generate_random_password_with(&mut rand::thread_rng(), length)
+    }
+
+    /// 使用指定的随机数生成器生成随机密码
+    ///
+    /// 传入固定种子的 RNG 可得到可复现的结果（如测试向量），
+    /// 也可以替换为经过认证的随机源。
+    pub fn generate_random_password_with<R: rand::Rng>(
+        &self,
+        rng: &mut R,
+        length: usize,
+    ) -> String {
         const CHARSET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ\
                                 abcdefghijklmnopqrstuvwxyz\
                                 0123456789\
                                 !@#$%^&*";
 
-        let mut rng = rand::thread_rng();
         (0..length)
             .map(|_| {
                 let idx = rng.gen_range(0..CHARSET.len());
@@ -160,6 +730,273 @@ impl CryptoCompressor {
         hex::encode(result)
     }
 
+    /// 判断数据的 SHA256 是否等于 `expected_hash`（十六进制，不区分大小写）
+    ///
+    /// 按字节常数时间比较，耗时不泄露哈希在哪一位开始不同。
+    pub fn hash_matches(&self, data: &[u8], expected_hash: &str) -> bool {
+        let Ok(expected) = hex::decode(expected_hash) else {
+            return false;
+        };
+        let actual = Sha256::digest(data);
+        expected.len() == actual.len()
+            && expected
+                .iter()
+                .zip(actual.iter())
+                .fold(0u8, |acc, (a, b)| acc | (a ^ b))
+                == 0
+    }
+
+    /// 计算文件列表的 Merkle 根（SHA256 十六进制）
+    ///
+    /// 文件按标准化路径（`/` 分隔）排序，叶子为 `SHA256(0x00 | 路径 | 0x00 | 哈希 | 0x00 | 大小)`，
+    /// 内部节点为 `SHA256(0x01 | 左 | 右)`，奇数个节点时末尾节点直接进入上一层。
+    /// 结果与文件列表的顺序无关。
+    pub fn merkle_root(files: &[FileMetadata]) -> String {
+        let mut entries: Vec<(String, &FileMetadata)> = files
+            .iter()
+            .map(|file| (file.path.replace('\\', "/"), file))
+            .collect();
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+
+        let mut level: Vec<[u8; 32]> = entries
+            .iter()
+            .map(|(path, file)| {
+                let mut hasher = Sha256::new();
+                hasher.update([0x00]);
+                hasher.update(path.as_bytes());
+                hasher.update([0x00]);
+                hasher.update(file.hash.as_bytes());
+                hasher.update([0x00]);
+                hasher.update(file.size.to_le_bytes());
+                hasher.finalize().into()
+            })
+            .collect();
+        if level.is_empty() {
+            return hex::encode(Sha256::digest([]));
+        }
+
+        while level.len() > 1 {
+            level = level
+                .chunks(2)
+                .map(|pair| match pair {
+                    [left, right] => {
+                        let mut hasher = Sha256::new();
+                        hasher.update([0x01]);
+                        hasher.update(left);
+                        hasher.update(right);
+                        hasher.finalize().into()
+                    }
+                    [single] => *single,
+                    _ => unreachable!(),
+                })
+                .collect();
+        }
+        hex::encode(level[0])
+    }
+
+    /// 整个文件树的签名：`v1:文件数:总大小:Merkle 根`
+    ///
+    /// 与上次同步时保存的签名相同（见 `tree_signatures_match`）即可跳过整个差异计算。
+    pub fn tree_signature(files: &[FileMetadata]) -> String {
+        let total_size: i64 = files.iter().map(|file| file.size).sum();
+        format!(
+            "{}:{}:{}:{}",
+            TREE_SIGNATURE_VERSION,
+            files.len(),
+            total_size,
+            Self::merkle_root(files)
+        )
+    }
+
+    /// 比较两个树签名是否表示相同的文件树
+    ///
+    /// 任一签名格式无法识别（如旧版本保存的签名）时返回 false，按有变化处理。
+    pub fn tree_signatures_match(a: &str, b: &str) -> bool {
+        let well_formed = |signature: &str| {
+            let parts: Vec<&str> = signature.split(':').collect();
+            parts.len() == 4
+                && parts[0] == TREE_SIGNATURE_VERSION
+                && parts[1].parse::<usize>().is_ok()
+                && parts[2].parse::<i64>().is_ok()
+                && parts[3].len() == 64
+        };
+        well_formed(a) && well_formed(b) && a.eq_ignore_ascii_case(b)
+    }
+
+    /// 计算数据的弱校验和（Adler-32），速度远快于 SHA256，但可能碰撞
+    pub fn calculate_weak_checksum(&self, data: &[u8]) -> u32 {
+        const MOD_ADLER: u32 = 65_521;
+        // 5552 是保证 b 累加不溢出 u32 的最大块长
+        let (mut a, mut b) = (1u32, 0u32);
+        for chunk in data.chunks(5552) {
+            for &byte in chunk {
+                a += byte as u32;
+                b += a;
+            }
+            a %= MOD_ADLER;
+            b %= MOD_ADLER;
+        }
+        (b << 16) | a
+    }
+
+    /// 流式计算文件的 SHA256 哈希（不把整个文件读入内存）
+    pub fn hash_file(&self, file_path: &str) -> Result<String, String> {
+        self.hash_file_with_hook(file_path, || {})
+    }
+
+    /// `hash_file` 的实现，`on_read` 在每次读取数据块后调用
+    fn hash_file_with_hook<F: FnMut()>(
+        &self,
+        file_path: &str,
+        on_read: F,
+    ) -> Result<String, String> {
+        let before = self.snapshot_file(file_path)?;
+
+        let file =
+            fs::File::open(file_path).map_err(|e| format!("打开文件失败 {}: {}", file_path, e))?;
+        let hash = self
+            .hash_reader(HookReader {
+                inner: file,
+                on_read,
+            })
+            .map_err(|e| format!("哈希文件失败 {}: {}", file_path, e))?;
+
+        if self.snapshot_file(file_path)? != before {
+            return Err("文件在哈希期间被修改".to_string());
+        }
+
+        Ok(hash)
+    }
+
+    /// 记录文件的大小和修改时间，未启用修改检测时返回 None
+    fn snapshot_file(&self, file_path: &str) -> Result<Option<(u64, SystemTime)>, String> {
+        if !self.detect_changes {
+            return Ok(None);
+        }
+
+        let metadata = fs::metadata(file_path)
+            .map_err(|e| format!("获取文件元数据失败 {}: {}", file_path, e))?;
+        let modified = metadata
+            .modified()
+            .map_err(|e| format!("获取修改时间失败 {}: {}", file_path, e))?;
+        Ok(Some((metadata.len(), modified)))
+    }
+
+    /// 流式计算任意读取源的 SHA256 哈希
+    pub fn hash_reader<R: Read>(&self, mut reader: R) -> Result<String, String> {
+        let mut hasher = Sha256::new();
+        let mut buffer = vec![0u8; self.hash_buffer_size];
+
+        loop {
+            match reader.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(n) => hasher.update(&buffer[..n]),
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(format!("读取数据失败: {}", e)),
+            }
+        }
+
+        Ok(hex::encode(hasher.finalize()))
+    }
+
+    /// 分块并行计算大文件的树形哈希
+    ///
+    /// 文件按 4MB 分块，各块的 SHA256 由 rayon 并行计算，再对
+    /// "文件长度 + 各块哈希" 计算一次 SHA256 得到最终结果。
+    /// 这是与 `hash_file` 不同的哈希方案，结果带 `sha256-tree:` 前缀以免与串行哈希混用。
+    pub fn hash_file_parallel(&self, file_path: &str) -> Result<String, String> {
+        self.hash_file_chunked(file_path, PARALLEL_HASH_CHUNK_SIZE)
+    }
+
+    /// `hash_file_parallel` 的实现，分块大小可配置
+    fn hash_file_chunked(&self, file_path: &str, chunk_size: u64) -> Result<String, String> {
+        let file_len = fs::metadata(file_path)
+            .map_err(|e| format!("获取文件元数据失败 {}: {}", file_path, e))?
+            .len();
+        let chunk_count = file_len.div_ceil(chunk_size).max(1);
+
+        let chunk_hashes: Vec<[u8; 32]> = (0..chunk_count)
+            .into_par_iter()
+            .map(|index| {
+                let offset = index * chunk_size;
+                let len = chunk_size.min(file_len - offset) as usize;
+
+                let mut file = fs::File::open(file_path)
+                    .map_err(|e| format!("打开文件失败 {}: {}", file_path, e))?;
+                file.seek(SeekFrom::Start(offset))
+                    .map_err(|e| format!("定位文件失败 {}: {}", file_path, e))?;
+                let mut buffer = vec![0u8; len];
+                file.read_exact(&mut buffer)
+                    .map_err(|e| format!("读取文件失败 {}: {}", file_path, e))?;
+
+                Ok(Sha256::digest(&buffer).into())
+            })
+            .collect::<Result<_, String>>()?;
+
+        let mut hasher = Sha256::new();
+        hasher.update(file_len.to_le_bytes());
+        for chunk_hash in &chunk_hashes {
+            hasher.update(chunk_hash);
+        }
+        Ok(format!("sha256-tree:{}", hex::encode(hasher.finalize())))
+    }
+
+    /// 按清单并行校验目录完整性
+    ///
+    /// 清单中的路径为相对 `root` 的路径（以 `/` 分隔），哈希为 SHA256 十六进制串
+    /// （允许带 `sha256:` 前缀）。绝对路径、含 `..` 等不安全分量或经过符号链接的条目
+    /// 不会被读取，直接计入 `mismatched`。
+    pub fn verify_directory(&self, root: &str, manifest: &[FileMetadata]) -> VerifyReport {
+        let root_path = Path::new(root);
+
+        let outcomes: Vec<(String, VerifyOutcome)> = manifest
+            .par_iter()
+            .map(|entry| {
+                let relative = entry.path.replace('\\', "/");
+                let Some(full_path) = manifest_entry_path(root_path, &relative) else {
+                    return (relative, VerifyOutcome::Mismatched);
+                };
+                let outcome = if !full_path.is_file() {
+                    VerifyOutcome::Missing
+                } else {
+                    let expected = entry.hash.strip_prefix("sha256:").unwrap_or(&entry.hash);
+                    match self.hash_file(&full_path.to_string_lossy()) {
+                        Ok(actual) if actual == expected => VerifyOutcome::Matched,
+                        _ => VerifyOutcome::Mismatched,
+                    }
+                };
+                (relative, outcome)
+            })
+            .collect();
+
+        let mut report = VerifyReport::default();
+        for (path, outcome) in outcomes {
+            match outcome {
+                VerifyOutcome::Matched => report.matched.push(path),
+                VerifyOutcome::Mismatched => report.mismatched.push(path),
+                VerifyOutcome::Missing => report.missing.push(path),
+            }
+        }
+
+        let expected_paths: HashSet<String> = manifest
+            .iter()
+            .map(|entry| entry.path.replace('\\', "/"))
+            .collect();
+        let mut on_disk = Vec::new();
+        let mut unreadable = Vec::new();
+        collect_files(root_path, root_path, false, &mut on_disk, &mut unreadable);
+        report.extra = on_disk
+            .into_iter()
+            .filter(|path| !expected_paths.contains(path))
+            .collect();
+
+        report.matched.sort();
+        report.mismatched.sort();
+        report.missing.sort();
+        report.extra.sort();
+        report
+    }
+
     /// 加密并计算哈希
     pub fn encrypt_with_hash(
         &self,
@@ -189,6 +1026,166 @@ impl CryptoCompressor {
     }
 }
 
+/// 目录校验报告
+#[derive(Debug, Clone, Default)]
+pub struct VerifyReport {
+    /// 哈希一致的文件
+    pub matched: Vec<String>,
+    /// 哈希不一致（或无法读取、路径不安全）的文件
+    pub mismatched: Vec<String>,
+    /// 清单中有但磁盘上不存在的文件
+    pub missing: Vec<String>,
+    /// 磁盘上有但清单中没有的文件
+    pub extra: Vec<String>,
+}
+
+impl VerifyReport {
+    /// 目录是否与清单完全一致
+    pub fn is_intact(&self) -> bool {
+        self.mismatched.is_empty() && self.missing.is_empty() && self.extra.is_empty()
+    }
+}
+
+/// 带 HMAC 完整性封印的差异清单
+///
+/// `manifest` 为差异结果的规范 JSON 序列化，`mac` 为其 HMAC-SHA256（十六进制），
+/// 可整体序列化后经不可信通道传输。
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SealedManifest {
+    pub manifest: String,
+    pub mac: String,
+}
+
+impl SealedManifest {
+    /// 序列化差异结果并计算 HMAC
+    pub fn seal(result: &DiffResult, key: &[u8]) -> Result<Self, String> {
+        let manifest =
+            serde_json::to_string(result).map_err(|e| format!("序列化差异结果失败: {}", e))?;
+        let mut mac = <HmacSha256 as Mac>::new_from_slice(key).expect("HMAC 接受任意长度密钥");
+        mac.update(manifest.as_bytes());
+        Ok(Self {
+            mac: hex::encode(mac.finalize().into_bytes()),
+            manifest,
+        })
+    }
+
+    /// 校验 HMAC（常数时间比较）后解析差异结果
+    pub fn open(&self, key: &[u8]) -> Result<DiffResult, String> {
+        let expected = hex::decode(&self.mac).map_err(|_| "清单校验失败".to_string())?;
+        let mut mac = <HmacSha256 as Mac>::new_from_slice(key).expect("HMAC 接受任意长度密钥");
+        mac.update(self.manifest.as_bytes());
+        mac.verify_slice(&expected)
+            .map_err(|_| "清单校验失败".to_string())?;
+
+        serde_json::from_str(&self.manifest).map_err(|e| format!("解析差异结果失败: {}", e))
+    }
+}
+
+/// HMAC-SHA256 标签长度
+pub const MAC_LEN: usize = 32;
+
+/// 在数据末尾追加 HMAC-SHA256 标签（只保证完整性，不加密）
+pub fn append_mac(mut data: Vec<u8>, key: &[u8]) -> Vec<u8> {
+    let mut mac = <HmacSha256 as Mac>::new_from_slice(key).expect("HMAC 接受任意长度密钥");
+    mac.update(&data);
+    data.extend_from_slice(&mac.finalize().into_bytes());
+    data
+}
+
+/// 校验 `append_mac` 追加的标签（常数时间比较），返回去掉标签后的数据
+pub fn verify_mac<'a>(blob: &'a [u8], key: &[u8]) -> Result<&'a [u8], String> {
+    if blob.len() < MAC_LEN {
+        return Err("签名数据太短".to_string());
+    }
+    let (data, tag) = blob.split_at(blob.len() - MAC_LEN);
+    let mut mac = <HmacSha256 as Mac>::new_from_slice(key).expect("HMAC 接受任意长度密钥");
+    mac.update(data);
+    mac.verify_slice(tag)
+        .map_err(|_| "签名校验失败: 数据可能被篡改".to_string())?;
+    Ok(data)
+}
+
+/// 检查 `nonce + 密文` 的长度，数据不完整时不交给 AEAD 处理
+///
+/// 空明文加密后只有 nonce 和认证标签，两者缺一都无法解密：不足 nonce 长度时返回
+/// "加密数据太短"，nonce 之后不足一个认证标签时返回 "密文缺少认证标签"。
+fn check_sealed_len(part: &[u8]) -> Result<(), String> {
+    if part.len() < NONCE_LEN {
+        return Err("加密数据太短".to_string());
+    }
+    if part.len() - NONCE_LEN < TAG_LEN {
+        return Err("密文缺少认证标签".to_string());
+    }
+    Ok(())
+}
+
+/// 从固定头部之后的数据中拆出 `kdf` 使用的盐，返回盐和其余数据
+fn split_salt(kdf: KdfParams, rest: &[u8]) -> Result<(&[u8], &[u8]), String> {
+    if rest.len() < kdf.salt_len() {
+        return Err("加密数据头部不完整".to_string());
+    }
+    Ok(rest.split_at(kdf.salt_len()))
+}
+
+/// 生成单次加密格式的头部
+fn header_for(kdf: KdfParams) -> [u8; HEADER_LEN] {
+    [
+        MAGIC[0],
+        MAGIC[1],
+        MAGIC[2],
+        MAGIC[3],
+        FORMAT_VERSION,
+        CIPHER_AES_256_GCM,
+        kdf.header_byte(),
+    ]
+}
+
+/// 每次读取后调用回调的读取器包装
+struct HookReader<R, F> {
+    inner: R,
+    on_read: F,
+}
+
+impl<R: Read, F: FnMut()> Read for HookReader<R, F> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        (self.on_read)();
+        Ok(n)
+    }
+}
+
+/// 单个清单条目的校验结果
+enum VerifyOutcome {
+    Matched,
+    Mismatched,
+    Missing,
+}
+
+/// 将清单中的相对路径安全地拼接到 `root` 下
+///
+/// 拒绝空路径、绝对路径、含空、`.`、`..` 或 `:` 分量的路径，以及 `root` 下任一分量
+/// （包括文件本身）为符号链接的路径，避免校验读取 `root` 之外的文件。
+fn manifest_entry_path(root: &Path, relative: &str) -> Option<PathBuf> {
+    let parts: Vec<&str> = relative.split('/').collect();
+    let unsafe_path = relative.is_empty()
+        || Path::new(relative).is_absolute()
+        || parts
+            .iter()
+            .any(|part| part.is_empty() || *part == "." || *part == ".." || part.contains(':'));
+    if unsafe_path {
+        return None;
+    }
+
+    let mut path = root.to_path_buf();
+    for part in parts {
+        path.push(part);
+        if fs::symlink_metadata(&path).is_ok_and(|metadata| metadata.file_type().is_symlink()) {
+            return None;
+        }
+    }
+    Some(path)
+}
+
 impl Default for CryptoCompressor {
     fn default() -> Self {
         Self::new()
@@ -198,7 +1195,6 @@ impl Default for CryptoCompressor {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::fs;
     use std::io::Write;
     use tempfile::NamedTempFile;
 
@@ -214,6 +1210,16 @@ mod tests {
         assert_eq!(data.to_vec(), decrypted);
     }
 
+    #[test]
+    fn test_self_test() {
+        CryptoCompressor::new().self_test().unwrap();
+        CryptoCompressor::new()
+            .with_deterministic_nonce(true)
+            .with_double_encryption_guard(true)
+            .self_test()
+            .unwrap();
+    }
+
     #[test]
     fn test_encrypt_decrypt_with_wrong_password() {
         let crypto = CryptoCompressor::new();
@@ -248,6 +1254,73 @@ mod tests {
         assert_eq!(test_content.to_vec(), decrypted_data);
     }
 
+    #[test]
+    fn test_encrypt_file_verified() {
+        let crypto = CryptoCompressor::new();
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("input.txt");
+        let output = dir.path().join("input.txt.enc");
+        fs::write(&input, b"durable content").unwrap();
+        let (input, output_path) = (input.to_str().unwrap(), output.to_str().unwrap());
+
+        crypto
+            .encrypt_file_verified(input, b"password", output_path)
+            .unwrap();
+        let written = fs::read(&output).unwrap();
+        assert_eq!(
+            crypto.decrypt_data(&written, b"password").unwrap(),
+            b"durable content"
+        );
+
+        // 写入后数据被损坏：校验失败并删除输出文件
+        let result =
+            crypto.encrypt_file_verified_with_hook(input, b"password", output_path, |path| {
+                let mut data = fs::read(path).unwrap();
+                let last = data.len() - 1;
+                data[last] ^= 0xff;
+                fs::write(path, data).unwrap();
+            });
+        assert!(result.unwrap_err().starts_with("加密文件校验失败"));
+        assert!(!output.exists());
+    }
+
+    #[test]
+    fn test_encrypt_file_in_place() {
+        let crypto = CryptoCompressor::new();
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("secret.txt");
+        fs::write(&file, b"original content").unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&file, fs::Permissions::from_mode(0o640)).unwrap();
+        }
+        let path = file.to_str().unwrap();
+
+        // 写入一半时失败：原文件不变，临时文件被清理
+        let result = crypto.encrypt_file_in_place_with_writer(path, b"password", |writer, data| {
+            writer.write_all(&data[..data.len() / 2])?;
+            Err(std::io::Error::other("injected failure"))
+        });
+        assert!(result.unwrap_err().contains("injected failure"));
+        assert_eq!(fs::read(&file).unwrap(), b"original content");
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+
+        crypto.encrypt_file_in_place(path, b"password").unwrap();
+        let encrypted = fs::read(&file).unwrap();
+        assert_eq!(
+            crypto.decrypt_data(&encrypted, b"password").unwrap(),
+            b"original content"
+        );
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&file).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o640);
+        }
+    }
+
     #[test]
     fn test_verify_password() {
         let crypto = CryptoCompressor::new();
@@ -273,6 +1346,21 @@ mod tests {
         assert_ne!(password1, password2); // 应该生成不同的密码
     }
 
+    #[test]
+    fn test_generate_random_password_with_seeded_rng() {
+        use rand::SeedableRng;
+        use rand::rngs::StdRng;
+
+        let crypto = CryptoCompressor::new();
+        let password1 = crypto.generate_random_password_with(&mut StdRng::seed_from_u64(42), 24);
+        let password2 = crypto.generate_random_password_with(&mut StdRng::seed_from_u64(42), 24);
+        let password3 = crypto.generate_random_password_with(&mut StdRng::seed_from_u64(7), 24);
+
+        assert_eq!(password1.len(), 24);
+        assert_eq!(password1, password2);
+        assert_ne!(password1, password3);
+    }
+
     #[test]
     fn test_calculate_hash() {
         let crypto = CryptoCompressor::new();
@@ -289,6 +1377,72 @@ mod tests {
         assert_eq!(hash1.len(), 64); // SHA256 哈希长度为64个十六进制字符
     }
 
+    #[test]
+    fn test_tree_signature() {
+        let file = |path: &str, hash: &str, size: i64| FileMetadata {
+            path: path.to_string(),
+            hash: hash.to_string(),
+            size,
+            modified_time: String::new(),
+            permissions: String::new(),
+            weak_checksum: None,
+        };
+        let tree = vec![
+            file("a.txt", "hash_a", 10),
+            file("dir/b.txt", "hash_b", 20),
+            file("dir/c.txt", "hash_c", 30),
+        ];
+        let signature = CryptoCompressor::tree_signature(&tree);
+        assert!(signature.starts_with("v1:3:60:"));
+
+        // 相同的树（顺序和路径分隔符不同）签名相同
+        let reordered = vec![
+            file("dir\\c.txt", "hash_c", 30),
+            file("a.txt", "hash_a", 10),
+            file("dir/b.txt", "hash_b", 20),
+        ];
+        let same = CryptoCompressor::tree_signature(&reordered);
+        assert!(CryptoCompressor::tree_signatures_match(&signature, &same));
+
+        // 任意单个文件的内容、大小或路径变化都会改变签名
+        let mut changes = Vec::new();
+        for index in 0..tree.len() {
+            let mut content = tree.clone();
+            content[index].hash.push('x');
+            let mut size = tree.clone();
+            size[index].size += 1;
+            let mut renamed = tree.clone();
+            renamed[index].path.push('x');
+            changes.extend([content, size, renamed]);
+        }
+        changes.push(tree[..2].to_vec());
+        for changed in changes {
+            let other = CryptoCompressor::tree_signature(&changed);
+            assert!(!CryptoCompressor::tree_signatures_match(&signature, &other));
+        }
+
+        assert!(!CryptoCompressor::tree_signatures_match(
+            "garbage", "garbage"
+        ));
+        assert!(CryptoCompressor::tree_signatures_match(
+            &CryptoCompressor::tree_signature(&[]),
+            &CryptoCompressor::tree_signature(&[])
+        ));
+    }
+
+    #[test]
+    fn test_calculate_weak_checksum() {
+        let crypto = CryptoCompressor::new();
+
+        assert_eq!(crypto.calculate_weak_checksum(b""), 1);
+        assert_eq!(crypto.calculate_weak_checksum(b"Wikipedia"), 0x11E6_0398);
+        let large = vec![0xffu8; 100_000];
+        assert_ne!(
+            crypto.calculate_weak_checksum(&large),
+            crypto.calculate_weak_checksum(&large[1..])
+        );
+    }
+
     #[test]
     fn test_encrypt_with_hash() {
         let crypto = CryptoCompressor::new();
@@ -317,4 +1471,477 @@ mod tests {
         assert!(result.is_err());
         assert!(result.unwrap_err().contains("完整性验证失败"));
     }
+
+    #[test]
+    fn test_deterministic_nonce() {
+        let data = b"Duplicate content for dedup";
+        let password = b"dedup_password";
+
+        let deterministic = CryptoCompressor::new().with_deterministic_nonce(true);
+        let first = deterministic.encrypt_data(data, password).unwrap();
+        let second = deterministic.encrypt_data(data, password).unwrap();
+        assert_eq!(first, second);
+        assert_eq!(
+            deterministic.decrypt_data(&first, password).unwrap(),
+            data.to_vec()
+        );
+
+        let random = CryptoCompressor::new();
+        let first = random.encrypt_data(data, password).unwrap();
+        let second = random.encrypt_data(data, password).unwrap();
+        assert_ne!(first, second);
+    }
+
+    #[test]
+    fn test_hash_file_matches_calculate_hash() {
+        let crypto = CryptoCompressor::new();
+        let content = vec![b'h'; 200 * 1024];
+
+        let mut temp_file = NamedTempFile::new().unwrap();
+        temp_file.write_all(&content).unwrap();
+
+        let hash = crypto
+            .hash_file(temp_file.path().to_str().unwrap())
+            .unwrap();
+        assert_eq!(hash, crypto.calculate_hash(&content));
+    }
+
+    #[test]
+    fn test_hash_buffer_size() {
+        let content: Vec<u8> = (0..300_000u32).map(|i| (i % 253) as u8).collect();
+        let mut temp_file = NamedTempFile::new().unwrap();
+        temp_file.write_all(&content).unwrap();
+        let path = temp_file.path().to_str().unwrap();
+
+        let expected = CryptoCompressor::new().calculate_hash(&content);
+        for size in [1, 4096, 1024 * 1024] {
+            let crypto = CryptoCompressor::new().with_hash_buffer_size(size).unwrap();
+            assert_eq!(crypto.hash_file(path).unwrap(), expected);
+        }
+
+        assert!(CryptoCompressor::new().with_hash_buffer_size(0).is_err());
+    }
+
+    #[test]
+    fn test_verify_directory() {
+        let crypto = CryptoCompressor::new();
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+
+        fs::create_dir(root.join("sub")).unwrap();
+        fs::write(root.join("good.txt"), b"good").unwrap();
+        fs::write(root.join("sub/corrupt.txt"), b"corrupted").unwrap();
+        fs::write(root.join("extra.txt"), b"extra").unwrap();
+
+        let entry = |path: &str, content: &[u8]| FileMetadata {
+            path: path.to_string(),
+            hash: crypto.calculate_hash(content),
+            size: content.len() as i64,
+            modified_time: String::new(),
+            permissions: String::new(),
+            weak_checksum: None,
+        };
+        let manifest = vec![
+            entry("good.txt", b"good"),
+            entry("sub/corrupt.txt", b"original"),
+            entry("missing.txt", b"missing"),
+        ];
+
+        let report = crypto.verify_directory(root.to_str().unwrap(), &manifest);
+
+        assert_eq!(report.matched, vec!["good.txt"]);
+        assert_eq!(report.mismatched, vec!["sub/corrupt.txt"]);
+        assert_eq!(report.missing, vec!["missing.txt"]);
+        assert_eq!(report.extra, vec!["extra.txt"]);
+        assert!(!report.is_intact());
+    }
+
+    #[test]
+    fn test_verify_directory_rejects_unsafe_paths() {
+        let crypto = CryptoCompressor::new();
+        let outer = tempfile::tempdir().unwrap();
+        let root = outer.path().join("root");
+        fs::create_dir(&root).unwrap();
+        fs::write(outer.path().join("secret.txt"), b"secret").unwrap();
+
+        let entry = |path: &str| FileMetadata {
+            path: path.to_string(),
+            hash: crypto.calculate_hash(b"secret"),
+            size: 6,
+            modified_time: String::new(),
+            permissions: String::new(),
+            weak_checksum: None,
+        };
+        let absolute = outer.path().join("secret.txt");
+        let manifest = vec![entry("../secret.txt"), entry(absolute.to_str().unwrap())];
+
+        let report = crypto.verify_directory(root.to_str().unwrap(), &manifest);
+
+        assert!(report.matched.is_empty());
+        assert_eq!(report.mismatched.len(), 2);
+        assert!(!report.is_intact());
+
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(outer.path(), root.join("link")).unwrap();
+            let report = crypto.verify_directory(root.to_str().unwrap(), &[]);
+            assert!(report.extra.is_empty(), "{:?}", report.extra);
+
+            // 经过符号链接的条目不会读取 `root` 之外的文件
+            std::os::unix::fs::symlink(&absolute, root.join("secret_link.txt")).unwrap();
+            let manifest = vec![entry("link/secret.txt"), entry("secret_link.txt")];
+            let report = crypto.verify_directory(root.to_str().unwrap(), &manifest);
+            assert!(report.matched.is_empty());
+            assert!(report.missing.is_empty());
+            assert_eq!(
+                report.mismatched,
+                vec!["link/secret.txt", "secret_link.txt"]
+            );
+        }
+    }
+
+    #[test]
+    fn test_hash_file_detects_concurrent_modification() {
+        let mut temp_file = NamedTempFile::new().unwrap();
+        temp_file.write_all(&vec![b'x'; 256 * 1024]).unwrap();
+        let path = temp_file.path().to_str().unwrap().to_string();
+
+        let bump_mtime = || {
+            let file = fs::OpenOptions::new().write(true).open(&path).unwrap();
+            let later = SystemTime::now() + std::time::Duration::from_secs(3600);
+            file.set_modified(later).unwrap();
+        };
+
+        let crypto = CryptoCompressor::new().with_change_detection(true);
+        let mut bumped = false;
+        let result = crypto.hash_file_with_hook(&path, || {
+            if !bumped {
+                bumped = true;
+                bump_mtime();
+            }
+        });
+        assert_eq!(result.unwrap_err(), "文件在哈希期间被修改");
+
+        // 文件稳定后可以正常哈希
+        assert!(crypto.hash_file(&path).is_ok());
+    }
+
+    #[test]
+    fn test_is_encrypted() {
+        let crypto = CryptoCompressor::new();
+        let plaintext = b"plain data that is long enough to look like anything at all";
+
+        let encrypted = crypto.encrypt_data(plaintext, b"password").unwrap();
+
+        assert!(encrypted.starts_with(MAGIC));
+        assert!(CryptoCompressor::is_encrypted(&encrypted));
+        assert!(!CryptoCompressor::is_encrypted(plaintext));
+    }
+
+    #[test]
+    fn test_double_encryption_guard() {
+        let password = b"password";
+        let encrypted = CryptoCompressor::new()
+            .encrypt_data(b"payload", password)
+            .unwrap();
+
+        let guarded = CryptoCompressor::new().with_double_encryption_guard(true);
+        let result = guarded.encrypt_data(&encrypted, password);
+        assert_eq!(result.unwrap_err(), "数据已加密，拒绝重复加密");
+        assert!(guarded.encrypt_data(b"payload", password).is_ok());
+
+        // 未启用保护时保持原有行为
+        assert!(
+            CryptoCompressor::new()
+                .encrypt_data(&encrypted, password)
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn test_sealed_manifest() {
+        let file = |path: &str, hash: &str| FileMetadata {
+            path: path.to_string(),
+            hash: hash.to_string(),
+            size: 10,
+            modified_time: String::new(),
+            permissions: String::new(),
+            weak_checksum: None,
+        };
+        let result = crate::diff::DiffCalculator::new()
+            .calculate_differences(
+                &[file("docs/a.txt", "h1"), file("docs/b.txt", "h2")],
+                &[file("docs/b.txt", "h0")],
+            )
+            .unwrap();
+
+        let sealed = result.seal(b"manifest key").unwrap();
+        let opened = sealed.open(b"manifest key").unwrap();
+        assert_eq!(opened.differences.len(), 2);
+        assert_eq!(opened.differences[0].path, "docs/a.txt");
+
+        // 篡改清单内容
+        let mut tampered = sealed.clone();
+        tampered.manifest = tampered.manifest.replace("docs/a.txt", "/etc/passwd");
+        assert_eq!(tampered.open(b"manifest key").unwrap_err(), "清单校验失败");
+
+        assert!(sealed.open(b"other key").is_err());
+    }
+
+    #[test]
+    fn test_encrypt_decrypt_empty_data() {
+        for crypto in [
+            CryptoCompressor::new(),
+            CryptoCompressor::new().with_deterministic_nonce(true),
+        ] {
+            let encrypted = crypto.encrypt_data(b"", b"password").unwrap();
+            assert_eq!(encrypted.len(), HEADER_LEN + NONCE_LEN + TAG_LEN);
+            assert!(CryptoCompressor::is_encrypted(&encrypted));
+
+            let decrypted = crypto.decrypt_data(&encrypted, b"password").unwrap();
+            assert!(decrypted.is_empty());
+            assert!(crypto.decrypt_data(&encrypted, b"wrong").is_err());
+        }
+
+        let mut stream = Vec::new();
+        let crypto = CryptoCompressor::new();
+        crypto
+            .encrypt_stream(&b""[..], &mut stream, b"password", 1024)
+            .unwrap();
+        assert!(
+            crypto
+                .decrypt_data(&stream, b"password")
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_decrypt_nonce_only_blob() {
+        let crypto = CryptoCompressor::new();
+        let encrypted = crypto.encrypt_data(b"", b"password").unwrap();
+
+        // 去掉认证标签，只剩头部和 nonce
+        let nonce_only = &encrypted[..HEADER_LEN + NONCE_LEN];
+        assert_eq!(
+            crypto.decrypt_data(nonce_only, b"password").unwrap_err(),
+            "密文缺少认证标签"
+        );
+        // 早期无头部格式同样处理
+        let legacy_nonce_only = &encrypted[HEADER_LEN..HEADER_LEN + NONCE_LEN];
+        assert_eq!(
+            crypto
+                .decrypt_data(legacy_nonce_only, b"password")
+                .unwrap_err(),
+            "密文缺少认证标签"
+        );
+        // nonce 不完整时仍为数据太短
+        assert_eq!(
+            crypto
+                .decrypt_data(&encrypted[..HEADER_LEN + NONCE_LEN - 1], b"password")
+                .unwrap_err(),
+            "加密数据太短"
+        );
+    }
+
+    #[test]
+    fn test_decrypt_partial_tag_blob() {
+        let crypto = CryptoCompressor::new();
+        let encrypted = crypto.encrypt_data(b"", b"password").unwrap();
+
+        // 认证标签只剩一半
+        let partial_tag = &encrypted[..HEADER_LEN + NONCE_LEN + TAG_LEN / 2];
+        assert_eq!(
+            crypto.decrypt_data(partial_tag, b"password").unwrap_err(),
+            "密文缺少认证标签"
+        );
+        assert!(!crypto.verify_password(partial_tag, b"password"));
+
+        let with_info = crypto
+            .encrypt_data_with_info(
+                b"",
+                b"password",
+                &FileInfo {
+                    name: "empty.txt".to_string(),
+                    size: 0,
+                },
+            )
+            .unwrap();
+        assert_eq!(
+            crypto
+                .decrypt_data(&with_info[..with_info.len() - 1], b"password")
+                .unwrap_err(),
+            "密文缺少认证标签"
+        );
+    }
+
+    #[test]
+    fn test_kdf_params_in_header() {
+        let default = CryptoCompressor::new();
+        assert_eq!(default.kdf_params(), KdfParams::default());
+        let blob = default.encrypt_data(b"data", b"password").unwrap();
+        assert_eq!(
+            CryptoCompressor::inspect_header(&blob).unwrap(),
+            KdfParams::default()
+        );
+
+        let tuned = CryptoCompressor::new().with_kdf_cost(10).unwrap();
+        let params = KdfParams {
+            algorithm: KdfAlgorithm::Pbkdf2Sha256,
+            iterations: 1024,
+        };
+        assert_eq!(tuned.kdf_params(), params);
+        // PBKDF2-HMAC-SHA256 已知答案（Python hashlib.pbkdf2_hmac 计算）
+        let salt: Vec<u8> = (0..16).collect();
+        assert_eq!(
+            hex::encode(params.derive_key(b"password", &salt)),
+            "ee824d980fbcabb70feb163b66031c7674d601472012ea4ae7d25a6371af8277"
+        );
+
+        // 每个加密数据使用不同的随机盐
+        let blob = tuned.encrypt_data(b"data", b"password").unwrap();
+        let again = tuned.encrypt_data(b"data", b"password").unwrap();
+        assert_eq!(CryptoCompressor::inspect_header(&blob).unwrap(), params);
+        assert_ne!(
+            blob[HEADER_LEN..HEADER_LEN + KDF_SALT_LEN],
+            again[HEADER_LEN..HEADER_LEN + KDF_SALT_LEN]
+        );
+        let mut stream = Vec::new();
+        tuned
+            .encrypt_stream(&b"stream data"[..], &mut stream, b"password", 1024)
+            .unwrap();
+        assert_eq!(CryptoCompressor::inspect_header(&stream).unwrap(), params);
+        let info = FileInfo {
+            name: "data.txt".to_string(),
+            size: 4,
+        };
+        let with_info = tuned
+            .encrypt_data_with_info(b"data", b"password", &info)
+            .unwrap();
+
+        // 解密按头部参数派生密钥，与解密方的配置无关
+        assert_eq!(default.decrypt_data(&blob, b"password").unwrap(), b"data");
+        assert_eq!(
+            default.decrypt_data(&stream, b"password").unwrap(),
+            b"stream data"
+        );
+        assert_eq!(
+            default.decrypt_data(&with_info, b"password").unwrap(),
+            b"data"
+        );
+        assert_eq!(default.read_info(&with_info, b"password").unwrap(), info);
+        assert!(default.decrypt_data(&blob, b"wrong").is_err());
+
+        // 盐参与认证
+        let mut tampered = blob.clone();
+        tampered[HEADER_LEN] ^= 1;
+        assert!(default.decrypt_data(&tampered, b"password").is_err());
+
+        // 迭代次数超过解密方上限的数据在派生密钥前被拒绝
+        let strict = CryptoCompressor::new().with_max_kdf_cost(8);
+        for encrypted in [&blob, &stream, &with_info] {
+            let err = strict.decrypt_data(encrypted, b"password").unwrap_err();
+            assert!(err.contains("密钥派生成本超出上限"), "{}", err);
+        }
+        let mut costly = blob.clone();
+        costly[6] = KDF_PBKDF2_SHA256_BASE + MAX_KDF_COST;
+        let err = default.decrypt_data(&costly, b"password").unwrap_err();
+        assert!(err.contains("密钥派生成本超出上限"), "{}", err);
+
+        assert!(CryptoCompressor::new().with_kdf_cost(25).is_err());
+        let mut unknown = blob.clone();
+        unknown[6] = KDF_PBKDF2_SHA256_BASE + MAX_KDF_COST + 1;
+        assert!(CryptoCompressor::inspect_header(&unknown).is_err());
+        assert!(CryptoCompressor::inspect_header(b"not encrypted").is_err());
+    }
+
+    #[test]
+    fn test_describe_blob() {
+        let crypto = CryptoCompressor::new().with_kdf_cost(10).unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data.bin");
+        fs::write(&path, vec![7u8; 3000]).unwrap();
+
+        let stream = crypto
+            .encrypt_file_stream(path.to_str().unwrap(), b"password", 1024)
+            .unwrap();
+        let info = CryptoCompressor::describe_blob(&stream).unwrap();
+        assert_eq!(
+            info,
+            BlobInfo {
+                authenticated: false,
+                magic: "SCLI".to_string(),
+                version: FORMAT_VERSION,
+                cipher_id: CIPHER_AES_256_GCM_STREAM,
+                cipher: Some("aes-256-gcm-stream".to_string()),
+                kdf_id: KDF_PBKDF2_SHA256_BASE + 10,
+                kdf: Some(KdfParams {
+                    algorithm: KdfAlgorithm::Pbkdf2Sha256,
+                    iterations: 1024,
+                }),
+                salt_len: KDF_SALT_LEN,
+                nonce_len: 7,
+                chunk_size: Some(1024),
+                chunk_count: Some(3),
+                range_offset: None,
+                truncated: false,
+            }
+        );
+
+        let truncated = CryptoCompressor::describe_blob(&stream[..stream.len() - 10]).unwrap();
+        assert_eq!(truncated.chunk_count, Some(2));
+        assert!(truncated.truncated);
+
+        let blob = CryptoCompressor::new()
+            .encrypt_data(b"data", b"password")
+            .unwrap();
+        let info = CryptoCompressor::describe_blob(&blob).unwrap();
+        assert_eq!(info.cipher.as_deref(), Some("aes-256-gcm"));
+        assert_eq!(info.kdf, Some(KdfParams::default()));
+        assert_eq!(info.salt_len, 0);
+        assert_eq!(info.nonce_len, NONCE_LEN);
+        assert_eq!(info.chunk_count, None);
+        assert!(!info.authenticated && !info.truncated);
+
+        assert_eq!(
+            CryptoCompressor::describe_blob(b"PK\x03\x04 not synccli").unwrap_err(),
+            "不是有效的加密数据"
+        );
+    }
+
+    #[test]
+    fn test_decrypt_legacy_format() {
+        let crypto = CryptoCompressor::new();
+        let password = b"legacy_password";
+        let (key_bytes, _) = crypto.derive_encryption_key(password);
+        let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key_bytes));
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+
+        let mut legacy = nonce.to_vec();
+        legacy.extend(cipher.encrypt(&nonce, b"old blob".as_ref()).unwrap());
+
+        assert_eq!(crypto.decrypt_data(&legacy, password).unwrap(), b"old blob");
+    }
+
+    #[test]
+    fn test_hash_file_parallel_is_deterministic() {
+        let crypto = CryptoCompressor::new();
+        let content: Vec<u8> = (0..10_000u32).flat_map(|i| i.to_le_bytes()).collect();
+
+        let mut first = NamedTempFile::new().unwrap();
+        first.write_all(&content).unwrap();
+        let mut second = NamedTempFile::new().unwrap();
+        second.write_all(&content).unwrap();
+        let first_path = first.path().to_str().unwrap();
+        let second_path = second.path().to_str().unwrap();
+
+        let hash = crypto.hash_file_chunked(first_path, 4096).unwrap();
+        assert_eq!(hash, crypto.hash_file_chunked(first_path, 4096).unwrap());
+        assert_eq!(hash, crypto.hash_file_chunked(second_path, 4096).unwrap());
+        assert!(hash.starts_with("sha256-tree:"));
+        assert_ne!(hash, crypto.hash_file(first_path).unwrap());
+
+        let whole = crypto.hash_file_parallel(first_path).unwrap();
+        assert_eq!(whole, crypto.hash_file_parallel(second_path).unwrap());
+    }
 }
@@ -0,0 +1,216 @@
+//! 带文件信息的加密格式
+//!
+//! 布局（整数均为小端）：
+//!
+//! ```text
+//! 头部：魔数 "SCLI" | 版本 u8 | 密码算法 u8 (=5) | 密钥派生算法 u8 | 信息块长度 u32
+//! 盐：仅 PBKDF2 密钥派生，16 字节
+//! 信息块：nonce 12 字节 | 密文（JSON 格式的 FileInfo，含 16 字节认证标签）
+//! 数据：nonce 12 字节 | 密文（含 16 字节认证标签）
+//! ```
+//!
+//! 信息块以头部和盐为附加认证数据，数据以头部、盐和整个信息块为附加认证数据，
+//! 因此信息块不能被替换到其他加密数据上。`read_info` 只解密信息块，
+//! 恢复工具无需解密整个文件即可得到原始文件名。
+
+use super::{
+    CIPHER_AES_256_GCM_WITH_INFO, CryptoCompressor, FORMAT_VERSION, HEADER_LEN, KdfParams, MAGIC,
+    NONCE_LEN, TAG_LEN, check_sealed_len, split_salt,
+};
+use aes_gcm::{
+    Aes256Gcm, Key, KeyInit, Nonce,
+    aead::{Aead, AeadCore, OsRng, Payload},
+};
+use serde::{Deserialize, Serialize};
+
+/// 带信息块格式的头部长度：基础头部 + 信息块长度(4)
+const INFO_HEADER_LEN: usize = HEADER_LEN + 4;
+
+/// 随加密数据一起保存的原始文件信息
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileInfo {
+    /// 原始文件名
+    pub name: String,
+    /// 原始文件大小
+    pub size: u64,
+}
+
+impl CryptoCompressor {
+    /// 加密数据，并附带一个用同一密钥加密的文件信息块
+    ///
+    /// 输出可用 `decrypt_data` 解密出数据，或用 `read_info` 只读取文件信息。
+    pub fn encrypt_data_with_info(
+        &self,
+        data: &[u8],
+        password: &[u8],
+        info: &FileInfo,
+    ) -> Result<Vec<u8>, String> {
+        if self.reject_encrypted_input && Self::is_encrypted(data) {
+            return Err("数据已加密，拒绝重复加密".to_string());
+        }
+
+        let info_json =
+            serde_json::to_vec(info).map_err(|e| format!("序列化文件信息失败: {}", e))?;
+        let info_len =
+            u32::try_from(info_json.len() + TAG_LEN).map_err(|_| "文件信息过长".to_string())?;
+
+        let (key_bytes, salt) = self.derive_encryption_key(password);
+        let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key_bytes));
+
+        let mut output = Vec::with_capacity(
+            INFO_HEADER_LEN + salt.len() + 2 * NONCE_LEN + info_len as usize + data.len() + TAG_LEN,
+        );
+        output.extend_from_slice(MAGIC);
+        output.extend_from_slice(&[
+            FORMAT_VERSION,
+            CIPHER_AES_256_GCM_WITH_INFO,
+            self.kdf.header_byte(),
+        ]);
+        output.extend_from_slice(&info_len.to_le_bytes());
+        output.extend_from_slice(&salt);
+
+        // 信息块以头部和盐为附加认证数据
+        let info_nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let info_ciphertext = cipher
+            .encrypt(
+                &info_nonce,
+                Payload {
+                    msg: &info_json,
+                    aad: &output,
+                },
+            )
+            .map_err(|e| format!("加密失败: {}", e))?;
+        output.extend_from_slice(&info_nonce);
+        output.extend_from_slice(&info_ciphertext);
+
+        // 数据以头部、盐和整个信息块为附加认证数据
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = cipher
+            .encrypt(
+                &nonce,
+                Payload {
+                    msg: data,
+                    aad: &output,
+                },
+            )
+            .map_err(|e| format!("加密失败: {}", e))?;
+        output.extend_from_slice(&nonce);
+        output.extend_from_slice(&ciphertext);
+        Ok(output)
+    }
+
+    /// 只解密 `encrypt_data_with_info` 输出中的文件信息块
+    pub fn read_info(&self, blob: &[u8], password: &[u8]) -> Result<FileInfo, String> {
+        let parts = split_info_blob(blob)?;
+        let key_bytes = self.derive_decryption_key(parts.kdf, password, parts.salt)?;
+        let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key_bytes));
+
+        let info_json = decrypt_part(&cipher, &blob[..parts.header_len], parts.info_block)?;
+        serde_json::from_slice(&info_json).map_err(|e| format!("解析文件信息失败: {}", e))
+    }
+
+    /// 解密带信息块的加密数据，返回数据明文
+    pub(super) fn decrypt_with_info(
+        &self,
+        blob: &[u8],
+        password: &[u8],
+    ) -> Result<Vec<u8>, String> {
+        let parts = split_info_blob(blob)?;
+        let key_bytes = self.derive_decryption_key(parts.kdf, password, parts.salt)?;
+        let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key_bytes));
+
+        let aad_len = parts.header_len + parts.info_block.len();
+        decrypt_part(&cipher, &blob[..aad_len], parts.data_block)
+    }
+}
+
+/// 带信息块的加密数据拆分后的各部分
+struct InfoBlobParts<'a> {
+    kdf: KdfParams,
+    salt: &'a [u8],
+    /// 头部和盐的总长度
+    header_len: usize,
+    /// 信息块（nonce + 密文）
+    info_block: &'a [u8],
+    /// 数据部分（nonce + 密文）
+    data_block: &'a [u8],
+}
+
+/// 校验头部并拆分出盐、信息块和数据部分
+fn split_info_blob(blob: &[u8]) -> Result<InfoBlobParts<'_>, String> {
+    if blob.len() < INFO_HEADER_LEN || !blob.starts_with(MAGIC) || blob[4] != FORMAT_VERSION {
+        return Err("不是有效的加密数据".to_string());
+    }
+    if blob[5] != CIPHER_AES_256_GCM_WITH_INFO {
+        return Err("加密数据不包含文件信息".to_string());
+    }
+    let kdf = KdfParams::from_header_byte(blob[6])?;
+    let (salt, rest) = split_salt(kdf, &blob[INFO_HEADER_LEN..])?;
+
+    let info_len = u32::from_le_bytes(blob[HEADER_LEN..INFO_HEADER_LEN].try_into().unwrap());
+    let info_end = NONCE_LEN
+        .checked_add(info_len as usize)
+        .filter(|&end| info_len as usize >= TAG_LEN && end <= rest.len())
+        .ok_or("加密数据太短")?;
+    let (info_block, data_block) = rest.split_at(info_end);
+    check_sealed_len(data_block)?;
+    Ok(InfoBlobParts {
+        kdf,
+        salt,
+        header_len: INFO_HEADER_LEN + salt.len(),
+        info_block,
+        data_block,
+    })
+}
+
+/// 解密 `nonce + 密文` 形式的一段数据
+fn decrypt_part(cipher: &Aes256Gcm, aad: &[u8], part: &[u8]) -> Result<Vec<u8>, String> {
+    let (nonce, ciphertext) = part.split_at(NONCE_LEN);
+    cipher
+        .decrypt(
+            Nonce::from_slice(nonce),
+            Payload {
+                msg: ciphertext,
+                aad,
+            },
+        )
+        .map_err(|e| format!("解密失败: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_info_block_round_trip() {
+        let crypto = CryptoCompressor::new();
+        let data = b"file body with its name kept inside";
+        let info = FileInfo {
+            name: "报告 2024.pdf".to_string(),
+            size: data.len() as u64,
+        };
+
+        let blob = crypto
+            .encrypt_data_with_info(data, b"password", &info)
+            .unwrap();
+        assert!(CryptoCompressor::is_encrypted(&blob));
+        assert_eq!(crypto.read_info(&blob, b"password").unwrap(), info);
+        assert_eq!(crypto.decrypt_data(&blob, b"password").unwrap(), data);
+
+        // 错误的密码无法读取信息块
+        let err = crypto.read_info(&blob, b"wrong").unwrap_err();
+        assert!(err.starts_with("解密失败"), "{}", err);
+        assert!(crypto.decrypt_data(&blob, b"wrong").is_err());
+
+        // 篡改信息块后数据也无法解密
+        let mut tampered = blob.clone();
+        tampered[INFO_HEADER_LEN + NONCE_LEN] ^= 1;
+        assert!(crypto.read_info(&tampered, b"password").is_err());
+        assert!(crypto.decrypt_data(&tampered, b"password").is_err());
+
+        // 普通加密数据不包含信息块
+        let plain = crypto.encrypt_data(data, b"password").unwrap();
+        let err = crypto.read_info(&plain, b"password").unwrap_err();
+        assert!(err.contains("不包含文件信息"), "{}", err);
+    }
+}
@@ -0,0 +1,1203 @@
+//! 分块（流式）加密格式
+//!
+//! 布局（整数均为小端）：
+//!
+//! ```text
+//! 头部：魔数 "SCLI" | 版本 u8 | 密码算法 u8 (=2) | 密钥派生算法 u8 | 分块大小 u32 | nonce 前缀 7 字节
+//! 盐：仅 PBKDF2 密钥派生，16 字节
+//! 数据帧：密文长度 u32 | 密文（含 16 字节认证标签）
+//! ```
+//!
+//! 范围加密（`encrypt_range`）使用密码算法标识 3，并在头部末尾追加该范围在原文件中的
+//! 起始偏移 u64（在盐之前），使每个范围都可独立解密并按偏移拼接回文件。
+//!
+//! 分块压缩加密（`encrypt_stream_compressed`）使用密码算法标识 4，每块明文先独立进行
+//! zlib 压缩再加密，帧长度即压缩后长度加认证标签，因此可以不解密前面的帧而直接
+//! 定位并解密、解压任意一块（`decrypt_chunk`）。
+//!
+//! 每帧的 nonce 为 `nonce 前缀 | 帧序号 u32 (大端) | 末帧标记 u8`，整个头部（含盐）作为每帧的
+//! 附加认证数据，因此帧的重排、截断或头部篡改都会导致解密失败。
+
+use super::{
+    BlobInfo, CIPHER_AES_256_GCM_COMPRESSED_STREAM, CIPHER_AES_256_GCM_RANGE,
+    CIPHER_AES_256_GCM_STREAM, CryptoCompressor, FORMAT_VERSION, HEADER_LEN, KdfParams, MAGIC,
+    TAG_LEN, split_salt,
+};
+use crate::compression::Compressor;
+use crate::progress::{Progress, ProgressReader, ProgressSink};
+use aes_gcm::{
+    Aes256Gcm, Key, KeyInit, Nonce,
+    aead::{Aead, OsRng, Payload, rand_core::RngCore},
+};
+use flate2::read::ZlibDecoder;
+use rayon::prelude::*;
+use std::fs;
+use std::io::{Read, Seek, SeekFrom, Write};
+
+/// nonce 前缀长度
+const NONCE_PREFIX_LEN: usize = 7;
+/// 分块格式头部长度：基础头部 + 分块大小(4) + nonce 前缀(7)
+pub(super) const STREAM_HEADER_LEN: usize = HEADER_LEN + 4 + NONCE_PREFIX_LEN;
+/// 范围加密格式头部长度：分块格式头部 + 起始偏移(8)
+const RANGE_HEADER_LEN: usize = STREAM_HEADER_LEN + 8;
+/// 每帧的长度字段大小
+pub(super) const FRAME_LEN_SIZE: usize = 4;
+/// 分块大小上限，解密时头部中的分块大小决定单帧缓冲区大小，不可信的头部不能超过该值
+const MAX_CHUNK_SIZE: usize = 64 * 1024 * 1024;
+/// 自动选择分块大小时的下限
+const MIN_AUTO_CHUNK_SIZE: usize = 64 * 1024;
+/// 自动选择分块大小时的上限
+const MAX_AUTO_CHUNK_SIZE: usize = 8 * 1024 * 1024;
+/// 自动选择分块大小时的目标分块数量
+const TARGET_CHUNK_COUNT: u64 = 64;
+
+/// 分块解密时对数据帧的限制
+#[derive(Debug, Clone, Copy)]
+pub(super) struct FrameLimits {
+    /// 除末帧外每帧明文的最小长度
+    pub(super) min_payload: usize,
+    /// 最多允许的帧数
+    pub(super) max_frames: u64,
+}
+
+impl Default for FrameLimits {
+    fn default() -> Self {
+        Self {
+            min_payload: 512,
+            max_frames: 1 << 24,
+        }
+    }
+}
+
+impl CryptoCompressor {
+    /// 分块加密：从 `reader` 读取明文，按 `chunk_size` 分帧写入 `writer`
+    ///
+    /// 内存占用与分块大小相关而与数据总量无关，返回写入的字节数。
+    /// `chunk_size` 须在最小帧长度（见 `with_frame_limits`，默认 512 字节）到 64MB 之间。
+    pub fn encrypt_stream<R: Read, W: Write>(
+        &self,
+        reader: R,
+        writer: W,
+        password: &[u8],
+        chunk_size: usize,
+    ) -> Result<u64, String> {
+        let mut nonce_prefix = [0u8; NONCE_PREFIX_LEN];
+        OsRng.fill_bytes(&mut nonce_prefix);
+        self.encrypt_stream_with_prefix(reader, writer, password, chunk_size, &nonce_prefix)
+    }
+
+    /// 分块加密并报告进度，进度单位为读取的明文字节数
+    ///
+    /// 读取器的总长度未知，`on_start` 收到的总量为 0；已知文件时使用
+    /// `encrypt_file_stream_with_progress`。
+    pub fn encrypt_stream_with_progress<R: Read, W: Write>(
+        &self,
+        reader: R,
+        writer: W,
+        password: &[u8],
+        chunk_size: usize,
+        progress: Option<&mut dyn ProgressSink>,
+    ) -> Result<u64, String> {
+        let progress = Progress::new(progress);
+        self.encrypt_stream_reporting(reader, writer, password, chunk_size, 0, &progress)
+    }
+
+    /// 分块加密，读取明文时向 `progress` 报告字节数
+    fn encrypt_stream_reporting<R: Read, W: Write>(
+        &self,
+        reader: R,
+        writer: W,
+        password: &[u8],
+        chunk_size: usize,
+        total: u64,
+        progress: &Progress,
+    ) -> Result<u64, String> {
+        progress.start(total);
+        let result = self.encrypt_stream(
+            ProgressReader::new(reader, progress),
+            writer,
+            password,
+            chunk_size,
+        );
+        progress.done();
+        result
+    }
+
+    /// 使用给定的 nonce 前缀进行分块加密
+    fn encrypt_stream_with_prefix<R: Read, W: Write>(
+        &self,
+        reader: R,
+        mut writer: W,
+        password: &[u8],
+        chunk_size: usize,
+        nonce_prefix: &[u8; NONCE_PREFIX_LEN],
+    ) -> Result<u64, String> {
+        self.check_encrypt_chunk_size(chunk_size)?;
+
+        let (key_bytes, salt) = self.derive_encryption_key(password);
+        let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key_bytes));
+
+        let mut header = stream_header(
+            CIPHER_AES_256_GCM_STREAM,
+            self.kdf,
+            chunk_size as u32,
+            nonce_prefix,
+        )
+        .to_vec();
+        header.extend_from_slice(&salt);
+        writer
+            .write_all(&header)
+            .map_err(|e| format!("写入加密数据失败: {}", e))?;
+        let frames_len = encrypt_frames(
+            &cipher,
+            nonce_prefix,
+            &header,
+            reader,
+            writer,
+            chunk_size,
+            false,
+        )?;
+        Ok(header.len() as u64 + frames_len)
+    }
+
+    /// 分块压缩加密：每块明文先独立压缩再加密，可用 `decrypt_chunk` 随机访问单个分块
+    ///
+    /// 与先整体压缩再加密相比压缩率略低，但任意分块都能单独解密和解压。
+    /// 输出也可用 `decrypt_stream` 或 `decrypt_data` 整体解密，返回写入的字节数。
+    pub fn encrypt_stream_compressed<R: Read, W: Write>(
+        &self,
+        reader: R,
+        mut writer: W,
+        password: &[u8],
+        chunk_size: usize,
+    ) -> Result<u64, String> {
+        self.check_encrypt_chunk_size(chunk_size)?;
+
+        let (key_bytes, salt) = self.derive_encryption_key(password);
+        let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key_bytes));
+
+        let mut nonce_prefix = [0u8; NONCE_PREFIX_LEN];
+        OsRng.fill_bytes(&mut nonce_prefix);
+        let mut header = stream_header(
+            CIPHER_AES_256_GCM_COMPRESSED_STREAM,
+            self.kdf,
+            chunk_size as u32,
+            &nonce_prefix,
+        )
+        .to_vec();
+        header.extend_from_slice(&salt);
+        writer
+            .write_all(&header)
+            .map_err(|e| format!("写入加密数据失败: {}", e))?;
+        let frames_len = encrypt_frames(
+            &cipher,
+            &nonce_prefix,
+            &header,
+            reader,
+            writer,
+            chunk_size,
+            true,
+        )?;
+        Ok(header.len() as u64 + frames_len)
+    }
+
+    /// 从 `encrypt_stream_compressed` 的输出中只解密并解压第 `index` 块（从 0 开始）
+    ///
+    /// 只读取各帧的长度字段跳过前面的帧，不解密其他分块。
+    pub fn decrypt_chunk(
+        &self,
+        blob: &[u8],
+        password: &[u8],
+        index: usize,
+    ) -> Result<Vec<u8>, String> {
+        if blob.len() < STREAM_HEADER_LEN {
+            return Err("加密数据头部不完整".to_string());
+        }
+        let (chunk_size, nonce_prefix, kdf) = parse_stream_header(
+            &blob[..STREAM_HEADER_LEN],
+            CIPHER_AES_256_GCM_COMPRESSED_STREAM,
+        )?;
+        let (salt, mut frames) = split_salt(kdf, &blob[STREAM_HEADER_LEN..])?;
+        let header = &blob[..STREAM_HEADER_LEN + salt.len()];
+        let max_payload = frame_payload_limit(chunk_size, true);
+
+        // 定位目标帧，并继续扫描到末尾以确定其是否为末帧
+        let mut target = None;
+        let mut count = 0usize;
+        while let Some(frame) = read_frame(&mut frames, max_payload)? {
+            if count as u64 >= self.frame_limits.max_frames {
+                return Err(format!(
+                    "数据帧数量超出上限: {}",
+                    self.frame_limits.max_frames
+                ));
+            }
+            if count == index {
+                target = Some(frame);
+            }
+            count += 1;
+        }
+        let frame = target.ok_or_else(|| format!("分块索引越界: {} (共 {} 块)", index, count))?;
+        let counter = u32::try_from(index).map_err(|_| "分块数量超出上限".to_string())?;
+
+        let key_bytes = self.derive_decryption_key(kdf, password, salt)?;
+        let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key_bytes));
+        let nonce = frame_nonce(&nonce_prefix, counter, index + 1 == count);
+        let compressed = cipher
+            .decrypt(
+                Nonce::from_slice(&nonce),
+                Payload {
+                    msg: &frame,
+                    aad: header,
+                },
+            )
+            .map_err(|e| format!("解密失败: {}", e))?;
+        decompress_frame(&compressed, chunk_size)
+    }
+
+    /// 分块解密：从 `reader` 读取分块格式的密文，将明文写入 `writer`
+    ///
+    /// 末帧缺失（数据被截断）或存在多余数据时返回错误，返回写入的明文字节数。
+    pub fn decrypt_stream<R: Read, W: Write>(
+        &self,
+        mut reader: R,
+        writer: W,
+        password: &[u8],
+    ) -> Result<u64, String> {
+        let mut header = [0u8; STREAM_HEADER_LEN];
+        reader
+            .read_exact(&mut header)
+            .map_err(|_| "加密数据头部不完整".to_string())?;
+        let compressed = header[5] == CIPHER_AES_256_GCM_COMPRESSED_STREAM;
+        let expected_cipher = if compressed {
+            CIPHER_AES_256_GCM_COMPRESSED_STREAM
+        } else {
+            CIPHER_AES_256_GCM_STREAM
+        };
+        let (chunk_size, nonce_prefix, kdf) = parse_stream_header(&header, expected_cipher)?;
+        let mut header = header.to_vec();
+        header.resize(STREAM_HEADER_LEN + kdf.salt_len(), 0);
+        reader
+            .read_exact(&mut header[STREAM_HEADER_LEN..])
+            .map_err(|_| "加密数据头部不完整".to_string())?;
+
+        let key_bytes = self.derive_decryption_key(kdf, password, &header[STREAM_HEADER_LEN..])?;
+        let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key_bytes));
+
+        decrypt_frames(
+            &cipher,
+            &nonce_prefix,
+            &header,
+            reader,
+            writer,
+            chunk_size,
+            compressed,
+            self.frame_limits,
+        )
+    }
+
+    /// 加密文件流（用于大文件）
+    ///
+    /// 按 `chunk_size` 分块读取并加密，输出分块格式，可用 `decrypt_data`
+    /// 或 `decrypt_stream` 解密。`chunk_size` 为 0 时根据文件大小自动选择，
+    /// 见 [`CryptoCompressor::adaptive_chunk_size`]。
+    pub fn encrypt_file_stream(
+        &self,
+        file_path: &str,
+        password: &[u8],
+        chunk_size: usize,
+    ) -> Result<Vec<u8>, String> {
+        self.encrypt_file_stream_with_progress(file_path, password, chunk_size, None)
+    }
+
+    /// 加密文件流并报告进度，进度单位为明文字节数，总量为文件大小
+    pub fn encrypt_file_stream_with_progress(
+        &self,
+        file_path: &str,
+        password: &[u8],
+        chunk_size: usize,
+        progress: Option<&mut dyn ProgressSink>,
+    ) -> Result<Vec<u8>, String> {
+        let file =
+            fs::File::open(file_path).map_err(|e| format!("打开文件失败 {}: {}", file_path, e))?;
+        let file_size = file
+            .metadata()
+            .map_err(|e| format!("读取文件元数据失败 {}: {}", file_path, e))?
+            .len();
+        let chunk_size = if chunk_size == 0 {
+            Self::adaptive_chunk_size(file_size)
+        } else {
+            chunk_size
+        };
+
+        let progress = Progress::new(progress);
+        let mut output = Vec::new();
+        self.encrypt_stream_reporting(
+            file,
+            &mut output,
+            password,
+            chunk_size,
+            file_size,
+            &progress,
+        )?;
+        Ok(output)
+    }
+
+    /// 并行加密文件：读入整个文件后各分块由 rayon 并行加密，再按顺序拼接数据帧
+    ///
+    /// 每帧的 nonce 只由前缀和帧序号决定，因此输出格式与 `encrypt_file_stream` 完全相同，
+    /// nonce 前缀相同时逐字节一致。`chunk_size` 为 0 时根据文件大小自动选择。
+    pub fn encrypt_file_parallel(
+        &self,
+        input_path: &str,
+        password: &[u8],
+        chunk_size: usize,
+    ) -> Result<Vec<u8>, String> {
+        let mut nonce_prefix = [0u8; NONCE_PREFIX_LEN];
+        OsRng.fill_bytes(&mut nonce_prefix);
+        self.encrypt_file_parallel_with_prefix(input_path, password, chunk_size, &nonce_prefix)
+    }
+
+    /// 使用给定的 nonce 前缀并行加密文件
+    fn encrypt_file_parallel_with_prefix(
+        &self,
+        input_path: &str,
+        password: &[u8],
+        chunk_size: usize,
+        nonce_prefix: &[u8; NONCE_PREFIX_LEN],
+    ) -> Result<Vec<u8>, String> {
+        let data = crate::error::read_file(input_path)?;
+        let chunk_size = if chunk_size == 0 {
+            Self::adaptive_chunk_size(data.len() as u64)
+        } else {
+            chunk_size
+        };
+        self.check_encrypt_chunk_size(chunk_size)?;
+
+        // 空文件也写出一个空的末帧，与顺序加密一致
+        let chunks: Vec<&[u8]> = if data.is_empty() {
+            vec![&[]]
+        } else {
+            data.chunks(chunk_size).collect()
+        };
+        if chunks.len() - 1 > u32::MAX as usize {
+            return Err("数据过大，超出分块数量上限".to_string());
+        }
+
+        let (key_bytes, salt) = self.derive_encryption_key(password);
+        let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key_bytes));
+        let mut header = stream_header(
+            CIPHER_AES_256_GCM_STREAM,
+            self.kdf,
+            chunk_size as u32,
+            nonce_prefix,
+        )
+        .to_vec();
+        header.extend_from_slice(&salt);
+
+        let last_index = chunks.len() - 1;
+        let frames = chunks
+            .par_iter()
+            .enumerate()
+            .map(|(index, chunk)| {
+                encrypt_frame(
+                    &cipher,
+                    nonce_prefix,
+                    &header,
+                    index as u32,
+                    index == last_index,
+                    chunk,
+                )
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+
+        let frames_len: usize = frames
+            .iter()
+            .map(|frame| FRAME_LEN_SIZE + frame.len())
+            .sum();
+        let mut output = Vec::with_capacity(header.len() + frames_len);
+        output.extend_from_slice(&header);
+        for frame in frames {
+            output.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+            output.extend_from_slice(&frame);
+        }
+        Ok(output)
+    }
+
+    /// 只加密文件中 `[start, start + len)` 范围内的数据
+    ///
+    /// 输出为独立的范围加密数据，头部记录该范围的起始偏移，用 `decrypt_range`
+    /// 解密后可按偏移拼接回原文件，适合只重新加密大文件中被修改的区域。
+    pub fn encrypt_range(
+        &self,
+        input_path: &str,
+        password: &[u8],
+        start: u64,
+        len: u64,
+    ) -> Result<Vec<u8>, String> {
+        let mut file = fs::File::open(input_path)
+            .map_err(|e| format!("打开文件失败 {}: {}", input_path, e))?;
+        let file_size = file
+            .metadata()
+            .map_err(|e| format!("读取文件元数据失败 {}: {}", input_path, e))?
+            .len();
+        if start.checked_add(len).is_none_or(|end| end > file_size) {
+            return Err(format!(
+                "加密范围超出文件大小: {}+{} > {}",
+                start, len, file_size
+            ));
+        }
+        file.seek(SeekFrom::Start(start))
+            .map_err(|e| format!("定位文件失败 {}: {}", input_path, e))?;
+
+        let chunk_size = Self::adaptive_chunk_size(len);
+        self.check_encrypt_chunk_size(chunk_size)?;
+        let (key_bytes, salt) = self.derive_encryption_key(password);
+        let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key_bytes));
+
+        let mut nonce_prefix = [0u8; NONCE_PREFIX_LEN];
+        OsRng.fill_bytes(&mut nonce_prefix);
+        let mut header = vec![0u8; RANGE_HEADER_LEN];
+        header[..STREAM_HEADER_LEN].copy_from_slice(&stream_header(
+            CIPHER_AES_256_GCM_RANGE,
+            self.kdf,
+            chunk_size as u32,
+            &nonce_prefix,
+        ));
+        header[STREAM_HEADER_LEN..].copy_from_slice(&start.to_le_bytes());
+        header.extend_from_slice(&salt);
+
+        let mut output = header.clone();
+        encrypt_frames(
+            &cipher,
+            &nonce_prefix,
+            &header,
+            file.take(len),
+            &mut output,
+            chunk_size,
+            false,
+        )?;
+        Ok(output)
+    }
+
+    /// 加密时的分块大小除满足 `check_chunk_size` 外，还不能小于解密时要求的最小帧长度，
+    /// 否则加密出的数据无法用同样的配置解密
+    fn check_encrypt_chunk_size(&self, chunk_size: usize) -> Result<(), String> {
+        check_chunk_size(chunk_size)?;
+        if chunk_size < self.frame_limits.min_payload {
+            return Err(format!(
+                "分块大小过小: {} 字节，下限 {} 字节",
+                chunk_size, self.frame_limits.min_payload
+            ));
+        }
+        Ok(())
+    }
+
+    /// 解密 `encrypt_range` 的输出，返回范围的起始偏移和明文
+    pub fn decrypt_range(&self, data: &[u8], password: &[u8]) -> Result<(u64, Vec<u8>), String> {
+        if data.len() < RANGE_HEADER_LEN {
+            return Err("加密数据头部不完整".to_string());
+        }
+        let (chunk_size, nonce_prefix, kdf) =
+            parse_stream_header(&data[..STREAM_HEADER_LEN], CIPHER_AES_256_GCM_RANGE)?;
+        let start = u64::from_le_bytes(
+            data[STREAM_HEADER_LEN..RANGE_HEADER_LEN]
+                .try_into()
+                .unwrap(),
+        );
+        let (salt, frames) = split_salt(kdf, &data[RANGE_HEADER_LEN..])?;
+        let header = &data[..RANGE_HEADER_LEN + salt.len()];
+
+        let key_bytes = self.derive_decryption_key(kdf, password, salt)?;
+        let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key_bytes));
+
+        let mut plaintext = Vec::new();
+        decrypt_frames(
+            &cipher,
+            &nonce_prefix,
+            header,
+            frames,
+            &mut plaintext,
+            chunk_size,
+            false,
+            self.frame_limits,
+        )?;
+        Ok((start, plaintext))
+    }
+
+    /// 根据数据大小选择分块大小
+    ///
+    /// 目标约为 64 个分块，取 2 的幂并限制在 64KB 到 8MB 之间：
+    /// 小文件不会因分块过多浪费帧开销，大文件也不会产生海量帧。
+    pub fn adaptive_chunk_size(data_len: u64) -> usize {
+        let target = data_len.div_ceil(TARGET_CHUNK_COUNT).next_power_of_two();
+        (target.min(MAX_AUTO_CHUNK_SIZE as u64) as usize).max(MIN_AUTO_CHUNK_SIZE)
+    }
+
+    /// 计算加密后相对明文增加的字节数
+    ///
+    /// `chunk_size` 为 None 时对应 `encrypt_data` 的单次加密格式，
+    /// 否则对应按该分块大小输出的分块格式。按默认的密钥派生计算，
+    /// 使用 PBKDF2（`with_kdf_cost`）时另有 16 字节的盐。
+    pub fn ciphertext_overhead(plaintext_len: u64, chunk_size: Option<usize>) -> u64 {
+        match chunk_size {
+            None => (HEADER_LEN + super::NONCE_LEN + TAG_LEN) as u64,
+            Some(chunk_size) => {
+                let frames = plaintext_len.div_ceil(chunk_size.max(1) as u64).max(1);
+                STREAM_HEADER_LEN as u64 + frames * (FRAME_LEN_SIZE + TAG_LEN) as u64
+            }
+        }
+    }
+}
+
+/// 将 `reader` 中的明文按 `chunk_size` 加密为数据帧写入 `writer`，返回写入的字节数
+///
+/// `compress` 为 true 时每块明文先独立压缩再加密。
+fn encrypt_frames<R: Read, W: Write>(
+    cipher: &Aes256Gcm,
+    nonce_prefix: &[u8; NONCE_PREFIX_LEN],
+    header: &[u8],
+    mut reader: R,
+    mut writer: W,
+    chunk_size: usize,
+    compress: bool,
+) -> Result<u64, String> {
+    let compressor = Compressor::new();
+    let mut written = 0u64;
+
+    // 预读下一块以确定当前块是否为末帧
+    let mut current = read_chunk(&mut reader, chunk_size)?;
+    let mut counter: u32 = 0;
+    loop {
+        let next = read_chunk(&mut reader, chunk_size)?;
+        let last = next.is_empty();
+        if compress {
+            current = compressor.compress(&current)?;
+        }
+
+        let ciphertext = encrypt_frame(cipher, nonce_prefix, header, counter, last, &current)?;
+
+        writer
+            .write_all(&(ciphertext.len() as u32).to_le_bytes())
+            .and_then(|_| writer.write_all(&ciphertext))
+            .map_err(|e| format!("写入加密数据失败: {}", e))?;
+        written += (FRAME_LEN_SIZE + ciphertext.len()) as u64;
+
+        if last {
+            break;
+        }
+        counter = counter.checked_add(1).ok_or("数据过大，超出分块数量上限")?;
+        current = next;
+    }
+
+    writer
+        .flush()
+        .map_err(|e| format!("写入加密数据失败: {}", e))?;
+    Ok(written)
+}
+
+/// 加密第 `counter` 帧的明文，返回密文（含认证标签）
+fn encrypt_frame(
+    cipher: &Aes256Gcm,
+    nonce_prefix: &[u8; NONCE_PREFIX_LEN],
+    header: &[u8],
+    counter: u32,
+    last: bool,
+    plaintext: &[u8],
+) -> Result<Vec<u8>, String> {
+    let nonce = frame_nonce(nonce_prefix, counter, last);
+    cipher
+        .encrypt(
+            Nonce::from_slice(&nonce),
+            Payload {
+                msg: plaintext,
+                aad: header,
+            },
+        )
+        .map_err(|e| format!("加密失败: {}", e))
+}
+
+/// 解密 `reader` 中的数据帧并将明文写入 `writer`，返回写入的明文字节数
+///
+/// `compressed` 为 true 时每帧解密后再解压。帧数超过上限时在解密前报错，
+/// 非末帧的明文短于下限时报错。
+#[allow(clippy::too_many_arguments)]
+fn decrypt_frames<R: Read, W: Write>(
+    cipher: &Aes256Gcm,
+    nonce_prefix: &[u8; NONCE_PREFIX_LEN],
+    header: &[u8],
+    mut reader: R,
+    mut writer: W,
+    chunk_size: usize,
+    compressed: bool,
+    limits: FrameLimits,
+) -> Result<u64, String> {
+    let max_payload = frame_payload_limit(chunk_size, compressed);
+    let mut written = 0u64;
+    let mut counter: u32 = 0;
+    let mut current = read_frame(&mut reader, max_payload)?.ok_or("加密数据被截断")?;
+    loop {
+        if u64::from(counter) >= limits.max_frames {
+            return Err(format!("数据帧数量超出上限: {}", limits.max_frames));
+        }
+        let next = read_frame(&mut reader, max_payload)?;
+        let last = next.is_none();
+
+        let nonce = frame_nonce(nonce_prefix, counter, last);
+        let plaintext = cipher
+            .decrypt(
+                Nonce::from_slice(&nonce),
+                Payload {
+                    msg: &current,
+                    aad: header,
+                },
+            )
+            .map_err(|e| format!("解密失败: {}", e))?;
+        let plaintext = if compressed {
+            decompress_frame(&plaintext, chunk_size)?
+        } else {
+            plaintext
+        };
+        if !last && plaintext.len() < limits.min_payload {
+            return Err(format!(
+                "数据帧过小: 第 {} 帧 {} 字节，下限 {} 字节",
+                counter,
+                plaintext.len(),
+                limits.min_payload
+            ));
+        }
+
+        writer
+            .write_all(&plaintext)
+            .map_err(|e| format!("写入解密数据失败: {}", e))?;
+        written += plaintext.len() as u64;
+
+        match next {
+            Some(frame) => {
+                current = frame;
+                counter = counter.checked_add(1).ok_or("分块数量超出上限")?;
+            }
+            None => break,
+        }
+    }
+
+    Ok(written)
+}
+
+/// 生成分块格式头部
+fn stream_header(
+    cipher_id: u8,
+    kdf: KdfParams,
+    chunk_size: u32,
+    nonce_prefix: &[u8; NONCE_PREFIX_LEN],
+) -> [u8; STREAM_HEADER_LEN] {
+    let mut header = [0u8; STREAM_HEADER_LEN];
+    header[..4].copy_from_slice(MAGIC);
+    header[4] = FORMAT_VERSION;
+    header[5] = cipher_id;
+    header[6] = kdf.header_byte();
+    header[7..11].copy_from_slice(&chunk_size.to_le_bytes());
+    header[HEADER_LEN + 4..].copy_from_slice(nonce_prefix);
+    header
+}
+
+/// 填充分块格式的头部参数和完整帧数（供 `describe_blob` 使用，不校验帧内容）
+pub(super) fn describe_frames(blob: &[u8], info: &mut BlobInfo) {
+    info.nonce_len = NONCE_PREFIX_LEN;
+    let header_len = if info.cipher_id == CIPHER_AES_256_GCM_RANGE {
+        RANGE_HEADER_LEN
+    } else {
+        STREAM_HEADER_LEN
+    };
+    if blob.len() < header_len + info.salt_len {
+        info.truncated = true;
+        return;
+    }
+    info.chunk_size = Some(u32::from_le_bytes(blob[7..11].try_into().unwrap()));
+    if info.cipher_id == CIPHER_AES_256_GCM_RANGE {
+        info.range_offset = Some(u64::from_le_bytes(
+            blob[STREAM_HEADER_LEN..RANGE_HEADER_LEN]
+                .try_into()
+                .unwrap(),
+        ));
+    }
+
+    let mut frames = &blob[header_len + info.salt_len..];
+    let mut count = 0u64;
+    while frames.len() >= FRAME_LEN_SIZE {
+        let frame_len = u32::from_le_bytes(frames[..FRAME_LEN_SIZE].try_into().unwrap()) as usize;
+        let Some(rest) = frames[FRAME_LEN_SIZE..].get(frame_len..) else {
+            break;
+        };
+        frames = rest;
+        count += 1;
+    }
+    info.chunk_count = Some(count);
+    info.truncated = !frames.is_empty() || count == 0;
+}
+
+/// 校验分块格式头部，返回分块大小、nonce 前缀和密钥派生参数
+fn parse_stream_header(
+    header: &[u8],
+    expected_cipher: u8,
+) -> Result<(usize, [u8; NONCE_PREFIX_LEN], KdfParams), String> {
+    if &header[..4] != MAGIC || header[4] != FORMAT_VERSION {
+        return Err("不是有效的分块加密数据".to_string());
+    }
+    if header[5] != expected_cipher {
+        return Err("不支持的加密算法".to_string());
+    }
+    let kdf = KdfParams::from_header_byte(header[6])?;
+    let chunk_size = u32::from_le_bytes([header[7], header[8], header[9], header[10]]) as usize;
+    check_chunk_size(chunk_size)?;
+    let mut nonce_prefix = [0u8; NONCE_PREFIX_LEN];
+    nonce_prefix.copy_from_slice(&header[HEADER_LEN + 4..STREAM_HEADER_LEN]);
+    Ok((chunk_size, nonce_prefix, kdf))
+}
+
+/// 分块大小须在 1 到 `MAX_CHUNK_SIZE` 之间
+fn check_chunk_size(chunk_size: usize) -> Result<(), String> {
+    if chunk_size == 0 || chunk_size > MAX_CHUNK_SIZE {
+        return Err(format!(
+            "无效的分块大小: {}（上限 {} 字节）",
+            chunk_size, MAX_CHUNK_SIZE
+        ));
+    }
+    Ok(())
+}
+
+/// 计算第 `counter` 帧的 nonce
+fn frame_nonce(prefix: &[u8; NONCE_PREFIX_LEN], counter: u32, last: bool) -> [u8; 12] {
+    let mut nonce = [0u8; 12];
+    nonce[..NONCE_PREFIX_LEN].copy_from_slice(prefix);
+    nonce[NONCE_PREFIX_LEN..11].copy_from_slice(&counter.to_be_bytes());
+    nonce[11] = last as u8;
+    nonce
+}
+
+/// 读取最多 `chunk_size` 字节，只有到达末尾时才会少于 `chunk_size`
+fn read_chunk<R: Read>(reader: &mut R, chunk_size: usize) -> Result<Vec<u8>, String> {
+    let mut chunk = Vec::with_capacity(chunk_size);
+    reader
+        .take(chunk_size as u64)
+        .read_to_end(&mut chunk)
+        .map_err(|e| format!("读取数据失败: {}", e))?;
+    Ok(chunk)
+}
+
+/// 单帧明文（压缩时为压缩后的数据）的最大长度
+///
+/// 不可压缩的数据经 zlib 压缩后会略微变大，上限参照 zlib 的 `deflateBound`。
+fn frame_payload_limit(chunk_size: usize, compressed: bool) -> usize {
+    if compressed {
+        chunk_size + (chunk_size >> 12) + (chunk_size >> 14) + (chunk_size >> 25) + 13 + 6
+    } else {
+        chunk_size
+    }
+}
+
+/// 解压单帧数据，解压结果超过 `chunk_size` 时报错（防止解压炸弹）
+fn decompress_frame(data: &[u8], chunk_size: usize) -> Result<Vec<u8>, String> {
+    let mut chunk = Vec::new();
+    ZlibDecoder::new(data)
+        .take(chunk_size as u64 + 1)
+        .read_to_end(&mut chunk)
+        .map_err(|e| format!("解压缩失败: {}", e))?;
+    if chunk.len() > chunk_size {
+        return Err("分块解压后大小超出分块大小".to_string());
+    }
+    Ok(chunk)
+}
+
+/// 读取一个数据帧，数据已结束时返回 None
+///
+/// `max_payload` 为帧内明文的最大长度，帧长度还需加上认证标签。
+fn read_frame<R: Read>(reader: &mut R, max_payload: usize) -> Result<Option<Vec<u8>>, String> {
+    let mut len_bytes = [0u8; FRAME_LEN_SIZE];
+    let mut filled = 0;
+    while filled < FRAME_LEN_SIZE {
+        match reader.read(&mut len_bytes[filled..]) {
+            Ok(0) if filled == 0 => return Ok(None),
+            Ok(0) => return Err("加密数据被截断".to_string()),
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(format!("读取加密数据失败: {}", e)),
+        }
+    }
+
+    let frame_len = u32::from_le_bytes(len_bytes) as usize;
+    if !(TAG_LEN..=max_payload + TAG_LEN).contains(&frame_len) {
+        return Err(format!("无效的数据帧长度: {}", frame_len));
+    }
+
+    let mut frame = vec![0u8; frame_len];
+    reader
+        .read_exact(&mut frame)
+        .map_err(|_| "加密数据被截断".to_string())?;
+    Ok(Some(frame))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stream_round_trip() {
+        let crypto = CryptoCompressor::new();
+        let data: Vec<u8> = (0..10_000u32).map(|i| (i % 251) as u8).collect();
+
+        let mut encrypted = Vec::new();
+        crypto
+            .encrypt_stream(&data[..], &mut encrypted, b"stream_password", 1024)
+            .unwrap();
+
+        let mut decrypted = Vec::new();
+        crypto
+            .decrypt_stream(&encrypted[..], &mut decrypted, b"stream_password")
+            .unwrap();
+        assert_eq!(decrypted, data);
+
+        // decrypt_data 也能识别分块格式
+        assert_eq!(
+            crypto.decrypt_data(&encrypted, b"stream_password").unwrap(),
+            data
+        );
+    }
+
+    #[test]
+    fn test_encrypt_file_parallel_matches_sequential() {
+        let crypto = CryptoCompressor::new();
+        let dir = tempfile::tempdir().unwrap();
+        let password = b"parallel_password";
+        let prefix = [7u8; NONCE_PREFIX_LEN];
+
+        for len in [0usize, 1000, 4096, 10_000] {
+            let path = dir.path().join(format!("input_{}.bin", len));
+            let data: Vec<u8> = (0..len as u32).map(|i| (i % 253) as u8).collect();
+            fs::write(&path, &data).unwrap();
+            let path = path.to_str().unwrap();
+
+            let mut sequential = Vec::new();
+            crypto
+                .encrypt_stream_with_prefix(
+                    fs::File::open(path).unwrap(),
+                    &mut sequential,
+                    password,
+                    1024,
+                    &prefix,
+                )
+                .unwrap();
+            let parallel = crypto
+                .encrypt_file_parallel_with_prefix(path, password, 1024, &prefix)
+                .unwrap();
+            assert_eq!(parallel, sequential, "len {}", len);
+            assert_eq!(crypto.decrypt_data(&parallel, password).unwrap(), data);
+        }
+
+        let path = dir.path().join("input_10000.bin");
+        let encrypted = crypto
+            .encrypt_file_parallel(path.to_str().unwrap(), password, 0)
+            .unwrap();
+        let mut decrypted = Vec::new();
+        crypto
+            .decrypt_stream(&encrypted[..], &mut decrypted, password)
+            .unwrap();
+        assert_eq!(decrypted.len(), 10_000);
+    }
+
+    #[test]
+    fn test_encrypt_file_stream_progress() {
+        use crate::progress::tests::RecordingSink;
+
+        let crypto = CryptoCompressor::new();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("input.bin");
+        fs::write(&path, vec![3u8; 5000]).unwrap();
+
+        let mut sink = RecordingSink::default();
+        let encrypted = crypto
+            .encrypt_file_stream_with_progress(path.to_str().unwrap(), b"pw", 1024, Some(&mut sink))
+            .unwrap();
+        sink.assert_complete();
+        assert_eq!(sink.total, Some(5000));
+        assert_eq!(
+            crypto.decrypt_data(&encrypted, b"pw").unwrap(),
+            vec![3u8; 5000]
+        );
+
+        let mut sink = RecordingSink::default();
+        let mut output = Vec::new();
+        crypto
+            .encrypt_stream_with_progress(
+                &[1u8; 100][..],
+                &mut output,
+                b"pw",
+                1024,
+                Some(&mut sink),
+            )
+            .unwrap();
+        assert_eq!((sink.total, sink.advanced), (Some(0), 100));
+    }
+
+    #[test]
+    fn test_stream_detects_truncation() {
+        let crypto = CryptoCompressor::new();
+        let data = vec![9u8; 3000];
+
+        let mut encrypted = Vec::new();
+        crypto
+            .encrypt_stream(&data[..], &mut encrypted, b"password", 1000)
+            .unwrap();
+
+        // 去掉末帧
+        let truncated = &encrypted[..encrypted.len() - (FRAME_LEN_SIZE + 1000 + TAG_LEN)];
+        let result = crypto.decrypt_stream(truncated, Vec::new(), b"password");
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_stream_rejects_oversized_chunk_size() {
+        let crypto = CryptoCompressor::new();
+        let data = [3u8; 100];
+
+        let result = crypto.encrypt_stream(&data[..], Vec::new(), b"password", MAX_CHUNK_SIZE + 1);
+        assert!(result.unwrap_err().contains("无效的分块大小"));
+
+        // 篡改头部中的分块大小：在读取任何数据帧之前拒绝
+        let mut encrypted = Vec::new();
+        crypto
+            .encrypt_stream(&data[..], &mut encrypted, b"password", 1024)
+            .unwrap();
+        encrypted[7..11].copy_from_slice(&u32::MAX.to_le_bytes());
+        let err = crypto.decrypt_data(&encrypted, b"password").unwrap_err();
+        assert!(err.contains("无效的分块大小"), "{}", err);
+    }
+
+    #[test]
+    fn test_stream_frame_limits() {
+        let data = vec![7u8; 10 * 1024];
+
+        // 10 帧超过 5 帧的上限
+        let mut encrypted = Vec::new();
+        CryptoCompressor::new()
+            .encrypt_stream(&data[..], &mut encrypted, b"password", 1024)
+            .unwrap();
+        let limited = CryptoCompressor::new().with_frame_limits(512, 5);
+        let err = limited
+            .decrypt_stream(&encrypted[..], Vec::new(), b"password")
+            .unwrap_err();
+        assert!(err.contains("数据帧数量超出上限"), "{}", err);
+        let relaxed = CryptoCompressor::new().with_frame_limits(512, 10);
+        assert!(
+            relaxed
+                .decrypt_stream(&encrypted[..], Vec::new(), b"password")
+                .is_ok()
+        );
+
+        // 默认配置不接受小于 512 字节的分块
+        let err = CryptoCompressor::new()
+            .encrypt_stream(&data[..], Vec::new(), b"password", 16)
+            .unwrap_err();
+        assert!(err.contains("分块大小过小"), "{}", err);
+        let err = CryptoCompressor::new()
+            .encrypt_stream_compressed(&data[..], Vec::new(), b"password", 16)
+            .unwrap_err();
+        assert!(err.contains("分块大小过小"), "{}", err);
+
+        // 放宽限制后加密出的大量 16 字节小帧默认被拒绝
+        let permissive = CryptoCompressor::new().with_frame_limits(16, 1 << 24);
+        let mut tiny_frames = Vec::new();
+        permissive
+            .encrypt_stream(&data[..], &mut tiny_frames, b"password", 16)
+            .unwrap();
+        let err = CryptoCompressor::new()
+            .decrypt_data(&tiny_frames, b"password")
+            .unwrap_err();
+        assert!(err.contains("数据帧过小"), "{}", err);
+        assert_eq!(
+            permissive.decrypt_data(&tiny_frames, b"password").unwrap(),
+            data
+        );
+
+        // 末帧不受最小长度限制
+        let mut single = Vec::new();
+        CryptoCompressor::new()
+            .encrypt_stream(&data[..16], &mut single, b"password", 1024)
+            .unwrap();
+        assert_eq!(
+            CryptoCompressor::new()
+                .decrypt_data(&single, b"password")
+                .unwrap(),
+            &data[..16]
+        );
+    }
+
+    #[test]
+    fn test_decrypt_chunk_random_access() {
+        let crypto = CryptoCompressor::new();
+        let chunk_size = 4096;
+        // 每块内容不同且可压缩，末块不满
+        let data: Vec<u8> = (0..10 * chunk_size + 100)
+            .map(|i| b"abcdefghij"[i / chunk_size % 10] ^ (i % 3) as u8)
+            .collect();
+
+        let mut encrypted = Vec::new();
+        crypto
+            .encrypt_stream_compressed(&data[..], &mut encrypted, b"password", chunk_size)
+            .unwrap();
+        assert!(encrypted.len() < data.len() / 4);
+
+        let middle = crypto.decrypt_chunk(&encrypted, b"password", 5).unwrap();
+        assert_eq!(middle, &data[5 * chunk_size..6 * chunk_size]);
+        let last = crypto.decrypt_chunk(&encrypted, b"password", 10).unwrap();
+        assert_eq!(last, &data[10 * chunk_size..]);
+
+        assert!(crypto.decrypt_chunk(&encrypted, b"password", 11).is_err());
+        assert!(crypto.decrypt_chunk(&encrypted, b"wrong", 5).is_err());
+
+        // 整体解密同样可用
+        assert_eq!(crypto.decrypt_data(&encrypted, b"password").unwrap(), data);
+
+        // 不可压缩的数据压缩后略大，仍在帧长度上限内
+        let noise: Vec<u8> = (0..3 * chunk_size).map(|_| rand::random::<u8>()).collect();
+        let mut encrypted = Vec::new();
+        crypto
+            .encrypt_stream_compressed(&noise[..], &mut encrypted, b"password", chunk_size)
+            .unwrap();
+        assert_eq!(
+            crypto.decrypt_chunk(&encrypted, b"password", 1).unwrap(),
+            &noise[chunk_size..2 * chunk_size]
+        );
+    }
+
+    #[test]
+    fn test_adaptive_chunk_size() {
+        let chunk_size_of = |encrypted: &[u8]| {
+            u32::from_le_bytes(encrypted[HEADER_LEN..HEADER_LEN + 4].try_into().unwrap()) as usize
+        };
+        let crypto = CryptoCompressor::new();
+        let dir = tempfile::tempdir().unwrap();
+
+        let small: Vec<u8> = (0..10_000u32).map(|i| (i % 7) as u8).collect();
+        let large: Vec<u8> = (0..8 * 1024 * 1024u32).map(|i| (i % 13) as u8).collect();
+        let mut chunk_sizes = Vec::new();
+        for (name, data) in [("small.bin", &small), ("large.bin", &large)] {
+            let path = dir.path().join(name);
+            fs::write(&path, data).unwrap();
+
+            let encrypted = crypto
+                .encrypt_file_stream(path.to_str().unwrap(), b"password", 0)
+                .unwrap();
+            chunk_sizes.push(chunk_size_of(&encrypted));
+            assert_eq!(&crypto.decrypt_data(&encrypted, b"password").unwrap(), data);
+        }
+
+        assert_eq!(chunk_sizes[0], MIN_AUTO_CHUNK_SIZE);
+        assert!(chunk_sizes[1] > chunk_sizes[0]);
+        assert_eq!(
+            CryptoCompressor::adaptive_chunk_size(u64::MAX),
+            MAX_AUTO_CHUNK_SIZE
+        );
+    }
+
+    #[test]
+    fn test_encrypt_range() {
+        let crypto = CryptoCompressor::new();
+        let data: Vec<u8> = (0..300_000u32).map(|i| (i * 31 % 256) as u8).collect();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("large.bin");
+        fs::write(&path, &data).unwrap();
+        let path = path.to_str().unwrap();
+
+        let encrypted = crypto
+            .encrypt_range(path, b"password", 100_000, 70_000)
+            .unwrap();
+        let (start, plaintext) = crypto.decrypt_range(&encrypted, b"password").unwrap();
+        assert_eq!(start, 100_000);
+        assert_eq!(plaintext, &data[100_000..170_000]);
+
+        // 偏移被认证，篡改后无法解密
+        let mut tampered = encrypted.clone();
+        tampered[STREAM_HEADER_LEN] ^= 1;
+        assert!(crypto.decrypt_range(&tampered, b"password").is_err());
+
+        assert!(
+            crypto
+                .encrypt_range(path, b"password", 250_000, 60_000)
+                .is_err()
+        );
+        assert!(
+            crypto
+                .decrypt_stream(&encrypted[..], Vec::new(), b"password")
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_stream_formats_with_kdf_cost() {
+        let tuned = CryptoCompressor::new().with_kdf_cost(4).unwrap();
+        let default = CryptoCompressor::new();
+        let strict = CryptoCompressor::new().with_max_kdf_cost(2);
+        let data: Vec<u8> = (0..5000u32).map(|i| (i % 7) as u8).collect();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("input.bin");
+        fs::write(&path, &data).unwrap();
+        let path = path.to_str().unwrap();
+
+        // 盐位于固定头部之后，帧数统计和各种解密方式都按头部中的参数处理
+        let parallel = tuned
+            .encrypt_file_parallel(path, b"password", 1024)
+            .unwrap();
+        let info = CryptoCompressor::describe_blob(&parallel).unwrap();
+        assert_eq!((info.chunk_count, info.truncated), (Some(5), false));
+        let mut decrypted = Vec::new();
+        default
+            .decrypt_stream(&parallel[..], &mut decrypted, b"password")
+            .unwrap();
+        assert_eq!(decrypted, data);
+
+        let mut compressed = Vec::new();
+        tuned
+            .encrypt_stream_compressed(&data[..], &mut compressed, b"password", 1024)
+            .unwrap();
+        assert_eq!(
+            default.decrypt_chunk(&compressed, b"password", 2).unwrap(),
+            &data[2048..3072]
+        );
+
+        let range = tuned.encrypt_range(path, b"password", 1000, 2000).unwrap();
+        let (start, plaintext) = default.decrypt_range(&range, b"password").unwrap();
+        assert_eq!((start, plaintext.as_slice()), (1000, &data[1000..3000]));
+
+        assert!(
+            strict
+                .decrypt_stream(&parallel[..], Vec::new(), b"password")
+                .is_err()
+        );
+        assert!(strict.decrypt_chunk(&compressed, b"password", 2).is_err());
+        assert!(strict.decrypt_range(&range, b"password").is_err());
+    }
+
+    #[test]
+    fn test_ciphertext_overhead_matches_output() {
+        let crypto = CryptoCompressor::new();
+
+        for len in [0usize, 1, 100, 4096, 5000] {
+            let data = vec![1u8; len];
+
+            let single = crypto.encrypt_data(&data, b"password").unwrap();
+            assert_eq!(
+                (single.len() - len) as u64,
+                CryptoCompressor::ciphertext_overhead(len as u64, None)
+            );
+
+            let mut chunked = Vec::new();
+            crypto
+                .encrypt_stream(&data[..], &mut chunked, b"password", 1024)
+                .unwrap();
+            assert_eq!(
+                (chunked.len() - len) as u64,
+                CryptoCompressor::ciphertext_overhead(len as u64, Some(1024))
+            );
+        }
+    }
+}
@@ -1,43 +1,715 @@
-use crate::{DiffResult, DiffStatistics, FileDiff, FileMetadata};
+use crate::progress::{Progress, ProgressSink};
+use crate::scan::{ScanOptions, is_hidden_path, scan_directory};
+use crate::{ByteRange, DiffError, DiffResult, DiffStatistics, FileDiff, FileMetadata};
 use rayon::prelude::*;
-use std::collections::HashMap;
+use std::borrow::Cow;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::fs;
+use std::io::{BufRead, BufReader, ErrorKind, Read};
+use std::mem::size_of;
+use std::panic::{self, AssertUnwindSafe};
 use std::path::Path;
+use std::sync::Arc;
+use unicode_normalization::UnicodeNormalization;
+
+/// `files_equal_streaming` 每次比较的块大小
+const COMPARE_BLOCK_SIZE: usize = 64 * 1024;
+
+pub mod block;
+pub mod text;
+
+pub use block::{BlockOp, apply_block_ops, block_diff};
+pub use text::{LineOp, text_diff};
+
+/// 自定义比较函数：返回 true 表示文件需要更新
+pub type Comparator = dyn Fn(&FileMetadata, &FileMetadata) -> bool + Send + Sync;
+
+/// 同步操作的代价模型
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CostModel {
+    /// 每传输一个字节的代价
+    pub per_byte_weight: f64,
+    /// 每个文件操作的固定代价（连接、元数据、确认等）
+    pub per_file_weight: f64,
+    /// 假定的压缩比（压缩后大小 / 原始大小）
+    pub compression_ratio: f64,
+}
+
+impl Default for CostModel {
+    fn default() -> Self {
+        Self {
+            per_byte_weight: 1.0,
+            per_file_weight: 4096.0,
+            compression_ratio: 1.0,
+        }
+    }
+}
+
+/// 按修改时间比较时，修改时间缺失或无法解析的处理策略
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MissingMtimePolicy {
+    /// 视为需要更新（无法确认文件未修改）
+    AlwaysUpdate,
+    /// 退回到哈希比较
+    FallbackToHash,
+    /// 记录为该文件的差异计算错误
+    Error,
+}
+
+/// 匹配两侧路径时使用的标准化规则
+///
+/// 所有规则在 `PathMatching::key` 中按固定顺序组合：分隔符统一为 `/` 后，
+/// 去掉末尾的 `/`，再做 Unicode 和大小写处理。两者同时启用时先分解为 NFD、
+/// 转小写后再组合为 NFC，保证 `"É"`、`"E\u{301}"`、`"é"` 得到相同结果。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PathMatching {
+    /// 忽略大小写
+    pub case_insensitive: bool,
+    /// 按 Unicode NFC 形式比较（如 macOS 上的 NFD 文件名）
+    pub unicode_nfc: bool,
+    /// 忽略末尾的 `/`
+    pub trailing_slash: bool,
+}
+
+impl PathMatching {
+    /// 计算用于匹配的路径键，`path` 应已统一分隔符
+    pub fn key(&self, path: &str) -> String {
+        let path = if self.trailing_slash {
+            path.trim_end_matches('/')
+        } else {
+            path
+        };
+        match (self.unicode_nfc, self.case_insensitive) {
+            (false, false) => path.to_string(),
+            (false, true) => path.to_lowercase(),
+            (true, false) => path.nfc().collect(),
+            // 小写映射可能产生非组合形式，因此在 NFD 上转小写后再组合
+            (true, true) => path
+                .nfd()
+                .collect::<String>()
+                .to_lowercase()
+                .nfc()
+                .collect(),
+        }
+    }
+}
+
+/// 由文件路径生成匹配键的方式
+///
+/// 键在分隔符统一为 `/` 之后、`PathMatching` 规则之前生成。
+#[derive(Debug, Clone, Copy, Default)]
+pub enum KeyFn {
+    /// 完整路径
+    #[default]
+    FullPath,
+    /// 只取文件名，忽略所在目录（扁平同步）
+    Basename,
+    /// 自定义函数，参数为统一分隔符后的路径
+    Custom(fn(&str) -> String),
+}
+
+impl KeyFn {
+    /// 计算 `path` 的匹配键
+    fn apply(&self, path: &str) -> String {
+        match self {
+            KeyFn::FullPath => path.to_string(),
+            KeyFn::Basename => path
+                .trim_end_matches('/')
+                .rsplit('/')
+                .next()
+                .unwrap_or_default()
+                .to_string(),
+            KeyFn::Custom(key_fn) => key_fn(path),
+        }
+    }
+}
+
+/// 按哈希比较时，目标端条目存在但哈希为空的处理策略
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EmptyDestHashPolicy {
+    /// 按普通的哈希比较处理（哈希必然不同，生成 update）
+    #[default]
+    TreatAsUpdate,
+    /// 视为目标端内容未知，生成 create 重新写入
+    TreatAsCreate,
+    /// 视为目标端已有该文件，不生成差异
+    Skip,
+}
+
+/// 同步模式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SyncMode {
+    /// 镜像：目标端与源端保持一致（创建、更新、删除）
+    #[default]
+    Mirror,
+    /// 只追加：只创建目标端缺少的文件，从不更新或删除已有文件（用于不可变存储）
+    AdditiveOnly,
+}
+
+/// 文件出现在差异结果中的原因
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiffReason {
+    /// 目标端不存在该文件
+    NewFile,
+    /// 源端不存在该文件
+    NotInSource,
+    /// 弱校验和不同
+    WeakChecksumDiffers,
+    /// 哈希不同
+    HashDiffers,
+    /// 大小不同
+    SizeDiffers,
+    /// 修改时间不同
+    MtimeDiffers,
+    /// 修改时间缺失或无法解析
+    MtimeMissing,
+    /// 权限不同
+    PermissionsDiffer,
+    /// 自定义比较函数判定需要更新
+    CustomComparator,
+    /// 相同内容已存在于目标端其他路径
+    ContentExists,
+    /// 目标端条目缺少哈希，内容未知
+    DestHashMissing,
+}
+
+impl DiffReason {
+    /// 人类可读的说明文字
+    pub fn explanation(&self) -> &'static str {
+        match self {
+            DiffReason::NewFile => "新文件",
+            DiffReason::NotInSource => "源端已删除",
+            DiffReason::WeakChecksumDiffers => "弱校验和不同",
+            DiffReason::HashDiffers => "哈希不同",
+            DiffReason::SizeDiffers => "大小不同",
+            DiffReason::MtimeDiffers => "修改时间不同",
+            DiffReason::MtimeMissing => "修改时间缺失",
+            DiffReason::PermissionsDiffer => "权限不同",
+            DiffReason::CustomComparator => "自定义比较判定需要更新",
+            DiffReason::ContentExists => "内容已存在于目标端",
+            DiffReason::DestHashMissing => "目标端哈希缺失",
+        }
+    }
+}
 
 /// 差异计算器
 pub struct DiffCalculator {
-    // 可以添加配置选项
+    /// 是否比较文件权限（见 `with_permission_comparison`）
+    compare_permissions: bool,
+    /// 自定义比较函数，替代默认的哈希/大小比较
+    comparator: Option<Arc<Comparator>>,
+    /// 是否标记内容已存在于目标端的文件（见 `with_content_dedup`）
+    content_dedup: bool,
+    /// 是否按大小和修改时间判断内容变化（见 `with_mtime_comparison`）
+    compare_mtime: bool,
+    /// 修改时间缺失时的处理策略
+    missing_mtime_policy: MissingMtimePolicy,
+    /// 是否为每个差异附加说明文字（见 `with_explanations`）
+    explanations: bool,
+    /// 按大小均衡划分的工作块数量（见 `with_size_balanced_chunks`）
+    balanced_chunks: Option<usize>,
+    /// 是否先比较弱校验和（见 `with_weak_checksum_first`）
+    weak_checksum_first: bool,
+    /// 同步模式
+    mode: SyncMode,
+    /// 输出路径所相对的根目录（见 `with_relative_to`）
+    relative_root: Option<String>,
+    /// 单次比较允许的最大文件数（见 `with_max_files`）
+    max_files: Option<usize>,
+    /// 拆分大文件的区间大小（见 `with_range_split`）
+    range_split: Option<u64>,
+    /// 是否忽略隐藏文件（见 `with_skip_hidden`）
+    skip_hidden: bool,
+    /// 匹配两侧路径时的标准化规则（见 `with_path_matching`）
+    path_matching: PathMatching,
+    /// 匹配键的生成方式（见 `with_key_fn`）
+    key_fn: KeyFn,
+    /// 是否为创建/更新附加写入后校验用的哈希（见 `with_verify_hash`）
+    verify_hash: bool,
+    /// 目标端哈希为空时的处理策略（见 `with_empty_dest_hash_policy`）
+    empty_dest_hash_policy: EmptyDestHashPolicy,
 }
 
 impl DiffCalculator {
     /// 创建新的差异计算器
     pub fn new() -> Self {
-        Self {}
+        Self {
+            compare_permissions: false,
+            comparator: None,
+            content_dedup: false,
+            compare_mtime: false,
+            missing_mtime_policy: MissingMtimePolicy::FallbackToHash,
+            explanations: false,
+            balanced_chunks: None,
+            weak_checksum_first: false,
+            mode: SyncMode::Mirror,
+            relative_root: None,
+            max_files: None,
+            range_split: None,
+            skip_hidden: false,
+            path_matching: PathMatching::default(),
+            key_fn: KeyFn::FullPath,
+            verify_hash: false,
+            empty_dest_hash_policy: EmptyDestHashPolicy::TreatAsUpdate,
+        }
+    }
+
+    /// 使用自定义比较函数判断已存在的文件是否需要更新
+    pub fn with_comparator<F>(mut self, comparator: F) -> Self
+    where
+        F: Fn(&FileMetadata, &FileMetadata) -> bool + Send + Sync + 'static,
+    {
+        self.comparator = Some(Arc::new(comparator));
+        self
+    }
+
+    /// 启用权限比较：权限不同的文件也视为需要更新
+    ///
+    /// 比较前会用 `normalize_permissions` 统一 `"644"`、`"0o644"`、`"-rw-r--r--"` 等写法。
+    pub fn with_permission_comparison(mut self, enabled: bool) -> Self {
+        self.compare_permissions = enabled;
+        self
+    }
+
+    /// 启用修改时间比较：大小和修改时间都相同的文件视为未修改，不再比较哈希
+    ///
+    /// 修改时间支持 RFC 3339 格式（如 `"2023-01-01T00:00:00Z"`）或 Unix 秒数。
+    pub fn with_mtime_comparison(mut self, enabled: bool) -> Self {
+        self.compare_mtime = enabled;
+        self
+    }
+
+    /// 设置修改时间比较模式下，修改时间缺失（空字符串）或无法解析时的处理策略
+    ///
+    /// 默认为 `MissingMtimePolicy::FallbackToHash`。
+    pub fn with_missing_mtime_policy(mut self, policy: MissingMtimePolicy) -> Self {
+        self.missing_mtime_policy = policy;
+        self
+    }
+
+    /// 为每个差异填充 `FileDiff::explanation`（如 "新文件"、"哈希不同"）
+    pub fn with_explanations(mut self, enabled: bool) -> Self {
+        self.explanations = enabled;
+        self
+    }
+
+    /// 并行比较前先将源文件按大小均衡地划分为 `chunk_count` 个工作块
+    ///
+    /// 默认按文件数量拆分任务，少数超大文件与大量小文件混合时容易出现单个线程
+    /// 处理全部大文件的情况。`chunk_count` 为 0 时使用 rayon 线程数。
+    /// 输出顺序与不划分时相同。
+    pub fn with_size_balanced_chunks(mut self, chunk_count: usize) -> Self {
+        self.balanced_chunks = Some(if chunk_count == 0 {
+            rayon::current_num_threads()
+        } else {
+            chunk_count
+        });
+        self
+    }
+
+    /// 先比较弱校验和，只有弱校验和相同时才比较强哈希（rsync 的两级校验）
+    ///
+    /// 两侧都带有 `weak_checksum` 且不同时直接判定需要更新；任一侧缺少弱校验和时
+    /// 按原有方式比较强哈希。
+    pub fn with_weak_checksum_first(mut self, enabled: bool) -> Self {
+        self.weak_checksum_first = enabled;
+        self
+    }
+
+    /// 设置同步模式，默认为 `SyncMode::Mirror`
+    pub fn with_mode(mut self, mode: SyncMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// 输出的差异路径相对于 `root`
+    ///
+    /// 只影响 `FileDiff::path` 和 `DiffError::path`，比较仍使用原始路径标准化后的结果。
+    /// 位于 `root` 下的路径去掉前缀和开头的分隔符，并统一使用 `/` 分隔；
+    /// 不在 `root` 下的路径原样输出。
+    pub fn with_relative_to(mut self, root: &str) -> Self {
+        let root = self.normalize_path(root);
+        self.relative_root = Some(root.trim_end_matches('/').to_string());
+        self
+    }
+
+    /// 限制单次比较的文件数量
+    ///
+    /// 源或目标文件列表超过 `max_files` 时 `calculate_differences` 直接返回错误，
+    /// 防止异常的超大输入耗尽内存。
+    pub fn with_max_files(mut self, max_files: usize) -> Self {
+        self.max_files = Some(max_files);
+        self
+    }
+
+    /// 将超过 `range_size` 字节的创建/更新拆分为多个区间条目，便于并行传输
+    ///
+    /// 每个条目的 `range` 给出偏移和长度，`size` 为该区间的长度，各区间首尾相接覆盖整个文件。
+    /// 统计信息仍按文件计数。`range_size` 为 0 时不拆分。
+    pub fn with_range_split(mut self, range_size: u64) -> Self {
+        self.range_split = (range_size > 0).then_some(range_size);
+        self
+    }
+
+    /// 忽略路径中任一部分以 `.` 开头的文件（如 `.git/HEAD`、`.env`）
+    ///
+    /// 两侧的隐藏文件都不参与比较、删除和统计，见 `ScanOptions::with_skip_hidden`。
+    pub fn with_skip_hidden(mut self, enabled: bool) -> Self {
+        self.skip_hidden = enabled;
+        self
+    }
+
+    /// 设置匹配源端和目标端路径时的标准化规则
+    ///
+    /// 同时作用于差异计算、删除扫描和内容去重；输出的路径保持源端或目标端的原始写法。
+    /// 同一侧有多个文件标准化后相同（如 `A.txt` 与 `a.txt`）时与 `with_key_fn` 一样报错。
+    pub fn with_path_matching(mut self, matching: PathMatching) -> Self {
+        self.path_matching = matching;
+        self
+    }
+
+    /// 设置源端和目标端文件的匹配键，如 `KeyFn::Basename` 按文件名匹配
+    ///
+    /// 使用 `FullPath` 以外的方式时，同一侧有多个文件得到相同的键会产生歧义，
+    /// `calculate_differences` 和 `find_files_to_delete` 直接返回错误并列出冲突的路径，
+    /// 而不是任选其一。
+    pub fn with_key_fn(mut self, key_fn: KeyFn) -> Self {
+        self.key_fn = key_fn;
+        self
+    }
+
+    /// 为创建和更新操作填充 `FileDiff::verify_hash`，供应用方写入后校验文件内容
+    ///
+    /// 只有源端哈希是完整的 SHA256（64 位十六进制，可带 `sha256:` 前缀）时才填充，
+    /// 截断或其他算法的哈希无法用于校验，此时保持为 `None`。
+    pub fn with_verify_hash(mut self, enabled: bool) -> Self {
+        self.verify_hash = enabled;
+        self
+    }
+
+    /// 设置目标端条目存在但哈希为空时的处理策略
+    ///
+    /// 只在按哈希比较内容时生效；使用自定义比较函数或按修改时间比较时不检查哈希。
+    pub fn with_empty_dest_hash_policy(mut self, policy: EmptyDestHashPolicy) -> Self {
+        self.empty_dest_hash_policy = policy;
+        self
+    }
+
+    /// 按 `skip_hidden` 过滤文件列表，未启用时直接借用原列表
+    fn visible_files<'a>(&self, files: &'a [FileMetadata]) -> Cow<'a, [FileMetadata]> {
+        if self.skip_hidden {
+            files
+                .iter()
+                .filter(|file| !is_hidden_path(&file.path))
+                .cloned()
+                .collect()
+        } else {
+            Cow::Borrowed(files)
+        }
+    }
+
+    /// 启用内容去重提示
+    ///
+    /// 需要创建或更新的文件，如果相同内容（哈希与大小都相同）已存在于目标端的其他路径，
+    /// 则标记为 `no_op` 并在 `copy_from` 中给出该路径，由应用方在服务端复制而不是重新上传。
+    /// `copy_from` 指向的路径可能在同一计划中被更新或删除，应用方须先完成所有复制再修改其他路径。
+    pub fn with_content_dedup(mut self, enabled: bool) -> Self {
+        self.content_dedup = enabled;
+        self
     }
 
     /// 计算文件差异
+    ///
+    /// 只比较源端文件，不检查需要删除的文件（`deletions_considered` 为 false），
+    /// 删除由 `find_files_to_delete` 或 `diff_directories` 处理。
     pub fn calculate_differences(
         &self,
         source_files: &[FileMetadata],
         dest_files: &[FileMetadata],
     ) -> Result<DiffResult, String> {
+        self.calculate_differences_with_progress(source_files, dest_files, None)
+    }
+
+    /// 计算文件差异并报告进度，进度单位为已比较的源文件数（不含被过滤的隐藏文件）
+    ///
+    /// 输入检查失败（文件数量超限、哈希算法不一致等）时不会调用 `progress`。
+    pub fn calculate_differences_with_progress(
+        &self,
+        source_files: &[FileMetadata],
+        dest_files: &[FileMetadata],
+        progress: Option<&mut dyn ProgressSink>,
+    ) -> Result<DiffResult, String> {
+        if let Some(max_files) = self.max_files
+            && (source_files.len() > max_files || dest_files.len() > max_files)
+        {
+            return Err("文件数量超过限制".to_string());
+        }
+
+        let source_files = &*self.visible_files(source_files);
+        let dest_files = &*self.visible_files(dest_files);
+
+        // 哈希算法不一致时逐一比较必然全部不同，提前报错而不是生成全量重传
+        self.check_hash_algorithms(source_files, dest_files)?;
+        if self.keys_may_collide() {
+            self.check_unique_keys(source_files, "源")?;
+            self.check_unique_keys(dest_files, "目标")?;
+        }
+
         // 创建目标文件的哈希映射，以路径为键
         let dest_map: HashMap<String, &FileMetadata> = dest_files
             .iter()
-            .map(|file| (self.normalize_path(&file.path), file))
+            .map(|file| (self.path_key(&file.path), file))
             .collect();
 
+        let progress = Progress::new(progress);
+        progress.start(source_files.len() as u64);
+        let compare = |source_file: &FileMetadata| {
+            let outcome = self.compare_file_guarded(source_file, &dest_map);
+            progress.advance(1);
+            outcome
+        };
+
         // 并行计算差异
-        let differences: Vec<FileDiff> = source_files
-            .par_iter()
-            .filter_map(|source_file| self.compare_file(source_file, &dest_map))
+        let outcomes: Vec<Result<Option<FileDiff>, DiffError>> = match self.balanced_chunks {
+            Some(chunk_count) => {
+                let mut indexed: Vec<_> = partition_by_size(source_files, chunk_count)
+                    .par_iter()
+                    .flat_map_iter(|chunk| {
+                        chunk
+                            .iter()
+                            .map(|&index| (index, compare(&source_files[index])))
+                    })
+                    .collect();
+                indexed.sort_unstable_by_key(|(index, _)| *index);
+                indexed.into_iter().map(|(_, outcome)| outcome).collect()
+            }
+            None => source_files.par_iter().map(compare).collect(),
+        };
+        progress.done();
+
+        let mut differences = Vec::new();
+        let mut errors = Vec::new();
+        for outcome in outcomes {
+            match outcome {
+                Ok(Some(diff)) => differences.push(diff),
+                Ok(None) => {}
+                Err(error) => errors.push(error),
+            }
+        }
+
+        Ok(self.finish(differences, errors, source_files.len(), dest_files))
+    }
+
+    /// 从两个 JSONL 读取器（每行一个 `FileMetadata`，空行忽略）计算差异
+    ///
+    /// 目标端需要完整读入以建立路径映射，源端逐行解析并立即比较，不保留源文件列表，
+    /// 适合源端或远程索引很大的情况。文件数量限制、哈希算法和匹配键冲突的检查与
+    /// `calculate_differences` 一致，源端在读到违规的那一行时报错。
+    pub fn calculate_differences_from_readers<R1: Read, R2: Read>(
+        &self,
+        source: R1,
+        dest: R2,
+    ) -> Result<DiffResult, String> {
+        let mut dest_files = Vec::new();
+        for (index, line) in BufReader::new(dest).lines().enumerate() {
+            if let Some(file) = parse_jsonl_line(line, index, "目标")? {
+                dest_files.push(file);
+            }
+        }
+        let exceeds_limit = |count: usize| self.max_files.is_some_and(|max| count > max);
+        if exceeds_limit(dest_files.len()) {
+            return Err("文件数量超过限制".to_string());
+        }
+
+        let unique_keys = self.keys_may_collide();
+        let visible_dest = self.visible_files(&dest_files);
+        if unique_keys {
+            self.check_unique_keys(&visible_dest, "目标")?;
+        }
+        let mut algorithms: HashSet<String> = visible_dest
+            .iter()
+            .filter_map(|file| hash_algorithm(&file.hash).map(str::to_string))
+            .collect();
+        if algorithms.len() > 1 {
+            return Err("哈希算法不一致".to_string());
+        }
+
+        let mut builder = self.incremental(&dest_files);
+        let mut source_count = 0;
+        let mut source_keys: HashMap<String, String> = HashMap::new();
+        for (index, line) in BufReader::new(source).lines().enumerate() {
+            let Some(file) = parse_jsonl_line(line, index, "源")? else {
+                continue;
+            };
+            source_count += 1;
+            if exceeds_limit(source_count) {
+                return Err("文件数量超过限制".to_string());
+            }
+            if self.skip_hidden && is_hidden_path(&file.path) {
+                continue;
+            }
+            if let Some(algorithm) = hash_algorithm(&file.hash) {
+                algorithms.insert(algorithm.to_string());
+                if algorithms.len() > 1 {
+                    return Err("哈希算法不一致".to_string());
+                }
+            }
+            if unique_keys
+                && let Some(previous) =
+                    source_keys.insert(self.path_key(&file.path), file.path.clone())
+            {
+                return Err(format!("源文件匹配键冲突: {} 与 {}", previous, file.path));
+            }
+            builder.push_source(&file);
+        }
+        Ok(builder.finalize())
+    }
+
+    /// 估算 `calculate_differences` 的峰值内存占用（字节），不实际计算差异
+    ///
+    /// 按最坏情况计算：目标端路径映射（含哈希表的空槽和控制字节）、并行比较的中间结果，
+    /// 以及每个源文件都产生一个差异时结果中的路径和哈希字符串。不包含输入本身占用的内存。
+    pub fn estimate_memory(&self, source: &[FileMetadata], dest: &[FileMetadata]) -> usize {
+        // 哈希表容量为 2 的幂且负载不超过 7/8，每个槽另有 1 字节控制位
+        let slot_size = size_of::<(String, &FileMetadata)>() + 1;
+        let buckets = if dest.is_empty() {
+            0
+        } else {
+            (dest.len() * 8 / 7 + 1).next_power_of_two()
+        };
+        let dest_paths: usize = dest.iter().map(|file| file.path.len()).sum();
+        let map_bytes = buckets * slot_size + dest_paths;
+
+        let average_dest_hash = if dest.is_empty() {
+            0
+        } else {
+            dest.iter().map(|file| file.hash.len()).sum::<usize>() / dest.len()
+        };
+        let source_strings: usize = source
+            .iter()
+            .map(|file| file.path.len() + file.hash.len() + average_dest_hash)
+            .sum();
+        let outcome_size = size_of::<Result<Option<FileDiff>, DiffError>>();
+        // 中间结果与最终结果在转换时同时存在
+        let result_bytes = source.len() * (outcome_size + size_of::<FileDiff>()) + source_strings;
+
+        map_bytes + result_bytes
+    }
+
+    /// 创建增量差异构建器，源文件通过 `IncrementalDiff::push_source` 逐个加入
+    pub fn incremental<'a>(&'a self, dest_files: &'a [FileMetadata]) -> IncrementalDiff<'a> {
+        let dest_map: HashMap<String, &FileMetadata> = dest_files
+            .iter()
+            .filter(|file| !(self.skip_hidden && is_hidden_path(&file.path)))
+            .map(|file| (self.path_key(&file.path), file))
             .collect();
+        let dest_files = self.visible_files(dest_files);
+        IncrementalDiff {
+            calculator: self,
+            statistics: DiffStatistics::tally(0, dest_files.len(), &[]),
+            dest_files,
+            dest_map,
+            differences: Vec::new(),
+            errors: Vec::new(),
+        }
+    }
+
+    /// 对逐个比较得到的差异做去重、路径改写、统计和区间拆分，生成最终结果
+    fn finish(
+        &self,
+        mut differences: Vec<FileDiff>,
+        mut errors: Vec<DiffError>,
+        total_source_files: usize,
+        dest_files: &[FileMetadata],
+    ) -> DiffResult {
+        if self.content_dedup {
+            self.mark_content_duplicates(&mut differences, dest_files);
+        }
+
+        if self.relative_root.is_some() {
+            for diff in &mut differences {
+                diff.path = self.output_path(&diff.path);
+                if let Some(copy_from) = &diff.copy_from {
+                    diff.copy_from = Some(self.output_path(copy_from));
+                }
+            }
+            for error in &mut errors {
+                error.path = self.output_path(&error.path);
+            }
+        }
 
         // 计算统计信息
-        let statistics = self.calculate_statistics(source_files, dest_files, &differences);
+        let statistics = DiffStatistics::tally(total_source_files, dest_files.len(), &differences);
 
-        Ok(DiffResult {
+        if let Some(range_size) = self.range_split {
+            differences = split_ranges(differences, range_size);
+        }
+
+        DiffResult {
             differences,
             statistics,
+            errors,
+        }
+    }
+
+    /// 检查两侧文件列表使用的哈希算法是否一致
+    ///
+    /// 算法由哈希的前缀识别（如 `blake3:...`、`sha256:...`），没有前缀的哈希不参与检查。
+    fn check_hash_algorithms(
+        &self,
+        source_files: &[FileMetadata],
+        dest_files: &[FileMetadata],
+    ) -> Result<(), String> {
+        let algorithms: HashSet<&str> = source_files
+            .iter()
+            .chain(dest_files.iter())
+            .filter_map(|file| hash_algorithm(&file.hash))
+            .collect();
+
+        if algorithms.len() > 1 {
+            return Err("哈希算法不一致".to_string());
+        }
+        Ok(())
+    }
+
+    /// 匹配键是否可能让同一侧的不同路径冲突（使用了 `FullPath` 以外的匹配键或路径标准化规则）
+    fn keys_may_collide(&self) -> bool {
+        !matches!(self.key_fn, KeyFn::FullPath) || self.path_matching != PathMatching::default()
+    }
+
+    /// 检查同一侧文件的匹配键是否唯一
+    fn check_unique_keys(&self, files: &[FileMetadata], side: &str) -> Result<(), String> {
+        let mut seen: HashMap<String, &str> = HashMap::with_capacity(files.len());
+        for file in files {
+            if let Some(previous) = seen.insert(self.path_key(&file.path), &file.path) {
+                return Err(format!(
+                    "{}文件匹配键冲突: {} 与 {}",
+                    side, previous, file.path
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// 比较单个文件；比较时 panic 会被捕获并记录为该文件的错误，避免整个进程被终止
+    fn compare_file_guarded(
+        &self,
+        source_file: &FileMetadata,
+        dest_map: &HashMap<String, &FileMetadata>,
+    ) -> Result<Option<FileDiff>, DiffError> {
+        panic::catch_unwind(AssertUnwindSafe(|| {
+            self.compare_file(source_file, dest_map)
+        }))
+        .unwrap_or_else(|payload| {
+            Err(format!(
+                "比较文件时发生内部错误: {}",
+                panic_message(&payload)
+            ))
+        })
+        .map_err(|message| DiffError {
+            path: source_file.path.clone(),
+            message,
         })
     }
 
@@ -46,53 +718,183 @@ impl DiffCalculator {
         &self,
         source_file: &FileMetadata,
         dest_map: &HashMap<String, &FileMetadata>,
-    ) -> Option<FileDiff> {
-        let normalized_path = self.normalize_path(&source_file.path);
+    ) -> Result<Option<FileDiff>, String> {
+        let normalized_path = self.path_key(&source_file.path);
 
-        match dest_map.get(&normalized_path) {
-            Some(dest_file) => {
-                // 文件存在，检查是否需要更新
-                if self.needs_update(source_file, dest_file) {
-                    Some(FileDiff {
-                        path: source_file.path.clone(),
-                        operation: "update".to_string(),
-                        source_hash: source_file.hash.clone(),
-                        dest_hash: dest_file.hash.clone(),
-                        size: source_file.size,
-                    })
-                } else {
-                    // 文件相同，无需更新
-                    None
+        let diff = match dest_map.get(&normalized_path) {
+            // 只追加模式下不覆盖目标端已有的文件
+            Some(_) if self.mode == SyncMode::AdditiveOnly => None,
+            Some(dest_file)
+                if dest_file.hash.is_empty()
+                    && self.comparator.is_none()
+                    && !self.compare_mtime =>
+            {
+                match self.empty_dest_hash_policy {
+                    EmptyDestHashPolicy::TreatAsUpdate => self
+                        .update_reason(source_file, dest_file)?
+                        .map(|reason| self.update_diff(source_file, dest_file, reason)),
+                    EmptyDestHashPolicy::TreatAsCreate => {
+                        Some(self.create_diff(source_file, DiffReason::DestHashMissing))
+                    }
+                    EmptyDestHashPolicy::Skip => None,
                 }
             }
-            None => {
-                // 文件不存在，需要创建
-                Some(FileDiff {
-                    path: source_file.path.clone(),
-                    operation: "create".to_string(),
-                    source_hash: source_file.hash.clone(),
-                    dest_hash: String::new(),
-                    size: source_file.size,
-                })
+            Some(dest_file) => {
+                // 文件存在，检查是否需要更新；文件相同时无需更新
+                self.update_reason(source_file, dest_file)?
+                    .map(|reason| self.update_diff(source_file, dest_file, reason))
+            }
+            // 文件不存在，需要创建
+            None => Some(self.create_diff(source_file, DiffReason::NewFile)),
+        };
+        Ok(diff)
+    }
+
+    /// 生成创建操作
+    fn create_diff(&self, source_file: &FileMetadata, reason: DiffReason) -> FileDiff {
+        FileDiff {
+            path: source_file.path.clone(),
+            operation: "create".to_string(),
+            source_hash: source_file.hash.clone(),
+            dest_hash: String::new(),
+            size: source_file.size,
+            copy_from: None,
+            explanation: self.explain(reason),
+            range: None,
+            verify_hash: self.verify_hash_for(source_file),
+        }
+    }
+
+    /// 生成更新操作
+    fn update_diff(
+        &self,
+        source_file: &FileMetadata,
+        dest_file: &FileMetadata,
+        reason: DiffReason,
+    ) -> FileDiff {
+        FileDiff {
+            path: source_file.path.clone(),
+            operation: "update".to_string(),
+            source_hash: source_file.hash.clone(),
+            dest_hash: dest_file.hash.clone(),
+            size: source_file.size,
+            copy_from: None,
+            explanation: self.explain(reason),
+            range: None,
+            verify_hash: self.verify_hash_for(source_file),
+        }
+    }
+
+    /// 将内容已存在于目标端其他路径的创建/更新操作改为 `no_op`
+    fn mark_content_duplicates(&self, differences: &mut [FileDiff], dest_files: &[FileMetadata]) {
+        let mut content_map: HashMap<(&str, i64), &FileMetadata> = HashMap::new();
+        for file in dest_files.iter().filter(|file| !file.hash.is_empty()) {
+            content_map.entry((&file.hash, file.size)).or_insert(file);
+        }
+
+        for diff in differences
+            .iter_mut()
+            .filter(|diff| diff.operation == "create" || diff.operation == "update")
+        {
+            if let Some(existing) = content_map.get(&(diff.source_hash.as_str(), diff.size))
+                && self.path_key(&existing.path) != self.path_key(&diff.path)
+            {
+                diff.operation = "no_op".to_string();
+                diff.copy_from = Some(existing.path.clone());
+                diff.explanation = self.explain(DiffReason::ContentExists);
             }
         }
     }
 
-    /// 判断文件是否需要更新
-    fn needs_update(&self, source_file: &FileMetadata, dest_file: &FileMetadata) -> bool {
-        // 主要比较哈希值
-        if source_file.hash != dest_file.hash {
-            return true;
+    /// 判断文件是否需要更新，需要时返回原因
+    fn update_reason(
+        &self,
+        source_file: &FileMetadata,
+        dest_file: &FileMetadata,
+    ) -> Result<Option<DiffReason>, String> {
+        if let Some(comparator) = &self.comparator {
+            return Ok(comparator(source_file, dest_file).then_some(DiffReason::CustomComparator));
+        }
+
+        let content_reason = if self.compare_mtime {
+            self.content_changed_by_mtime(source_file, dest_file)?
+        } else if self.weak_checksum_first
+            && let (Some(source_weak), Some(dest_weak)) =
+                (source_file.weak_checksum, dest_file.weak_checksum)
+            && source_weak != dest_weak
+        {
+            Some(DiffReason::WeakChecksumDiffers)
+        } else {
+            content_changed_by_hash(source_file, dest_file)
+        };
+        if content_reason.is_some() {
+            return Ok(content_reason);
+        }
+
+        // 启用权限比较时，权限不同也需要更新
+        if self.compare_permissions
+            && normalize_permissions(&source_file.permissions)
+                != normalize_permissions(&dest_file.permissions)
+        {
+            return Ok(Some(DiffReason::PermissionsDiffer));
         }
 
-        // 如果哈希相同但大小不同，也需要更新
+        Ok(None)
+    }
+
+    /// 按大小和修改时间判断内容是否变化，修改时间不可用时按策略处理
+    fn content_changed_by_mtime(
+        &self,
+        source_file: &FileMetadata,
+        dest_file: &FileMetadata,
+    ) -> Result<Option<DiffReason>, String> {
         if source_file.size != dest_file.size {
-            return true;
+            return Ok(Some(DiffReason::SizeDiffers));
+        }
+
+        match (
+            parse_mtime(&source_file.modified_time),
+            parse_mtime(&dest_file.modified_time),
+        ) {
+            (Some(source_mtime), Some(dest_mtime)) => {
+                Ok((source_mtime != dest_mtime).then_some(DiffReason::MtimeDiffers))
+            }
+            _ => match self.missing_mtime_policy {
+                MissingMtimePolicy::AlwaysUpdate => Ok(Some(DiffReason::MtimeMissing)),
+                MissingMtimePolicy::FallbackToHash => {
+                    Ok(content_changed_by_hash(source_file, dest_file))
+                }
+                MissingMtimePolicy::Error => Err(format!(
+                    "修改时间缺失或无法解析: {:?} / {:?}",
+                    source_file.modified_time, dest_file.modified_time
+                )),
+            },
+        }
+    }
+
+    /// 启用 `verify_hash` 时返回源文件的完整 SHA256
+    fn verify_hash_for(&self, source_file: &FileMetadata) -> Option<String> {
+        if !self.verify_hash {
+            return None;
         }
+        full_sha256(&source_file.hash)
+    }
+
+    /// 启用说明时返回原因的说明文字
+    fn explain(&self, reason: DiffReason) -> Option<String> {
+        self.explanations.then(|| reason.explanation().to_string())
+    }
 
-        // 可以添加更多的比较条件，如修改时间等
-        // 这里暂时只比较哈希和大小
-        false
+    /// 输出到差异结果中的路径（设置了 `with_relative_to` 时转为相对路径）
+    fn output_path(&self, path: &str) -> String {
+        let Some(root) = &self.relative_root else {
+            return path.to_string();
+        };
+        let normalized = self.normalize_path(path);
+        match normalized.strip_prefix(root.as_str()) {
+            Some(rest) if rest.starts_with('/') => rest.trim_start_matches('/').to_string(),
+            _ => path.to_string(),
+        }
     }
 
     /// 标准化路径（处理不同操作系统的路径分隔符）
@@ -101,69 +903,178 @@ impl DiffCalculator {
         path.replace('\\', "/")
     }
 
-    /// 计算统计信息
-    fn calculate_statistics(
+    /// 用于匹配两侧文件的路径键
+    fn path_key(&self, path: &str) -> String {
+        let key = self.key_fn.apply(&self.normalize_path(path));
+        self.path_matching.key(&key)
+    }
+
+    /// 直接比较磁盘上的两个目录
+    ///
+    /// 用 `scan_directory` 扫描两侧目录（路径相对于各自的根目录），再计算差异，
+    /// 结果中包含需要删除的文件（只追加模式下不检查删除），统计信息按全部差异计算。
+    /// 扫描时无法读取的文件不参与比较，作为错误追加到结果中。
+    pub fn diff_directories(
         &self,
-        source_files: &[FileMetadata],
-        dest_files: &[FileMetadata],
-        differences: &[FileDiff],
-    ) -> DiffStatistics {
-        let mut files_to_create = 0;
-        let mut files_to_update = 0;
-        let mut files_to_delete = 0;
-        let mut total_size = 0;
-
-        for diff in differences {
-            match diff.operation.as_str() {
-                "create" => files_to_create += 1,
-                "update" => files_to_update += 1,
-                "delete" => files_to_delete += 1,
-                _ => {}
-            }
-            total_size += diff.size;
-        }
+        source_root: &str,
+        dest_root: &str,
+        options: &ScanOptions,
+    ) -> Result<DiffResult, String> {
+        let (source_files, source_errors) = scan_directory(source_root, options)?;
+        let (dest_files, dest_errors) = scan_directory(dest_root, options)?;
 
-        DiffStatistics {
-            total_source_files: source_files.len(),
-            total_dest_files: dest_files.len(),
-            files_to_create,
-            files_to_update,
-            files_to_delete,
-            total_size,
+        let mut result = self.calculate_differences(&source_files, &dest_files)?;
+        let deletes = self.find_files_to_delete(&source_files, &dest_files)?;
+        let delete_statistics = DiffStatistics::tally(0, 0, &deletes);
+        result.statistics.files_to_delete += delete_statistics.files_to_delete;
+        result.statistics.total_size += delete_statistics.total_size;
+        result.statistics.deletions_considered = self.mode != SyncMode::AdditiveOnly;
+        result.differences.extend(deletes);
+
+        let scan_errors = source_errors
+            .into_iter()
+            .map(|error| ("源", error))
+            .chain(dest_errors.into_iter().map(|error| ("目标", error)));
+        for (side, error) in scan_errors {
+            result.errors.push(DiffError {
+                path: error.path,
+                message: format!("扫描{}目录失败: {}", side, error.message),
+            });
         }
+        Ok(result)
     }
 
     /// 查找需要删除的文件（在目标目录中存在但源目录中不存在）
+    ///
+    /// 匹配键冲突的检查与 `calculate_differences` 一致。
     pub fn find_files_to_delete(
         &self,
         source_files: &[FileMetadata],
         dest_files: &[FileMetadata],
-    ) -> Vec<FileDiff> {
+    ) -> Result<Vec<FileDiff>, String> {
+        if self.mode == SyncMode::AdditiveOnly {
+            return Ok(Vec::new());
+        }
+        let source_files = &*self.visible_files(source_files);
+        let dest_files = &*self.visible_files(dest_files);
+        if self.keys_may_collide() {
+            self.check_unique_keys(source_files, "源")?;
+            self.check_unique_keys(dest_files, "目标")?;
+        }
+
         // 创建源文件的哈希映射
         let source_map: HashMap<String, &FileMetadata> = source_files
             .iter()
-            .map(|file| (self.normalize_path(&file.path), file))
+            .map(|file| (self.path_key(&file.path), file))
             .collect();
 
-        dest_files
+        let deletes = dest_files
             .par_iter()
             .filter_map(|dest_file| {
-                let normalized_path = self.normalize_path(&dest_file.path);
+                let normalized_path = self.path_key(&dest_file.path);
                 if !source_map.contains_key(&normalized_path) {
                     Some(FileDiff {
-                        path: dest_file.path.clone(),
+                        path: self.output_path(&dest_file.path),
                         operation: "delete".to_string(),
                         source_hash: String::new(),
                         dest_hash: dest_file.hash.clone(),
                         size: dest_file.size,
+                        copy_from: None,
+                        explanation: self.explain(DiffReason::NotInSource),
+                        range: None,
+                        verify_hash: None,
                     })
                 } else {
                     None
                 }
             })
+            .collect();
+        Ok(deletes)
+    }
+
+    /// 与内容寻址存储（按哈希判断是否存在）比较
+    ///
+    /// 哈希不在 `present_hashes` 中的源文件输出 `create`（需要上传），已存在的输出
+    /// `no_op`（只需引用）；同一计划中重复出现的新内容只上传一次。路径仅作为引用。
+    pub fn diff_against_cas(
+        &self,
+        source: &[FileMetadata],
+        present_hashes: &HashSet<String>,
+    ) -> Vec<FileDiff> {
+        let mut uploaded: HashSet<&str> = HashSet::new();
+        source
+            .iter()
+            .map(|file| {
+                let present =
+                    present_hashes.contains(&file.hash) || !uploaded.insert(file.hash.as_str());
+                let (operation, reason) = if present {
+                    ("no_op", DiffReason::ContentExists)
+                } else {
+                    ("create", DiffReason::NewFile)
+                };
+                FileDiff {
+                    path: file.path.clone(),
+                    operation: operation.to_string(),
+                    source_hash: file.hash.clone(),
+                    dest_hash: if present {
+                        file.hash.clone()
+                    } else {
+                        String::new()
+                    },
+                    size: file.size,
+                    copy_from: None,
+                    explanation: self.explain(reason),
+                    range: None,
+                    verify_hash: None,
+                }
+            })
+            .collect()
+    }
+
+    /// 源文件列表中有、目标文件列表中没有的内容哈希（忽略路径）
+    pub fn new_content_hashes(source: &[FileMetadata], dest: &[FileMetadata]) -> HashSet<String> {
+        let dest_hashes: HashSet<&str> = dest.iter().map(|file| file.hash.as_str()).collect();
+        source
+            .iter()
+            .filter(|file| !dest_hashes.contains(file.hash.as_str()))
+            .map(|file| file.hash.clone())
             .collect()
     }
 
+    /// 增量同步时需要拉取的分块：源端分块清单（如 `Compressor::chunk_and_store` 的结果）中
+    /// 目标端没有的哈希
+    ///
+    /// 保持在清单中首次出现的顺序，清单内重复的分块只拉取一次。
+    pub fn missing_chunks(source_recipe: &[String], have: &HashSet<String>) -> Vec<String> {
+        let mut requested = HashSet::new();
+        source_recipe
+            .iter()
+            .filter(|hash| !have.contains(*hash) && requested.insert(hash.as_str()))
+            .cloned()
+            .collect()
+    }
+
+    /// 逐块比较两个文件的内容是否完全相同
+    ///
+    /// 大小不同时直接返回 false；否则两个文件同步按块读取，遇到第一个不同的块即返回，
+    /// 不再读取剩余部分。适合哈希不可信但需要字节级确认的场景。
+    pub fn files_equal_streaming(path_a: &str, path_b: &str) -> Result<bool, String> {
+        let open =
+            |path: &str| fs::File::open(path).map_err(|e| format!("打开文件失败 {}: {}", path, e));
+        let len = |file: &fs::File, path: &str| {
+            file.metadata()
+                .map(|metadata| metadata.len())
+                .map_err(|e| format!("读取文件元数据失败 {}: {}", path, e))
+        };
+        let (file_a, file_b) = (open(path_a)?, open(path_b)?);
+        if len(&file_a, path_a)? != len(&file_b, path_b)? {
+            return Ok(false);
+        }
+
+        readers_equal(file_a, file_b, COMPARE_BLOCK_SIZE)
+            .map_err(|e| format!("读取文件失败 {} / {}: {}", path_a, path_b, e))
+    }
+
     /// 按优先级排序差异列表
     pub fn sort_by_priority(&self, differences: &mut [FileDiff]) {
         differences.sort_by(|a, b| {
@@ -179,8 +1090,89 @@ impl DiffCalculator {
         });
     }
 
+    /// 估算单个差异操作的代价
+    ///
+    /// 创建和更新需要传输压缩后的内容，代价为固定代价加上估算的传输字节代价；
+    /// 删除和 `no_op`（服务端复制）不传输内容，只计固定代价。
+    pub fn estimate_cost(diff: &FileDiff, model: &CostModel) -> f64 {
+        let transferred_bytes = match diff.operation.as_str() {
+            "create" | "update" => diff.size.max(0) as f64 * model.compression_ratio,
+            _ => 0.0,
+        };
+        model.per_file_weight + transferred_bytes * model.per_byte_weight
+    }
+
+    /// 计算执行顺序，使目标磁盘的预计占用始终不超过 `free_bytes`
+    ///
+    /// 写入操作（create、update、带 `copy_from` 的 no_op）按原顺序执行，放不下时先删除
+    /// 最大的可删除文件腾出空间；仍被待执行复制依赖（作为 `copy_from`）的文件不会提前删除，
+    /// 能解除这种依赖的写入优先执行。update 按原子替换估算：执行时需要 `size` 字节的临时空间，
+    /// 完成后占用不变。剩余的删除保持原顺序排在最后；无论怎样排序都放不下时返回错误。
+    pub fn order_for_space(diff: &DiffResult, free_bytes: i64) -> Result<Vec<FileDiff>, String> {
+        let (mut pending_deletes, mut pending_writes): (Vec<&FileDiff>, Vec<&FileDiff>) = diff
+            .differences
+            .iter()
+            .partition(|diff| diff.operation == "delete");
+        let copy_source =
+            |diff: &FileDiff| diff.copy_from.as_ref().map(|path| path.replace('\\', "/"));
+
+        let mut ordered = Vec::with_capacity(diff.differences.len());
+        let mut available = free_bytes;
+        while !pending_writes.is_empty() {
+            let blocked: HashSet<String> = pending_writes
+                .iter()
+                .filter_map(|write| copy_source(write))
+                .collect();
+            let is_blocked = |delete: &FileDiff| blocked.contains(&delete.path.replace('\\', "/"));
+            let fits = |write: &&FileDiff| disk_usage(write).0 <= available;
+            let unblocks_delete = |write: &&FileDiff| {
+                copy_source(write).is_some_and(|source| {
+                    pending_deletes
+                        .iter()
+                        .any(|delete| delete.path.replace('\\', "/") == source)
+                })
+            };
+
+            let next_write = pending_writes
+                .iter()
+                .position(|write| fits(write) && unblocks_delete(write))
+                .or_else(|| pending_writes.iter().position(fits));
+            if let Some(index) = next_write {
+                let write = pending_writes.remove(index);
+                available -= disk_usage(write).1;
+                ordered.push(write.clone());
+                continue;
+            }
+
+            let largest_delete = pending_deletes
+                .iter()
+                .enumerate()
+                .filter(|(_, delete)| !is_blocked(delete))
+                .max_by_key(|(_, delete)| delete.size)
+                .map(|(index, _)| index);
+            let Some(index) = largest_delete else {
+                let write = pending_writes
+                    .iter()
+                    .min_by_key(|write| disk_usage(write).0)
+                    .expect("pending_writes 非空");
+                return Err(format!(
+                    "目标磁盘空间不足: {} 需要 {} 字节，最多可用 {} 字节",
+                    write.path,
+                    disk_usage(write).0,
+                    available
+                ));
+            };
+            let delete = pending_deletes.remove(index);
+            available += delete.size.max(0);
+            ordered.push(delete.clone());
+        }
+
+        ordered.extend(pending_deletes.into_iter().cloned());
+        Ok(ordered)
+    }
+
     /// 获取文件优先级
-    fn get_file_priority(&self, diff: &FileDiff) -> i32 {
+    pub(crate) fn get_file_priority(&self, diff: &FileDiff) -> i32 {
         let mut priority = 0;
 
         // 操作类型优先级
@@ -202,25 +1194,25 @@ impl DiffCalculator {
 
         // 文件类型优先级
         let path = Path::new(&diff.path);
-        if let Some(extension) = path.extension() {
-            if let Some(ext_str) = extension.to_str() {
-                match ext_str.to_lowercase().as_str() {
-                    "json" | "yaml" | "yml" | "toml" | "ini" => priority += 30, // 配置文件
-                    "md" | "txt" | "readme" => priority += 25,                  // 文档文件
-                    "go" | "rs" | "py" | "js" | "ts" => priority += 20,         // 源代码文件
-                    _ => {}
-                }
+        if let Some(extension) = path.extension()
+            && let Some(ext_str) = extension.to_str()
+        {
+            match ext_str.to_lowercase().as_str() {
+                "json" | "yaml" | "yml" | "toml" | "ini" => priority += 30, // 配置文件
+                "md" | "txt" | "readme" => priority += 25,                  // 文档文件
+                "go" | "rs" | "py" | "js" | "ts" => priority += 20,         // 源代码文件
+                _ => {}
             }
         }
 
         // 特殊文件名优先级
-        if let Some(filename) = path.file_name() {
-            if let Some(name_str) = filename.to_str() {
-                match name_str.to_lowercase().as_str() {
-                    "makefile" | "dockerfile" | "readme.md" => priority += 40,
-                    "package.json" | "go.mod" | "cargo.toml" => priority += 35,
-                    _ => {}
-                }
+        if let Some(filename) = path.file_name()
+            && let Some(name_str) = filename.to_str()
+        {
+            match name_str.to_lowercase().as_str() {
+                "makefile" | "dockerfile" | "readme.md" => priority += 40,
+                "package.json" | "go.mod" | "cargo.toml" => priority += 35,
+                _ => {}
             }
         }
 
@@ -228,16 +1220,359 @@ impl DiffCalculator {
     }
 }
 
-impl Default for DiffCalculator {
-    fn default() -> Self {
-        Self::new()
+/// 将文件按大小均衡地划分为最多 `chunk_count` 组，返回每组的文件下标
+///
+/// 使用最长处理时间优先（LPT）贪心：按大小降序依次放入当前总量最小的组。
+/// 每个文件至少按 1 字节计，使空文件的比较开销也被计入。
+pub(crate) fn partition_by_size(files: &[FileMetadata], chunk_count: usize) -> Vec<Vec<usize>> {
+    let chunk_count = chunk_count.clamp(1, files.len().max(1));
+    let mut order: Vec<usize> = (0..files.len()).collect();
+    order.sort_by_key(|&index| Reverse(files[index].size));
+
+    let mut chunks = vec![Vec::new(); chunk_count];
+    let mut loads: BinaryHeap<Reverse<(u64, usize)>> =
+        (0..chunk_count).map(|chunk| Reverse((0, chunk))).collect();
+    for index in order {
+        let Reverse((load, chunk)) = loads.pop().expect("至少有一个分组");
+        chunks[chunk].push(index);
+        loads.push(Reverse((load + files[index].size.max(1) as u64, chunk)));
     }
+
+    chunks
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+/// 增量差异构建器（见 `DiffCalculator::incremental`）
+///
+/// 适合边扫描边比较的场景：每加入一个源文件立即与目标端比较，
+/// 随时可用 `current_statistics` 查看到目前为止的统计信息。
+pub struct IncrementalDiff<'a> {
+    calculator: &'a DiffCalculator,
+    dest_files: Cow<'a, [FileMetadata]>,
+    dest_map: HashMap<String, &'a FileMetadata>,
+    differences: Vec<FileDiff>,
+    errors: Vec<DiffError>,
+    statistics: DiffStatistics,
+}
+
+impl IncrementalDiff<'_> {
+    /// 加入一个源文件并与目标端比较
+    pub fn push_source(&mut self, source_file: &FileMetadata) {
+        if self.calculator.skip_hidden && is_hidden_path(&source_file.path) {
+            return;
+        }
+        self.statistics.total_source_files += 1;
+        match self
+            .calculator
+            .compare_file_guarded(source_file, &self.dest_map)
+        {
+            Ok(Some(diff)) => {
+                self.statistics.record(&diff);
+                self.differences.push(diff);
+            }
+            Ok(None) => {}
+            Err(error) => self.errors.push(error),
+        }
+    }
+
+    /// 到目前为止已加入的源文件的统计信息快照
+    ///
+    /// 启用内容去重时，`finalize` 会把部分差异改为 no_op，最终统计可能小于快照。
+    pub fn current_statistics(&self) -> DiffStatistics {
+        self.statistics.clone()
+    }
+
+    /// 结束构建，生成与 `calculate_differences` 一致的差异结果
+    pub fn finalize(self) -> DiffResult {
+        self.calculator.finish(
+            self.differences,
+            self.errors,
+            self.statistics.total_source_files,
+            &self.dest_files,
+        )
+    }
+}
+
+/// 同步按块读取两个读取器并比较，遇到不同的块立即返回 false
+fn readers_equal<A: Read, B: Read>(mut a: A, mut b: B, block_size: usize) -> std::io::Result<bool> {
+    let mut block_a = vec![0u8; block_size];
+    let mut block_b = vec![0u8; block_size];
+    loop {
+        let len_a = read_block(&mut a, &mut block_a)?;
+        let len_b = read_block(&mut b, &mut block_b)?;
+        if block_a[..len_a] != block_b[..len_b] {
+            return Ok(false);
+        }
+        if len_a < block_size {
+            return Ok(true);
+        }
+    }
+}
+
+/// 尽量读满 `buf`，只有到达末尾时才会少于缓冲区长度
+fn read_block<R: Read>(reader: &mut R, buf: &mut [u8]) -> std::io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
+
+/// 将超过 `range_size` 的创建/更新差异拆分为多个区间条目
+fn split_ranges(differences: Vec<FileDiff>, range_size: u64) -> Vec<FileDiff> {
+    let mut split = Vec::with_capacity(differences.len());
+    for diff in differences {
+        let size = diff.size.max(0) as u64;
+        let splittable = diff.operation == "create" || diff.operation == "update";
+        if !splittable || size <= range_size {
+            split.push(diff);
+            continue;
+        }
+
+        let mut offset = 0;
+        while offset < size {
+            let len = range_size.min(size - offset);
+            split.push(FileDiff {
+                size: len as i64,
+                range: Some(ByteRange { offset, len }),
+                ..diff.clone()
+            });
+            offset += len;
+        }
+    }
+    split
+}
+
+/// 按哈希和大小判断内容是否变化
+fn content_changed_by_hash(
+    source_file: &FileMetadata,
+    dest_file: &FileMetadata,
+) -> Option<DiffReason> {
+    // 主要比较哈希值；哈希相同但大小不同，也需要更新
+    if source_file.hash != dest_file.hash {
+        Some(DiffReason::HashDiffers)
+    } else if source_file.size != dest_file.size {
+        Some(DiffReason::SizeDiffers)
+    } else {
+        None
+    }
+}
+
+/// 将修改时间解析为 Unix 秒数
+///
+/// 支持纯数字的 Unix 秒数，以及 `YYYY-MM-DDTHH:MM:SS[.小数][Z|±HH:MM]` 形式的
+/// RFC 3339 时间（小数秒被忽略，缺少时区时按 UTC 处理）。各字段必须是固定位数的数字，
+/// 时区偏移的小时为 0..=23、分钟为 0..=59，秒最大为 60（闰秒）。
+fn parse_mtime(mtime: &str) -> Option<i64> {
+    let mtime = mtime.trim();
+    if mtime.is_empty() {
+        return None;
+    }
+    if let Ok(seconds) = mtime.parse::<i64>() {
+        return Some(seconds);
+    }
+
+    let bytes = mtime.as_bytes();
+    if bytes.len() < 19 || !matches!(bytes[10], b'T' | b't' | b' ') {
+        return None;
+    }
+    let field = |range: std::ops::Range<usize>| {
+        let digits = mtime.get(range)?;
+        if !digits.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        digits.parse::<i64>().ok()
+    };
+    let (year, month, day) = (field(0..4)?, field(5..7)?, field(8..10)?);
+    let (hour, minute, second) = (field(11..13)?, field(14..16)?, field(17..19)?);
+    if bytes[4] != b'-' || bytes[7] != b'-' || bytes[13] != b':' || bytes[16] != b':' {
+        return None;
+    }
+    if !(1..=12).contains(&month) || hour > 23 || minute > 59 || second > 60 {
+        return None;
+    }
+    let leap_year = year % 4 == 0 && (year % 100 != 0 || year % 400 == 0);
+    let days_in_month = match month {
+        2 if leap_year => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    };
+    if !(1..=days_in_month).contains(&day) {
+        return None;
+    }
+
+    // 跳过小数秒，剩余部分为时区
+    let mut rest = &mtime[19..];
+    if let Some(fraction) = rest.strip_prefix('.') {
+        rest = fraction.trim_start_matches(|c: char| c.is_ascii_digit());
+    }
+    let offset = match rest.as_bytes() {
+        b"" | b"Z" | b"z" => 0,
+        [sign @ (b'+' | b'-'), h1, h2, b':', m1, m2] => {
+            let digit = |b: &u8| b.is_ascii_digit().then(|| i64::from(b - b'0'));
+            let hours = digit(h1)? * 10 + digit(h2)?;
+            let minutes = digit(m1)? * 10 + digit(m2)?;
+            if hours > 23 || minutes > 59 {
+                return None;
+            }
+            let offset = hours * 3600 + minutes * 60;
+            if *sign == b'-' { -offset } else { offset }
+        }
+        _ => return None,
+    };
+
+    // 公历日期转换为自 1970-01-01 起的天数
+    let (y, m) = if month <= 2 {
+        (year - 1, month + 9)
+    } else {
+        (year, month - 3)
+    };
+    let era = y.div_euclid(400);
+    let year_of_era = y - era * 400;
+    let day_of_year = (153 * m + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146_097 + day_of_era - 719_468;
+
+    days.checked_mul(86_400)?
+        .checked_add(hour * 3600 + minute * 60 + second)?
+        .checked_sub(offset)
+}
+
+/// 从 panic 负载中提取错误信息
+pub(crate) fn panic_message(payload: &Box<dyn std::any::Any + Send>) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "未知错误".to_string()
+    }
+}
+
+/// 辅助函数：操作对目标磁盘占用的影响，返回 (执行时需要的可用空间, 完成后的占用变化)
+fn disk_usage(diff: &FileDiff) -> (i64, i64) {
+    let size = diff.size.max(0);
+    match diff.operation.as_str() {
+        "create" => (size, size),
+        "update" => (size, 0),
+        "no_op" if diff.copy_from.is_some() => (size, size),
+        "delete" => (0, -size),
+        _ => (0, 0),
+    }
+}
+
+/// 将权限字符串标准化为四位八进制形式（如 `"0644"`）
+///
+/// 支持 `"644"`、`"0644"`、`"0o644"` 等八进制写法，以及 `"-rw-r--r--"`、
+/// `"rwxr-xr-x"` 等 `ls -l` 风格的符号写法（含 setuid/setgid/sticky 位）。
+/// 无法识别的字符串原样返回（去除首尾空白）。
+pub fn normalize_permissions(permissions: &str) -> String {
+    let trimmed = permissions.trim();
+    parse_octal_permissions(trimmed)
+        .or_else(|| parse_symbolic_permissions(trimmed))
+        .map(|mode| format!("{:04o}", mode))
+        .unwrap_or_else(|| trimmed.to_string())
+}
+
+/// 解析八进制权限写法
+fn parse_octal_permissions(permissions: &str) -> Option<u32> {
+    let digits = permissions
+        .strip_prefix("0o")
+        .or_else(|| permissions.strip_prefix("0O"))
+        .unwrap_or(permissions);
+    if digits.is_empty() || digits.len() > 5 {
+        return None;
+    }
+    u32::from_str_radix(digits, 8)
+        .ok()
+        .filter(|mode| *mode <= 0o7777)
+}
+
+/// 解析符号权限写法（可带一个文件类型前缀字符）
+fn parse_symbolic_permissions(permissions: &str) -> Option<u32> {
+    let chars: Vec<char> = permissions.chars().collect();
+    let bits = match chars.len() {
+        9 => &chars[..],
+        10 => &chars[1..],
+        _ => return None,
+    };
+
+    let mut mode = 0u32;
+    for (group, triplet) in bits.chunks(3).enumerate() {
+        let shift = 6 - 3 * group as u32;
+        match triplet[0] {
+            'r' => mode |= 0o4 << shift,
+            '-' => {}
+            _ => return None,
+        }
+        match triplet[1] {
+            'w' => mode |= 0o2 << shift,
+            '-' => {}
+            _ => return None,
+        }
+        // 执行位可能与 setuid(s/S)、setgid(s/S)、sticky(t/T) 合并显示
+        let special = 0o4000 >> group;
+        match (triplet[2], group) {
+            ('x', _) => mode |= 0o1 << shift,
+            ('-', _) => {}
+            ('s', 0 | 1) => mode |= (0o1 << shift) | special,
+            ('S', 0 | 1) => mode |= special,
+            ('t', 2) => mode |= 0o1 | special,
+            ('T', 2) => mode |= special,
+            _ => return None,
+        }
+    }
+
+    Some(mode)
+}
+
+/// 提取哈希的算法前缀，例如 `"blake3:ab12"` 返回 `Some("blake3")`
+fn hash_algorithm(hash: &str) -> Option<&str> {
+    hash.split_once(':').map(|(algorithm, _)| algorithm)
+}
+
+/// 解析 JSONL 的第 `index` 行（从 0 开始），空行返回 `None`
+fn parse_jsonl_line(
+    line: std::io::Result<String>,
+    index: usize,
+    side: &str,
+) -> Result<Option<FileMetadata>, String> {
+    let line = line.map_err(|e| format!("读取{}文件列表失败: {}", side, e))?;
+    if line.trim().is_empty() {
+        return Ok(None);
+    }
+    serde_json::from_str(&line)
+        .map(Some)
+        .map_err(|e| format!("解析{}文件列表第 {} 行失败: {}", side, index + 1, e))
+}
+
+/// 哈希为完整的 SHA256 时返回其小写十六进制形式（去掉 `sha256:` 前缀）
+fn full_sha256(hash: &str) -> Option<String> {
+    let digest = match hash.split_once(':') {
+        Some((algorithm, digest)) if algorithm.eq_ignore_ascii_case("sha256") => digest,
+        Some(_) => return None,
+        None => hash,
+    };
+    (digest.len() == 64 && digest.bytes().all(|b| b.is_ascii_hexdigit()))
+        .then(|| digest.to_ascii_lowercase())
+}
+
+impl Default for DiffCalculator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
     use crate::FileMetadata;
+    use crate::tests::test_diff;
 
     fn create_test_file(path: &str, hash: &str, size: i64) -> FileMetadata {
         FileMetadata {
@@ -246,6 +1581,7 @@ mod tests {
             size,
             modified_time: "2023-01-01T00:00:00Z".to_string(),
             permissions: "0644".to_string(),
+            weak_checksum: None,
         }
     }
 
@@ -297,6 +1633,743 @@ mod tests {
         assert_eq!(result.statistics.files_to_update, 0);
     }
 
+    #[test]
+    fn test_content_dedup_hint() {
+        let source_files = vec![
+            create_test_file("build/app.js", "hash_app", 300),
+            create_test_file("build/vendor.js", "hash_vendor_v2", 800),
+            create_test_file("build/new.js", "hash_new", 50),
+        ];
+        let dest_files = vec![
+            create_test_file("old/app.js", "hash_app", 300),
+            create_test_file("build/vendor.js", "hash_vendor_v1", 700),
+            create_test_file("cache/vendor.js", "hash_vendor_v2", 800),
+        ];
+
+        let result = DiffCalculator::new()
+            .with_content_dedup(true)
+            .calculate_differences(&source_files, &dest_files)
+            .unwrap();
+
+        assert_eq!(result.differences.len(), 3);
+        let created = &result.differences[0];
+        assert_eq!(created.operation, "no_op");
+        assert_eq!(created.copy_from.as_deref(), Some("old/app.js"));
+        let updated = &result.differences[1];
+        assert_eq!(updated.operation, "no_op");
+        assert_eq!(updated.copy_from.as_deref(), Some("cache/vendor.js"));
+        let new_file = &result.differences[2];
+        assert_eq!(new_file.operation, "create");
+        assert!(new_file.copy_from.is_none());
+
+        assert_eq!(result.statistics.files_to_create, 1);
+        assert_eq!(result.statistics.files_to_update, 0);
+        assert_eq!(result.statistics.total_size, 50);
+
+        // 默认不启用
+        let result = DiffCalculator::new()
+            .calculate_differences(&source_files, &dest_files)
+            .unwrap();
+        assert!(
+            result
+                .differences
+                .iter()
+                .all(|diff| diff.copy_from.is_none())
+        );
+
+        // 复制来源与目标路径一样转为相对路径
+        let source_files = vec![create_test_file("/srv/build/app.js", "hash_app", 300)];
+        let dest_files = vec![create_test_file("/srv/old/app.js", "hash_app", 300)];
+        let result = DiffCalculator::new()
+            .with_content_dedup(true)
+            .with_relative_to("/srv")
+            .calculate_differences(&source_files, &dest_files)
+            .unwrap();
+        assert_eq!(result.differences[0].path, "build/app.js");
+        assert_eq!(
+            result.differences[0].copy_from.as_deref(),
+            Some("old/app.js")
+        );
+    }
+
+    #[test]
+    fn test_estimate_cost() {
+        let model = CostModel {
+            per_byte_weight: 0.01,
+            per_file_weight: 50.0,
+            compression_ratio: 0.5,
+        };
+        let small = DiffCalculator::estimate_cost(&test_diff("a.txt", "create", 1000), &model);
+        let large = DiffCalculator::estimate_cost(&test_diff("b.bin", "update", 1_000_000), &model);
+        let deleted =
+            DiffCalculator::estimate_cost(&test_diff("c.bin", "delete", 1_000_000), &model);
+        assert_eq!(small, 55.0);
+        assert!(large > small);
+        assert_eq!(deleted, 50.0);
+
+        let differences = vec![
+            test_diff("a.txt", "create", 1000),
+            test_diff("b.bin", "update", 1_000_000),
+            test_diff("c.bin", "delete", 1_000_000),
+        ];
+        let result = DiffResult {
+            statistics: DiffStatistics::tally(2, 2, &differences),
+            differences,
+            errors: Vec::new(),
+        };
+        assert_eq!(result.total_estimated_cost(&model), small + large + deleted);
+    }
+
+    #[test]
+    fn test_parse_mtime() {
+        assert_eq!(parse_mtime("1970-01-01T00:00:00Z"), Some(0));
+        assert_eq!(parse_mtime("2023-01-01T00:00:00Z"), Some(1_672_531_200));
+        assert_eq!(
+            parse_mtime("2023-01-01T08:00:00.123+08:00"),
+            Some(1_672_531_200)
+        );
+        assert_eq!(parse_mtime("1672531200"), Some(1_672_531_200));
+        assert_eq!(parse_mtime(""), None);
+        assert_eq!(parse_mtime("yesterday"), None);
+
+        // 日期须在当月天数之内，闰年二月有 29 天
+        assert_eq!(parse_mtime("2024-02-29T00:00:00Z"), Some(1_709_164_800));
+        assert_eq!(parse_mtime("2023-02-29T00:00:00Z"), None);
+        assert_eq!(parse_mtime("2024-02-31T00:00:00Z"), None);
+        assert_eq!(parse_mtime("2024-04-31T00:00:00Z"), None);
+        assert_eq!(parse_mtime("1900-02-29T00:00:00Z"), None);
+        assert!(parse_mtime("2000-02-29T00:00:00Z").is_some());
+
+        // 时区偏移和秒数必须在有效范围内，且为两位数字
+        assert_eq!(parse_mtime("2023-01-01T00:00:00+9999999999999999:00"), None);
+        assert_eq!(parse_mtime("2023-01-01T00:00:00+01:-30"), None);
+        assert_eq!(parse_mtime("2023-01-01T00:00:00+24:00"), None);
+        assert_eq!(parse_mtime("2023-01-01T00:00:00+01:60"), None);
+        assert_eq!(parse_mtime("2023-01-01T00:00:00+1:00"), None);
+        assert_eq!(parse_mtime("2023-01-01T00:00:61Z"), None);
+        assert_eq!(parse_mtime("2023-01-01T00:00:+5Z"), None);
+        assert_eq!(parse_mtime("2023-01-01T00:00:60Z"), Some(1_672_531_260));
+        assert_eq!(
+            parse_mtime("2022-12-31T23:30:00-00:30"),
+            Some(1_672_531_200)
+        );
+    }
+
+    #[test]
+    fn test_missing_mtime_policy() {
+        let dest = create_test_file("/test/file.txt", "hash_old", 100);
+        let mut source = create_test_file("/test/file.txt", "hash_new", 100);
+        source.modified_time = String::new();
+        let calculator = |policy| {
+            DiffCalculator::new()
+                .with_mtime_comparison(true)
+                .with_missing_mtime_policy(policy)
+        };
+
+        let result = calculator(MissingMtimePolicy::AlwaysUpdate)
+            .calculate_differences(std::slice::from_ref(&source), std::slice::from_ref(&dest))
+            .unwrap();
+        assert_eq!(result.differences.len(), 1);
+        assert_eq!(result.differences[0].operation, "update");
+
+        // 退回到哈希比较：哈希不同则更新，相同则不更新
+        let result = calculator(MissingMtimePolicy::FallbackToHash)
+            .calculate_differences(std::slice::from_ref(&source), std::slice::from_ref(&dest))
+            .unwrap();
+        assert_eq!(result.differences.len(), 1);
+        let mut unchanged = source.clone();
+        unchanged.hash = dest.hash.clone();
+        let result = calculator(MissingMtimePolicy::FallbackToHash)
+            .calculate_differences(&[unchanged], std::slice::from_ref(&dest))
+            .unwrap();
+        assert!(result.differences.is_empty());
+
+        let result = calculator(MissingMtimePolicy::Error)
+            .calculate_differences(std::slice::from_ref(&source), std::slice::from_ref(&dest))
+            .unwrap();
+        assert!(result.differences.is_empty());
+        assert_eq!(result.errors.len(), 1);
+        assert_eq!(result.errors[0].path, "/test/file.txt");
+
+        // 修改时间都可用时不看哈希
+        let mut same_mtime = source.clone();
+        same_mtime.modified_time = dest.modified_time.clone();
+        let result = calculator(MissingMtimePolicy::Error)
+            .calculate_differences(&[same_mtime], std::slice::from_ref(&dest))
+            .unwrap();
+        assert!(result.differences.is_empty());
+        assert!(result.errors.is_empty());
+    }
+
+    #[test]
+    fn test_explanations() {
+        let source_files = vec![
+            create_test_file("/test/new.txt", "hash_new", 10),
+            create_test_file("/test/changed.txt", "hash_b", 20),
+            create_test_file("/test/resized.txt", "hash_c", 30),
+        ];
+        let dest_files = vec![
+            create_test_file("/test/changed.txt", "hash_a", 20),
+            create_test_file("/test/resized.txt", "hash_c", 31),
+        ];
+
+        let result = DiffCalculator::new()
+            .with_explanations(true)
+            .calculate_differences(&source_files, &dest_files)
+            .unwrap();
+        let explanations: Vec<Option<&str>> = result
+            .differences
+            .iter()
+            .map(|diff| diff.explanation.as_deref())
+            .collect();
+        assert_eq!(
+            explanations,
+            vec![Some("新文件"), Some("哈希不同"), Some("大小不同")]
+        );
+
+        let result = DiffCalculator::new()
+            .calculate_differences(&source_files, &dest_files)
+            .unwrap();
+        assert!(
+            result
+                .differences
+                .iter()
+                .all(|diff| diff.explanation.is_none())
+        );
+    }
+
+    #[test]
+    fn test_partition_by_size_balances_bytes() {
+        // 少量超大文件 + 大量小文件
+        let mut files: Vec<FileMetadata> = (0..4)
+            .map(|i| create_test_file(&format!("big{}.bin", i), "h", 100_000_000))
+            .collect();
+        files.extend(
+            (0..2000).map(|i| create_test_file(&format!("small{}.txt", i), "h", 1000 + i % 97)),
+        );
+
+        let chunks = partition_by_size(&files, 4);
+        assert_eq!(chunks.len(), 4);
+        let mut seen: Vec<usize> = chunks.iter().flatten().copied().collect();
+        seen.sort_unstable();
+        assert_eq!(seen, (0..files.len()).collect::<Vec<_>>());
+
+        let weights: Vec<i64> = chunks
+            .iter()
+            .map(|chunk| chunk.iter().map(|&i| files[i].size).sum())
+            .collect();
+        let max = *weights.iter().max().unwrap() as f64;
+        let min = *weights.iter().min().unwrap() as f64;
+        assert!(max / min < 1.01, "{:?}", weights);
+        // 超大文件分散到不同的块
+        for chunk in &chunks {
+            assert_eq!(chunk.iter().filter(|&&i| i < 4).count(), 1);
+        }
+    }
+
+    #[test]
+    fn test_size_balanced_chunks_preserve_order() {
+        let source_files: Vec<FileMetadata> = (0..200)
+            .map(|i| create_test_file(&format!("f{}.txt", i), &format!("s{}", i), (i * i) as i64))
+            .collect();
+        let dest_files: Vec<FileMetadata> = (0..200)
+            .step_by(2)
+            .map(|i| create_test_file(&format!("f{}.txt", i), "old", (i * i) as i64))
+            .collect();
+
+        let plain = DiffCalculator::new()
+            .calculate_differences(&source_files, &dest_files)
+            .unwrap();
+        let balanced = DiffCalculator::new()
+            .with_size_balanced_chunks(3)
+            .calculate_differences(&source_files, &dest_files)
+            .unwrap();
+
+        let paths = |result: &DiffResult| -> Vec<String> {
+            result
+                .differences
+                .iter()
+                .map(|diff| diff.path.clone())
+                .collect()
+        };
+        assert_eq!(paths(&plain), paths(&balanced));
+    }
+
+    #[test]
+    fn test_weak_checksum_first() {
+        let with_weak = |hash: &str, weak: u32| FileMetadata {
+            weak_checksum: Some(weak),
+            ..create_test_file("/test/file.bin", hash, 100)
+        };
+        let calculator = DiffCalculator::new()
+            .with_weak_checksum_first(true)
+            .with_explanations(true);
+        let diff = |source: FileMetadata, dest: FileMetadata| {
+            calculator
+                .calculate_differences(&[source], &[dest])
+                .unwrap()
+                .differences
+        };
+
+        // 弱校验和不同：不看强哈希直接判定更新
+        let differences = diff(with_weak("same", 1), with_weak("same", 2));
+        assert_eq!(differences.len(), 1);
+        assert_eq!(differences[0].operation, "update");
+        assert_eq!(differences[0].explanation.as_deref(), Some("弱校验和不同"));
+
+        // 弱校验和相同：继续比较强哈希（捕获碰撞）
+        let differences = diff(with_weak("strong_a", 7), with_weak("strong_b", 7));
+        assert_eq!(differences.len(), 1);
+        assert_eq!(differences[0].explanation.as_deref(), Some("哈希不同"));
+        assert!(diff(with_weak("same", 7), with_weak("same", 7)).is_empty());
+
+        // 缺少弱校验和时按强哈希比较
+        assert!(
+            diff(
+                with_weak("same", 1),
+                create_test_file("/test/file.bin", "same", 100)
+            )
+            .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_additive_only_mode() {
+        let calculator = DiffCalculator::new().with_mode(SyncMode::AdditiveOnly);
+        let source_files = vec![
+            create_test_file("/archive/existing.log", "hash_new", 200),
+            create_test_file("/archive/missing.log", "hash_m", 50),
+        ];
+        let dest_files = vec![
+            create_test_file("/archive/existing.log", "hash_old", 100),
+            create_test_file("/archive/retired.log", "hash_r", 10),
+        ];
+
+        let result = calculator
+            .calculate_differences(&source_files, &dest_files)
+            .unwrap();
+        assert_eq!(result.differences.len(), 1);
+        assert_eq!(result.differences[0].path, "/archive/missing.log");
+        assert_eq!(result.differences[0].operation, "create");
+        assert_eq!(result.statistics.files_to_update, 0);
+
+        assert!(
+            calculator
+                .find_files_to_delete(&source_files, &dest_files)
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_diff_against_cas() {
+        let source = vec![
+            create_test_file("a.bin", "hash_stored", 10),
+            create_test_file("b.bin", "hash_new", 20),
+            create_test_file("copy_of_b.bin", "hash_new", 20),
+            create_test_file("c.bin", "hash_other", 30),
+        ];
+        let present: HashSet<String> = ["hash_stored", "hash_unrelated"]
+            .iter()
+            .map(|hash| hash.to_string())
+            .collect();
+
+        let diffs = DiffCalculator::new().diff_against_cas(&source, &present);
+        let operations: Vec<(&str, &str)> = diffs
+            .iter()
+            .map(|diff| (diff.path.as_str(), diff.operation.as_str()))
+            .collect();
+        assert_eq!(
+            operations,
+            vec![
+                ("a.bin", "no_op"),
+                ("b.bin", "create"),
+                ("copy_of_b.bin", "no_op"),
+                ("c.bin", "create"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_missing_chunks() {
+        let recipe: Vec<String> = ["c1", "c2", "c3", "c2", "c4", "c1", "c5"]
+            .iter()
+            .map(|hash| hash.to_string())
+            .collect();
+        let have: HashSet<String> = ["c1", "c4"].iter().map(|hash| hash.to_string()).collect();
+
+        assert_eq!(
+            DiffCalculator::missing_chunks(&recipe, &have),
+            vec!["c2", "c3", "c5"]
+        );
+        assert_eq!(
+            DiffCalculator::missing_chunks(&recipe, &recipe.iter().cloned().collect()),
+            Vec::<String>::new()
+        );
+    }
+
+    #[test]
+    fn test_new_content_hashes() {
+        let source = vec![
+            create_test_file("a.bin", "hash_shared", 10),
+            create_test_file("b.bin", "hash_new", 20),
+            create_test_file("dup_of_b.bin", "hash_new", 20),
+            create_test_file("c.bin", "hash_moved", 30),
+        ];
+        // 内容相同但路径不同仍视为已存在
+        let dest = vec![
+            create_test_file("a.bin", "hash_shared", 10),
+            create_test_file("elsewhere/c.bin", "hash_moved", 30),
+            create_test_file("d.bin", "hash_dest_only", 40),
+        ];
+
+        let expected: HashSet<String> = ["hash_new".to_string()].into_iter().collect();
+        assert_eq!(DiffCalculator::new_content_hashes(&source, &dest), expected);
+
+        // 完全不相交时返回源端全部哈希
+        let disjoint = vec![create_test_file("x.bin", "hash_x", 1)];
+        let all: HashSet<String> = ["hash_shared", "hash_new", "hash_moved"]
+            .iter()
+            .map(|hash| hash.to_string())
+            .collect();
+        assert_eq!(DiffCalculator::new_content_hashes(&source, &disjoint), all);
+        assert!(DiffCalculator::new_content_hashes(&source, &source).is_empty());
+    }
+
+    #[test]
+    fn test_relative_to_root() {
+        let source = vec![
+            create_test_file("/srv/data/a.txt", "hash_a", 10),
+            create_test_file("/srv/data/sub/b.txt", "hash_b", 20),
+            create_test_file("C:\\srv\\data\\c.txt", "hash_c", 30),
+            create_test_file("/srv/database/d.txt", "hash_d", 40),
+        ];
+        let dest = vec![
+            create_test_file("/srv/data/a.txt", "hash_old", 10),
+            create_test_file("/srv/data/gone.txt", "hash_gone", 5),
+        ];
+
+        let calculator = DiffCalculator::new().with_relative_to("/srv/data/");
+        let result = calculator.calculate_differences(&source, &dest).unwrap();
+        let paths: Vec<(&str, &str)> = result
+            .differences
+            .iter()
+            .map(|diff| (diff.path.as_str(), diff.operation.as_str()))
+            .collect();
+        assert_eq!(
+            paths,
+            vec![
+                ("a.txt", "update"),
+                ("sub/b.txt", "create"),
+                // 不在根目录下的路径原样输出（前缀相同但不是子目录的也不截断）
+                ("C:\\srv\\data\\c.txt", "create"),
+                ("/srv/database/d.txt", "create"),
+            ]
+        );
+
+        let deletes = calculator.find_files_to_delete(&source, &dest).unwrap();
+        assert_eq!(deletes.len(), 1);
+        assert_eq!(deletes[0].path, "gone.txt");
+
+        let windows = DiffCalculator::new().with_relative_to("C:\\srv\\data");
+        let result = windows.calculate_differences(&source[2..3], &[]).unwrap();
+        assert_eq!(result.differences[0].path, "c.txt");
+    }
+
+    #[test]
+    fn test_max_files() {
+        let files: Vec<FileMetadata> = (0..4)
+            .map(|i| create_test_file(&format!("file{}.txt", i), "hash", 10))
+            .collect();
+        let calculator = DiffCalculator::new().with_max_files(3);
+
+        assert!(
+            calculator
+                .calculate_differences(&files[..3], &files[..3])
+                .is_ok()
+        );
+        assert_eq!(
+            calculator
+                .calculate_differences(&files, &files[..1])
+                .unwrap_err(),
+            "文件数量超过限制"
+        );
+        assert_eq!(
+            calculator
+                .calculate_differences(&files[..1], &files)
+                .unwrap_err(),
+            "文件数量超过限制"
+        );
+    }
+
+    #[test]
+    fn test_diff_directories() {
+        let source = tempfile::tempdir().unwrap();
+        let dest = tempfile::tempdir().unwrap();
+        std::fs::create_dir(source.path().join("sub")).unwrap();
+        std::fs::create_dir(dest.path().join("sub")).unwrap();
+
+        std::fs::write(source.path().join("new.txt"), b"created").unwrap();
+        std::fs::write(source.path().join("sub/changed.txt"), b"after").unwrap();
+        std::fs::write(dest.path().join("sub/changed.txt"), b"before").unwrap();
+        std::fs::write(source.path().join("same.txt"), b"same").unwrap();
+        std::fs::write(dest.path().join("same.txt"), b"same").unwrap();
+        std::fs::write(dest.path().join("gone.txt"), b"deleted").unwrap();
+
+        let result = DiffCalculator::new()
+            .diff_directories(
+                source.path().to_str().unwrap(),
+                dest.path().to_str().unwrap(),
+                &ScanOptions::new(),
+            )
+            .unwrap();
+
+        let operations: Vec<(&str, &str)> = result
+            .differences
+            .iter()
+            .map(|diff| (diff.path.as_str(), diff.operation.as_str()))
+            .collect();
+        assert_eq!(
+            operations,
+            vec![
+                ("new.txt", "create"),
+                ("sub/changed.txt", "update"),
+                ("gone.txt", "delete"),
+            ]
+        );
+        assert_eq!(result.statistics.total_source_files, 3);
+        assert_eq!(result.statistics.total_dest_files, 3);
+        assert_eq!(result.statistics.files_to_create, 1);
+        assert_eq!(result.statistics.files_to_update, 1);
+        assert_eq!(result.statistics.files_to_delete, 1);
+        assert_eq!(result.statistics.total_size, 7 + 5 + 7);
+    }
+
+    #[test]
+    fn test_deletions_considered() {
+        let source = tempfile::tempdir().unwrap();
+        let dest = tempfile::tempdir().unwrap();
+        std::fs::write(source.path().join("a.txt"), b"a").unwrap();
+        std::fs::write(dest.path().join("a.txt"), b"a").unwrap();
+        let (source_root, dest_root) = (
+            source.path().to_str().unwrap(),
+            dest.path().to_str().unwrap(),
+        );
+
+        // 检查过删除，但没有需要删除的文件
+        let result = DiffCalculator::new()
+            .diff_directories(source_root, dest_root, &ScanOptions::new())
+            .unwrap();
+        assert_eq!(result.statistics.files_to_delete, 0);
+        assert!(result.statistics.deletions_considered);
+        assert!(
+            result
+                .remaining_after(&HashSet::new())
+                .statistics
+                .deletions_considered
+        );
+
+        let result = DiffCalculator::new()
+            .with_mode(SyncMode::AdditiveOnly)
+            .diff_directories(source_root, dest_root, &ScanOptions::new())
+            .unwrap();
+        assert!(!result.statistics.deletions_considered);
+
+        let files = vec![create_test_file("a.txt", "hash1", 1)];
+        let calculator = DiffCalculator::new();
+        let result = calculator.calculate_differences(&files, &[]).unwrap();
+        assert!(!result.statistics.deletions_considered);
+        assert!(
+            !calculator
+                .incremental(&[])
+                .finalize()
+                .statistics
+                .deletions_considered
+        );
+        assert!(result.invert().statistics.deletions_considered);
+
+        // 旧版本序列化的统计信息没有该字段
+        let legacy = r#"{"total_source_files":1,"total_dest_files":0,"files_to_create":1,
+            "files_to_update":0,"files_to_delete":0,"total_size":1}"#;
+        let statistics: DiffStatistics = serde_json::from_str(legacy).unwrap();
+        assert!(!statistics.deletions_considered);
+    }
+
+    #[test]
+    fn test_range_split() {
+        let source = vec![
+            create_test_file("large.bin", "hash_large", 10_500),
+            create_test_file("small.bin", "hash_small", 100),
+        ];
+        let result = DiffCalculator::new()
+            .with_range_split(4096)
+            .calculate_differences(&source, &[])
+            .unwrap();
+
+        let large: Vec<&FileDiff> = result
+            .differences
+            .iter()
+            .filter(|diff| diff.path == "large.bin")
+            .collect();
+        let ranges: Vec<(u64, u64)> = large
+            .iter()
+            .map(|diff| diff.range.map(|range| (range.offset, range.len)).unwrap())
+            .collect();
+        assert_eq!(ranges, vec![(0, 4096), (4096, 4096), (8192, 2308)]);
+        assert_eq!(large.iter().map(|diff| diff.size).sum::<i64>(), 10_500);
+
+        let small: Vec<&FileDiff> = result
+            .differences
+            .iter()
+            .filter(|diff| diff.path == "small.bin")
+            .collect();
+        assert_eq!(small.len(), 1);
+        assert_eq!(small[0].range, None);
+
+        // 统计信息按文件计数
+        assert_eq!(result.statistics.files_to_create, 2);
+        assert_eq!(result.statistics.total_size, 10_600);
+
+        // 区间以 offset/len 字段序列化
+        let json = serde_json::to_value(large[1]).unwrap();
+        assert_eq!(json["offset"], 4096);
+        assert_eq!(json["len"], 4096);
+        let parsed: FileDiff = serde_json::from_value(json).unwrap();
+        assert_eq!(parsed.range, large[1].range);
+        assert!(
+            serde_json::to_value(small[0])
+                .unwrap()
+                .get("offset")
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn test_incremental_statistics() {
+        let calculator = DiffCalculator::new();
+        let dest_files = vec![
+            create_test_file("same.txt", "h1", 100),
+            create_test_file("changed.txt", "old", 200),
+        ];
+        let source_files = vec![
+            create_test_file("same.txt", "h1", 100),
+            create_test_file("new.txt", "h2", 300),
+            create_test_file("changed.txt", "new", 250),
+            create_test_file("another.txt", "h3", 50),
+        ];
+
+        let mut builder = calculator.incremental(&dest_files);
+        let initial = builder.current_statistics();
+        assert_eq!(initial.total_source_files, 0);
+        assert_eq!(initial.total_dest_files, 2);
+
+        let expected = [(0, 0, 0), (1, 0, 300), (1, 1, 550), (2, 1, 600)];
+        for (file, (creates, updates, size)) in source_files.iter().zip(expected) {
+            builder.push_source(file);
+            let snapshot = builder.current_statistics();
+            assert_eq!(snapshot.files_to_create, creates);
+            assert_eq!(snapshot.files_to_update, updates);
+            assert_eq!(snapshot.total_size, size);
+        }
+
+        let last_snapshot = builder.current_statistics();
+        let result = builder.finalize();
+        let batch = calculator
+            .calculate_differences(&source_files, &dest_files)
+            .unwrap();
+        assert_eq!(last_snapshot, result.statistics);
+        assert_eq!(result.statistics, batch.statistics);
+        assert_eq!(result.differences.len(), batch.differences.len());
+    }
+
+    #[test]
+    fn test_skip_hidden() {
+        let source_files = vec![
+            create_test_file(".git/HEAD", "h1", 10),
+            create_test_file(".env", "h2", 20),
+            create_test_file("src/main.rs", "h3", 30),
+        ];
+        let dest_files = vec![create_test_file(".DS_Store", "h4", 40)];
+
+        let calculator = DiffCalculator::new();
+        let result = calculator
+            .calculate_differences(&source_files, &dest_files)
+            .unwrap();
+        assert_eq!(result.differences.len(), 3);
+        assert_eq!(
+            calculator
+                .find_files_to_delete(&source_files, &dest_files)
+                .unwrap()
+                .len(),
+            1
+        );
+
+        let calculator = DiffCalculator::new().with_skip_hidden(true);
+        let result = calculator
+            .calculate_differences(&source_files, &dest_files)
+            .unwrap();
+        let paths: Vec<&str> = result.differences.iter().map(|d| d.path.as_str()).collect();
+        assert_eq!(paths, vec!["src/main.rs"]);
+        assert_eq!(result.statistics.total_source_files, 1);
+        assert_eq!(result.statistics.total_dest_files, 0);
+        assert!(
+            calculator
+                .find_files_to_delete(&source_files, &dest_files)
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_files_equal_streaming() {
+        let dir = tempfile::tempdir().unwrap();
+        let write = |name: &str, data: &[u8]| {
+            let path = dir.path().join(name);
+            std::fs::write(&path, data).unwrap();
+            path.to_str().unwrap().to_string()
+        };
+        let content: Vec<u8> = (0..300_000u32).map(|i| (i % 253) as u8).collect();
+        let mut early_diff = content.clone();
+        early_diff[10] ^= 0xff;
+        let mut late_diff = content.clone();
+        *late_diff.last_mut().unwrap() ^= 0xff;
+
+        let original = write("original.bin", &content);
+        let copy = write("copy.bin", &content);
+        let early = write("early.bin", &early_diff);
+        let late = write("late.bin", &late_diff);
+        let shorter = write("shorter.bin", &content[..content.len() - 1]);
+
+        assert!(DiffCalculator::files_equal_streaming(&original, &copy).unwrap());
+        assert!(!DiffCalculator::files_equal_streaming(&original, &early).unwrap());
+        assert!(!DiffCalculator::files_equal_streaming(&original, &late).unwrap());
+        assert!(!DiffCalculator::files_equal_streaming(&original, &shorter).unwrap());
+        assert!(DiffCalculator::files_equal_streaming(&original, "/nonexistent/file").is_err());
+
+        // 第一块就不同时只读取第一块
+        struct CountingReader<'a> {
+            data: &'a [u8],
+            read: usize,
+        }
+        impl Read for CountingReader<'_> {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                let n = (&self.data[self.read..]).read(buf)?;
+                self.read += n;
+                Ok(n)
+            }
+        }
+        let mut a = CountingReader {
+            data: &content,
+            read: 0,
+        };
+        let mut b = CountingReader {
+            data: &early_diff,
+            read: 0,
+        };
+        assert!(!readers_equal(&mut a, &mut b, 4096).unwrap());
+        assert_eq!((a.read, b.read), (4096, 4096));
+    }
+
     #[test]
     fn test_normalize_path() {
         let calculator = DiffCalculator::new();
@@ -311,6 +2384,410 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_estimate_memory() {
+        let files = |count: usize, prefix: &str| -> Vec<FileMetadata> {
+            (0..count)
+                .map(|i| {
+                    create_test_file(
+                        &format!("{}/dir/file_{:06}.txt", prefix, i),
+                        &format!("{:064x}", i),
+                        10,
+                    )
+                })
+                .collect()
+        };
+        let calculator = DiffCalculator::new();
+        assert_eq!(calculator.estimate_memory(&[], &[]), 0);
+
+        let small = calculator.estimate_memory(&files(1000, "src"), &files(1000, "dst"));
+        let large = calculator.estimate_memory(&files(10000, "src"), &files(10000, "dst"));
+        let ratio = large as f64 / small as f64;
+        assert!((8.0..=12.0).contains(&ratio), "ratio {}", ratio);
+
+        // 与实际分配的容量比较：两侧路径全部不同，每个源文件都产生差异
+        let source = files(10000, "src");
+        let dest = files(10000, "dst");
+        let dest_map: HashMap<String, &FileMetadata> = dest
+            .iter()
+            .map(|file| (calculator.path_key(&file.path), file))
+            .collect();
+        let result = calculator.calculate_differences(&source, &dest).unwrap();
+        assert_eq!(result.differences.len(), source.len());
+        let measured = dest_map.capacity() * size_of::<(String, &FileMetadata)>()
+            + dest_map.keys().map(|key| key.capacity()).sum::<usize>()
+            + result.differences.capacity() * size_of::<FileDiff>()
+            + result
+                .differences
+                .iter()
+                .map(|diff| diff.path.capacity() + diff.source_hash.capacity())
+                .sum::<usize>();
+        assert!(
+            large >= measured / 2 && large <= measured * 3,
+            "{} vs {}",
+            large,
+            measured
+        );
+    }
+
+    #[test]
+    fn test_key_fn_basename() {
+        let calculator = DiffCalculator::new().with_key_fn(KeyFn::Basename);
+        let source = vec![
+            create_test_file("reports/2024/report.txt", "hash1", 100),
+            create_test_file("notes.md", "hash2", 50),
+        ];
+        let dest = vec![
+            create_test_file("flat\\report.txt", "hash1", 100),
+            create_test_file("flat/notes.md", "hash_old", 40),
+            create_test_file("flat/stale.log", "hash3", 10),
+        ];
+
+        let result = calculator.calculate_differences(&source, &dest).unwrap();
+        assert_eq!(result.differences.len(), 1);
+        assert_eq!(result.differences[0].path, "notes.md");
+        assert_eq!(result.differences[0].operation, "update");
+        let deletes = calculator.find_files_to_delete(&source, &dest).unwrap();
+        assert_eq!(deletes.len(), 1);
+        assert_eq!(deletes[0].path, "flat/stale.log");
+
+        let custom = DiffCalculator::new().with_key_fn(KeyFn::Custom(|path| {
+            path.trim_start_matches("flat/").to_string()
+        }));
+        let result = custom
+            .calculate_differences(
+                &[create_test_file("a.txt", "h", 1)],
+                &[create_test_file("flat/a.txt", "h", 1)],
+            )
+            .unwrap();
+        assert!(result.differences.is_empty());
+    }
+
+    #[test]
+    fn test_key_fn_ambiguous_basename() {
+        let calculator = DiffCalculator::new().with_key_fn(KeyFn::Basename);
+        let source = vec![
+            create_test_file("a/report.txt", "hash1", 100),
+            create_test_file("b/report.txt", "hash2", 100),
+        ];
+        let dest = vec![create_test_file("report.txt", "hash1", 100)];
+
+        let err = calculator
+            .calculate_differences(&source, &dest)
+            .unwrap_err();
+        assert!(
+            err.contains("a/report.txt") && err.contains("b/report.txt"),
+            "{}",
+            err
+        );
+        let err = calculator
+            .calculate_differences(&dest, &source)
+            .unwrap_err();
+        assert!(err.starts_with("目标"), "{}", err);
+
+        // 完整路径匹配时没有歧义
+        assert!(
+            DiffCalculator::new()
+                .calculate_differences(&source, &dest)
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn test_verify_hash() {
+        let crypto = crate::crypto::CryptoCompressor::new();
+        let content = b"promised content";
+        let full_hash = crypto.calculate_hash(content);
+        let source = vec![
+            create_test_file("full.txt", &full_hash, content.len() as i64),
+            create_test_file(
+                "prefixed.txt",
+                &format!("SHA256:{}", full_hash.to_uppercase()),
+                16,
+            ),
+            create_test_file("short.txt", &full_hash[..16], 16),
+        ];
+        assert_eq!(full_sha256(&format!("blake3:{}", full_hash)), None);
+
+        let result = DiffCalculator::new()
+            .calculate_differences(&source, &[])
+            .unwrap();
+        assert!(
+            result
+                .differences
+                .iter()
+                .all(|diff| diff.verify_hash.is_none())
+        );
+
+        let result = DiffCalculator::new()
+            .with_verify_hash(true)
+            .calculate_differences(&source, &[])
+            .unwrap();
+        let verify: Vec<Option<&str>> = result
+            .differences
+            .iter()
+            .map(|diff| diff.verify_hash.as_deref())
+            .collect();
+        assert_eq!(
+            verify,
+            vec![Some(full_hash.as_str()), Some(full_hash.as_str()), None]
+        );
+
+        // 应用方写入后按 verify_hash 校验，能发现写坏的文件
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("full.txt");
+        let expected = result.differences[0].verify_hash.as_deref().unwrap();
+        fs::write(&path, content).unwrap();
+        assert_eq!(crypto.hash_file(path.to_str().unwrap()).unwrap(), expected);
+        fs::write(&path, b"promised c0ntent").unwrap();
+        assert_ne!(crypto.hash_file(path.to_str().unwrap()).unwrap(), expected);
+        assert!(!crypto.hash_matches(&fs::read(&path).unwrap(), expected));
+    }
+
+    #[test]
+    fn test_calculate_differences_progress() {
+        use crate::progress::tests::RecordingSink;
+
+        let source: Vec<FileMetadata> = (0..50)
+            .map(|i| create_test_file(&format!("file_{}.txt", i), "hash", i))
+            .collect();
+        for calculator in [
+            DiffCalculator::new(),
+            DiffCalculator::new().with_size_balanced_chunks(4),
+        ] {
+            let mut sink = RecordingSink::default();
+            let result = calculator
+                .calculate_differences_with_progress(&source, &[], Some(&mut sink))
+                .unwrap();
+            assert_eq!(result.differences.len(), 50);
+            sink.assert_complete();
+            assert_eq!(sink.total, Some(50));
+        }
+
+        // 输入检查失败时不报告进度
+        let mut sink = RecordingSink::default();
+        assert!(
+            DiffCalculator::new()
+                .with_max_files(10)
+                .calculate_differences_with_progress(&source, &[], Some(&mut sink))
+                .is_err()
+        );
+        assert!(sink.events.is_empty());
+    }
+
+    #[test]
+    fn test_calculate_differences_from_readers() {
+        let source = vec![
+            create_test_file("same.txt", "hash1", 10),
+            create_test_file("changed.txt", "hash_new", 20),
+            create_test_file("new.txt", "hash3", 30),
+        ];
+        let dest = vec![
+            create_test_file("same.txt", "hash1", 10),
+            create_test_file("changed.txt", "hash_old", 20),
+            create_test_file("gone.txt", "hash4", 40),
+        ];
+        let to_jsonl = |files: &[FileMetadata]| -> String {
+            files
+                .iter()
+                .map(|file| serde_json::to_string(file).unwrap() + "\n")
+                .collect::<String>()
+                + "\n"
+        };
+
+        let calculator = DiffCalculator::new();
+        let expected = calculator.calculate_differences(&source, &dest).unwrap();
+        let result = calculator
+            .calculate_differences_from_readers(
+                to_jsonl(&source).as_bytes(),
+                to_jsonl(&dest).as_bytes(),
+            )
+            .unwrap();
+        assert_eq!(result.statistics, expected.statistics);
+        assert_eq!(
+            serde_json::to_string(&result.differences).unwrap(),
+            serde_json::to_string(&expected.differences).unwrap()
+        );
+
+        let err = calculator
+            .calculate_differences_from_readers(&b"{\"path\": 1}"[..], &b""[..])
+            .unwrap_err();
+        assert!(err.contains("第 1 行"), "{}", err);
+        assert!(
+            calculator
+                .with_max_files(2)
+                .calculate_differences_from_readers(to_jsonl(&source).as_bytes(), &b""[..])
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_empty_dest_hash_policy() {
+        let source = vec![create_test_file("file.txt", "hash1", 100)];
+        let dest = vec![create_test_file("file.txt", "", 100)];
+        let operations = |policy: EmptyDestHashPolicy| -> Vec<(String, Option<String>)> {
+            DiffCalculator::new()
+                .with_explanations(true)
+                .with_empty_dest_hash_policy(policy)
+                .calculate_differences(&source, &dest)
+                .unwrap()
+                .differences
+                .into_iter()
+                .map(|diff| (diff.operation, diff.explanation))
+                .collect()
+        };
+
+        assert_eq!(
+            operations(EmptyDestHashPolicy::TreatAsUpdate),
+            vec![("update".to_string(), Some("哈希不同".to_string()))]
+        );
+        assert_eq!(
+            operations(EmptyDestHashPolicy::TreatAsCreate),
+            vec![("create".to_string(), Some("目标端哈希缺失".to_string()))]
+        );
+        assert!(operations(EmptyDestHashPolicy::Skip).is_empty());
+
+        // 按修改时间比较时不检查哈希
+        let result = DiffCalculator::new()
+            .with_mtime_comparison(true)
+            .with_empty_dest_hash_policy(EmptyDestHashPolicy::TreatAsCreate)
+            .calculate_differences(&source, &dest)
+            .unwrap();
+        assert!(
+            result
+                .differences
+                .iter()
+                .all(|diff| diff.operation != "create")
+        );
+    }
+
+    #[test]
+    fn test_order_for_space() {
+        let diff = |path: &str, operation: &str, size: i64, copy_from: Option<&str>| FileDiff {
+            copy_from: copy_from.map(str::to_string),
+            ..test_diff(path, operation, size)
+        };
+        let differences = vec![
+            diff("new_big.bin", "create", 90, None),
+            diff("moved.bin", "no_op", 40, Some("old_small.bin")),
+            diff("config.toml", "update", 30, None),
+            diff("old_big.bin", "delete", 80, None),
+            diff("old_small.bin", "delete", 40, None),
+        ];
+        let result = DiffResult {
+            statistics: DiffStatistics::tally(3, 3, &differences),
+            differences,
+            errors: Vec::new(),
+        };
+        // 按顺序执行时的最少剩余空间
+        let min_available = |order: &[FileDiff], free_bytes: i64| {
+            let mut available = free_bytes;
+            let mut min = available;
+            for diff in order {
+                let (needed, delta) = disk_usage(diff);
+                min = min.min(available - needed);
+                available -= delta;
+            }
+            min
+        };
+
+        // 先创建后删除的默认顺序需要超过 50 字节的空间
+        assert!(min_available(&result.differences, 50) < 0);
+
+        let ordered = DiffCalculator::order_for_space(&result, 50).unwrap();
+        let paths: Vec<&str> = ordered.iter().map(|diff| diff.path.as_str()).collect();
+        assert_eq!(
+            paths,
+            vec![
+                "moved.bin",
+                "old_big.bin",
+                "new_big.bin",
+                "old_small.bin",
+                "config.toml"
+            ]
+        );
+        assert!(min_available(&ordered, 50) >= 0);
+
+        // 唯一的删除被待执行的复制依赖，无法腾出空间
+        let differences = vec![
+            diff("moved.bin", "no_op", 40, Some("old_small.bin")),
+            diff("old_small.bin", "delete", 40, None),
+        ];
+        let result = DiffResult {
+            statistics: DiffStatistics::tally(1, 1, &differences),
+            differences,
+            errors: Vec::new(),
+        };
+        assert_eq!(
+            DiffCalculator::order_for_space(&result, 30).unwrap_err(),
+            "目标磁盘空间不足: moved.bin 需要 40 字节，最多可用 30 字节"
+        );
+    }
+
+    #[test]
+    fn test_path_matching() {
+        let matching = PathMatching {
+            case_insensitive: true,
+            unicode_nfc: true,
+            trailing_slash: true,
+        };
+        let calculator = DiffCalculator::new().with_path_matching(matching);
+
+        // NFD 形式、大写、末尾带分隔符的同一路径
+        let source = vec![create_test_file("Docs\\CAFE\u{301}/", "hash1", 100)];
+        let dest = vec![create_test_file("docs/café", "hash1", 100)];
+        assert_eq!(matching.key("Docs/CAFE\u{301}/"), matching.key("docs/café"));
+
+        let result = calculator.calculate_differences(&source, &dest).unwrap();
+        assert!(result.differences.is_empty());
+        assert!(
+            calculator
+                .find_files_to_delete(&source, &dest)
+                .unwrap()
+                .is_empty()
+        );
+
+        // 默认规则下视为不同的文件
+        let calculator = DiffCalculator::new();
+        let result = calculator.calculate_differences(&source, &dest).unwrap();
+        assert_eq!(result.differences[0].operation, "create");
+        assert_eq!(result.differences[0].path, "Docs\\CAFE\u{301}/");
+        assert_eq!(
+            calculator
+                .find_files_to_delete(&source, &dest)
+                .unwrap()
+                .len(),
+            1
+        );
+
+        // 标准化后同一侧出现重复路径时报错，删除扫描也一样
+        let calculator = DiffCalculator::new().with_path_matching(matching);
+        let dest = vec![
+            create_test_file("docs/café", "hash1", 100),
+            create_test_file("DOCS/CAFÉ", "hash2", 100),
+        ];
+        let err = calculator
+            .calculate_differences(&source, &dest)
+            .unwrap_err();
+        assert!(err.contains("匹配键冲突"), "{}", err);
+        let err = calculator.find_files_to_delete(&source, &dest).unwrap_err();
+        assert!(err.starts_with("目标"), "{}", err);
+        let jsonl = |files: &[FileMetadata]| {
+            files
+                .iter()
+                .map(|file| serde_json::to_string(file).unwrap() + "\n")
+                .collect::<String>()
+        };
+        assert!(
+            calculator
+                .calculate_differences_from_readers(
+                    jsonl(&source).as_bytes(),
+                    jsonl(&dest).as_bytes()
+                )
+                .is_err()
+        );
+    }
+
     #[test]
     fn test_find_files_to_delete() {
         let calculator = DiffCalculator::new();
@@ -321,10 +2798,91 @@ mod tests {
             create_test_file("/test/delete.txt", "hash2", 512),
         ];
 
-        let to_delete = calculator.find_files_to_delete(&source_files, &dest_files);
+        let to_delete = calculator
+            .find_files_to_delete(&source_files, &dest_files)
+            .unwrap();
 
         assert_eq!(to_delete.len(), 1);
         assert_eq!(to_delete[0].operation, "delete");
         assert_eq!(to_delete[0].path, "/test/delete.txt");
     }
+
+    #[test]
+    fn test_hash_algorithm_mismatch() {
+        let calculator = DiffCalculator::new();
+
+        let source_files = vec![create_test_file("/test/file.txt", "blake3:aaaa", 1024)];
+        let dest_files = vec![create_test_file("/test/file.txt", "sha256:aaaa", 1024)];
+
+        let result = calculator.calculate_differences(&source_files, &dest_files);
+        assert_eq!(result.unwrap_err(), "哈希算法不一致");
+
+        let dest_files = vec![create_test_file("/test/file.txt", "blake3:aaaa", 1024)];
+        let result = calculator
+            .calculate_differences(&source_files, &dest_files)
+            .unwrap();
+        assert!(result.differences.is_empty());
+    }
+
+    #[test]
+    fn test_normalize_permissions() {
+        assert_eq!(normalize_permissions("644"), "0644");
+        assert_eq!(normalize_permissions("0644"), "0644");
+        assert_eq!(normalize_permissions("0o644"), "0644");
+        assert_eq!(normalize_permissions("-rw-r--r--"), "0644");
+        assert_eq!(normalize_permissions("drwxr-xr-x"), "0755");
+        assert_eq!(normalize_permissions("rwsr-xr-t"), "5755");
+        assert_ne!(normalize_permissions("755"), normalize_permissions("644"));
+    }
+
+    #[test]
+    fn test_permission_comparison_uses_normalized_form() {
+        let calculator = DiffCalculator::new().with_permission_comparison(true);
+
+        let mut source = create_test_file("/test/file.txt", "same_hash", 1024);
+        let mut dest = create_test_file("/test/file.txt", "same_hash", 1024);
+        source.permissions = "-rw-r--r--".to_string();
+        dest.permissions = "644".to_string();
+
+        let result = calculator
+            .calculate_differences(&[source.clone()], &[dest.clone()])
+            .unwrap();
+        assert!(result.differences.is_empty());
+
+        dest.permissions = "755".to_string();
+        let result = calculator
+            .calculate_differences(&[source], &[dest])
+            .unwrap();
+        assert_eq!(result.differences.len(), 1);
+        assert_eq!(result.differences[0].operation, "update");
+    }
+
+    #[test]
+    fn test_panicking_comparator_is_recorded_as_error() {
+        let calculator = DiffCalculator::new().with_comparator(|source, dest| {
+            if source.path.contains("malformed") {
+                panic!("无法比较 {}", source.path);
+            }
+            source.hash != dest.hash
+        });
+
+        let source_files = vec![
+            create_test_file("/test/malformed.txt", "hash1", 10),
+            create_test_file("/test/changed.txt", "hash_new", 10),
+        ];
+        let dest_files = vec![
+            create_test_file("/test/malformed.txt", "hash1", 10),
+            create_test_file("/test/changed.txt", "hash_old", 10),
+        ];
+
+        let result = calculator
+            .calculate_differences(&source_files, &dest_files)
+            .unwrap();
+
+        assert_eq!(result.differences.len(), 1);
+        assert_eq!(result.differences[0].path, "/test/changed.txt");
+        assert_eq!(result.errors.len(), 1);
+        assert_eq!(result.errors[0].path, "/test/malformed.txt");
+        assert!(result.errors[0].message.contains("无法比较"));
+    }
 }
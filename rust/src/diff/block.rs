@@ -0,0 +1,162 @@
+use super::DiffCalculator;
+use std::collections::HashMap;
+
+/// 块级差异操作，按顺序执行即可由旧文件重建新文件
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BlockOp {
+    /// 从旧文件 `offset` 处复制 `len` 字节
+    Copy { offset: u64, len: u64 },
+    /// 写入新数据
+    Insert(Vec<u8>),
+}
+
+/// 计算块级差异：将旧数据按 `block_size` 切块，在新数据中逐字节查找相同的块
+///
+/// 命中的块输出为 `Copy`，未命中的字节逐个输出为 `Insert`，结果可能很零碎，
+/// 可用 `DiffCalculator::coalesce_block_ops` 合并。
+pub fn block_diff(old: &[u8], new: &[u8], block_size: usize) -> Vec<BlockOp> {
+    let block_size = block_size.max(1);
+    let mut blocks: HashMap<&[u8], usize> = HashMap::new();
+    for (index, block) in old.chunks_exact(block_size).enumerate() {
+        blocks.entry(block).or_insert(index * block_size);
+    }
+
+    let mut ops = Vec::new();
+    let mut position = 0;
+    while position < new.len() {
+        let matched = new
+            .get(position..position + block_size)
+            .and_then(|window| blocks.get(window));
+        match matched {
+            Some(&offset) => {
+                ops.push(BlockOp::Copy {
+                    offset: offset as u64,
+                    len: block_size as u64,
+                });
+                position += block_size;
+            }
+            None => {
+                ops.push(BlockOp::Insert(vec![new[position]]));
+                position += 1;
+            }
+        }
+    }
+
+    ops
+}
+
+/// 按顺序执行块级差异操作，由旧数据重建新数据
+pub fn apply_block_ops(old: &[u8], ops: &[BlockOp]) -> Result<Vec<u8>, String> {
+    let mut output = Vec::new();
+    for op in ops {
+        match op {
+            BlockOp::Copy { offset, len } => {
+                let range = usize::try_from(*offset)
+                    .ok()
+                    .zip(usize::try_from(*len).ok())
+                    .and_then(|(start, len)| old.get(start..start.checked_add(len)?))
+                    .ok_or_else(|| format!("复制范围超出旧数据: {}+{}", offset, len))?;
+                output.extend_from_slice(range);
+            }
+            BlockOp::Insert(data) => output.extend_from_slice(data),
+        }
+    }
+    Ok(output)
+}
+
+impl DiffCalculator {
+    /// 合并相邻的块级差异操作
+    ///
+    /// 连续的 `Insert` 合并为一个；连续且源区域首尾相接的 `Copy` 合并为一个。
+    /// 合并不改变重建结果，只减少操作数量。区域末端超出 `u64` 范围的 `Copy` 不合并。
+    pub fn coalesce_block_ops(ops: Vec<BlockOp>) -> Vec<BlockOp> {
+        let mut coalesced: Vec<BlockOp> = Vec::with_capacity(ops.len());
+        for op in ops {
+            match (coalesced.last_mut(), op) {
+                (Some(BlockOp::Insert(data)), BlockOp::Insert(more)) => data.extend(more),
+                (
+                    Some(BlockOp::Copy { offset, len }),
+                    BlockOp::Copy {
+                        offset: next_offset,
+                        len: next_len,
+                    },
+                ) if offset.checked_add(*len) == Some(next_offset)
+                    && next_offset.checked_add(next_len).is_some() =>
+                {
+                    *len += next_len
+                }
+                (_, op) => coalesced.push(op),
+            }
+        }
+        coalesced
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_block_diff_round_trip() {
+        let old: Vec<u8> = (0..4096u32).map(|i| (i * 7 % 251) as u8).collect();
+        let mut new = old[..1024].to_vec();
+        new.extend_from_slice(b"inserted bytes");
+        new.extend_from_slice(&old[2048..]);
+
+        let ops = block_diff(&old, &new, 64);
+        assert_eq!(apply_block_ops(&old, &ops).unwrap(), new);
+        assert!(ops.iter().any(|op| matches!(op, BlockOp::Copy { .. })));
+    }
+
+    #[test]
+    fn test_coalesce_block_ops() {
+        let old: Vec<u8> = (0..1024u32).map(|i| (i * 7 % 251) as u8).collect();
+        let mut new = old[..512].to_vec();
+        new.extend_from_slice(b"patched");
+        new.extend_from_slice(&old[512..]);
+
+        let ops = block_diff(&old, &new, 32);
+        let coalesced = DiffCalculator::coalesce_block_ops(ops.clone());
+
+        assert_eq!(apply_block_ops(&old, &coalesced).unwrap(), new);
+        assert!(coalesced.len() < ops.len());
+        assert_eq!(
+            coalesced,
+            vec![
+                BlockOp::Copy {
+                    offset: 0,
+                    len: 512
+                },
+                BlockOp::Insert(b"patched".to_vec()),
+                BlockOp::Copy {
+                    offset: 512,
+                    len: 512
+                },
+            ]
+        );
+
+        // 源区域不相接的 Copy 不合并
+        let gapped = vec![
+            BlockOp::Copy { offset: 0, len: 8 },
+            BlockOp::Copy { offset: 16, len: 8 },
+        ];
+        assert_eq!(DiffCalculator::coalesce_block_ops(gapped.clone()), gapped);
+
+        // 末端溢出的区域不合并，也不会 panic
+        let overflowing = vec![
+            BlockOp::Copy {
+                offset: u64::MAX,
+                len: 1,
+            },
+            BlockOp::Copy { offset: 0, len: 8 },
+            BlockOp::Copy {
+                offset: 8,
+                len: u64::MAX,
+            },
+        ];
+        assert_eq!(
+            DiffCalculator::coalesce_block_ops(overflowing.clone()),
+            overflowing
+        );
+    }
+}
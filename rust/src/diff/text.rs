@@ -0,0 +1,159 @@
+/// 行级差异操作
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LineOp {
+    /// 两侧相同的行
+    Equal(String),
+    /// 新文本中插入的行
+    Insert(String),
+    /// 旧文本中删除的行
+    Delete(String),
+}
+
+/// LCS 表的单元格数上限（约 64 MiB），超过时 `text_diff` 返回错误而不是耗尽内存
+const MAX_LCS_CELLS: usize = 1 << 24;
+
+/// 计算两段文本的行级差异（基于最长公共子序列）
+///
+/// 与文件元数据差异无关，用于展示已更新文本文件内部的具体改动。
+/// 两侧相同的首尾行先被剥离，剩余部分的时间和空间复杂度为 O(n*m)；
+/// 剩余行数的乘积超过上限时返回错误。
+pub fn text_diff(old: &str, new: &str) -> Result<Vec<LineOp>, String> {
+    let old_lines: Vec<&str> = old.lines().collect();
+    let new_lines: Vec<&str> = new.lines().collect();
+
+    let prefix = old_lines
+        .iter()
+        .zip(&new_lines)
+        .take_while(|(a, b)| a == b)
+        .count();
+    let suffix = old_lines[prefix..]
+        .iter()
+        .rev()
+        .zip(new_lines[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let old_mid = &old_lines[prefix..old_lines.len() - suffix];
+    let new_mid = &new_lines[prefix..new_lines.len() - suffix];
+    let (n, m) = (old_mid.len(), new_mid.len());
+
+    let cells = (n + 1)
+        .checked_mul(m + 1)
+        .filter(|&cells| cells <= MAX_LCS_CELLS)
+        .ok_or_else(|| format!("文本差异过大: {} 行与 {} 行的改动区域超出比较上限", n, m))?;
+
+    // lcs[i * (m + 1) + j] 为 old_mid[i..] 与 new_mid[j..] 的最长公共子序列长度
+    let width = m + 1;
+    let mut lcs = vec![0u32; cells];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lcs[i * width + j] = if old_mid[i] == new_mid[j] {
+                lcs[(i + 1) * width + j + 1] + 1
+            } else {
+                lcs[(i + 1) * width + j].max(lcs[i * width + j + 1])
+            };
+        }
+    }
+
+    let mut ops = Vec::with_capacity(old_lines.len().max(new_lines.len()));
+    ops.extend(
+        old_lines[..prefix]
+            .iter()
+            .map(|line| LineOp::Equal(line.to_string())),
+    );
+    let (mut i, mut j) = (0, 0);
+    while i < n && j < m {
+        if old_mid[i] == new_mid[j] {
+            ops.push(LineOp::Equal(old_mid[i].to_string()));
+            i += 1;
+            j += 1;
+        } else if lcs[(i + 1) * width + j] >= lcs[i * width + j + 1] {
+            ops.push(LineOp::Delete(old_mid[i].to_string()));
+            i += 1;
+        } else {
+            ops.push(LineOp::Insert(new_mid[j].to_string()));
+            j += 1;
+        }
+    }
+    ops.extend(
+        old_mid[i..]
+            .iter()
+            .map(|line| LineOp::Delete(line.to_string())),
+    );
+    ops.extend(
+        new_mid[j..]
+            .iter()
+            .map(|line| LineOp::Insert(line.to_string())),
+    );
+    ops.extend(
+        old_lines[old_lines.len() - suffix..]
+            .iter()
+            .map(|line| LineOp::Equal(line.to_string())),
+    );
+
+    Ok(ops)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn equal(line: &str) -> LineOp {
+        LineOp::Equal(line.to_string())
+    }
+
+    fn insert(line: &str) -> LineOp {
+        LineOp::Insert(line.to_string())
+    }
+
+    fn delete(line: &str) -> LineOp {
+        LineOp::Delete(line.to_string())
+    }
+
+    #[test]
+    fn test_text_diff_insert_only() {
+        let ops = text_diff("a\nc", "a\nb\nc\nd").unwrap();
+        assert_eq!(ops, vec![equal("a"), insert("b"), equal("c"), insert("d")]);
+    }
+
+    #[test]
+    fn test_text_diff_delete_only() {
+        let ops = text_diff("a\nb\nc", "a\nc").unwrap();
+        assert_eq!(ops, vec![equal("a"), delete("b"), equal("c")]);
+    }
+
+    #[test]
+    fn test_text_diff_mixed() {
+        let ops = text_diff("one\ntwo\nthree\nfour", "one\n2\nthree\nfour\nfive").unwrap();
+        assert_eq!(
+            ops,
+            vec![
+                equal("one"),
+                delete("two"),
+                insert("2"),
+                equal("three"),
+                equal("four"),
+                insert("five"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_text_diff_identical() {
+        let ops = text_diff("x\ny", "x\ny").unwrap();
+        assert_eq!(ops, vec![equal("x"), equal("y")]);
+    }
+
+    #[test]
+    fn test_text_diff_rejects_oversized_input() {
+        let old: String = (0..5000).map(|i| format!("old {}\n", i)).collect();
+        let new: String = (0..5000).map(|i| format!("new {}\n", i)).collect();
+        assert!(text_diff(&old, &new).unwrap_err().contains("文本差异过大"));
+
+        // 大文件中的少量改动只比较改动区域
+        let edited = old.replacen("old 2500\n", "changed\n", 1);
+        let ops = text_diff(&old, &edited).unwrap();
+        assert_eq!(ops.len(), 5001);
+        assert_eq!(ops[2500], delete("old 2500"));
+        assert_eq!(ops[2501], insert("changed"));
+    }
+}
@@ -0,0 +1,131 @@
+use std::fmt;
+use std::io;
+
+/// FFI 返回的 `OperationResult::error_kind` 取值
+///
+/// 取值是稳定的字符串常量，Go 侧可直接按值比较；IO 错误使用对应的
+/// `io::ErrorKind` 名称，其他失败按类别归类。
+pub mod kind {
+    /// 文件或目录不存在
+    pub const NOT_FOUND: &str = "NotFound";
+    /// 权限不足
+    pub const PERMISSION_DENIED: &str = "PermissionDenied";
+    /// 目标已存在
+    pub const ALREADY_EXISTS: &str = "AlreadyExists";
+    /// 期望文件却得到目录
+    pub const IS_A_DIRECTORY: &str = "IsADirectory";
+    /// 期望目录却得到文件
+    pub const NOT_A_DIRECTORY: &str = "NotADirectory";
+    /// 磁盘空间不足
+    pub const STORAGE_FULL: &str = "StorageFull";
+    /// 数据提前结束
+    pub const UNEXPECTED_EOF: &str = "UnexpectedEof";
+    /// 其他 IO 错误
+    pub const IO: &str = "Io";
+    /// 调用参数无效（空指针、非 UTF-8、JSON 格式错误、环境变量缺失等）
+    pub const INVALID_ARGUMENT: &str = "InvalidArgument";
+    /// 操作本身失败（加密、压缩、差异计算等非 IO 错误）
+    pub const FAILED: &str = "Failed";
+    /// 内部错误（FFI 调用中发生 panic）
+    pub const INTERNAL: &str = "Internal";
+}
+
+/// 将 `io::ErrorKind` 映射为稳定的 `error_kind` 常量
+pub fn io_kind_name(io_kind: io::ErrorKind) -> &'static str {
+    match io_kind {
+        io::ErrorKind::NotFound => kind::NOT_FOUND,
+        io::ErrorKind::PermissionDenied => kind::PERMISSION_DENIED,
+        io::ErrorKind::AlreadyExists => kind::ALREADY_EXISTS,
+        io::ErrorKind::IsADirectory => kind::IS_A_DIRECTORY,
+        io::ErrorKind::NotADirectory => kind::NOT_A_DIRECTORY,
+        io::ErrorKind::StorageFull => kind::STORAGE_FULL,
+        io::ErrorKind::UnexpectedEof => kind::UNEXPECTED_EOF,
+        _ => kind::IO,
+    }
+}
+
+/// 同步操作错误
+#[derive(Debug)]
+pub enum SyncError {
+    /// IO 错误，保留原始 `io::Error` 以便调用方按 `io::ErrorKind` 区分
+    /// "文件不存在"、"权限不足" 等情况
+    Io { context: String, source: io::Error },
+}
+
+impl SyncError {
+    /// 创建 IO 错误，`context` 描述失败的操作（如 "读取文件失败 /a.txt"）
+    pub fn io(context: impl Into<String>, source: io::Error) -> Self {
+        SyncError::Io {
+            context: context.into(),
+            source,
+        }
+    }
+
+    /// 获取 IO 错误的类型
+    pub fn io_kind(&self) -> Option<io::ErrorKind> {
+        match self {
+            SyncError::Io { source, .. } => Some(source.kind()),
+        }
+    }
+
+    /// 获取错误对应的 `error_kind` 常量（见 [`kind`]）
+    pub fn kind_name(&self) -> &'static str {
+        match self {
+            SyncError::Io { source, .. } => io_kind_name(source.kind()),
+        }
+    }
+}
+
+impl fmt::Display for SyncError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SyncError::Io { context, source } => write!(f, "{}: {}", context, source),
+        }
+    }
+}
+
+impl std::error::Error for SyncError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SyncError::Io { source, .. } => Some(source),
+        }
+    }
+}
+
+impl From<SyncError> for String {
+    fn from(error: SyncError) -> Self {
+        error.to_string()
+    }
+}
+
+/// 读取整个文件，失败时保留 IO 错误类型
+pub fn read_file(file_path: &str) -> Result<Vec<u8>, SyncError> {
+    std::fs::read(file_path).map_err(|e| SyncError::io(format!("读取文件失败 {}", file_path), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_file_not_found_kind() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("missing.txt");
+
+        let error = read_file(path.to_str().unwrap()).unwrap_err();
+
+        assert_eq!(error.io_kind(), Some(io::ErrorKind::NotFound));
+        assert!(String::from(error).starts_with("读取文件失败"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_read_file_directory_kind() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let error = read_file(dir.path().to_str().unwrap()).unwrap_err();
+
+        assert_eq!(error.io_kind(), Some(io::ErrorKind::IsADirectory));
+        assert_eq!(error.kind_name(), kind::IS_A_DIRECTORY);
+    }
+}
@@ -1,35 +1,159 @@
-use rayon::prelude::*;
-use serde::{Deseridalize, Serialize};
-use std::collections::HashMap;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap, HashMap, HashSet};
 use std::ffi::{CStr, CString};
-use std::os::raw::c_char;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::os::raw::{c_char, c_void};
+use std::path::Path;
+use zeroize::Zeroizing;
 
+pub mod atomic;
 pub mod compression;
 pub mod crypto;
 pub mod diff;
+pub mod error;
+pub mod progress;
+pub mod scan;
 
 use compression::Compressor;
 use crypto::CryptoCompressor;
 use diff::DiffCalculator;
 
 /// 文件元数据结构
-#[derive(Debug,Clone,Serialize,Deseridalize)]
+#[derive(Debug,Clone,Serialize,Deserialize)]
 pub struct FileMetadata{
     pub path: String,
     pub hash: String,
     pub size: i64,
+    #[serde(default)]
     pub modified_time: String,
+    #[serde(default)]
     pub permissions: String,
+    /// 弱校验和（Adler-32），用于在比较强哈希之前快速排除不同的文件
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub weak_checksum: Option<u32>,
+}
+
+impl FileMetadata {
+    /// 将原始字节路径无损编码为字符串
+    ///
+    /// 有效的 UTF-8 片段原样保留（`%` 转义为 `%25`），无效字节编码为 `%XX`，
+    /// 编码后的路径可以像普通路径一样参与差异比较，并由 `decode_path_bytes` 还原。
+    pub fn encode_path_bytes(bytes: &[u8]) -> String {
+        let mut encoded = String::with_capacity(bytes.len());
+        for chunk in bytes.utf8_chunks() {
+            for ch in chunk.valid().chars() {
+                if ch == '%' {
+                    encoded.push_str("%25");
+                } else {
+                    encoded.push(ch);
+                }
+            }
+            for byte in chunk.invalid() {
+                encoded.push_str(&format!("%{:02X}", byte));
+            }
+        }
+        encoded
+    }
+
+    /// 还原 `encode_path_bytes` 编码的路径
+    pub fn decode_path_bytes(path: &str) -> Result<Vec<u8>, String> {
+        let bytes = path.as_bytes();
+        let mut decoded = Vec::with_capacity(bytes.len());
+        let mut i = 0;
+        while i < bytes.len() {
+            if bytes[i] == b'%' {
+                let byte = bytes
+                    .get(i + 1..i + 3)
+                    .and_then(|hex| std::str::from_utf8(hex).ok())
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                    .ok_or_else(|| format!("无效的路径编码: {}", path))?;
+                decoded.push(byte);
+                i += 3;
+            } else {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+        Ok(decoded)
+    }
+}
+
+/// 文件元数据的序列化视图
+///
+/// 默认只输出 path、hash、size，可按需加入修改时间和权限，
+/// 生成的 JSON 仍可直接反序列化为 `FileMetadata`（缺省字段为空字符串）。
+#[derive(Debug, Serialize)]
+pub struct FileMetadataView<'a> {
+    pub path: &'a str,
+    pub hash: &'a str,
+    pub size: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub modified_time: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub permissions: Option<&'a str>,
+    #[serde(skip)]
+    metadata: &'a FileMetadata,
+}
+
+impl<'a> FileMetadataView<'a> {
+    /// 创建只包含 path、hash、size 的精简视图
+    pub fn lean(metadata: &'a FileMetadata) -> Self {
+        Self {
+            path: &metadata.path,
+            hash: &metadata.hash,
+            size: metadata.size,
+            modified_time: None,
+            permissions: None,
+            metadata,
+        }
+    }
+
+    /// 设置是否在视图中包含修改时间
+    pub fn with_modified_time(mut self, include: bool) -> Self {
+        self.modified_time = include.then_some(self.metadata.modified_time.as_str());
+        self
+    }
+
+    /// 设置是否在视图中包含权限
+    pub fn with_permissions(mut self, include: bool) -> Self {
+        self.permissions = include.then_some(self.metadata.permissions.as_str());
+        self
+    }
 }
 
 /// 文件差异结构
-#[derive(Debug,Clone,Serialize,Deseridalize)]
+#[derive(Debug,Clone,Serialize,Deserialize)]
 pub struct FileDiff{
     pub path: String,
     pub operation: String,
     pub source_hash: String,
     pub dest_hash:String,
     pub size: i64,
+    /// 内容相同的目标文件路径（`no_op` 操作时由服务端复制，无需上传）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub copy_from: Option<String>,
+    /// 人类可读的差异原因（见 `DiffCalculator::with_explanations`）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub explanation: Option<String>,
+    /// 大文件拆分为多个条目时本条目覆盖的字节区间（见 `DiffCalculator::with_range_split`）
+    #[serde(default, flatten, skip_serializing_if = "Option::is_none")]
+    pub range: Option<ByteRange>,
+    /// 写入完成后用于校验的完整 SHA256（十六进制小写，见 `DiffCalculator::with_verify_hash`）
+    ///
+    /// 拆分为区间的条目携带整个文件的哈希，应在全部区间写入后再校验。
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub verify_hash: Option<String>,
+}
+
+/// 文件中的字节区间，序列化为 `offset` 和 `len` 两个字段
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ByteRange {
+    pub offset: u64,
+    pub len: u64,
 }
 
 /// 操作结果结构
@@ -38,6 +162,9 @@ pub struct OperationResult {
     pub success: bool,
     pub message: String,
     pub data: Option<String>,
+    /// 失败时的错误类别（取值见 `error::kind`，如 "NotFound"、"InvalidArgument"），成功时为空
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error_kind: Option<String>,
 }
 
 /// 差异计算结果
@@ -45,10 +172,20 @@ pub struct OperationResult {
 pub struct DiffResult {
     pub differences: Vec<FileDiff>,
     pub statistics: DiffStatistics,
+    /// 无法完成比较的文件（不影响其他文件的结果）
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<DiffError>,
+}
+
+/// 单个文件的差异计算错误
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DiffError {
+    pub path: String,
+    pub message: String,
 }
 
 /// 差异统计信息
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct DiffStatistics {
     pub total_source_files: usize,
     pub total_dest_files: usize,
@@ -56,6 +193,589 @@ pub struct DiffStatistics {
     pub files_to_update: usize,
     pub files_to_delete: usize,
     pub total_size: i64,
+    /// 是否检查过需要删除的文件；为 false 时 `files_to_delete` 为 0 表示未检查，
+    /// 而不是没有需要删除的文件
+    #[serde(default)]
+    pub deletions_considered: bool,
+}
+
+impl DiffStatistics {
+    /// 根据差异列表统计各类操作数量和总大小
+    ///
+    /// 拆分为多个区间条目的文件只按路径计数一次，大小累加各区间的长度。
+    pub(crate) fn tally(
+        total_source_files: usize,
+        total_dest_files: usize,
+        differences: &[FileDiff],
+    ) -> Self {
+        let mut statistics = DiffStatistics {
+            total_source_files,
+            total_dest_files,
+            files_to_create: 0,
+            files_to_update: 0,
+            files_to_delete: 0,
+            total_size: 0,
+            deletions_considered: false,
+        };
+
+        let mut ranged_files = HashSet::new();
+        for diff in differences {
+            match diff.range {
+                Some(range) => {
+                    let first = ranged_files.insert((diff.operation.as_str(), diff.path.as_str()));
+                    let len = i64::try_from(range.len).unwrap_or(i64::MAX);
+                    statistics.add(&diff.operation, usize::from(first), len);
+                }
+                None => statistics.record(diff),
+            }
+        }
+
+        statistics
+    }
+
+    /// 将单个差异计入统计
+    pub(crate) fn record(&mut self, diff: &FileDiff) {
+        self.add(&diff.operation, 1, diff.size);
+    }
+
+    /// 辅助函数：按操作类型累加文件数和大小
+    fn add(&mut self, operation: &str, files: usize, size: i64) {
+        match operation {
+            "create" => self.files_to_create += files,
+            "update" => self.files_to_update += files,
+            "delete" => self.files_to_delete += files,
+            // 内容已存在于目标端，无需传输
+            "no_op" => return,
+            _ => {}
+        }
+        self.total_size = self.total_size.saturating_add(size);
+    }
+}
+
+impl DiffResult {
+    /// 取优先级最高的 N 个差异（按优先级降序，同优先级保持原顺序）
+    ///
+    /// 使用容量为 N 的二叉堆，复杂度 O(m log n)，无需对整个差异列表排序。
+    pub fn top_n_by_priority(&self, n: usize) -> Vec<FileDiff> {
+        if n == 0 {
+            return Vec::new();
+        }
+
+        let calculator = DiffCalculator::new();
+        let mut heap = BinaryHeap::with_capacity(n + 1);
+        for (index, diff) in self.differences.iter().enumerate() {
+            let priority = calculator.get_file_priority(diff);
+            heap.push(Reverse((priority, Reverse(index))));
+            if heap.len() > n {
+                heap.pop();
+            }
+        }
+
+        heap.into_sorted_vec()
+            .into_iter()
+            .map(|Reverse((_, Reverse(index)))| self.differences[index].clone())
+            .collect()
+    }
+
+    /// 按文件扩展名（小写）分组统计差异，无扩展名的文件归入空字符串键
+    ///
+    /// 差异结果中没有按扩展名划分的源/目标文件总数，因此各分组的
+    /// `total_source_files` 和 `total_dest_files` 为 0。
+    pub fn stats_by_extension(&self) -> HashMap<String, DiffStatistics> {
+        let mut groups: HashMap<String, Vec<FileDiff>> = HashMap::new();
+        for diff in &self.differences {
+            let extension = Path::new(&diff.path)
+                .extension()
+                .map(|ext| ext.to_string_lossy().to_lowercase())
+                .unwrap_or_default();
+            groups.entry(extension).or_default().push(diff.clone());
+        }
+
+        groups
+            .into_iter()
+            .map(|(extension, diffs)| {
+                let mut statistics = DiffStatistics::tally(0, 0, &diffs);
+                statistics.deletions_considered = self.statistics.deletions_considered;
+                (extension, statistics)
+            })
+            .collect()
+    }
+
+    /// 按代价模型估算整个同步计划的总代价（各差异操作代价之和）
+    pub fn total_estimated_cost(&self, model: &diff::CostModel) -> f64 {
+        self.differences
+            .iter()
+            .map(|diff| DiffCalculator::estimate_cost(diff, model))
+            .sum()
+    }
+
+    /// 估算压缩后实际需要传输的字节数（仅 create 和 update）
+    ///
+    /// `sampler` 为每个差异返回预估压缩比（压缩后大小 / 原始大小），可按文件类型
+    /// 查表或对文件做一次抽样压缩得到；负数按 0 处理。
+    pub fn estimate_transfer_bytes(&self, sampler: &dyn Fn(&FileDiff) -> f64) -> i64 {
+        self.differences
+            .iter()
+            .filter(|diff| diff.operation == "create" || diff.operation == "update")
+            .map(|diff| (diff.size as f64 * sampler(diff).max(0.0)).round() as i64)
+            .sum()
+    }
+
+    /// 本次同步变动的文件占目标端文件总数的比例（创建、更新、删除之和），范围为 [0, 1]
+    ///
+    /// `total_dest_files` 为 0 时返回 0。
+    pub fn churn_ratio(&self, total_dest_files: usize) -> f64 {
+        let statistics = &self.statistics;
+        let changed =
+            statistics.files_to_create + statistics.files_to_update + statistics.files_to_delete;
+        clamped_ratio(changed as f64, total_dest_files as f64)
+    }
+
+    /// 按字节计算的变动比例：差异涉及的总大小占目标端总字节数的比例，范围为 [0, 1]
+    ///
+    /// `total_dest_bytes` 为 0 时返回 0。
+    pub fn churn_bytes_ratio(&self, total_dest_bytes: u64) -> f64 {
+        clamped_ratio(self.statistics.total_size as f64, total_dest_bytes as f64)
+    }
+
+    /// 用 `key` 计算 HMAC 封印差异结果，接收方用 `SealedManifest::open` 校验
+    pub fn seal(&self, key: &[u8]) -> Result<crypto::SealedManifest, String> {
+        crypto::SealedManifest::seal(self, key)
+    }
+
+    /// 计算反向差异（用于回滚计划）
+    ///
+    /// create 变为 delete，delete 变为 create（仅当删除前的哈希已知时），
+    /// update 交换源/目标哈希。带 `copy_from` 的 `no_op`（服务端复制）视为写入了复制目标路径：
+    /// 原先不存在的路径变为 delete，原先存在的路径变为恢复旧哈希的 update。
+    /// 源和目标文件总数随之互换，原有的差异说明和校验哈希不再适用而被清除。
+    /// 反转后的删除来自原结果中完整的创建列表，因此总是视为已检查删除。
+    /// 同一文件拆分出的多个创建区间合并为一个删除，大小为各区间长度之和。
+    ///
+    /// 复制的源路径在同一计划中也被更新或删除时，正向计划必须先完成复制再修改源路径；
+    /// 反向计划只撤销复制目标路径，源路径由它自己的条目反转恢复，两者互不依赖。
+    pub fn invert(&self) -> DiffResult {
+        let differences: Vec<FileDiff> = self
+            .differences
+            .iter()
+            .filter_map(|diff| match diff.operation.as_str() {
+                "create" => Some(FileDiff {
+                    operation: "delete".to_string(),
+                    source_hash: String::new(),
+                    dest_hash: diff.source_hash.clone(),
+                    explanation: None,
+                    verify_hash: None,
+                    ..diff.clone()
+                }),
+                "delete" if !diff.dest_hash.is_empty() => Some(FileDiff {
+                    operation: "create".to_string(),
+                    source_hash: diff.dest_hash.clone(),
+                    dest_hash: String::new(),
+                    explanation: None,
+                    verify_hash: None,
+                    ..diff.clone()
+                }),
+                "update" => Some(FileDiff {
+                    source_hash: diff.dest_hash.clone(),
+                    dest_hash: diff.source_hash.clone(),
+                    explanation: None,
+                    verify_hash: None,
+                    ..diff.clone()
+                }),
+                "no_op" if diff.copy_from.is_some() => Some(FileDiff {
+                    operation: if diff.dest_hash.is_empty() {
+                        "delete"
+                    } else {
+                        "update"
+                    }
+                    .to_string(),
+                    source_hash: diff.dest_hash.clone(),
+                    dest_hash: diff.source_hash.clone(),
+                    copy_from: None,
+                    explanation: None,
+                    verify_hash: None,
+                    ..diff.clone()
+                }),
+                _ => None,
+            })
+            .collect();
+        let differences = merge_range_deletes(differences);
+
+        let mut statistics = DiffStatistics::tally(
+            self.statistics.total_dest_files,
+            self.statistics.total_source_files,
+            &differences,
+        );
+        statistics.deletions_considered = true;
+
+        DiffResult {
+            differences,
+            statistics,
+            errors: self.errors.clone(),
+        }
+    }
+
+    /// 去掉路径已在 `completed` 中的差异，返回剩余部分（用于从检查点恢复应用）
+    ///
+    /// 统计信息按剩余差异重新计算，源和目标文件总数保持不变。
+    pub fn remaining_after(&self, completed: &HashSet<String>) -> DiffResult {
+        let differences: Vec<FileDiff> = self
+            .differences
+            .iter()
+            .filter(|diff| !completed.contains(&diff.path))
+            .cloned()
+            .collect();
+
+        let mut statistics = DiffStatistics::tally(
+            self.statistics.total_source_files,
+            self.statistics.total_dest_files,
+            &differences,
+        );
+        statistics.deletions_considered = self.statistics.deletions_considered;
+
+        DiffResult {
+            differences,
+            statistics,
+            errors: self.errors.clone(),
+        }
+    }
+
+    /// 按标准化路径（分隔符统一为 `/`）排序差异和错误，使序列化结果稳定
+    ///
+    /// 同一文件拆分出的多个区间按偏移排序；路径相同的其他条目保持原有顺序。
+    pub fn sort_by_path(&mut self) {
+        let sort_key = |path: &str| path.replace('\\', "/");
+        self.differences.sort_by_cached_key(|diff| {
+            (sort_key(&diff.path), diff.range.map(|range| range.offset))
+        });
+        self.errors
+            .sort_by_cached_key(|error| sort_key(&error.path));
+    }
+
+    /// 合并同一路径（同一区间）的多个条目，使每个路径只剩一个操作，并重新计算统计信息
+    ///
+    /// 路径按 `/` 标准化后分组，各组保持首次出现的位置。每组按以下优先级解析：
+    /// 存在 create/update 时以最后一个为准，若组内还有 delete 或 update（目标端已存在该文件）
+    /// 则结果为 update，缺少的目标哈希从这些条目中补全；否则为最后一个 delete；
+    /// 只有 no_op 等其他操作时保留最后一个。源和目标文件总数保持不变。
+    pub fn canonicalize(&mut self) {
+        let mut order: Vec<(String, Option<u64>)> = Vec::new();
+        let mut groups: HashMap<(String, Option<u64>), Vec<FileDiff>> = HashMap::new();
+        for diff in self.differences.drain(..) {
+            let key = (
+                diff.path.replace('\\', "/"),
+                diff.range.map(|range| range.offset),
+            );
+            if !groups.contains_key(&key) {
+                order.push(key.clone());
+            }
+            groups.entry(key).or_default().push(diff);
+        }
+
+        self.differences = order
+            .into_iter()
+            .filter_map(|key| groups.remove(&key))
+            .filter_map(resolve_path_entries)
+            .collect();
+
+        let deletions_considered = self.statistics.deletions_considered;
+        self.statistics = DiffStatistics::tally(
+            self.statistics.total_source_files,
+            self.statistics.total_dest_files,
+            &self.differences,
+        );
+        self.statistics.deletions_considered = deletions_considered;
+    }
+
+    /// 在内存中按顺序模拟执行差异，返回目标端最终的文件列表（按路径排序）
+    ///
+    /// 用于在真正执行前发现顺序错误：update 或 delete 不存在的文件、从不存在的文件
+    /// 复制（带 `copy_from` 的 no_op）都会返回错误。create 允许覆盖已有文件
+    /// （如 `EmptyDestHashPolicy::TreatAsCreate` 产生的计划）。区间条目按区间末尾扩展文件大小。
+    /// 路径分隔符统一为 `/`；写入的文件清空修改时间和弱校验和，只保留原有权限。
+    pub fn simulate_apply(
+        &self,
+        initial_dest: &[FileMetadata],
+    ) -> Result<Vec<FileMetadata>, String> {
+        let key = |path: &str| path.replace('\\', "/");
+        let mut files: BTreeMap<String, FileMetadata> = initial_dest
+            .iter()
+            .map(|file| (key(&file.path), file.clone()))
+            .collect();
+
+        for (index, diff) in self.differences.iter().enumerate() {
+            let path = key(&diff.path);
+            let fail =
+                |reason: &str| format!("第 {} 个操作无法执行: {} {}", index + 1, reason, path);
+            let existing = files.get(&path);
+            match diff.operation.as_str() {
+                "create" | "update" => {
+                    if diff.operation == "update" && existing.is_none() {
+                        return Err(fail("更新不存在的文件"));
+                    }
+                }
+                "delete" => {
+                    if files.remove(&path).is_none() {
+                        return Err(fail("删除不存在的文件"));
+                    }
+                    continue;
+                }
+                "no_op" => match &diff.copy_from {
+                    Some(copy_from) if !files.contains_key(&key(copy_from)) => {
+                        return Err(fail(&format!("复制源不存在 {} ->", copy_from)));
+                    }
+                    Some(_) => {}
+                    None => continue,
+                },
+                other => return Err(fail(&format!("未知操作 {}", other))),
+            }
+
+            let size = match diff.range {
+                Some(range) => {
+                    let end = range
+                        .offset
+                        .checked_add(range.len)
+                        .and_then(|end| i64::try_from(end).ok())
+                        .ok_or_else(|| fail("区间越界"))?;
+                    existing.map_or(0, |file| file.size).max(end)
+                }
+                None => diff.size,
+            };
+            let permissions = existing
+                .map(|file| file.permissions.clone())
+                .unwrap_or_default();
+            files.insert(
+                path.clone(),
+                FileMetadata {
+                    path,
+                    hash: diff.source_hash.clone(),
+                    size,
+                    modified_time: String::new(),
+                    permissions,
+                    weak_checksum: None,
+                },
+            );
+        }
+
+        Ok(files.into_values().collect())
+    }
+
+    /// 检查差异列表是否自洽，返回发现的全部问题
+    ///
+    /// 检查项：路径为空、大小为负、未知操作、create/update 缺少源哈希、
+    /// create 带有目标哈希、delete 带有源哈希，以及同一路径（同一区间）出现多次。
+    pub fn validate(&self) -> Result<(), Vec<String>> {
+        let mut violations = Vec::new();
+        let mut seen = HashSet::new();
+
+        for (index, diff) in self.differences.iter().enumerate() {
+            let mut report = |message: String| {
+                violations.push(format!("第 {} 个差异 {}: {}", index, diff.path, message))
+            };
+
+            if diff.path.is_empty() {
+                report("路径为空".to_string());
+            }
+            if diff.size < 0 {
+                report(format!("大小为负数 ({})", diff.size));
+            }
+            match diff.operation.as_str() {
+                "create" | "update" | "no_op" if diff.source_hash.is_empty() => {
+                    report(format!("{} 缺少源哈希", diff.operation));
+                }
+                _ => {}
+            }
+            match diff.operation.as_str() {
+                "create" if !diff.dest_hash.is_empty() => {
+                    report("create 不应有目标哈希".to_string());
+                }
+                "delete" if !diff.source_hash.is_empty() => {
+                    report("delete 不应有源哈希".to_string());
+                }
+                "create" | "update" | "delete" | "no_op" => {}
+                other => report(format!("未知操作: {}", other)),
+            }
+            // 按区间拆分的条目共享同一路径，以路径和区间起点区分
+            let offset = diff.range.map(|range| range.offset);
+            if !seen.insert((diff.path.as_str(), offset)) {
+                report("路径重复".to_string());
+            }
+        }
+
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(violations)
+        }
+    }
+
+    /// 将差异结果以 JSON 格式保存到文件
+    pub fn save_to_file(&self, path: &str) -> Result<(), String> {
+        let file = File::create(path).map_err(|e| format!("创建文件失败 {}: {}", path, e))?;
+        let mut writer = BufWriter::new(file);
+        serde_json::to_writer(&mut writer, self)
+            .map_err(|e| format!("序列化差异结果失败: {}", e))?;
+        writer
+            .flush()
+            .map_err(|e| format!("写入文件失败 {}: {}", path, e))
+    }
+
+    /// 从 JSON 文件加载差异结果
+    ///
+    /// 通过带缓冲的文件句柄流式解析，不会先把整个文件读入 `String`，
+    /// 适合体积很大的差异清单。
+    pub fn load_from_file(path: &str) -> Result<DiffResult, String> {
+        let file = File::open(path).map_err(|e| format!("打开文件失败 {}: {}", path, e))?;
+        serde_json::from_reader(BufReader::new(file))
+            .map_err(|e| format!("解析差异结果失败 {}: {}", path, e))
+    }
+
+    /// 以 CSV 格式写出差异（列：path, operation, source_hash, dest_hash, size）
+    ///
+    /// 首行为表头；含逗号、双引号或换行的字段用双引号包裹，内部双引号写作两个，
+    /// 可直接用 Excel 等表格软件打开。
+    pub fn to_csv(&self, mut writer: impl Write) -> Result<(), String> {
+        let write_error = |e: std::io::Error| format!("写入 CSV 失败: {}", e);
+        writeln!(writer, "{}", CSV_HEADER.join(",")).map_err(write_error)?;
+        for diff in &self.differences {
+            let size = diff.size.to_string();
+            let fields = [
+                diff.path.as_str(),
+                diff.operation.as_str(),
+                diff.source_hash.as_str(),
+                diff.dest_hash.as_str(),
+                size.as_str(),
+            ];
+            let line: Vec<String> = fields.iter().map(|field| csv_escape(field)).collect();
+            writeln!(writer, "{}", line.join(",")).map_err(write_error)?;
+        }
+        writer.flush().map_err(write_error)
+    }
+
+    /// 读取 `to_csv` 写出的 CSV 差异
+    ///
+    /// CSV 中没有源/目标文件总数，统计信息中的这两项为 0。
+    pub fn from_csv(mut reader: impl Read) -> Result<DiffResult, String> {
+        let mut text = String::new();
+        reader
+            .read_to_string(&mut text)
+            .map_err(|e| format!("读取 CSV 失败: {}", e))?;
+
+        let mut records = parse_csv(&text)?.into_iter();
+        match records.next() {
+            Some(header) if header == CSV_HEADER => {}
+            _ => return Err("CSV 表头无效".to_string()),
+        }
+
+        let mut differences = Vec::new();
+        for (index, record) in records.enumerate() {
+            let line = index + 2;
+            let [path, operation, source_hash, dest_hash, size]: [String; 5] = record
+                .try_into()
+                .map_err(|_| format!("CSV 第 {} 行列数错误", line))?;
+            let size = size
+                .parse()
+                .map_err(|_| format!("CSV 第 {} 行大小无效: {}", line, size))?;
+            differences.push(FileDiff {
+                path,
+                operation,
+                source_hash,
+                dest_hash,
+                size,
+                copy_from: None,
+                explanation: None,
+                range: None,
+                verify_hash: None,
+            });
+        }
+
+        Ok(DiffResult {
+            statistics: DiffStatistics::tally(0, 0, &differences),
+            differences,
+            errors: Vec::new(),
+        })
+    }
+
+    /// 统计待传输文件（create + update）的大小分布
+    ///
+    /// `buckets` 为升序排列的上界（不含），返回每个桶的
+    /// `(上界, 文件数, 字节总数)`；大于等于最后一个上界的文件归入
+    /// 上界为 `i64::MAX` 的溢出桶。
+    pub fn size_histogram(&self, buckets: &[i64]) -> Vec<(i64, usize, i64)> {
+        let mut histogram: Vec<(i64, usize, i64)> = buckets
+            .iter()
+            .map(|&upper| (upper, 0, 0))
+            .chain(std::iter::once((i64::MAX, 0, 0)))
+            .collect();
+
+        for diff in &self.differences {
+            if diff.operation != "create" && diff.operation != "update" {
+                continue;
+            }
+            let index = buckets
+                .iter()
+                .position(|&upper| diff.size < upper)
+                .unwrap_or(buckets.len());
+            histogram[index].1 += 1;
+            histogram[index].2 += diff.size;
+        }
+
+        histogram
+    }
+}
+
+/// CSV 差异的列名
+const CSV_HEADER: [&str; 5] = ["path", "operation", "source_hash", "dest_hash", "size"];
+
+/// 按 RFC 4180 转义 CSV 字段
+fn csv_escape(field: &str) -> String {
+    if field.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// 解析 CSV 文本为记录列表，支持带引号的字段（可含逗号、换行和转义的双引号）
+fn parse_csv(text: &str) -> Result<Vec<Vec<String>>, String> {
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut field = String::new();
+    let mut chars = text.chars().peekable();
+    let mut in_quotes = false;
+
+    while let Some(c) = chars.next() {
+        if in_quotes {
+            match c {
+                '"' if chars.peek() == Some(&'"') => {
+                    chars.next();
+                    field.push('"');
+                }
+                '"' => in_quotes = false,
+                _ => field.push(c),
+            }
+            continue;
+        }
+        match c {
+            '"' if field.is_empty() => in_quotes = true,
+            ',' => record.push(std::mem::take(&mut field)),
+            '\r' if chars.peek() == Some(&'\n') => {}
+            '\n' => {
+                record.push(std::mem::take(&mut field));
+                records.push(std::mem::take(&mut record));
+            }
+            _ => field.push(c),
+        }
+    }
+    if in_quotes {
+        return Err("CSV 引号未闭合".to_string());
+    }
+    if !field.is_empty() || !record.is_empty() {
+        record.push(field);
+        records.push(record);
+    }
+
+    Ok(records)
 }
 
 /// 主要的性能模块结构
@@ -89,6 +809,11 @@ impl SyncEngine {
         self.crypto_compressor.encrypt_file(file_path, key)
     }
 
+    /// 加密数据
+    pub fn encrypt_data(&self, data: &[u8], key: &[u8]) -> Result<Vec<u8>, String> {
+        self.crypto_compressor.encrypt_data(data, key)
+    }
+
     /// 解密文件
     pub fn decrypt_file(&self, encrypted_data: &[u8], key: &[u8]) -> Result<Vec<u8>, String> {
         self.crypto_compressor.decrypt_data(encrypted_data, key)
@@ -103,6 +828,113 @@ impl SyncEngine {
     pub fn decompress_data(&self, compressed_data: &[u8]) -> Result<Vec<u8>, String> {
         self.compressor.decompress(compressed_data)
     }
+
+    /// 解压缩数据并校验解压结果的 SHA256，与 `expected_hash` 不一致时报错
+    pub fn decompress_verified(
+        &self,
+        compressed: &[u8],
+        expected_hash: &str,
+    ) -> Result<Vec<u8>, String> {
+        let data = self.compressor.decompress(compressed)?;
+        if !self.crypto_compressor.hash_matches(&data, expected_hash) {
+            return Err("解压数据校验失败: 哈希不匹配".to_string());
+        }
+        Ok(data)
+    }
+
+    /// 压缩数据并在末尾追加压缩结果的 HMAC-SHA256
+    ///
+    /// 只提供完整性校验（防篡改），数据本身不加密；需要保密时使用 `encrypt_file`。
+    pub fn compress_signed(&self, data: &[u8], key: &[u8]) -> Result<Vec<u8>, String> {
+        let compressed = self.compressor.compress(data)?;
+        Ok(crypto::append_mac(compressed, key))
+    }
+
+    /// 校验 `compress_signed` 生成的 HMAC（常数时间比较）后解压缩
+    pub fn decompress_verified_signed(&self, blob: &[u8], key: &[u8]) -> Result<Vec<u8>, String> {
+        let compressed = crypto::verify_mac(blob, key)?;
+        self.compressor.decompress(compressed)
+    }
+
+    /// 将文件流式压缩到另一个文件
+    pub fn compress_file_to_path(
+        &self,
+        input_path: &str,
+        output_path: &str,
+    ) -> Result<compression::CompressionResult, String> {
+        self.compressor
+            .compress_file_to_file(input_path, output_path)
+    }
+}
+
+impl Default for SyncEngine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// 辅助函数：将同一路径的多个差异条目解析为一个（规则见 `DiffResult::canonicalize`）
+fn resolve_path_entries(entries: Vec<FileDiff>) -> Option<FileDiff> {
+    let is_write = |diff: &&FileDiff| diff.operation == "create" || diff.operation == "update";
+    let Some(write) = entries.iter().rev().find(is_write) else {
+        return entries
+            .iter()
+            .rev()
+            .find(|diff| diff.operation == "delete")
+            .or(entries.last())
+            .cloned();
+    };
+
+    // delete 或 update 说明目标端已存在该文件
+    let existing_dest_hash = entries
+        .iter()
+        .rev()
+        .find(|diff| diff.operation == "delete" || diff.operation == "update")
+        .map(|diff| diff.dest_hash.clone());
+    let mut resolved = write.clone();
+    if let Some(dest_hash) = existing_dest_hash {
+        if resolved.operation != "update" {
+            resolved.operation = "update".to_string();
+            resolved.explanation = None;
+        }
+        if resolved.dest_hash.is_empty() {
+            resolved.dest_hash = dest_hash;
+        }
+    }
+    Some(resolved)
+}
+
+/// 辅助函数：将同一路径的多个删除区间合并为一个不带区间的删除（见 `DiffResult::invert`）
+fn merge_range_deletes(differences: Vec<FileDiff>) -> Vec<FileDiff> {
+    let mut merged: Vec<FileDiff> = Vec::with_capacity(differences.len());
+    let mut delete_index: HashMap<String, usize> = HashMap::new();
+    for mut diff in differences {
+        let Some(range) = diff.range.filter(|_| diff.operation == "delete") else {
+            merged.push(diff);
+            continue;
+        };
+        let len = i64::try_from(range.len).unwrap_or(i64::MAX);
+        match delete_index.get(&diff.path) {
+            Some(&index) => {
+                merged[index].size = merged[index].size.saturating_add(len);
+            }
+            None => {
+                delete_index.insert(diff.path.clone(), merged.len());
+                diff.size = len;
+                diff.range = None;
+                merged.push(diff);
+            }
+        }
+    }
+    merged
+}
+
+/// 辅助函数：计算 `part / total` 并限制在 [0, 1]，`total` 为 0 时返回 0
+fn clamped_ratio(part: f64, total: f64) -> f64 {
+    if total <= 0.0 {
+        return 0.0;
+    }
+    (part / total).clamp(0.0, 1.0)
 }
 
 /// 辅助函数：将 Rust 字符串转换为 C 字符串
@@ -127,60 +959,48 @@ fn from_c_string(c_str: *const c_char) -> Result<String, String> {
     }
 }
 
+/// 辅助函数：捕获 FFI 调用中的 panic，转换为失败的 `OperationResult`
+///
+/// panic 跨越 FFI 边界展开到调用方是未定义行为，所有导出函数都经由此函数执行。
+fn ffi_guard<F: FnOnce() -> *mut c_char>(f: F) -> *mut c_char {
+    std::panic::catch_unwind(std::panic::AssertUnwindSafe(f)).unwrap_or_else(|payload| {
+        let result = OperationResult {
+            success: false,
+            message: format!("内部错误: {}", diff::panic_message(&payload)),
+            data: None,
+            error_kind: Some(error::kind::INTERNAL.to_string()),
+        };
+        to_c_string(serde_json::to_string(&result).unwrap_or_default())
+    })
+}
+
 // ============================================================================
 // C FFI 接口 - 供 Go 调用
 // ============================================================================
 
 /// C FFI: 计算文件差异
-#[no_mangle]
+///
+/// 差异和错误按路径排序（见 `DiffResult::sort_by_path`），相同输入总是得到逐字节相同的 JSON。
+#[unsafe(no_mangle)]
 pub extern "C" fn calculate_diff(
     source_files_json: *const c_char,
     dest_files_json: *const c_char,
 ) -> *mut c_char {
-    let source_json = match from_c_string(source_files_json) {
-        Ok(s) => s,
-        Err(e) => {
-            let result = OperationResult {
-                success: false,
-                message: format!("解析源文件列表失败: {}", e),
-                data: None,
-            };
-            return to_c_string(serde_json::to_string(&result).unwrap_or_default());
-        }
-    };
-
-    let dest_json = match from_c_string(dest_files_json) {
-        Ok(s) => s,
-        Err(e) => {
-            let result = OperationResult {
-                success: false,
-                message: format!("解析目标文件列表失败: {}", e),
-                data: None,
-            };
-            return to_c_string(serde_json::to_string(&result).unwrap_or_default());
-        }
-    };
-
-    // 解析 JSON
-    let source_files: Vec<FileMetadata> = match serde_json::from_str(&source_json) {
-        Ok(files) => files,
-        Err(e) => {
-            let result = OperationResult {
-                success: false,
-                message: format!("反序列化源文件失败: {}", e),
-                data: None,
-            };
-            return to_c_string(serde_json::to_string(&result).unwrap_or_default());
-        }
-    };
+    ffi_guard(|| calculate_diff_impl(source_files_json, dest_files_json))
+}
 
-    let dest_files: Vec<FileMetadata> = match serde_json::from_str(&dest_json) {
-        Ok(files) => files,
-        Err(e) => {
+fn calculate_diff_impl(
+    source_files_json: *const c_char,
+    dest_files_json: *const c_char,
+) -> *mut c_char {
+    let (source_files, dest_files) = match parse_file_lists(source_files_json, dest_files_json) {
+        Ok(lists) => lists,
+        Err(message) => {
             let result = OperationResult {
                 success: false,
-                message: format!("反序列化目标文件失败: {}", e),
+                message,
                 data: None,
+                error_kind: Some(error::kind::INVALID_ARGUMENT.to_string()),
             };
             return to_c_string(serde_json::to_string(&result).unwrap_or_default());
         }
@@ -189,11 +1009,13 @@ pub extern "C" fn calculate_diff(
     // 计算差异
     let engine = SyncEngine::new();
     match engine.calculate_differences(&source_files, &dest_files) {
-        Ok(diff_result) => {
+        Ok(mut diff_result) => {
+            diff_result.sort_by_path();
             let result = OperationResult {
                 success: true,
                 message: "差异计算完成".to_string(),
                 data: Some(serde_json::to_string(&diff_result).unwrap_or_default()),
+                error_kind: None,
             };
             to_c_string(serde_json::to_string(&result).unwrap_or_default())
         }
@@ -202,18 +1024,193 @@ pub extern "C" fn calculate_diff(
                 success: false,
                 message: format!("差异计算失败: {}", e),
                 data: None,
+                error_kind: Some(error::kind::FAILED.to_string()),
             };
             to_c_string(serde_json::to_string(&result).unwrap_or_default())
         }
     }
 }
 
+/// 辅助函数：解析 FFI 传入的源/目标文件列表 JSON
+fn parse_file_lists(
+    source_files_json: *const c_char,
+    dest_files_json: *const c_char,
+) -> Result<(Vec<FileMetadata>, Vec<FileMetadata>), String> {
+    let source_json =
+        from_c_string(source_files_json).map_err(|e| format!("解析源文件列表失败: {}", e))?;
+    let dest_json =
+        from_c_string(dest_files_json).map_err(|e| format!("解析目标文件列表失败: {}", e))?;
+
+    let source_files =
+        serde_json::from_str(&source_json).map_err(|e| format!("反序列化源文件失败: {}", e))?;
+    let dest_files =
+        serde_json::from_str(&dest_json).map_err(|e| format!("反序列化目标文件失败: {}", e))?;
+    Ok((source_files, dest_files))
+}
+
+/// 差异回调：每个 `FileDiff` 的 JSON 调用一次，指针仅在回调期间有效
+pub type DiffCallback = extern "C" fn(diff_json: *const c_char, user_data: *mut c_void);
+
+/// C FFI: 计算文件差异，逐条通过回调发送差异
+///
+/// 每个差异的 JSON 字符串只在回调执行期间有效，调用方需要时应自行复制。
+/// 返回值的 `data` 中为统计信息和差异计算错误（`{"statistics": ..., "errors": [...]}`）。
+#[unsafe(no_mangle)]
+pub extern "C" fn calculate_diff_streaming(
+    source_files_json: *const c_char,
+    dest_files_json: *const c_char,
+    callback: Option<DiffCallback>,
+    user_data: *mut c_void,
+) -> *mut c_char {
+    ffi_guard(|| {
+        calculate_diff_streaming_impl(source_files_json, dest_files_json, callback, user_data)
+    })
+}
+
+fn calculate_diff_streaming_impl(
+    source_files_json: *const c_char,
+    dest_files_json: *const c_char,
+    callback: Option<DiffCallback>,
+    user_data: *mut c_void,
+) -> *mut c_char {
+    let outcome = match callback {
+        Some(callback) => {
+            stream_differences(source_files_json, dest_files_json, callback, user_data)
+        }
+        None => Err((error::kind::INVALID_ARGUMENT, "回调函数为空".to_string())),
+    };
+
+    let result = match outcome {
+        Ok(summary) => OperationResult {
+            success: true,
+            message: "差异计算完成".to_string(),
+            data: Some(summary.to_string()),
+            error_kind: None,
+        },
+        Err((kind, message)) => OperationResult {
+            success: false,
+            message,
+            data: None,
+            error_kind: Some(kind.to_string()),
+        },
+    };
+    to_c_string(serde_json::to_string(&result).unwrap_or_default())
+}
+
+/// 计算差异并逐条调用回调，返回统计信息和差异计算错误
+///
+/// 失败时返回 `error_kind` 类别和错误信息。
+fn stream_differences(
+    source_files_json: *const c_char,
+    dest_files_json: *const c_char,
+    callback: DiffCallback,
+    user_data: *mut c_void,
+) -> Result<serde_json::Value, (&'static str, String)> {
+    let (source_files, dest_files) = parse_file_lists(source_files_json, dest_files_json)
+        .map_err(|e| (error::kind::INVALID_ARGUMENT, e))?;
+    let failed = |e: String| (error::kind::FAILED, e);
+    let mut diff_result = SyncEngine::new()
+        .calculate_differences(&source_files, &dest_files)
+        .map_err(|e| failed(format!("差异计算失败: {}", e)))?;
+    diff_result.sort_by_path();
+
+    for diff in &diff_result.differences {
+        let json =
+            serde_json::to_string(diff).map_err(|e| failed(format!("序列化差异失败: {}", e)))?;
+        let c_json = CString::new(json).map_err(|e| failed(format!("序列化差异失败: {}", e)))?;
+        // c_json 在回调返回后才释放
+        callback(c_json.as_ptr(), user_data);
+    }
+
+    Ok(serde_json::json!({
+        "statistics": diff_result.statistics,
+        "errors": diff_result.errors,
+    }))
+}
+
+/// C FFI: 计算文件差异，路径以原始字节传递
+///
+/// 输入中每个文件的 `path` 为原始路径字节的 Base64（对应 Go 的 `[]byte`），
+/// 因此非 UTF-8 路径也能参与比较；输出中差异的 `path`、`copy_from`
+/// 和错误的 `path` 同样为原始字节的 Base64。
+#[unsafe(no_mangle)]
+pub extern "C" fn calculate_diff_raw_paths(
+    source_files_json: *const c_char,
+    dest_files_json: *const c_char,
+) -> *mut c_char {
+    ffi_guard(|| calculate_diff_raw_paths_impl(source_files_json, dest_files_json))
+}
+
+fn calculate_diff_raw_paths_impl(
+    source_files_json: *const c_char,
+    dest_files_json: *const c_char,
+) -> *mut c_char {
+    let result = match diff_raw_paths(source_files_json, dest_files_json) {
+        Ok(diff_result) => OperationResult {
+            success: true,
+            message: "差异计算完成".to_string(),
+            data: Some(serde_json::to_string(&diff_result).unwrap_or_default()),
+            error_kind: None,
+        },
+        Err((kind, message)) => OperationResult {
+            success: false,
+            message,
+            data: None,
+            error_kind: Some(kind.to_string()),
+        },
+    };
+    to_c_string(serde_json::to_string(&result).unwrap_or_default())
+}
+
+/// 解码 Base64 路径、计算差异，再将结果中的路径编码回 Base64
+///
+/// 失败时返回 `error_kind` 类别和错误信息。
+fn diff_raw_paths(
+    source_files_json: *const c_char,
+    dest_files_json: *const c_char,
+) -> Result<DiffResult, (&'static str, String)> {
+    let invalid = |e: String| (error::kind::INVALID_ARGUMENT, e);
+    let (mut source_files, mut dest_files) =
+        parse_file_lists(source_files_json, dest_files_json).map_err(invalid)?;
+    for file in source_files.iter_mut().chain(dest_files.iter_mut()) {
+        let bytes = BASE64
+            .decode(&file.path)
+            .map_err(|e| invalid(format!("解码路径失败 {}: {}", file.path, e)))?;
+        file.path = FileMetadata::encode_path_bytes(&bytes);
+    }
+
+    let mut diff_result = SyncEngine::new()
+        .calculate_differences(&source_files, &dest_files)
+        .map_err(|e| (error::kind::FAILED, format!("差异计算失败: {}", e)))?;
+    diff_result.sort_by_path();
+
+    let to_base64 = |path: &str| {
+        FileMetadata::decode_path_bytes(path)
+            .map(|b| BASE64.encode(b))
+            .map_err(|e| (error::kind::FAILED, e))
+    };
+    for diff in &mut diff_result.differences {
+        diff.path = to_base64(&diff.path)?;
+        if let Some(copy_from) = &diff.copy_from {
+            diff.copy_from = Some(to_base64(copy_from)?);
+        }
+    }
+    for error in &mut diff_result.errors {
+        error.path = to_base64(&error.path)?;
+    }
+    Ok(diff_result)
+}
+
 /// C FFI: 加密文件
-#[no_mangle]
+#[unsafe(no_mangle)]
 pub extern "C" fn encrypt_file(
     file_path: *const c_char,
     key: *const c_char,
 ) -> *mut c_char {
+    ffi_guard(|| encrypt_file_impl(file_path, key))
+}
+
+fn encrypt_file_impl(file_path: *const c_char, key: *const c_char) -> *mut c_char {
     let path = match from_c_string(file_path) {
         Ok(s) => s,
         Err(e) => {
@@ -221,6 +1218,7 @@ pub extern "C" fn encrypt_file(
                 success: false,
                 message: format!("解析文件路径失败: {}", e),
                 data: None,
+                error_kind: Some(error::kind::INVALID_ARGUMENT.to_string()),
             };
             return to_c_string(serde_json::to_string(&result).unwrap_or_default());
         }
@@ -233,19 +1231,34 @@ pub extern "C" fn encrypt_file(
                 success: false,
                 message: format!("解析密钥失败: {}", e),
                 data: None,
+                error_kind: Some(error::kind::INVALID_ARGUMENT.to_string()),
+            };
+            return to_c_string(serde_json::to_string(&result).unwrap_or_default());
+        }
+    };
+
+    let file_data = match error::read_file(&path) {
+        Ok(data) => data,
+        Err(e) => {
+            let result = OperationResult {
+                success: false,
+                error_kind: Some(e.kind_name().to_string()),
+                message: format!("文件加密失败: {}", e),
+                data: None,
             };
             return to_c_string(serde_json::to_string(&result).unwrap_or_default());
         }
     };
 
     let engine = SyncEngine::new();
-    match engine.encrypt_file(&path, key_str.as_bytes()) {
+    match engine.encrypt_data(&file_data, key_str.as_bytes()) {
         Ok(encrypted_data) => {
-            let encoded = base64::encode(&encrypted_data);
+            let encoded = BASE64.encode(&encrypted_data);
             let result = OperationResult {
                 success: true,
                 message: "文件加密完成".to_string(),
                 data: Some(encoded),
+                error_kind: None,
             };
             to_c_string(serde_json::to_string(&result).unwrap_or_default())
         }
@@ -254,34 +1267,152 @@ pub extern "C" fn encrypt_file(
                 success: false,
                 message: format!("文件加密失败: {}", e),
                 data: None,
+                error_kind: Some(error::kind::FAILED.to_string()),
             };
             to_c_string(serde_json::to_string(&result).unwrap_or_default())
         }
     }
 }
 
-/// C FFI: 压缩文件
-#[no_mangle]
-pub extern "C" fn compress_file(file_path: *const c_char) -> *mut c_char {
-    let path = match from_c_string(file_path) {
+/// C FFI: 加密文件（密钥从环境变量读取）
+///
+/// 与 `encrypt_file` 不同，密钥不经过参数传递，而是在 Rust 侧读取指定的
+/// 环境变量，使用后立即清零，避免密钥出现在参数或内存转储中。
+#[unsafe(no_mangle)]
+pub extern "C" fn encrypt_file_env(
+    file_path: *const c_char,
+    env_var_name: *const c_char,
+) -> *mut c_char {
+    ffi_guard(|| encrypt_file_env_impl(file_path, env_var_name))
+}
+
+fn encrypt_file_env_impl(file_path: *const c_char, env_var_name: *const c_char) -> *mut c_char {
+    let path = match from_c_string(file_path) {
+        Ok(s) => s,
+        Err(e) => {
+            let result = OperationResult {
+                success: false,
+                message: format!("解析文件路径失败: {}", e),
+                data: None,
+                error_kind: Some(error::kind::INVALID_ARGUMENT.to_string()),
+            };
+            return to_c_string(serde_json::to_string(&result).unwrap_or_default());
+        }
+    };
+
+    let var_name = match from_c_string(env_var_name) {
+        Ok(s) => s,
+        Err(e) => {
+            let result = OperationResult {
+                success: false,
+                message: format!("解析环境变量名失败: {}", e),
+                data: None,
+                error_kind: Some(error::kind::INVALID_ARGUMENT.to_string()),
+            };
+            return to_c_string(serde_json::to_string(&result).unwrap_or_default());
+        }
+    };
+
+    let key = match std::env::var(&var_name) {
+        Ok(value) if !value.is_empty() => Zeroizing::new(value),
+        Ok(_) => {
+            let result = OperationResult {
+                success: false,
+                message: format!("环境变量 {} 为空", var_name),
+                data: None,
+                error_kind: Some(error::kind::INVALID_ARGUMENT.to_string()),
+            };
+            return to_c_string(serde_json::to_string(&result).unwrap_or_default());
+        }
+        Err(std::env::VarError::NotPresent) => {
+            let result = OperationResult {
+                success: false,
+                message: format!("环境变量 {} 未设置", var_name),
+                data: None,
+                error_kind: Some(error::kind::INVALID_ARGUMENT.to_string()),
+            };
+            return to_c_string(serde_json::to_string(&result).unwrap_or_default());
+        }
+        Err(std::env::VarError::NotUnicode(_)) => {
+            let result = OperationResult {
+                success: false,
+                message: format!("环境变量 {} 的值无效: 不是有效的 Unicode", var_name),
+                data: None,
+                error_kind: Some(error::kind::INVALID_ARGUMENT.to_string()),
+            };
+            return to_c_string(serde_json::to_string(&result).unwrap_or_default());
+        }
+    };
+
+    let file_data = match error::read_file(&path) {
+        Ok(data) => data,
+        Err(e) => {
+            let result = OperationResult {
+                success: false,
+                error_kind: Some(e.kind_name().to_string()),
+                message: format!("文件加密失败: {}", e),
+                data: None,
+            };
+            return to_c_string(serde_json::to_string(&result).unwrap_or_default());
+        }
+    };
+
+    let engine = SyncEngine::new();
+    let encrypted = engine.encrypt_data(&file_data, key.as_bytes());
+    // 派生密钥后立即清零读取到的环境变量值
+    drop(key);
+
+    match encrypted {
+        Ok(encrypted_data) => {
+            let encoded = BASE64.encode(&encrypted_data);
+            let result = OperationResult {
+                success: true,
+                message: "文件加密完成".to_string(),
+                data: Some(encoded),
+                error_kind: None,
+            };
+            to_c_string(serde_json::to_string(&result).unwrap_or_default())
+        }
+        Err(e) => {
+            let result = OperationResult {
+                success: false,
+                message: format!("文件加密失败: {}", e),
+                data: None,
+                error_kind: Some(error::kind::FAILED.to_string()),
+            };
+            to_c_string(serde_json::to_string(&result).unwrap_or_default())
+        }
+    }
+}
+
+/// C FFI: 压缩文件
+#[unsafe(no_mangle)]
+pub extern "C" fn compress_file(file_path: *const c_char) -> *mut c_char {
+    ffi_guard(|| compress_file_impl(file_path))
+}
+
+fn compress_file_impl(file_path: *const c_char) -> *mut c_char {
+    let path = match from_c_string(file_path) {
         Ok(s) => s,
         Err(e) => {
             let result = OperationResult {
                 success: false,
                 message: format!("解析文件路径失败: {}", e),
                 data: None,
+                error_kind: Some(error::kind::INVALID_ARGUMENT.to_string()),
             };
             return to_c_string(serde_json::to_string(&result).unwrap_or_default());
         }
     };
 
     // 读取文件
-    let file_data = match std::fs::read(&path) {
+    let file_data = match error::read_file(&path) {
         Ok(data) => data,
         Err(e) => {
             let result = OperationResult {
                 success: false,
-                message: format!("读取文件失败: {}", e),
+                error_kind: Some(e.kind_name().to_string()),
+                message: e.to_string(),
                 data: None,
             };
             return to_c_string(serde_json::to_string(&result).unwrap_or_default());
@@ -291,11 +1422,12 @@ pub extern "C" fn compress_file(file_path: *const c_char) -> *mut c_char {
     let engine = SyncEngine::new();
     match engine.compress_data(&file_data) {
         Ok(compressed_data) => {
-            let encoded = base64::encode(&compressed_data);
+            let encoded = BASE64.encode(&compressed_data);
             let result = OperationResult {
                 success: true,
                 message: "文件压缩完成".to_string(),
                 data: Some(encoded),
+                error_kind: None,
             };
             to_c_string(serde_json::to_string(&result).unwrap_or_default())
         }
@@ -304,14 +1436,60 @@ pub extern "C" fn compress_file(file_path: *const c_char) -> *mut c_char {
                 success: false,
                 message: format!("文件压缩失败: {}", e),
                 data: None,
+                error_kind: Some(error::kind::FAILED.to_string()),
             };
             to_c_string(serde_json::to_string(&result).unwrap_or_default())
         }
     }
 }
 
+/// C FFI: 将文件流式压缩到指定路径，`data` 中返回压缩统计信息（JSON）
+#[unsafe(no_mangle)]
+pub extern "C" fn compress_file_to_path(
+    input: *const c_char,
+    output: *const c_char,
+) -> *mut c_char {
+    ffi_guard(|| compress_file_to_path_impl(input, output))
+}
+
+fn compress_file_to_path_impl(input: *const c_char, output: *const c_char) -> *mut c_char {
+    let paths = from_c_string(input).and_then(|input| Ok((input, from_c_string(output)?)));
+    let (input_path, output_path) = match paths {
+        Ok(paths) => paths,
+        Err(e) => {
+            let result = OperationResult {
+                success: false,
+                message: format!("解析文件路径失败: {}", e),
+                data: None,
+                error_kind: Some(error::kind::INVALID_ARGUMENT.to_string()),
+            };
+            return to_c_string(serde_json::to_string(&result).unwrap_or_default());
+        }
+    };
+
+    let engine = SyncEngine::new();
+    let result = match engine.compress_file_to_path(&input_path, &output_path) {
+        Ok(stats) => OperationResult {
+            success: true,
+            message: "文件压缩完成".to_string(),
+            data: Some(serde_json::to_string(&stats).unwrap_or_default()),
+            error_kind: None,
+        },
+        Err(e) => OperationResult {
+            success: false,
+            message: format!("文件压缩失败: {}", e),
+            data: None,
+            error_kind: Some(error::kind::FAILED.to_string()),
+        },
+    };
+    to_c_string(serde_json::to_string(&result).unwrap_or_default())
+}
+
 /// C FFI: 释放字符串内存
-#[no_mangle]
+///
+/// `s` 必须是由本库返回且尚未释放的字符串指针（或空指针）。
+#[unsafe(no_mangle)]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
 pub extern "C" fn free_string(s: *mut c_char) {
     if !s.is_null() {
         unsafe {
@@ -324,11 +1502,27 @@ pub extern "C" fn free_string(s: *mut c_char) {
 mod tests {
     use super::*;
 
+    /// 测试用差异条目：哈希为空，其他字段为默认值，需要时用结构体更新语法覆盖
+    pub(crate) fn test_diff(path: &str, operation: &str, size: i64) -> FileDiff {
+        FileDiff {
+            path: path.to_string(),
+            operation: operation.to_string(),
+            source_hash: String::new(),
+            dest_hash: String::new(),
+            size,
+            copy_from: None,
+            explanation: None,
+            range: None,
+            verify_hash: None,
+        }
+    }
+
     #[test]
     fn test_sync_engine_creation() {
         let engine = SyncEngine::new();
         // 基本的创建测试
-        assert!(true);
+        let compressed = engine.compress_data(b"hello").unwrap();
+        assert_eq!(engine.decompress_data(&compressed).unwrap(), b"hello");
     }
 
     #[test]
@@ -339,6 +1533,7 @@ mod tests {
             size: 1024,
             modified_time: "2023-01-01T00:00:00Z".to_string(),
             permissions: "0644".to_string(),
+            weak_checksum: None,
         };
 
         let json = serde_json::to_string(&metadata).unwrap();
@@ -347,5 +1542,967 @@ mod tests {
         assert_eq!(metadata.path, deserialized.path);
         assert_eq!(metadata.hash, deserialized.hash);
     }
+
+    #[test]
+    fn test_size_histogram() {
+        let result = DiffResult {
+            differences: vec![
+                test_diff("a.txt", "create", 100),
+                test_diff("b.txt", "update", 900),
+                test_diff("c.bin", "create", 500 * 1024),
+                test_diff("d.iso", "update", 200 * 1024 * 1024),
+                test_diff("e.old", "delete", 10),
+            ],
+            statistics: DiffStatistics {
+                total_source_files: 4,
+                total_dest_files: 3,
+                files_to_create: 2,
+                files_to_update: 2,
+                files_to_delete: 1,
+                total_size: 0,
+                deletions_considered: true,
+            },
+            errors: Vec::new(),
+        };
+
+        let histogram = result.size_histogram(&[1024, 1024 * 1024, 100 * 1024 * 1024]);
+
+        assert_eq!(histogram.len(), 4);
+        assert_eq!(histogram[0], (1024, 2, 1000));
+        assert_eq!(histogram[1], (1024 * 1024, 1, 500 * 1024));
+        assert_eq!(histogram[2], (100 * 1024 * 1024, 0, 0));
+        assert_eq!(histogram[3], (i64::MAX, 1, 200 * 1024 * 1024));
+    }
+
+    #[test]
+    fn test_top_n_by_priority() {
+        let differences = vec![
+            test_diff("big.bin", "update", 50 * 1024 * 1024),
+            test_diff("Cargo.toml", "create", 100),
+            test_diff("old.log", "delete", 10),
+            test_diff("notes.txt", "update", 100),
+            test_diff("main.rs", "create", 2 * 1024 * 1024),
+            test_diff("data.bin", "create", 100),
+        ];
+        let result = DiffResult {
+            statistics: DiffStatistics::tally(6, 4, &differences),
+            differences,
+            errors: Vec::new(),
+        };
+
+        let top = result.top_n_by_priority(3);
+
+        let mut expected = result.differences.clone();
+        DiffCalculator::new().sort_by_priority(&mut expected);
+        let top_paths: Vec<&str> = top.iter().map(|d| d.path.as_str()).collect();
+        let expected_paths: Vec<&str> = expected[..3].iter().map(|d| d.path.as_str()).collect();
+        assert_eq!(top_paths, expected_paths);
+        assert_eq!(top_paths, vec!["Cargo.toml", "main.rs", "data.bin"]);
+        assert!(result.top_n_by_priority(0).is_empty());
+        assert_eq!(result.top_n_by_priority(10).len(), 6);
+    }
+
+    #[test]
+    fn test_stats_by_extension() {
+        let differences = vec![
+            test_diff("src/app.js", "create", 100),
+            test_diff("src/util.JS", "update", 50),
+            test_diff("img/logo.png", "create", 4000),
+            test_diff("img/old.png", "delete", 1000),
+            test_diff("Makefile", "update", 20),
+        ];
+        let result = DiffResult {
+            statistics: DiffStatistics::tally(4, 4, &differences),
+            differences,
+            errors: Vec::new(),
+        };
+
+        let stats = result.stats_by_extension();
+
+        assert_eq!(stats.len(), 3);
+        let js = &stats["js"];
+        assert_eq!(
+            (js.files_to_create, js.files_to_update, js.total_size),
+            (1, 1, 150)
+        );
+        let png = &stats["png"];
+        assert_eq!(
+            (png.files_to_create, png.files_to_delete, png.total_size),
+            (1, 1, 5000)
+        );
+        let none = &stats[""];
+        assert_eq!((none.files_to_update, none.total_size), (1, 20));
+    }
+
+    #[test]
+    fn test_remaining_after_checkpoint() {
+        let differences = vec![
+            test_diff("a.txt", "create", 10),
+            test_diff("b.txt", "update", 20),
+            test_diff("c.txt", "delete", 30),
+            test_diff("d.txt", "create", 40),
+        ];
+        let result = DiffResult {
+            statistics: DiffStatistics::tally(5, 4, &differences),
+            differences,
+            errors: Vec::new(),
+        };
+
+        let completed: HashSet<String> = ["a.txt", "c.txt", "unrelated.txt"]
+            .iter()
+            .map(|path| path.to_string())
+            .collect();
+        let remaining = result.remaining_after(&completed);
+
+        let paths: Vec<&str> = remaining
+            .differences
+            .iter()
+            .map(|diff| diff.path.as_str())
+            .collect();
+        assert_eq!(paths, vec!["b.txt", "d.txt"]);
+        assert_eq!(remaining.statistics.files_to_create, 1);
+        assert_eq!(remaining.statistics.files_to_update, 1);
+        assert_eq!(remaining.statistics.files_to_delete, 0);
+        assert_eq!(remaining.statistics.total_size, 60);
+        assert_eq!(remaining.statistics.total_source_files, 5);
+        assert_eq!(remaining.statistics.total_dest_files, 4);
+
+        assert_eq!(result.remaining_after(&HashSet::new()).differences.len(), 4);
+    }
+
+    #[test]
+    fn test_compress_signed() {
+        let engine = SyncEngine::new();
+        let data = b"public but tamper-evident payload ".repeat(40);
+        let key = b"integrity key";
+
+        let blob = engine.compress_signed(&data, key).unwrap();
+        assert_eq!(engine.decompress_verified_signed(&blob, key).unwrap(), data);
+
+        let err = engine
+            .decompress_verified_signed(&blob, b"wrong key")
+            .unwrap_err();
+        assert!(err.contains("签名校验失败"), "{}", err);
+
+        let mut tampered = blob.clone();
+        tampered[blob.len() / 2] ^= 0x01;
+        assert!(engine.decompress_verified_signed(&tampered, key).is_err());
+        let mut tampered = blob.clone();
+        *tampered.last_mut().unwrap() ^= 0x01;
+        assert!(engine.decompress_verified_signed(&tampered, key).is_err());
+        assert!(engine.decompress_verified_signed(&blob[..16], key).is_err());
+    }
+
+    #[test]
+    fn test_decompress_verified() {
+        let engine = SyncEngine::new();
+        let data = b"verified payload, verified payload".repeat(50);
+        let hash = CryptoCompressor::new().calculate_hash(&data);
+        let compressed = engine.compress_data(&data).unwrap();
+
+        assert_eq!(
+            engine.decompress_verified(&compressed, &hash).unwrap(),
+            data
+        );
+        let upper = hash.to_uppercase();
+        assert_eq!(
+            engine.decompress_verified(&compressed, &upper).unwrap(),
+            data
+        );
+
+        // 内容被替换成另一段合法的压缩数据
+        let swapped = engine.compress_data(b"something else").unwrap();
+        let err = engine.decompress_verified(&swapped, &hash).unwrap_err();
+        assert!(err.contains("哈希不匹配"), "{}", err);
+
+        // 压缩数据中的字节被篡改
+        let mut tampered = compressed.clone();
+        let middle = tampered.len() / 2;
+        tampered[middle] ^= 0x55;
+        assert!(engine.decompress_verified(&tampered, &hash).is_err());
+
+        assert!(engine.decompress_verified(&compressed, "not-hex").is_err());
+    }
+
+    #[test]
+    fn test_diff_csv_round_trip() {
+        let diff =
+            |path: &str, operation: &str, source_hash: &str, dest_hash: &str, size| FileDiff {
+                source_hash: source_hash.to_string(),
+                dest_hash: dest_hash.to_string(),
+                ..test_diff(path, operation, size)
+            };
+        let differences = vec![
+            diff("plain.txt", "create", "h1", "", 10),
+            diff("a, b.txt", "update", "h2", "h3", 20),
+            diff("say \"hi\".txt", "delete", "", "h4", 30),
+            diff("line\nbreak,\"both\".txt", "update", "h5", "h6", 40),
+        ];
+        let result = DiffResult {
+            statistics: DiffStatistics::tally(4, 4, &differences),
+            differences,
+            errors: Vec::new(),
+        };
+
+        let mut csv = Vec::new();
+        result.to_csv(&mut csv).unwrap();
+        let text = String::from_utf8(csv.clone()).unwrap();
+        assert!(text.starts_with("path,operation,source_hash,dest_hash,size\n"));
+        assert!(text.contains("\"a, b.txt\",update,h2,h3,20\n"));
+        assert!(text.contains("\"say \"\"hi\"\".txt\",delete,,h4,30\n"));
+
+        let parsed = DiffResult::from_csv(csv.as_slice()).unwrap();
+        assert_eq!(parsed.differences.len(), result.differences.len());
+        for (parsed, original) in parsed.differences.iter().zip(&result.differences) {
+            assert_eq!(parsed.path, original.path);
+            assert_eq!(parsed.operation, original.operation);
+            assert_eq!(parsed.source_hash, original.source_hash);
+            assert_eq!(parsed.dest_hash, original.dest_hash);
+            assert_eq!(parsed.size, original.size);
+        }
+        assert_eq!(parsed.statistics.files_to_update, 2);
+
+        assert!(DiffResult::from_csv("wrong,header\n".as_bytes()).is_err());
+        assert!(
+            DiffResult::from_csv(
+                "path,operation,source_hash,dest_hash,size\n\"open,create,h,,1\n".as_bytes()
+            )
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn test_validate_diff() {
+        let diff =
+            |path: &str, operation: &str, source_hash: &str, dest_hash: &str, size| FileDiff {
+                source_hash: source_hash.to_string(),
+                dest_hash: dest_hash.to_string(),
+                ..test_diff(path, operation, size)
+            };
+        let result = |differences: Vec<FileDiff>| DiffResult {
+            statistics: DiffStatistics::tally(0, 0, &differences),
+            differences,
+            errors: Vec::new(),
+        };
+
+        let valid = result(vec![
+            diff("a.txt", "create", "h1", "", 10),
+            diff("b.txt", "update", "h2", "h3", 20),
+            diff("c.txt", "delete", "", "h4", 30),
+            diff("d.txt", "delete", "", "", 0),
+        ]);
+        assert!(valid.validate().is_ok());
+
+        let invalid = result(vec![
+            diff("update.txt", "update", "", "h1", 10),
+            diff("create.txt", "create", "h2", "h3", 10),
+            diff("negative.txt", "create", "h4", "", -5),
+            diff("dup.txt", "create", "h5", "", 1),
+            diff("dup.txt", "update", "h6", "h7", 1),
+            diff("delete.txt", "delete", "h8", "h9", 1),
+            diff("odd.txt", "rename", "h10", "", 1),
+        ]);
+        let violations = invalid.validate().unwrap_err();
+        assert_eq!(violations.len(), 6, "{:?}", violations);
+        let expected = [
+            ("update.txt", "缺少源哈希"),
+            ("create.txt", "不应有目标哈希"),
+            ("negative.txt", "大小为负数"),
+            ("dup.txt", "路径重复"),
+            ("delete.txt", "不应有源哈希"),
+            ("odd.txt", "未知操作"),
+        ];
+        for (path, message) in expected {
+            assert!(
+                violations
+                    .iter()
+                    .any(|v| v.contains(path) && v.contains(message)),
+                "{} {}: {:?}",
+                path,
+                message,
+                violations
+            );
+        }
+    }
+
+    #[test]
+    fn test_canonicalize() {
+        let diff = |path: &str, operation: &str, source_hash: &str, dest_hash: &str| FileDiff {
+            source_hash: source_hash.to_string(),
+            dest_hash: dest_hash.to_string(),
+            ..test_diff(path, operation, 10)
+        };
+        let differences = vec![
+            diff("a.txt", "create", "new_a", ""),
+            diff("b.txt", "delete", "", "old_b"),
+            diff("a.txt", "delete", "", "old_a"),
+            diff("c.txt", "create", "c1", ""),
+            diff("dir\\d.txt", "update", "d1", "old_d"),
+            diff("c.txt", "create", "c2", ""),
+            diff("dir/d.txt", "create", "d2", ""),
+            diff("e.txt", "no_op", "e", ""),
+            diff("e.txt", "delete", "", "old_e"),
+            diff("b.txt", "delete", "", "old_b"),
+            diff("f.txt", "no_op", "f", ""),
+            diff("f.txt", "no_op", "f", ""),
+        ];
+        let mut result = DiffResult {
+            statistics: DiffStatistics::tally(5, 5, &differences),
+            differences,
+            errors: Vec::new(),
+        };
+        result.canonicalize();
+
+        let resolved: Vec<(&str, &str, &str, &str)> = result
+            .differences
+            .iter()
+            .map(|diff| {
+                (
+                    diff.path.as_str(),
+                    diff.operation.as_str(),
+                    diff.source_hash.as_str(),
+                    diff.dest_hash.as_str(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            resolved,
+            vec![
+                ("a.txt", "update", "new_a", "old_a"),
+                ("b.txt", "delete", "", "old_b"),
+                ("c.txt", "create", "c2", ""),
+                ("dir/d.txt", "update", "d2", "old_d"),
+                ("e.txt", "delete", "", "old_e"),
+                ("f.txt", "no_op", "f", ""),
+            ]
+        );
+        assert_eq!(result.statistics.files_to_create, 1);
+        assert_eq!(result.statistics.files_to_update, 2);
+        assert_eq!(result.statistics.files_to_delete, 2);
+        assert_eq!(result.statistics.total_size, 50);
+        assert_eq!(result.statistics.total_source_files, 5);
+        assert!(result.validate().is_ok());
+    }
+
+    #[test]
+    fn test_simulate_apply() {
+        let file = |path: &str, hash: &str, size: i64| FileMetadata {
+            path: path.to_string(),
+            hash: hash.to_string(),
+            size,
+            modified_time: String::new(),
+            permissions: "0644".to_string(),
+            weak_checksum: None,
+        };
+        let diff = |path: &str, operation: &str, source_hash: &str, size: i64| FileDiff {
+            source_hash: source_hash.to_string(),
+            ..test_diff(path, operation, size)
+        };
+        let initial = vec![file("a.txt", "a1", 10), file("b.txt", "b1", 20)];
+
+        let mut differences = vec![
+            diff("new/c.txt", "create", "c1", 30),
+            diff("a.txt", "update", "a2", 15),
+            FileDiff {
+                copy_from: Some("b.txt".to_string()),
+                ..diff("d.txt", "no_op", "b1", 20)
+            },
+            diff("b.txt", "delete", "", 20),
+        ];
+        let result = DiffResult {
+            statistics: DiffStatistics::tally(3, 2, &differences),
+            differences: differences.clone(),
+            errors: Vec::new(),
+        };
+        let applied = result.simulate_apply(&initial).unwrap();
+        let summary: Vec<(&str, &str, i64)> = applied
+            .iter()
+            .map(|file| (file.path.as_str(), file.hash.as_str(), file.size))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("a.txt", "a2", 15),
+                ("d.txt", "b1", 20),
+                ("new/c.txt", "c1", 30)
+            ]
+        );
+        assert_eq!(applied[0].permissions, "0644");
+
+        // 复制源在复制之前就被删除
+        differences.swap(2, 3);
+        let result = DiffResult {
+            statistics: DiffStatistics::tally(3, 2, &differences),
+            differences,
+            errors: Vec::new(),
+        };
+        assert_eq!(
+            result.simulate_apply(&initial).unwrap_err(),
+            "第 4 个操作无法执行: 复制源不存在 b.txt -> d.txt"
+        );
+
+        let differences = vec![diff("c.txt", "update", "c2", 5)];
+        let missing = DiffResult {
+            statistics: DiffStatistics::tally(1, 2, &differences),
+            differences,
+            errors: Vec::new(),
+        };
+        assert_eq!(
+            missing.simulate_apply(&initial).unwrap_err(),
+            "第 1 个操作无法执行: 更新不存在的文件 c.txt"
+        );
+
+        // 区间末尾超出 u64 或 i64 范围
+        for (offset, len) in [(u64::MAX, 2), (i64::MAX as u64, 1)] {
+            let differences = vec![FileDiff {
+                range: Some(ByteRange { offset, len }),
+                ..diff("huge.bin", "create", "h", 1)
+            }];
+            let overflow = DiffResult {
+                statistics: DiffStatistics::tally(1, 0, &differences),
+                differences,
+                errors: Vec::new(),
+            };
+            assert_eq!(
+                overflow.simulate_apply(&[]).unwrap_err(),
+                "第 1 个操作无法执行: 区间越界 huge.bin"
+            );
+        }
+    }
+
+    #[test]
+    fn test_churn_ratio() {
+        let differences = vec![
+            test_diff("a", "create", 100),
+            test_diff("b", "update", 200),
+            test_diff("c", "delete", 300),
+            test_diff("d", "no_op", 400),
+        ];
+        let result = DiffResult {
+            statistics: DiffStatistics::tally(10, 10, &differences),
+            differences,
+            errors: Vec::new(),
+        };
+
+        assert_eq!(result.churn_ratio(10), 0.3);
+        assert_eq!(result.churn_ratio(2), 1.0);
+        assert_eq!(result.churn_ratio(0), 0.0);
+        assert_eq!(result.churn_bytes_ratio(6000), 0.1);
+        assert_eq!(result.churn_bytes_ratio(100), 1.0);
+        assert_eq!(result.churn_bytes_ratio(0), 0.0);
+    }
+
+    #[test]
+    fn test_estimate_transfer_bytes() {
+        let differences = vec![
+            test_diff("a.txt", "create", 1000),
+            test_diff("b.log", "update", 3000),
+            test_diff("c.txt", "delete", 5000),
+            test_diff("d.txt", "no_op", 7000),
+        ];
+        let result = DiffResult {
+            statistics: DiffStatistics::tally(3, 3, &differences),
+            differences,
+            errors: Vec::new(),
+        };
+
+        // 只有 create 和 update 需要传输
+        assert_eq!(result.estimate_transfer_bytes(&|_| 0.25), (1000 + 3000) / 4);
+        let by_type = |diff: &FileDiff| {
+            if diff.path.ends_with(".log") {
+                0.1
+            } else {
+                0.5
+            }
+        };
+        assert_eq!(result.estimate_transfer_bytes(&by_type), 500 + 300);
+    }
+
+    #[test]
+    fn test_invert_diff() {
+        let diff = |path: &str, operation: &str, source_hash: &str, dest_hash: &str| FileDiff {
+            source_hash: source_hash.to_string(),
+            dest_hash: dest_hash.to_string(),
+            ..test_diff(path, operation, 10)
+        };
+        let differences = vec![
+            diff("new.txt", "create", "h_new", ""),
+            diff("changed.txt", "update", "h_after", "h_before"),
+            diff("gone.txt", "delete", "", "h_gone"),
+        ];
+        let result = DiffResult {
+            statistics: DiffStatistics::tally(2, 3, &differences),
+            differences,
+            errors: Vec::new(),
+        };
+
+        let inverse = result.invert();
+
+        assert_eq!(inverse.differences.len(), 3);
+        let created = &inverse.differences[0];
+        assert_eq!(created.operation, "delete");
+        assert_eq!(created.dest_hash, "h_new");
+        let updated = &inverse.differences[1];
+        assert_eq!(updated.operation, "update");
+        assert_eq!(updated.source_hash, "h_before");
+        assert_eq!(updated.dest_hash, "h_after");
+        let deleted = &inverse.differences[2];
+        assert_eq!(deleted.operation, "create");
+        assert_eq!(deleted.source_hash, "h_gone");
+
+        assert_eq!(inverse.statistics.total_source_files, 3);
+        assert_eq!(inverse.statistics.total_dest_files, 2);
+        assert_eq!(inverse.statistics.files_to_create, 1);
+        assert_eq!(inverse.statistics.files_to_update, 1);
+        assert_eq!(inverse.statistics.files_to_delete, 1);
+    }
+
+    #[test]
+    fn test_range_entries_counted_once() {
+        let source = vec![FileMetadata {
+            path: "big".to_string(),
+            hash: "h_big".to_string(),
+            size: 300,
+            modified_time: String::new(),
+            permissions: "0644".to_string(),
+            weak_checksum: None,
+        }];
+        let result = DiffCalculator::new()
+            .with_range_split(100)
+            .calculate_differences(&source, &[])
+            .unwrap();
+        assert_eq!(result.differences.len(), 3);
+        assert_eq!(result.statistics.files_to_create, 1);
+
+        let remaining = result.remaining_after(&HashSet::new());
+        assert_eq!(remaining.statistics, result.statistics);
+
+        let mut canonical = result.remaining_after(&HashSet::new());
+        canonical.canonicalize();
+        assert_eq!(canonical.differences.len(), 3);
+        assert_eq!(canonical.statistics, result.statistics);
+
+        let by_extension = result.stats_by_extension();
+        assert_eq!(by_extension[""].files_to_create, 1);
+        assert_eq!(by_extension[""].total_size, 300);
+
+        let inverse = result.invert();
+        assert_eq!(inverse.differences.len(), 1);
+        assert_eq!(inverse.differences[0].operation, "delete");
+        assert_eq!(inverse.differences[0].size, 300);
+        assert_eq!(inverse.differences[0].range, None);
+        assert_eq!(inverse.statistics.files_to_delete, 1);
+        assert_eq!(inverse.statistics.total_size, 300);
+
+        let applied = result.simulate_apply(&[]).unwrap();
+        assert_eq!(applied[0].size, 300);
+        assert!(inverse.simulate_apply(&applied).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_invert_content_copies() {
+        let copy = |path: &str, source_hash: &str, dest_hash: &str| FileDiff {
+            source_hash: source_hash.to_string(),
+            dest_hash: dest_hash.to_string(),
+            copy_from: Some("cache/shared.js".to_string()),
+            ..test_diff(path, "no_op", 10)
+        };
+        let differences = vec![
+            copy("build/new.js", "h_shared", ""),
+            copy("build/old.js", "h_shared", "h_previous"),
+            FileDiff {
+                source_hash: "h_cache_v2".to_string(),
+                dest_hash: "h_shared".to_string(),
+                ..test_diff("cache/shared.js", "update", 10)
+            },
+        ];
+        let result = DiffResult {
+            statistics: DiffStatistics::tally(3, 2, &differences),
+            differences,
+            errors: Vec::new(),
+        };
+
+        let inverse = result.invert();
+
+        let operations: Vec<&str> = inverse
+            .differences
+            .iter()
+            .map(|diff| diff.operation.as_str())
+            .collect();
+        assert_eq!(operations, vec!["delete", "update", "update"]);
+        assert_eq!(inverse.differences[0].path, "build/new.js");
+        assert_eq!(inverse.differences[0].dest_hash, "h_shared");
+        assert_eq!(inverse.differences[1].source_hash, "h_previous");
+        assert!(
+            inverse
+                .differences
+                .iter()
+                .all(|diff| diff.copy_from.is_none())
+        );
+        // 复制源路径的修改由它自己的条目反转
+        assert_eq!(inverse.differences[2].path, "cache/shared.js");
+        assert_eq!(inverse.differences[2].source_hash, "h_shared");
+        assert_eq!(inverse.statistics.files_to_delete, 1);
+        assert_eq!(inverse.statistics.files_to_update, 2);
+    }
+
+    #[test]
+    fn test_save_and_load_large_diff_result() {
+        let differences: Vec<FileDiff> = (0..20_000)
+            .map(|i| FileDiff {
+                source_hash: format!("{:064x}", i),
+                dest_hash: format!("{:064x}", i * 7),
+                ..test_diff(
+                    &format!("dir{}/file{}.bin", i % 50, i),
+                    ["create", "update", "delete"][i % 3],
+                    i as i64 * 13,
+                )
+            })
+            .collect();
+        let result = DiffResult {
+            statistics: DiffStatistics::tally(20_000, 15_000, &differences),
+            differences,
+            errors: vec![DiffError {
+                path: "broken.txt".to_string(),
+                message: "比较失败".to_string(),
+            }],
+        };
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("diff.json");
+        let path = path.to_str().unwrap();
+        result.save_to_file(path).unwrap();
+        assert!(std::fs::metadata(path).unwrap().len() > 1024 * 1024);
+
+        let loaded = DiffResult::load_from_file(path).unwrap();
+        assert_eq!(
+            serde_json::to_value(&loaded).unwrap(),
+            serde_json::to_value(&result).unwrap()
+        );
+        let missing = dir.path().join("missing.json");
+        assert!(DiffResult::load_from_file(missing.to_str().unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_lean_metadata_view() {
+        let metadata = FileMetadata {
+            path: "/test/file.txt".to_string(),
+            hash: "abc123".to_string(),
+            size: 1024,
+            modified_time: "2023-01-01T00:00:00Z".to_string(),
+            permissions: "0644".to_string(),
+            weak_checksum: None,
+        };
+
+        let lean_json = serde_json::to_string(&FileMetadataView::lean(&metadata)).unwrap();
+        assert!(!lean_json.contains("modified_time"));
+        assert!(!lean_json.contains("permissions"));
+
+        let full_json = serde_json::to_string(
+            &FileMetadataView::lean(&metadata)
+                .with_modified_time(true)
+                .with_permissions(true),
+        )
+        .unwrap();
+        assert!(full_json.contains("2023-01-01T00:00:00Z"));
+        assert!(full_json.contains("0644"));
+
+        let lean: FileMetadata = serde_json::from_str(&lean_json).unwrap();
+        assert_eq!(lean.path, "/test/file.txt");
+        assert_eq!(lean.size, 1024);
+        assert_eq!(lean.modified_time, "");
+        assert_eq!(lean.permissions, "");
+
+        let engine = SyncEngine::new();
+        let changed: FileMetadata =
+            serde_json::from_str(r#"{"path":"/test/file.txt","hash":"def456","size":1024}"#)
+                .unwrap();
+        let result = engine
+            .calculate_differences(&[changed], std::slice::from_ref(&lean))
+            .unwrap();
+        assert_eq!(result.differences.len(), 1);
+        assert_eq!(result.differences[0].operation, "update");
+    }
+
+    #[test]
+    fn test_calculate_diff_raw_paths_ffi() {
+        let raw_path: &[u8] = b"dir/caf\xe9.txt";
+        let entry = |path: &[u8], hash: &str| {
+            let path = BASE64.encode(path);
+            serde_json::json!({"path": path, "hash": hash, "size": 10})
+        };
+        let source = serde_json::json!([entry(raw_path, "hash_new"), entry(b"\xff.bin", "h1")]);
+        let dest = serde_json::json!([entry(raw_path, "hash_old"), entry(b"\xfe.bin", "h2")]);
+        let source_c = CString::new(source.to_string()).unwrap();
+        let dest_c = CString::new(dest.to_string()).unwrap();
+
+        let result = call_ffi(calculate_diff_raw_paths(source_c.as_ptr(), dest_c.as_ptr()));
+        assert!(result.success, "{}", result.message);
+        let diff_result: DiffResult = serde_json::from_str(&result.data.unwrap()).unwrap();
+
+        let mut operations: Vec<(Vec<u8>, String)> = diff_result
+            .differences
+            .iter()
+            .map(|diff| (BASE64.decode(&diff.path).unwrap(), diff.operation.clone()))
+            .collect();
+        operations.sort();
+        assert_eq!(
+            operations,
+            vec![
+                (raw_path.to_vec(), "update".to_string()),
+                (b"\xff.bin".to_vec(), "create".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_calculate_diff_ffi_sorted() {
+        let entry =
+            |path: &str, hash: &str| serde_json::json!({"path": path, "hash": hash, "size": 10});
+        let source = serde_json::json!([
+            entry("zeta.txt", "h1"),
+            entry("b\\inner.txt", "h2"),
+            entry("alpha.txt", "h3"),
+            entry("b/a.txt", "h4"),
+            entry("mid.txt", "new"),
+        ]);
+        let dest = serde_json::json!([entry("mid.txt", "old")]);
+        let source_c = CString::new(source.to_string()).unwrap();
+        let dest_c = CString::new(dest.to_string()).unwrap();
+
+        let first = call_ffi(calculate_diff(source_c.as_ptr(), dest_c.as_ptr()));
+        let second = call_ffi(calculate_diff(source_c.as_ptr(), dest_c.as_ptr()));
+        assert!(first.success, "{}", first.message);
+        let data = first.data.unwrap();
+        assert_eq!(data, second.data.unwrap());
+
+        let diff_result: DiffResult = serde_json::from_str(&data).unwrap();
+        let paths: Vec<&str> = diff_result
+            .differences
+            .iter()
+            .map(|diff| diff.path.as_str())
+            .collect();
+        assert_eq!(
+            paths,
+            vec![
+                "alpha.txt",
+                "b/a.txt",
+                "b\\inner.txt",
+                "mid.txt",
+                "zeta.txt"
+            ]
+        );
+    }
+
+    /// 调用 FFI 并解析返回的 OperationResult
+    fn call_ffi(raw: *mut c_char) -> OperationResult {
+        let json = unsafe { CStr::from_ptr(raw) }.to_str().unwrap().to_string();
+        free_string(raw);
+        serde_json::from_str(&json).unwrap()
+    }
+
+    #[test]
+    fn test_path_bytes_round_trip() {
+        let paths: [&[u8]; 4] = [
+            b"plain/file.txt",
+            b"100%/done.txt",
+            b"dir/caf\xe9.txt",
+            b"\xff\xfe%41\xc3",
+        ];
+        for path in paths {
+            let encoded = FileMetadata::encode_path_bytes(path);
+            assert_eq!(FileMetadata::decode_path_bytes(&encoded).unwrap(), path);
+        }
+        assert_eq!(
+            FileMetadata::encode_path_bytes(b"plain/file.txt"),
+            "plain/file.txt"
+        );
+        assert_eq!(FileMetadata::encode_path_bytes(b"caf\xe9"), "caf%E9");
+        // 不同的无效字节序列不会像有损转换那样合并为同一路径
+        assert_ne!(
+            FileMetadata::encode_path_bytes(b"\xe9"),
+            FileMetadata::encode_path_bytes(b"\xff")
+        );
+        assert!(FileMetadata::decode_path_bytes("bad%G1").is_err());
+    }
+
+    #[test]
+    fn test_compress_file_to_path_ffi() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("input.txt");
+        let output = dir.path().join("input.txt.z");
+        let data = "synccli stream compression ".repeat(10_000);
+        std::fs::write(&input, &data).unwrap();
+
+        let input_c = CString::new(input.to_str().unwrap()).unwrap();
+        let output_c = CString::new(output.to_str().unwrap()).unwrap();
+        let result = call_ffi(compress_file_to_path(input_c.as_ptr(), output_c.as_ptr()));
+        assert!(result.success, "{}", result.message);
+
+        let stats: serde_json::Value = serde_json::from_str(&result.data.unwrap()).unwrap();
+        let compressed = std::fs::read(&output).unwrap();
+        assert_eq!(stats["original_size"], data.len());
+        assert_eq!(stats["compressed_size"], compressed.len());
+        assert!(stats["compression_ratio"].as_f64().unwrap() > 90.0);
+
+        let engine = SyncEngine::new();
+        assert_eq!(
+            engine.decompress_data(&compressed).unwrap(),
+            data.as_bytes()
+        );
+    }
+
+    #[test]
+    fn test_ffi_guard_converts_panic_to_error() {
+        let result = call_ffi(ffi_guard(|| panic!("serde 边界情况")));
+        assert!(!result.success);
+        assert_eq!(result.message, "内部错误: serde 边界情况");
+        assert!(result.data.is_none());
+
+        let ok = call_ffi(ffi_guard(|| {
+            to_c_string(
+                serde_json::to_string(&OperationResult {
+                    success: true,
+                    message: "ok".to_string(),
+                    data: None,
+                    error_kind: None,
+                })
+                .unwrap(),
+            )
+        }));
+        assert!(ok.success);
+    }
+
+    extern "C" fn collect_diff(diff_json: *const c_char, user_data: *mut c_void) {
+        let collected = unsafe { &mut *(user_data as *mut Vec<FileDiff>) };
+        let json = unsafe { CStr::from_ptr(diff_json) }.to_str().unwrap();
+        collected.push(serde_json::from_str(json).unwrap());
+    }
+
+    #[test]
+    fn test_calculate_diff_streaming() {
+        let source = CString::new(
+            r#"[{"path":"a.txt","hash":"h1","size":1},{"path":"b.txt","hash":"h2","size":2}]"#,
+        )
+        .unwrap();
+        let dest = CString::new(r#"[{"path":"b.txt","hash":"h0","size":2}]"#).unwrap();
+        let mut collected: Vec<FileDiff> = Vec::new();
+
+        let result = call_ffi(calculate_diff_streaming(
+            source.as_ptr(),
+            dest.as_ptr(),
+            Some(collect_diff),
+            &mut collected as *mut Vec<FileDiff> as *mut c_void,
+        ));
+
+        assert!(result.success, "{}", result.message);
+        let operations: Vec<&str> = collected.iter().map(|d| d.operation.as_str()).collect();
+        assert_eq!(operations, vec!["create", "update"]);
+        let summary: serde_json::Value = serde_json::from_str(&result.data.unwrap()).unwrap();
+        assert_eq!(summary["statistics"]["files_to_create"], 1);
+        assert_eq!(summary["statistics"]["files_to_update"], 1);
+
+        let result = call_ffi(calculate_diff_streaming(
+            source.as_ptr(),
+            dest.as_ptr(),
+            None,
+            std::ptr::null_mut(),
+        ));
+        assert!(!result.success);
+    }
+
+    #[test]
+    fn test_compress_file_reports_io_error_kind() {
+        let dir = tempfile::tempdir().unwrap();
+        let missing = CString::new(dir.path().join("missing.bin").to_str().unwrap()).unwrap();
+
+        let result = call_ffi(compress_file(missing.as_ptr()));
+
+        assert!(!result.success);
+        assert_eq!(result.error_kind.as_deref(), Some("NotFound"));
+    }
+
+    #[test]
+    fn test_ffi_error_kinds() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir_path = CString::new(dir.path().to_str().unwrap()).unwrap();
+        let key = CString::new("password").unwrap();
+        let is_a_directory = Some(error::kind::IS_A_DIRECTORY);
+        let invalid_argument = Some(error::kind::INVALID_ARGUMENT);
+
+        let result = call_ffi(encrypt_file(dir_path.as_ptr(), key.as_ptr()));
+        assert!(!result.success);
+        assert_eq!(result.error_kind.as_deref(), is_a_directory);
+
+        let result = call_ffi(encrypt_file(std::ptr::null(), key.as_ptr()));
+        assert_eq!(result.error_kind.as_deref(), invalid_argument);
+
+        let invalid = CString::new("not json").unwrap();
+        let result = call_ffi(calculate_diff(invalid.as_ptr(), invalid.as_ptr()));
+        assert!(!result.success);
+        assert_eq!(result.error_kind.as_deref(), invalid_argument);
+
+        let result = call_ffi(calculate_diff_raw_paths(invalid.as_ptr(), invalid.as_ptr()));
+        assert_eq!(result.error_kind.as_deref(), invalid_argument);
+
+        let result = call_ffi(ffi_guard(|| panic!("boom")));
+        assert_eq!(result.error_kind.as_deref(), Some(error::kind::INTERNAL));
+    }
+
+    /// 串行化修改进程环境变量的测试，避免并行测试互相干扰
+    static ENV_LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+    #[test]
+    fn test_encrypt_file_env() {
+        let mut temp_file = tempfile::NamedTempFile::new().unwrap();
+        temp_file.write_all(b"secret file content").unwrap();
+        let path = CString::new(temp_file.path().to_str().unwrap()).unwrap();
+
+        let var_name = "SYNCCLI_TEST_ENCRYPT_FILE_ENV_KEY";
+        let c_var_name = CString::new(var_name).unwrap();
+
+        let result = {
+            let _guard = ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+            unsafe { std::env::set_var(var_name, "env_password") };
+            let result = call_ffi(encrypt_file_env(path.as_ptr(), c_var_name.as_ptr()));
+            unsafe { std::env::remove_var(var_name) };
+            result
+        };
+        assert!(result.success, "{}", result.message);
+
+        let encrypted = BASE64.decode(result.data.unwrap()).unwrap();
+        let engine = SyncEngine::new();
+        let decrypted = engine.decrypt_file(&encrypted, b"env_password").unwrap();
+        assert_eq!(decrypted, b"secret file content");
+
+        let missing = CString::new("SYNCCLI_TEST_ENCRYPT_FILE_ENV_MISSING").unwrap();
+        let result = call_ffi(encrypt_file_env(path.as_ptr(), missing.as_ptr()));
+        assert!(!result.success);
+        assert!(result.message.contains("未设置"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_encrypt_file_env_rejects_non_unicode_value() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let temp_file = tempfile::NamedTempFile::new().unwrap();
+        let path = CString::new(temp_file.path().to_str().unwrap()).unwrap();
+
+        let var_name = "SYNCCLI_TEST_ENCRYPT_FILE_ENV_NON_UNICODE";
+        let c_var_name = CString::new(var_name).unwrap();
+
+        let result = {
+            let _guard = ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+            let value = std::ffi::OsStr::from_bytes(b"\xff\xfe");
+            unsafe { std::env::set_var(var_name, value) };
+            let result = call_ffi(encrypt_file_env(path.as_ptr(), c_var_name.as_ptr()));
+            unsafe { std::env::remove_var(var_name) };
+            result
+        };
+        assert!(!result.success);
+        assert!(result.message.contains("无效"), "{}", result.message);
+        assert!(!result.message.contains("未设置"));
+    }
 }
 
@@ -0,0 +1,127 @@
+//! 进度报告：各类耗时操作共用的 `ProgressSink` 接口
+
+use std::io::{self, Read};
+use std::sync::Mutex;
+
+/// 进度接收方
+///
+/// 每次操作依次调用一次 `on_start`、若干次 `on_advance` 和一次 `on_done`。
+/// 进度单位由操作决定（文件数或字节数，见各 `*_with_progress` 方法），
+/// 操作成功完成时 `on_advance` 的累计值等于 `on_start` 给出的总量；
+/// 总量事先未知时为 0。并行操作会从多个线程调用，因此要求 `Send`。
+pub trait ProgressSink: Send {
+    /// 操作开始，`total` 为预计的总量
+    fn on_start(&mut self, total: u64);
+    /// 又完成了 `delta` 个单位
+    fn on_advance(&mut self, delta: u64);
+    /// 操作结束（出错时也会调用）
+    fn on_done(&mut self);
+}
+
+/// 可选进度接收方的包装，允许在并行任务中共享
+pub(crate) struct Progress<'a> {
+    sink: Option<Mutex<&'a mut dyn ProgressSink>>,
+}
+
+impl<'a> Progress<'a> {
+    pub(crate) fn new(sink: Option<&'a mut dyn ProgressSink>) -> Self {
+        Self {
+            sink: sink.map(Mutex::new),
+        }
+    }
+
+    fn with_sink(&self, f: impl FnOnce(&mut dyn ProgressSink)) {
+        if let Some(sink) = &self.sink {
+            // 接收方 panic 不影响后续的进度报告
+            let mut sink = sink.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            f(&mut **sink);
+        }
+    }
+
+    pub(crate) fn start(&self, total: u64) {
+        self.with_sink(|sink| sink.on_start(total));
+    }
+
+    pub(crate) fn advance(&self, delta: u64) {
+        if delta > 0 {
+            self.with_sink(|sink| sink.on_advance(delta));
+        }
+    }
+
+    pub(crate) fn done(&self) {
+        self.with_sink(|sink| sink.on_done());
+    }
+}
+
+/// 每次读取后按读到的字节数报告进度的读取器
+pub(crate) struct ProgressReader<'p, 'a, R> {
+    inner: R,
+    progress: &'p Progress<'a>,
+}
+
+impl<'p, 'a, R> ProgressReader<'p, 'a, R> {
+    pub(crate) fn new(inner: R, progress: &'p Progress<'a>) -> Self {
+        Self { inner, progress }
+    }
+}
+
+impl<R: Read> Read for ProgressReader<'_, '_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.progress.advance(read as u64);
+        Ok(read)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// 记录全部回调的测试用接收方
+    #[derive(Debug, Default)]
+    pub(crate) struct RecordingSink {
+        pub(crate) events: Vec<String>,
+        pub(crate) total: Option<u64>,
+        pub(crate) advanced: u64,
+    }
+
+    impl ProgressSink for RecordingSink {
+        fn on_start(&mut self, total: u64) {
+            self.events.push("start".to_string());
+            self.total = Some(total);
+        }
+
+        fn on_advance(&mut self, delta: u64) {
+            if self.events.last().map(String::as_str) != Some("advance") {
+                self.events.push("advance".to_string());
+            }
+            self.advanced += delta;
+        }
+
+        fn on_done(&mut self) {
+            self.events.push("done".to_string());
+        }
+    }
+
+    impl RecordingSink {
+        /// 断言完整的生命周期，且累计进度等于总量
+        pub(crate) fn assert_complete(&self) {
+            assert_eq!(self.events, vec!["start", "advance", "done"]);
+            assert_eq!(Some(self.advanced), self.total);
+        }
+    }
+
+    #[test]
+    fn test_progress_without_sink() {
+        let progress = Progress::new(None);
+        progress.start(10);
+        progress.advance(10);
+        progress.done();
+
+        let mut read = Vec::new();
+        ProgressReader::new(&b"data"[..], &progress)
+            .read_to_end(&mut read)
+            .unwrap();
+        assert_eq!(read, b"data");
+    }
+}
@@ -0,0 +1,362 @@
+//! 目录扫描：遍历目录树并生成 `FileMetadata` 列表
+
+use crate::FileMetadata;
+use crate::crypto::CryptoCompressor;
+use crate::progress::{Progress, ProgressSink};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+
+/// 目录扫描选项
+#[derive(Debug, Clone, Default)]
+pub struct ScanOptions {
+    /// 哈希期间是否检测文件修改（见 `with_change_detection`）
+    detect_changes: bool,
+    /// 是否跳过隐藏文件和目录（见 `with_skip_hidden`）
+    skip_hidden: bool,
+}
+
+impl ScanOptions {
+    /// 创建默认扫描选项
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 哈希前后检查文件大小和修改时间，文件在哈希期间被修改时扫描失败
+    ///
+    /// 见 `CryptoCompressor::with_change_detection`。
+    pub fn with_change_detection(mut self, enabled: bool) -> Self {
+        self.detect_changes = enabled;
+        self
+    }
+
+    /// 跳过名称以 `.` 开头的文件和目录（如 `.git/`、`.env`、`.DS_Store`），隐藏目录不会被遍历
+    pub fn with_skip_hidden(mut self, enabled: bool) -> Self {
+        self.skip_hidden = enabled;
+        self
+    }
+}
+
+/// 扫描时无法读取的单个文件或目录
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScanError {
+    /// 相对于扫描根目录的路径
+    pub path: String,
+    pub message: String,
+}
+
+/// 判断路径中是否有以 `.` 开头的组成部分（`.` 和 `..` 除外）
+pub(crate) fn is_hidden_path(path: &str) -> bool {
+    path.split(['/', '\\'])
+        .any(|component| component.starts_with('.') && component != "." && component != "..")
+}
+
+/// 扫描目录树，返回其中所有文件的元数据（按路径排序）和扫描中遇到的错误
+///
+/// 路径相对于 `root` 并以 `/` 分隔；哈希为流式计算的 SHA256 十六进制串，修改时间为
+/// Unix 秒数，权限为八进制字符串（非 Unix 平台为空）。指向目录的符号链接不会展开，
+/// 避免目录循环。单个文件或子目录无法读取时记录到错误列表并继续扫描，
+/// 只有 `root` 不是目录时直接返回错误。
+pub fn scan_directory(
+    root: &str,
+    options: &ScanOptions,
+) -> Result<(Vec<FileMetadata>, Vec<ScanError>), String> {
+    scan_directory_with_progress(root, options, None)
+}
+
+/// 扫描目录树并报告进度
+///
+/// 遍历目录完成后以文件总数调用 `on_start`，每读取一个文件（无论成功与否）前进 1。
+pub fn scan_directory_with_progress(
+    root: &str,
+    options: &ScanOptions,
+    progress: Option<&mut dyn ProgressSink>,
+) -> Result<(Vec<FileMetadata>, Vec<ScanError>), String> {
+    let root_path = Path::new(root);
+    if !root_path.is_dir() {
+        return Err(format!("不是有效的目录: {}", root));
+    }
+
+    let mut relative_paths = Vec::new();
+    let mut errors = Vec::new();
+    collect_files(
+        root_path,
+        root_path,
+        options.skip_hidden,
+        &mut relative_paths,
+        &mut errors,
+    );
+    relative_paths.sort();
+
+    let progress = Progress::new(progress);
+    progress.start(relative_paths.len() as u64);
+    let (files, file_errors) = scan_files(root_path, &relative_paths, options, &progress);
+    progress.done();
+    errors.extend(file_errors);
+    errors.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok((files, errors))
+}
+
+/// 并行读取 `relative_paths` 中各文件的元数据，失败的文件记录为错误
+fn scan_files(
+    root: &Path,
+    relative_paths: &[String],
+    options: &ScanOptions,
+    progress: &Progress,
+) -> (Vec<FileMetadata>, Vec<ScanError>) {
+    let crypto = CryptoCompressor::new().with_change_detection(options.detect_changes);
+    let outcomes: Vec<Result<FileMetadata, ScanError>> = relative_paths
+        .par_iter()
+        .map(|relative| {
+            let outcome = file_metadata(&crypto, root, relative).map_err(|message| ScanError {
+                path: relative.clone(),
+                message,
+            });
+            progress.advance(1);
+            outcome
+        })
+        .collect();
+
+    let mut files = Vec::with_capacity(outcomes.len());
+    let mut errors = Vec::new();
+    for outcome in outcomes {
+        match outcome {
+            Ok(file) => files.push(file),
+            Err(error) => errors.push(error),
+        }
+    }
+    (files, errors)
+}
+
+/// 递归收集 `dir` 下的所有文件，路径相对于 `root` 并以 `/` 分隔
+///
+/// 不跟随指向目录的符号链接，避免循环链接或逃逸出 `root`。无法读取的目录或条目
+/// 记录到 `errors`，不影响其他条目。
+pub(crate) fn collect_files(
+    root: &Path,
+    dir: &Path,
+    skip_hidden: bool,
+    out: &mut Vec<String>,
+    errors: &mut Vec<ScanError>,
+) {
+    let relative = |path: &Path| {
+        path.strip_prefix(root)
+            .unwrap_or(path)
+            .to_string_lossy()
+            .replace('\\', "/")
+    };
+    let mut record = |path: &Path, message: String| {
+        errors.push(ScanError {
+            path: relative(path),
+            message,
+        })
+    };
+
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => return record(dir, format!("读取目录失败: {}", e)),
+    };
+
+    let mut subdirs = Vec::new();
+    for entry in entries {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                record(dir, format!("读取目录失败: {}", e));
+                continue;
+            }
+        };
+        let path = entry.path();
+        if skip_hidden && entry.file_name().to_string_lossy().starts_with('.') {
+            continue;
+        }
+        let file_type = match entry.file_type() {
+            Ok(file_type) => file_type,
+            Err(e) => {
+                record(&path, format!("读取文件类型失败: {}", e));
+                continue;
+            }
+        };
+
+        if file_type.is_dir() {
+            subdirs.push(path);
+        } else if file_type.is_file() || path.is_file() {
+            out.push(relative(&path));
+        }
+    }
+
+    for subdir in subdirs {
+        collect_files(root, &subdir, skip_hidden, out, errors);
+    }
+}
+
+/// 读取单个文件的元数据并计算哈希
+fn file_metadata(
+    crypto: &CryptoCompressor,
+    root: &Path,
+    relative: &str,
+) -> Result<FileMetadata, String> {
+    let full_path = root.join(relative);
+    let metadata = fs::metadata(&full_path)
+        .map_err(|e| format!("读取文件元数据失败 {}: {}", full_path.display(), e))?;
+    let full_path = full_path.to_string_lossy();
+
+    let modified_time = metadata
+        .modified()
+        .ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map(|duration| duration.as_secs().to_string())
+        .unwrap_or_default();
+
+    Ok(FileMetadata {
+        path: relative.to_string(),
+        hash: crypto.hash_file(&full_path)?,
+        size: metadata.len() as i64,
+        modified_time,
+        permissions: permissions_string(&metadata),
+        weak_checksum: None,
+    })
+}
+
+/// 文件权限的八进制字符串（如 `"644"`）
+#[cfg(unix)]
+fn permissions_string(metadata: &fs::Metadata) -> String {
+    use std::os::unix::fs::PermissionsExt;
+    format!("{:o}", metadata.permissions().mode() & 0o7777)
+}
+
+#[cfg(not(unix))]
+fn permissions_string(_metadata: &fs::Metadata) -> String {
+    String::new()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scan_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("sub/deeper")).unwrap();
+        fs::write(dir.path().join("top.txt"), b"top").unwrap();
+        fs::write(dir.path().join("sub/deeper/leaf.txt"), b"leaf").unwrap();
+
+        let options = ScanOptions::new().with_change_detection(true);
+        let (files, errors) = scan_directory(dir.path().to_str().unwrap(), &options).unwrap();
+        assert!(errors.is_empty());
+
+        let paths: Vec<&str> = files.iter().map(|file| file.path.as_str()).collect();
+        assert_eq!(paths, vec!["sub/deeper/leaf.txt", "top.txt"]);
+
+        let top = &files[1];
+        assert_eq!(top.hash, CryptoCompressor::new().calculate_hash(b"top"));
+        assert_eq!(top.size, 3);
+        assert!(top.modified_time.parse::<u64>().is_ok());
+
+        assert!(scan_directory(dir.path().join("top.txt").to_str().unwrap(), &options).is_err());
+    }
+
+    #[test]
+    fn test_scan_skip_hidden() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join(".git/objects")).unwrap();
+        fs::create_dir_all(dir.path().join("src")).unwrap();
+        fs::write(dir.path().join(".git/HEAD"), b"ref").unwrap();
+        fs::write(dir.path().join(".git/objects/ab"), b"obj").unwrap();
+        fs::write(dir.path().join(".env"), b"SECRET=1").unwrap();
+        fs::write(dir.path().join("src/.main.rs.swp"), b"swap").unwrap();
+        fs::write(dir.path().join("src/main.rs"), b"fn main() {}").unwrap();
+        let root = dir.path().to_str().unwrap();
+
+        let paths = |options: &ScanOptions| -> Vec<String> {
+            scan_directory(root, options)
+                .unwrap()
+                .0
+                .into_iter()
+                .map(|file| file.path)
+                .collect()
+        };
+        assert_eq!(
+            paths(&ScanOptions::new()),
+            vec![
+                ".env",
+                ".git/HEAD",
+                ".git/objects/ab",
+                "src/.main.rs.swp",
+                "src/main.rs"
+            ]
+        );
+        assert_eq!(
+            paths(&ScanOptions::new().with_skip_hidden(true)),
+            vec!["src/main.rs"]
+        );
+
+        assert!(is_hidden_path(".git/HEAD"));
+        assert!(is_hidden_path("a\\.cache\\b"));
+        assert!(!is_hidden_path("./src/main.rs"));
+        assert!(!is_hidden_path("../file.txt"));
+    }
+
+    #[test]
+    fn test_scan_progress() {
+        use crate::progress::tests::RecordingSink;
+
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("sub")).unwrap();
+        for name in ["a.txt", "b.txt", "sub/c.txt"] {
+            fs::write(dir.path().join(name), name).unwrap();
+        }
+
+        let mut sink = RecordingSink::default();
+        let (files, _) = scan_directory_with_progress(
+            dir.path().to_str().unwrap(),
+            &ScanOptions::new(),
+            Some(&mut sink),
+        )
+        .unwrap();
+        assert_eq!(files.len(), 3);
+        sink.assert_complete();
+        assert_eq!(sink.total, Some(3));
+    }
+
+    #[test]
+    fn test_scan_partial_result() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("good.txt"), b"good").unwrap();
+        fs::write(dir.path().join("secret.txt"), b"secret").unwrap();
+
+        // 文件在收集之后、读取之前消失
+        let relative_paths = vec!["gone.txt".to_string(), "good.txt".to_string()];
+        let (files, errors) = scan_files(
+            dir.path(),
+            &relative_paths,
+            &ScanOptions::new(),
+            &Progress::new(None),
+        );
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].path, "good.txt");
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].path, "gone.txt");
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let secret = dir.path().join("secret.txt");
+            fs::set_permissions(&secret, fs::Permissions::from_mode(0o000)).unwrap();
+            let (files, errors) =
+                scan_directory(dir.path().to_str().unwrap(), &ScanOptions::new()).unwrap();
+            // root 可以读取任何文件，此时不会产生错误
+            if unsafe { libc::geteuid() } != 0 {
+                assert_eq!(files.len(), 1);
+                assert_eq!(files[0].path, "good.txt");
+                assert_eq!(errors.len(), 1);
+                assert_eq!(errors[0].path, "secret.txt");
+            } else {
+                assert_eq!(files.len(), 2);
+            }
+            fs::set_permissions(&secret, fs::Permissions::from_mode(0o644)).unwrap();
+        }
+    }
+}